@@ -0,0 +1,55 @@
+// Indexing and search timings on a generated corpus, run with `cargo bench --bench indexing`
+// No benchmark framework: a few repetitions and the fastest time, enough to compare two builds
+use std::time::{Duration, Instant};
+use rust::bm25::{Bm25Params, Bm25Scorer};
+use rust::chunker::ChunkingConfig;
+use rust::corpus::{Corpus, Document};
+
+const DOCUMENTS: usize = 2_000;
+const WORDS_PER_DOCUMENT: usize = 400;
+const VOCABULARY: u64 = 20_000;
+const RUNS: usize = 5;
+
+// Zipf-like word choice, so a few words are very common like in real text
+fn generate(seed: u64) -> Vec<Document> {
+    let mut state = seed;
+    let mut next = move || {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        state >> 33
+    };
+    (0..DOCUMENTS)
+        .map(|i| {
+            let words: Vec<String> = (0..WORDS_PER_DOCUMENT)
+                .map(|_| {
+                    let rank = VOCABULARY / (next() % VOCABULARY + 1);
+                    format!("w{}", rank)
+                })
+                .collect();
+            Document::new(&format!("{}.txt", i), &words.join(" "))
+        })
+        .collect()
+}
+
+fn fastest<T>(mut run: impl FnMut() -> T) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(run());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let documents = generate(42);
+    let chunking = ChunkingConfig::default();
+    let indexing = fastest(|| Corpus::new(documents.clone(), chunking));
+    println!("index {} documents: {:?}", DOCUMENTS, indexing);
+
+    let corpus = Corpus::new(documents, chunking);
+    let queries = ["w1 w2", "w10 w200 w3000", "w5 w17 w123 w4567 w19999"];
+    let scorer = Bm25Scorer { corpus: &corpus, params: Bm25Params::default() };
+    let search = fastest(|| queries.iter().map(|query| corpus.search_with(query, &scorer).unwrap()).collect::<Vec<_>>());
+    println!("search {} queries: {:?}", queries.len(), search);
+}
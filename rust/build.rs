@@ -0,0 +1,12 @@
+fn main() {
+    // Only the grpc feature has anything to generate, plain builds need neither protoc nor tonic
+    #[cfg(feature = "grpc")]
+    {
+        // Use the protoc shipped as a crate so building doesn't depend on a system install
+        let mut config = tonic_prost_build::Config::new();
+        config.protoc_executable(protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform"));
+        tonic_prost_build::configure()
+            .compile_with_config(config, &["proto/tfidf.proto"], &["proto"])
+            .expect("failed to compile proto/tfidf.proto");
+    }
+}
@@ -0,0 +1,33 @@
+// Chunking must never split a UTF-8 character or lose text, whatever the strategy, size and overlap
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rust::chunker::{chunk_with_config, ChunkStrategy, ChunkingConfig};
+use rust::corpus::DocId;
+
+#[derive(Debug, Arbitrary)]
+struct Input<'a> {
+    text: &'a str,
+    strategy: u8,
+    size: u8,
+    overlap: u8,
+}
+
+fuzz_target!(|input: Input| {
+    let strategy = [ChunkStrategy::Chars, ChunkStrategy::Words, ChunkStrategy::Lines][input.strategy as usize % 3];
+    let Ok(config) = ChunkingConfig::new(strategy, input.size as usize, input.overlap as usize) else {
+        return;
+    };
+    // Slicing at a non-boundary would already have panicked inside chunk_with_config
+    let chunks = chunk_with_config(input.text, &config, DocId(0));
+    assert!(chunks.iter().all(|chunk| input.text.contains(chunk.text.as_str())));
+
+    // Without overlap the chunks add up to the text, except that a text without a single word
+    // has no word chunks at all
+    let no_overlap = ChunkingConfig { overlap: 0, ..config };
+    let joined: String = chunk_with_config(input.text, &no_overlap, DocId(0)).iter().map(|c| c.text.as_str()).collect();
+    if !(strategy == ChunkStrategy::Words && input.text.trim().is_empty()) {
+        assert_eq!(joined, input.text);
+    }
+});
@@ -0,0 +1,29 @@
+// Loading an index file must fail cleanly on garbage. Random bytes almost never get past the
+// checksum, so the input is also wrapped in a valid header to reach the JSON body parser
+#![no_main]
+
+use std::sync::Arc;
+use libfuzzer_sys::fuzz_target;
+use rust::analyzer::Analyzer;
+use rust::persist::{read_corpus, FORMAT_VERSION};
+
+// Same FNV-1a as the index header's checksum
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fuzz_target!(|body: &str| {
+    let analyzer = Arc::new(Analyzer::default());
+    let _ = read_corpus(body, Arc::clone(&analyzer));
+
+    let header = format!("TFIDX {} {:016x} {:016x}\n", FORMAT_VERSION, analyzer.fingerprint(), fnv1a(body.as_bytes()));
+    if let Ok(corpus) = read_corpus(&(header + body), analyzer) {
+        // A body that deserializes can still be inconsistent, searching it must not panic either
+        let _ = corpus.search("rust OR python");
+    }
+});
@@ -0,0 +1,33 @@
+// Any string must either parse or produce an error whose caret rendering doesn't panic,
+// and a parsed query must evaluate without panicking under both rankers
+#![no_main]
+
+use std::sync::LazyLock;
+use libfuzzer_sys::fuzz_target;
+use rust::bm25::{Bm25Params, Bm25Scorer};
+use rust::chunker::{ChunkStrategy, ChunkingConfig};
+use rust::corpus::{Corpus, Document};
+use rust::query::parse_query;
+use rust::tfidf::{TfIdfParams, TfIdfScorer};
+
+static CORPUS: LazyLock<Corpus> = LazyLock::new(|| {
+    Corpus::new(
+        vec![
+            Document::new("a.txt", "rust borrow checker, rust is fast"),
+            Document::new("b.txt", "python garbage collector\nnaïve café"),
+        ],
+        ChunkingConfig::new(ChunkStrategy::Words, 4, 1).unwrap(),
+    )
+});
+
+fuzz_target!(|input: &str| {
+    match parse_query(input) {
+        Ok(query) => {
+            query.evaluate(&TfIdfScorer::new(&CORPUS, TfIdfParams::default()));
+            query.evaluate(&Bm25Scorer { corpus: &CORPUS, params: Bm25Params::default() });
+        }
+        Err(error) => {
+            error.render(input);
+        }
+    }
+});
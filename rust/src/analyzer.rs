@@ -0,0 +1,564 @@
+use std::collections::{HashMap, HashSet};
+use crate::persist::fnv1a;
+
+/// A term produced by the analyzer, with where it came from in the original text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    /// Position of the word in the text, filters keep it so dropped stopwords leave a gap
+    pub position: u32,
+    /// Byte range of the word in the original text
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Splits raw text into tokens
+// Send + Sync are required so an analyzer can be shared between query threads
+pub trait Tokenizer: Send + Sync {
+    fn tokenize(&self, text: &str) -> Vec<Token>;
+
+    /// Name and settings, part of the analyzer fingerprint saved with an index
+    fn describe(&self) -> String;
+}
+
+/// Transforms the token stream: change, drop or add tokens
+pub trait TokenFilter: Send + Sync {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token>;
+
+    /// Name and settings, part of the analyzer fingerprint saved with an index
+    fn describe(&self) -> String;
+}
+
+/// Rewrites the text character by character before it is tokenized
+// Unlike a token filter it can change what the tokenizer sees, e.g. make an emoji a word. Every
+// character maps to any number of characters, so the analyzer can map the offsets of the tokens
+// back to the original text, which is what highlighting and snippets cut from
+pub trait CharFilter: Send + Sync {
+    /// Append what c becomes to out, nothing to drop it
+    fn map(&self, c: char, out: &mut String);
+
+    /// Name and settings, part of the analyzer fingerprint saved with an index
+    fn describe(&self) -> String;
+}
+
+/// Splits on whitespace and removes punctuation from the end of every word, "fox." becomes "fox"
+#[derive(Debug, Default)]
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        for word in text.split_whitespace() {
+            let cleaned = word.trim_end_matches(|c: char| !c.is_alphanumeric());
+            if cleaned.is_empty() {
+                continue;
+            }
+            // split_whitespace returns slices into text, so pointer arithmetic gives the byte offset
+            let start = word.as_ptr() as usize - text.as_ptr() as usize;
+            tokens.push(Token {
+                text: cleaned.to_string(),
+                position: tokens.len() as u32,
+                start,
+                end: start + cleaned.len(),
+            });
+        }
+        tokens
+    }
+
+    fn describe(&self) -> String {
+        "whitespace".to_string()
+    }
+}
+
+/// Tokenizer for source code: identifiers are kept whole and also split into their
+/// camelCase / snake_case parts, and operators like `::` and `->` become tokens
+// "parseHttpRequest" yields "parseHttpRequest", "parse", "Http" and "Request", all at the
+// same position, so both the exact identifier and its words can be searched for
+#[derive(Debug, Default)]
+pub struct CodeTokenizer;
+
+impl CodeTokenizer {
+    /// Multi-character operators that are kept as tokens, longest first
+    const SYMBOLS: [&'static str; 10] = ["::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", ".."];
+
+    /// Split an identifier into its words: "HTTPServer_config2" -> ["HTTP", "Server", "config2"]
+    pub fn split_identifier(identifier: &str) -> Vec<&str> {
+        let mut parts = Vec::new();
+        for word in identifier.split('_').filter(|w| !w.is_empty()) {
+            let chars: Vec<(usize, char)> = word.char_indices().collect();
+            let mut start = 0;
+            for i in 1..chars.len() {
+                let (pos, c) = chars[i];
+                let previous = chars[i - 1].1;
+                let next_is_lower = chars.get(i + 1).is_some_and(|(_, n)| n.is_lowercase());
+                // A new word starts at "aB" (camelCase) or at the last capital of "ABc" (acronym then word)
+                let boundary = c.is_uppercase() && (previous.is_lowercase() || previous.is_ascii_digit()
+                    || (previous.is_uppercase() && next_is_lower));
+                if boundary {
+                    parts.push(&word[start..pos]);
+                    start = pos;
+                }
+            }
+            parts.push(&word[start..]);
+        }
+        parts
+    }
+}
+
+impl Tokenizer for CodeTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut position = 0;
+        let mut pos = 0;
+
+        while pos < text.len() {
+            let rest = &text[pos..];
+            let c = rest.chars().next().unwrap();
+
+            if c.is_alphanumeric() || c == '_' {
+                let length = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                let identifier = &rest[..length];
+                tokens.push(Token { text: identifier.to_string(), position, start: pos, end: pos + length });
+
+                let parts = CodeTokenizer::split_identifier(identifier);
+                if parts.len() > 1 {
+                    for part in parts {
+                        // part is a slice of identifier, so its offset is found with pointer arithmetic
+                        let start = pos + (part.as_ptr() as usize - identifier.as_ptr() as usize);
+                        tokens.push(Token { text: part.to_string(), position, start, end: start + part.len() });
+                    }
+                }
+                position += 1;
+                pos += length;
+            } else if let Some(symbol) = CodeTokenizer::SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+                tokens.push(Token { text: symbol.to_string(), position, start: pos, end: pos + symbol.len() });
+                position += 1;
+                pos += symbol.len();
+            } else {
+                pos += c.len_utf8();
+            }
+        }
+        tokens
+    }
+
+    fn describe(&self) -> String {
+        "code".to_string()
+    }
+}
+
+/// Folds every token to lowercase so "Rust" and "rust" are the same term
+#[derive(Debug, Default)]
+pub struct Lowercase;
+
+impl TokenFilter for Lowercase {
+    fn filter(&self, mut tokens: Vec<Token>) -> Vec<Token> {
+        for token in &mut tokens {
+            token.text = token.text.to_lowercase();
+        }
+        tokens
+    }
+
+    fn describe(&self) -> String {
+        "lowercase".to_string()
+    }
+}
+
+/// Drops tokens that appear in a stopword list
+#[derive(Debug)]
+pub struct Stopwords {
+    words: HashSet<String>,
+}
+
+impl Stopwords {
+    pub fn new<I: IntoIterator<Item = String>>(words: I) -> Stopwords {
+        Stopwords { words: words.into_iter().collect() }
+    }
+
+    /// A short list of the most common English function words
+    pub fn english() -> Stopwords {
+        let words = [
+            "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+            "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+            "they", "this", "to", "was", "will", "with",
+        ];
+        Stopwords::new(words.iter().map(|w| w.to_string()))
+    }
+
+    /// One word per line, blank lines and lines starting with # are skipped
+    pub fn parse(text: &str) -> Stopwords {
+        Stopwords::new(text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(str::to_string))
+    }
+}
+
+impl TokenFilter for Stopwords {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens.into_iter().filter(|t| !self.words.contains(&t.text)).collect()
+    }
+
+    fn describe(&self) -> String {
+        // Sort so the description doesn't depend on HashSet iteration order
+        let mut words: Vec<&String> = self.words.iter().collect();
+        words.sort();
+        format!("stopwords({:016x})", fnv1a(format!("{:?}", words).as_bytes()))
+    }
+}
+
+/// A deliberately small English suffix stripper, not a full Porter stemmer
+// It only handles plurals and -ing/-ed, which covers the most common mismatches
+// like "tokens"/"token" while staying easy to read
+#[derive(Debug, Default)]
+pub struct LightStemmer;
+
+impl LightStemmer {
+    pub fn stem(word: &str) -> String {
+        // (suffix, replacement, minimum length of what remains)
+        let rules = [("sses", "ss", 1), ("ies", "y", 2), ("ing", "", 3), ("ed", "", 3), ("s", "", 3)];
+        if word.ends_with("ss") || word.ends_with("us") {
+            return word.to_string();
+        }
+        for (suffix, replacement, min_stem) in rules {
+            if let Some(stem) = word.strip_suffix(suffix)
+                && stem.chars().count() >= min_stem
+            {
+                return format!("{}{}", stem, replacement);
+            }
+        }
+        word.to_string()
+    }
+}
+
+impl TokenFilter for LightStemmer {
+    fn filter(&self, mut tokens: Vec<Token>) -> Vec<Token> {
+        for token in &mut tokens {
+            token.text = LightStemmer::stem(&token.text);
+        }
+        tokens
+    }
+
+    fn describe(&self) -> String {
+        "light_stemmer".to_string()
+    }
+}
+
+/// Adds the synonyms of a token at the same position, so "car" also matches "automobile"
+#[derive(Debug)]
+pub struct Synonyms {
+    synonyms: HashMap<String, Vec<String>>,
+}
+
+impl Synonyms {
+    pub fn new(synonyms: HashMap<String, Vec<String>>) -> Synonyms {
+        Synonyms { synonyms }
+    }
+}
+
+impl TokenFilter for Synonyms {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        let mut expanded = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let extra = self.synonyms.get(&token.text).cloned().unwrap_or_default();
+            for synonym in extra {
+                expanded.push(Token { text: synonym, ..token.clone() });
+            }
+            expanded.push(token);
+        }
+        expanded
+    }
+
+    fn describe(&self) -> String {
+        let mut entries: Vec<(&String, &Vec<String>)> = self.synonyms.iter().collect();
+        entries.sort();
+        format!("synonyms({:016x})", fnv1a(format!("{:?}", entries).as_bytes()))
+    }
+}
+
+/// Drops tokens shorter than min or longer than max characters
+// min = 2 removes single letters, a max around 40 removes base64 blobs and hashes
+#[derive(Debug)]
+pub struct LengthFilter {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl LengthFilter {
+    pub fn new(min: usize, max: usize) -> LengthFilter {
+        LengthFilter { min, max }
+    }
+}
+
+impl TokenFilter for LengthFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .filter(|t| {
+                let length = t.text.chars().count();
+                length >= self.min && length <= self.max
+            })
+            .collect()
+    }
+
+    fn describe(&self) -> String {
+        format!("length({}..={})", self.min, self.max)
+    }
+}
+
+/// Drops tokens that are pure numbers, like "42", "3.14" or "1,000"
+// Numbers are mostly unique (line numbers, versions, ids), so they bloat the vocabulary
+// and each one gets a very high IDF that can dominate a query
+#[derive(Debug, Default)]
+pub struct DropNumbers;
+
+impl DropNumbers {
+    pub fn is_number(text: &str) -> bool {
+        text.chars().any(|c| c.is_ascii_digit())
+            && text.chars().all(|c| c.is_ascii_digit() || c == '.' || c == ',' || c == '-' || c == '+')
+    }
+}
+
+impl TokenFilter for DropNumbers {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens.into_iter().filter(|t| !DropNumbers::is_number(&t.text)).collect()
+    }
+
+    fn describe(&self) -> String {
+        "drop_numbers".to_string()
+    }
+}
+
+/// Folds full-width forms to ASCII, "Ｒｕｓｔ！" becomes "Rust!" and the ideographic space a space
+// CJK input methods type Latin letters and digits as full-width characters, which are different
+// terms from the ASCII ones a query is usually typed with
+#[derive(Debug, Default)]
+pub struct FoldWidth;
+
+impl CharFilter for FoldWidth {
+    fn map(&self, c: char, out: &mut String) {
+        out.push(match c {
+            // The full-width block is ASCII 0x21..=0x7E moved up by 0xFEE0
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+            '\u{3000}' => ' ',
+            c => c,
+        });
+    }
+
+    fn describe(&self) -> String {
+        "fold_width".to_string()
+    }
+}
+
+/// What the Emoji char filter does with emoji
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmojiMode {
+    /// Remove them, "great🔥" is "great"
+    Strip,
+    /// Make every emoji a word of its own named by its code point, "great🔥" is "great emoji_1f525"
+    Name,
+}
+
+/// Strips emoji or turns them into searchable words, see EmojiMode
+// Without it an emoji is punctuation to the tokenizers: dropped at the end of a word, glued to the
+// word after it at the start ("🔥deal" is one term). Named emoji are searchable with the emoji
+// itself, a query goes through the same analyzer. Joiners, variation selectors and skin tones
+// only change how an emoji looks and are always dropped
+#[derive(Debug)]
+pub struct Emoji {
+    pub mode: EmojiMode,
+}
+
+impl Emoji {
+    pub fn new(mode: EmojiMode) -> Emoji {
+        Emoji { mode }
+    }
+
+    /// The pictographic blocks of Unicode, not the full emoji property table
+    pub fn is_emoji(c: char) -> bool {
+        matches!(c, '\u{1f000}'..='\u{1faff}' | '\u{2600}'..='\u{27bf}' | '\u{2b50}' | '\u{2b55}' | '\u{2300}'..='\u{23ff}')
+    }
+
+    fn is_modifier(c: char) -> bool {
+        matches!(c, '\u{200d}' | '\u{fe0e}' | '\u{fe0f}' | '\u{1f3fb}'..='\u{1f3ff}' | '\u{20e3}')
+    }
+}
+
+impl CharFilter for Emoji {
+    fn map(&self, c: char, out: &mut String) {
+        if Emoji::is_modifier(c) || (Emoji::is_emoji(c) && self.mode == EmojiMode::Strip) {
+            return;
+        }
+        if Emoji::is_emoji(c) {
+            // Spaces on both sides, "a🔥b" is three words
+            out.push_str(&format!(" emoji_{:x} ", c as u32));
+        } else {
+            out.push(c);
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.mode {
+            EmojiMode::Strip => "emoji(strip)".to_string(),
+            EmojiMode::Name => "emoji(name)".to_string(),
+        }
+    }
+}
+
+/// A tokenizer followed by an ordered list of filters
+// A Corpus owns exactly one Analyzer and runs both chunk text (index time) and
+// queries (query time) through it, so the two can never drift apart
+pub struct Analyzer {
+    char_filters: Vec<Box<dyn CharFilter>>,
+    tokenizer: Box<dyn Tokenizer>,
+    filters: Vec<Box<dyn TokenFilter>>,
+}
+
+impl Default for Analyzer {
+    /// Whitespace tokenizer with lowercasing, the behaviour of the original term_frequency
+    fn default() -> Self {
+        Analyzer::new(WhitespaceTokenizer).with_filter(Lowercase)
+    }
+}
+
+impl Analyzer {
+    pub fn new<T: Tokenizer + 'static>(tokenizer: T) -> Analyzer {
+        Analyzer { char_filters: Vec::new(), tokenizer: Box::new(tokenizer), filters: Vec::new() }
+    }
+
+    /// Append a char filter, char filters run in order before the tokenizer
+    pub fn with_char_filter<F: CharFilter + 'static>(mut self, filter: F) -> Analyzer {
+        self.char_filters.push(Box::new(filter));
+        self
+    }
+
+    /// Append a filter to the end of the pipeline
+    pub fn with_filter<F: TokenFilter + 'static>(mut self, filter: F) -> Analyzer {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Run text through the tokenizer and every filter in order
+    pub fn tokens(&self, text: &str) -> Vec<Token> {
+        let tokens = if self.char_filters.is_empty() { self.tokenizer.tokenize(text) } else { self.tokenize_filtered(text) };
+        // fold threads the token list through every filter, like a chain of function calls
+        self.filters.iter().fold(tokens, |tokens, filter| filter.filter(tokens))
+    }
+
+    // Tokenize the char filtered text, with offsets pointing into the original text again
+    fn tokenize_filtered(&self, text: &str) -> Vec<Token> {
+        let mut filtered = String::with_capacity(text.len());
+        // For every byte of filtered, the byte range of the original character it came from
+        let mut sources: Vec<(usize, usize)> = Vec::with_capacity(text.len());
+        let (mut current, mut next) = (String::new(), String::new());
+        for (start, c) in text.char_indices() {
+            current.clear();
+            current.push(c);
+            // Every char filter maps what the one before it produced
+            for filter in &self.char_filters {
+                next.clear();
+                current.chars().for_each(|c| filter.map(c, &mut next));
+                std::mem::swap(&mut current, &mut next);
+            }
+            filtered.push_str(&current);
+            sources.resize(filtered.len(), (start, start + c.len_utf8()));
+        }
+        let mut tokens = self.tokenizer.tokenize(&filtered);
+        for token in &mut tokens {
+            let end = sources[token.end.max(token.start + 1) - 1].1;
+            token.start = sources[token.start].0;
+            token.end = end;
+        }
+        tokens
+    }
+
+    /// Just the term text of every token
+    pub fn analyze(&self, text: &str) -> Vec<String> {
+        self.tokens(text).into_iter().map(|t| t.text).collect()
+    }
+
+    /// The whole pipeline in order, e.g. "whitespace | lowercase | light_stemmer"
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = self.char_filters.iter().map(|f| f.describe()).collect();
+        parts.push(self.tokenizer.describe());
+        parts.extend(self.filters.iter().map(|f| f.describe()));
+        parts.join(" | ")
+    }
+
+    /// Hash of the pipeline description that is stable across runs and Rust versions
+    // std's DefaultHasher is explicitly allowed to change between releases, so we can't store its output
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a(self.describe().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze_and_fingerprint() {
+        let analyzer = Analyzer::default();
+        assert_eq!(analyzer.analyze("The quick, brown Fox."), vec!["the", "quick", "brown", "fox"]);
+
+        let case_sensitive = Analyzer::new(WhitespaceTokenizer);
+        assert_eq!(case_sensitive.analyze("Fox!"), vec!["Fox"]);
+        assert_ne!(analyzer.fingerprint(), case_sensitive.fingerprint());
+        assert_eq!(analyzer.fingerprint(), Analyzer::default().fingerprint());
+    }
+
+    #[test]
+    fn test_composed_pipeline() {
+        let mut synonyms = HashMap::new();
+        synonyms.insert("car".to_string(), vec!["automobile".to_string()]);
+        let analyzer = Analyzer::default()
+            .with_filter(Stopwords::english())
+            .with_filter(LightStemmer)
+            .with_filter(Synonyms::new(synonyms))
+            .with_filter(LengthFilter::new(2, 20));
+
+        let tokens = analyzer.tokens("The cars are parked in a row");
+        let terms: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(terms, vec!["automobile", "car", "park", "row"]);
+        // Positions and offsets still point at the original words
+        assert_eq!(tokens[1].position, 1);
+        assert_eq!(&"The cars are parked in a row"[tokens[1].start..tokens[1].end], "cars");
+        assert_eq!(analyzer.describe().split(" | ").count(), 6);
+    }
+
+    #[test]
+    fn test_length_and_number_filters() {
+        let analyzer = Analyzer::default()
+            .with_filter(LengthFilter::new(2, 12))
+            .with_filter(DropNumbers);
+
+        let terms = analyzer.analyze("a 42 3.14 v2 x aGVsbG8gd29ybGQgaGVsbG8= python 1,000");
+        assert_eq!(terms, vec!["v2", "python"]);
+    }
+
+    #[test]
+    fn test_char_filters_keep_offsets() {
+        let analyzer = Analyzer::default().with_char_filter(FoldWidth).with_char_filter(Emoji::new(EmojiMode::Name));
+        let text = "Ｒｕｓｔ！ great🔥 ❤️";
+        let tokens = analyzer.tokens(text);
+        let terms: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(terms, vec!["rust", "great", "emoji_1f525", "emoji_2764"]);
+        // Offsets point at the original characters, full-width letters are 3 bytes each
+        assert_eq!(&text[tokens[0].start..tokens[0].end], "Ｒｕｓｔ");
+        assert_eq!(&text[tokens[2].start..tokens[2].end], "🔥");
+        assert_eq!(analyzer.analyze("🔥"), vec!["emoji_1f525"]);
+        assert_eq!(analyzer.describe(), "fold_width | emoji(name) | whitespace | lowercase");
+
+        let strip = Analyzer::default().with_char_filter(Emoji::new(EmojiMode::Strip));
+        assert_eq!(strip.analyze("🔥deal 👍🏽"), vec!["deal"]);
+    }
+
+    #[test]
+    fn test_code_tokenizer() {
+        assert_eq!(CodeTokenizer::split_identifier("parseHTTPRequest"), vec!["parse", "HTTP", "Request"]);
+        assert_eq!(CodeTokenizer::split_identifier("load_directory_recursive"), vec!["load", "directory", "recursive"]);
+
+        let analyzer = Analyzer::new(CodeTokenizer).with_filter(Lowercase);
+        let terms = analyzer.analyze("fn chunk_text() -> Vec<Chunk> { std::fs::read_dir }");
+        assert_eq!(
+            terms,
+            vec!["fn", "chunk_text", "chunk", "text", "->", "vec", "chunk", "std", "::", "fs", "::", "read_dir", "read", "dir"]
+        );
+    }
+}
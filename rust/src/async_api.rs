@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::task;
+use crate::analyzer::Analyzer;
+use crate::corpus::{Corpus, CorpusBuilder};
+use crate::index::{Index, Snapshot};
+use crate::persist::{load_corpus, save_corpus};
+use crate::query::QueryError;
+use crate::search::SearchResult;
+
+// Loading, indexing and scoring are CPU and disk bound and would stall an async runtime if they
+// ran on its worker threads. Every function here moves the work to tokio's blocking pool, so an
+// async web service can simply .await them
+
+/// Errors have to be Send to cross from the blocking pool back to the awaiting task
+pub type AsyncError = Box<dyn Error + Send + Sync>;
+
+// Run a closure on the blocking pool, turning a panic in it into an error
+async fn blocking<T, F>(work: F) -> Result<T, AsyncError>
+where
+    F: FnOnce() -> Result<T, AsyncError> + Send + 'static,
+    T: Send + 'static,
+{
+    task::spawn_blocking(work).await?
+}
+
+/// Load and index every source added to the builder
+pub async fn build(builder: CorpusBuilder) -> Result<Corpus, AsyncError> {
+    // Box<dyn Error> from build() isn't Send, so only its message crosses threads
+    blocking(move || builder.build().map_err(|e| e.to_string().into())).await
+}
+
+/// Read an index file written by save_corpus
+pub async fn load(path: impl Into<PathBuf>, analyzer: Arc<Analyzer>) -> Result<Corpus, AsyncError> {
+    let path = path.into();
+    blocking(move || Ok(load_corpus(&path, analyzer)?)).await
+}
+
+/// Write an index file, the corpus is shared rather than copied
+pub async fn save(corpus: Arc<Corpus>, path: impl Into<PathBuf>) -> Result<(), AsyncError> {
+    let path = path.into();
+    blocking(move || Ok(save_corpus(&corpus, &path)?)).await
+}
+
+/// An Index whose queries and updates can be awaited
+// Cloning is cheap and every clone refers to the same index, hand one to every request handler
+#[derive(Clone)]
+pub struct AsyncIndex {
+    index: Index,
+}
+
+impl AsyncIndex {
+    pub fn new(corpus: Corpus) -> AsyncIndex {
+        AsyncIndex { index: Index::new(corpus) }
+    }
+
+    /// Await an index shared with synchronous code, e.g. one of an IndexRegistry
+    pub fn from_index(index: Index) -> AsyncIndex {
+        AsyncIndex { index }
+    }
+
+    /// The underlying index, for the synchronous API
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Parse and rank a query against the current snapshot
+    pub async fn search(&self, query: &str) -> Result<Result<Vec<SearchResult>, QueryError>, AsyncError> {
+        let snapshot = self.index.snapshot();
+        let query = query.to_string();
+        blocking(move || Ok(snapshot.search(&query))).await
+    }
+
+    /// Run any read-only work, e.g. scoring with a Bm25Scorer, against the current snapshot
+    pub async fn read<T, F>(&self, work: F) -> Result<T, AsyncError>
+    where
+        F: FnOnce(&Snapshot) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let snapshot = self.index.snapshot();
+        blocking(move || Ok(work(&snapshot))).await
+    }
+
+    /// Rebuild from the builder's sources and swap the new corpus in, queries keep running meanwhile
+    pub async fn rebuild(&self, builder: CorpusBuilder) -> Result<u64, AsyncError> {
+        let corpus = build(builder).await?;
+        let index = self.index.clone();
+        blocking(move || Ok(index.replace(corpus))).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::Document;
+
+    #[tokio::test]
+    async fn test_async_build_search_and_rebuild() {
+        let builder = Corpus::builder()
+            .add_document(Document::new("a.txt", "rust borrow checker"))
+            .add_document(Document::new("b.txt", "python garbage collector"));
+        let index = AsyncIndex::new(build(builder).await.unwrap());
+
+        let results = index.search("borrow").await.unwrap().unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(index.search("(borrow").await.unwrap().is_err());
+
+        let generation = index
+            .rebuild(
+                Corpus::builder()
+                    .add_document(Document::new("c.txt", "borrow borrow"))
+                    .add_document(Document::new("d.txt", "python")),
+            )
+            .await
+            .unwrap();
+        assert_eq!(generation, 1);
+        let results = index.search("borrow").await.unwrap().unwrap();
+        assert_eq!(index.index().snapshot().path(results[0].doc), Some("c.txt"));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::analyzer::Token;
+use crate::corpus::{ChunkId, Corpus};
+use crate::inverted_index::InvertedIndex;
+use crate::kernel;
+use crate::query::TermScorer;
+use crate::search::Score;
+
+/// BM25's two tuning knobs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bm25Params {
+    /// How quickly repeated occurrences of a term stop adding to the score, 0 ignores tf entirely
+    pub k1: Score,
+    /// How strongly long chunks are penalized, 0 = not at all, 1 = fully normalized by length
+    pub b: Score,
+}
+
+impl Default for Bm25Params {
+    // The values Lucene and most papers use as a starting point
+    fn default() -> Bm25Params {
+        Bm25Params { k1: 1.2, b: 0.75 }
+    }
+}
+
+/// BM25 inverse document frequency, never negative
+// The textbook ln((N - df + 0.5) / (df + 0.5)) goes negative for terms in more than half the
+// chunks, the + 1 inside the log (as Lucene does it) keeps common terms at a small positive weight
+pub fn idf_bm25(num_chunks: usize, doc_freq: usize) -> Score {
+    let (n, df) = (num_chunks as Score, doc_freq as Score);
+    (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+}
+
+/// BM25 contribution of one analyzed term to every chunk that contains it
+pub fn term_scores_bm25(term: &str, index: &InvertedIndex, params: &Bm25Params) -> Vec<(ChunkId, Score)> {
+    let postings = index.postings(term);
+    if postings.is_empty() {
+        return Vec::new();
+    }
+    let idf = idf_bm25(index.num_chunks(), postings.len());
+    // Gather the inputs into flat arrays, then score the whole list in one vectorized pass
+    let tfs: Vec<Score> = postings.iter().map(|posting| posting.tf as Score).collect();
+    let lengths: Vec<Score> = postings.iter().map(|posting| index.chunk_len(posting.chunk) as Score).collect();
+    let scores = kernel::bm25_scores(&tfs, &lengths, idf, params, index.avg_len());
+    postings.iter().map(|posting| posting.chunk).zip(scores).collect()
+}
+
+/// Query evaluation with BM25 term scores
+pub struct Bm25Scorer<'a> {
+    pub corpus: &'a Corpus,
+    pub params: Bm25Params,
+}
+
+impl TermScorer for Bm25Scorer<'_> {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        self.corpus.analyzer().tokens(text)
+    }
+
+    fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)> {
+        term_scores_bm25(term, self.corpus.index(), &self.params)
+    }
+
+    fn positions(&self, term: &str, chunk: ChunkId) -> Vec<u32> {
+        self.corpus.index().positions(term, chunk).to_vec()
+    }
+
+    fn bm25_params(&self) -> Option<Bm25Params> {
+        Some(self.params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_bm25_saturation_and_length() {
+        let files = vec![
+            Document::new("once.txt", "rust"),
+            Document::new("often.txt", "rust rust rust rust"),
+            Document::new("long.txt", "rust and a lot of other words about nothing"),
+            Document::new("other.txt", "python"),
+        ];
+        let corpus = Corpus::new(files, ChunkingConfig::default());
+        let score = |path: &str, params: Bm25Params| -> Score {
+            let chunk = corpus.chunks().iter().find(|c| corpus.path(c.doc) == Some(path)).unwrap().id;
+            let scores = term_scores_bm25("rust", corpus.index(), &params);
+            scores.iter().find(|(id, _)| *id == chunk).unwrap().1
+        };
+
+        let default = Bm25Params::default();
+        // More occurrences score higher, but far less than 4x
+        assert!(score("often.txt", default) > score("once.txt", default));
+        assert!(score("often.txt", default) < 2.0 * score("once.txt", default));
+        // Length only matters when b > 0
+        assert!(score("long.txt", default) < score("once.txt", default));
+        let no_length = Bm25Params { k1: 1.2, b: 0.0 };
+        assert_eq!(score("long.txt", no_length), score("once.txt", no_length));
+        assert!(idf_bm25(4, 4) > 0.0);
+    }
+}
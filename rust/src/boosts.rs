@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use crate::corpus::{Corpus, DocId};
+
+// Lexical scores say how well a chunk matches the query, not how much the document can be
+// trusted. Authority signals (PageRank over the links between pages, view counts, an editor's
+// rating) are computed elsewhere and handed over as a sidecar file next to the corpus, mapping a
+// document path to a weight. The weights become document boosts, which multiply the score of
+// every chunk of the document, the same as --boost
+
+/// Static weights of documents by path, read from a sidecar file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Boosts(pub HashMap<String, f32>);
+
+impl Boosts {
+    /// One path,weight pair per line. A header line, blank lines and # comments are skipped, and
+    /// the path may be in double quotes. The weight is after the last comma, paths may contain commas
+    pub fn parse_csv(text: &str) -> Result<Boosts, String> {
+        let mut boosts = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (path, weight) = line.rsplit_once(',').ok_or_else(|| format!("line {}: expected path,weight", number + 1))?;
+            let Ok(weight) = weight.trim().parse::<f32>() else {
+                // path,weight or url,pagerank, whatever the header calls them
+                if number == 0 {
+                    continue;
+                }
+                return Err(format!("line {}: {} is not a number", number + 1, weight.trim()));
+            };
+            let path = path.trim();
+            let path = path.strip_prefix('"').and_then(|path| path.strip_suffix('"')).unwrap_or(path);
+            boosts.insert(path.to_string(), check(weight).map_err(|e| format!("line {}: {}", number + 1, e))?);
+        }
+        Ok(Boosts(boosts))
+    }
+
+    /// A JSON object from path to weight, {"docs/intro.txt": 2.5, ...}
+    pub fn parse_json(text: &str) -> Result<Boosts, String> {
+        let boosts: HashMap<String, f32> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        for (path, weight) in &boosts {
+            check(*weight).map_err(|e| format!("{}: {}", path, e))?;
+        }
+        Ok(Boosts(boosts))
+    }
+
+    /// A .json file is read as JSON, anything else as CSV
+    pub fn load(path: &Path) -> Result<Boosts, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let boosts = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Boosts::parse_json(&text),
+            _ => Boosts::parse_csv(&text),
+        };
+        Ok(boosts.map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    /// Set the boost of every document with a weight, returns the paths that aren't in the corpus
+    pub fn apply(&self, corpus: &mut Corpus) -> Vec<&str> {
+        let mut missing = Vec::new();
+        // Collect first, set_boost needs the corpus mutably
+        let found: Vec<(DocId, f32)> = self
+            .0
+            .iter()
+            .filter_map(|(path, weight)| match corpus.doc_id(path) {
+                Some(id) => Some((id, *weight)),
+                None => {
+                    missing.push(path.as_str());
+                    None
+                }
+            })
+            .collect();
+        for (id, weight) in found {
+            corpus.set_boost(id, weight);
+        }
+        missing.sort_unstable();
+        missing
+    }
+}
+
+/// Multiply the boost of every document whose path starts with prefix by factor, as --boost does,
+/// returns how many there were. Applied after a sidecar the two weights multiply
+pub fn boost_prefix(corpus: &mut Corpus, prefix: &str, factor: f32) -> usize {
+    // Collect first, set_boost needs the corpus mutably
+    let found: Vec<(DocId, f32)> =
+        corpus.documents().iter().filter(|doc| doc.path.starts_with(prefix)).map(|doc| (doc.id, doc.boost)).collect();
+    for (id, boost) in &found {
+        corpus.set_boost(*id, boost * factor);
+    }
+    found.len()
+}
+
+// A boost multiplies scores, a negative one would turn the ranking upside down
+fn check(weight: f32) -> Result<f32, String> {
+    if weight.is_finite() && weight >= 0.0 { Ok(weight) } else { Err(format!("the weight {} must be 0 or more", weight)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_sidecar_boosts_reorder_results() {
+        let csv = "path,pagerank\n# computed nightly\n\"notes, old.txt\",0.5\nb.txt, 3\n";
+        let boosts = Boosts::parse_csv(csv).unwrap();
+        assert_eq!(boosts.0.get("notes, old.txt"), Some(&0.5));
+        assert_eq!(Boosts::parse_json(r#"{"b.txt": 3, "notes, old.txt": 0.5}"#).unwrap(), boosts);
+        assert!(Boosts::parse_csv("a.txt,-1").is_err());
+        assert!(Boosts::parse_json(r#"{"a.txt": "high"}"#).is_err());
+
+        let mut corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "rust rust borrow checker"))
+            .add_document(Document::new("b.txt", "rust garbage collector"))
+            .add_document(Document::new("c.txt", "python interpreter"))
+            .build()
+            .unwrap();
+        assert_eq!(corpus.path(corpus.search("rust").unwrap()[0].doc), Some("a.txt"));
+        assert_eq!(boosts.apply(&mut corpus), ["notes, old.txt"]);
+        assert_eq!(corpus.path(corpus.search("rust").unwrap()[0].doc), Some("b.txt"));
+
+        // --boost on top of a sidecar weight multiplies it rather than replacing it
+        assert_eq!(boost_prefix(&mut corpus, "b", 0.5), 1);
+        let b = corpus.doc_id("b.txt").unwrap();
+        assert_eq!(corpus.doc_boost(b), 1.5);
+        assert_eq!(boost_prefix(&mut corpus, "missing/", 2.0), 0);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// Under load the same queries arrive again and again: the front page search, a dashboard that
+// refreshes, a retry. A response can be reused for as long as the index it was computed from is
+// served, and the index generation says exactly that: every Index::replace or update increments
+// it. Keying entries on the generation makes invalidation automatic, the first lookup after a
+// reindex finds the cache stale and empties it, without the reindex having to know about the cache
+
+/// Responses by request key, valid for one index generation, least recently used evicted first
+pub struct ResultCache<V> {
+    capacity: usize,
+    state: Mutex<CacheState<V>>,
+}
+
+struct CacheState<V> {
+    /// The generation every entry was computed from
+    generation: u64,
+    /// Key -> (value, the tick of its last use)
+    entries: HashMap<String, (V, u64)>,
+    tick: u64,
+}
+
+impl<V: Clone> ResultCache<V> {
+    /// A cache of at most capacity entries, 0 caches nothing
+    pub fn new(capacity: usize) -> ResultCache<V> {
+        ResultCache { capacity, state: Mutex::new(CacheState { generation: 0, entries: HashMap::new(), tick: 0 }) }
+    }
+
+    /// The cached value for key if it was computed from this generation
+    pub fn get(&self, generation: u64, key: &str) -> Option<V> {
+        let mut state = self.state.lock().unwrap();
+        if generation != state.generation {
+            // A request still holding an older snapshot doesn't empty the cache of the newer one
+            if generation > state.generation {
+                state.entries.clear();
+                state.generation = generation;
+            }
+            return None;
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        let (value, last_used) = state.entries.get_mut(key)?;
+        *last_used = tick;
+        Some(value.clone())
+    }
+
+    /// Cache a value computed from a generation, values of older generations are dropped
+    pub fn insert(&self, generation: u64, key: String, value: V) {
+        let mut state = self.state.lock().unwrap();
+        if self.capacity == 0 || generation < state.generation {
+            return;
+        }
+        if generation > state.generation {
+            state.entries.clear();
+            state.generation = generation;
+        }
+        // Eviction scans every entry, cheap next to the search a hit saves for caches of a few thousand
+        if state.entries.len() >= self.capacity
+            && !state.entries.contains_key(&key)
+            && let Some(oldest) = state.entries.iter().min_by_key(|(_, (_, last_used))| *last_used).map(|(key, _)| key.clone())
+        {
+            state.entries.remove(&oldest);
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.entries.insert(key, (value, tick));
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_by_generation_and_recency() {
+        let cache = ResultCache::new(2);
+        cache.insert(1, "rust".to_string(), 10);
+        cache.insert(1, "python".to_string(), 20);
+        assert_eq!(cache.get(1, "rust"), Some(10));
+        // python was used longest ago
+        cache.insert(1, "go".to_string(), 30);
+        assert_eq!((cache.get(1, "python"), cache.get(1, "rust"), cache.len()), (None, Some(10), 2));
+
+        // A late insert from an older snapshot is ignored, a lookup from a newer one empties the cache
+        cache.insert(0, "old".to_string(), 0);
+        assert_eq!(cache.get(0, "old"), None);
+        assert_eq!(cache.get(2, "rust"), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(1, "go"), None);
+
+        let disabled = ResultCache::new(0);
+        disabled.insert(1, "rust".to_string(), 10);
+        assert_eq!(disabled.get(1, "rust"), None);
+    }
+}
@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// A UI or a server that started a long build or a batch of queries needs a way to stop it short
+// of killing the thread. A token is a shared flag: the caller keeps a clone and cancels it, the
+// work checks it between files, chunks or queries and returns Cancelled. Nothing is swapped in or
+// returned half done, a cancelled CorpusBuilder::build drops what it had built and an Index keeps
+// serving the corpus it had.
+// Checked by CorpusBuilder::cancel_on, Corpus::search_batch and IndexRegistry::reload. The CLI
+// cancels a build on Ctrl-C and the gRPC Reload call when its client goes away. The rest runs to
+// the end: single queries, the MCP tools, which are single queries too, and whole-corpus passes
+// like tfidf::document_term_matrix
+
+/// A flag that asks a running operation to stop, clones share it
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Ask every operation holding a clone of this token to stop, it can't be undone
+    pub fn cancel(&self) {
+        // Relaxed is enough, the flag guards no other data and is only ever set
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Err(Cancelled) once the token has been cancelled, for use with ?
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() { Err(Cancelled) } else { Ok(()) }
+    }
+}
+
+impl CancellationToken {
+    /// A guard that cancels the token when dropped, unless it is disarmed first
+    // For work running on another thread on behalf of a future: dropping the future drops the guard
+    pub fn drop_guard(self) -> DropGuard {
+        DropGuard(Some(self))
+    }
+}
+
+/// Cancels its token when dropped, see CancellationToken::drop_guard
+#[derive(Debug)]
+pub struct DropGuard(Option<CancellationToken>);
+
+impl DropGuard {
+    /// Let the guard go without cancelling, the work finished
+    pub fn disarm(mut self) -> CancellationToken {
+        self.0.take().expect("the token is only taken here")
+    }
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        if let Some(token) = &self.0 {
+            token.cancel();
+        }
+    }
+}
+
+/// The error of an operation that was stopped through its CancellationToken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the operation was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bm25::{Bm25Params, Bm25Scorer};
+    use crate::corpus::{Corpus, Document};
+    use crate::progress::Stage;
+    use crate::search::SearchOptions;
+
+    #[test]
+    fn test_cancel_build_and_batch() {
+        let builder = || {
+            Corpus::builder()
+                .add_document(Document::new("a.txt", "rust borrow checker"))
+                .add_document(Document::new("b.txt", "python garbage collector"))
+        };
+        // Cancelled from the progress callback, once loading is over and indexing has started
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let result = builder()
+            .cancel_on(token.clone())
+            .on_progress(move |progress| {
+                if progress.stage == Stage::Indexing {
+                    canceller.cancel();
+                }
+            })
+            .build();
+        assert!(result.err().is_some_and(|error| error.downcast_ref::<Cancelled>().is_some()));
+
+        let corpus = builder().build().unwrap();
+        let scorer = Bm25Scorer { corpus: &corpus, params: Bm25Params::default() };
+        let options = SearchOptions::default();
+        let batch = corpus.search_batch(&["rust", "python"], &scorer, &options, &CancellationToken::new()).unwrap();
+        assert_eq!(batch.iter().map(|timed| timed.results.len()).collect::<Vec<_>>(), [1, 1]);
+        let error = corpus.search_batch(&["rust", "python"], &scorer, &options, &token).unwrap_err();
+        assert_eq!(error.to_string(), "the operation was cancelled");
+
+        let token = CancellationToken::new();
+        assert!(!token.clone().drop_guard().disarm().is_cancelled());
+        drop(token.clone().drop_guard());
+        assert!(token.is_cancelled());
+    }
+}
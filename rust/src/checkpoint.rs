@@ -0,0 +1,252 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::chunker::ChunkingConfig;
+use crate::corpus::{Corpus, Document};
+use crate::limits::ResourceLimits;
+use crate::loader::{list_directory_files, read_file};
+use crate::persist::{load_corpus, save_corpus};
+
+// Indexing a huge directory in one go means one crash loses all the work. Instead the files are
+// indexed in segments of a fixed number of documents, each saved as an index file as soon as it is
+// done, and a manifest records which segments exist. Files are listed sorted by path, so a rerun
+// cuts the same segments and can skip every one the manifest already has. With a postings limit
+// a segment is also cut once it holds that many postings, so how many documents a segment has
+// depends on the documents, and a rerun takes the counts from the manifest. A segment is dropped as
+// soon as it is saved, the corpus is put together at the end by reading them back one at a time,
+// so indexing holds one segment and the merge the merged corpus and one segment
+
+const MANIFEST: &str = "manifest.json";
+
+/// Everything that decides what a segment contains, a checkpoint is only resumed if all of it matches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Settings {
+    source: String,
+    extensions: Vec<String>,
+    chunking: ChunkingConfig,
+    analyzer: u64,
+    segment_size: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SegmentEntry {
+    file: String,
+    documents: usize,
+    /// Path of the segment's last document, to notice files added or removed since the checkpoint
+    last_path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    settings: Settings,
+    segments: Vec<SegmentEntry>,
+}
+
+/// Result of build_resumable
+pub struct ResumableBuild {
+    pub corpus: Corpus,
+    pub segments: usize,
+    /// Segments loaded from the checkpoint instead of being indexed again
+    pub reused: usize,
+}
+
+/// Index a directory segment by segment, saving every segment and a manifest in checkpoint_dir
+/// Running it again after an interruption picks up after the last saved segment
+/// template supplies the chunking, analyzer and index options, e.g. an empty Corpus::builder().build()
+pub fn build_resumable(
+    source: &str,
+    extensions: &[&str],
+    template: &Corpus,
+    checkpoint_dir: &Path,
+    segment_size: usize,
+) -> Result<ResumableBuild, Box<dyn Error>> {
+    build_resumable_limited(source, extensions, template, checkpoint_dir, segment_size, &ResourceLimits::default())
+}
+
+/// Like build_resumable, also cutting a segment as soon as it holds limits.max_postings postings
+pub fn build_resumable_limited(
+    source: &str,
+    extensions: &[&str],
+    template: &Corpus,
+    checkpoint_dir: &Path,
+    segment_size: usize,
+    limits: &ResourceLimits,
+) -> Result<ResumableBuild, Box<dyn Error>> {
+    if segment_size == 0 {
+        return Err("the segment size must be at least 1".into());
+    }
+    fs::create_dir_all(checkpoint_dir)?;
+    let settings = Settings {
+        source: source.to_string(),
+        extensions: extensions.iter().map(|e| e.to_string()).collect(),
+        chunking: *template.chunking(),
+        analyzer: template.analyzer().fingerprint(),
+        segment_size,
+    };
+    let manifest_path = checkpoint_dir.join(MANIFEST);
+    let mut manifest = if manifest_path.exists() {
+        let manifest: Manifest = serde_json::from_str(&fs::read_to_string(&manifest_path)?)?;
+        if manifest.settings != settings {
+            return Err(format!(
+                "{} was made with different settings, delete it to start over",
+                checkpoint_dir.display()
+            )
+            .into());
+        }
+        manifest
+    } else {
+        Manifest { settings, segments: Vec::new() }
+    };
+
+    let files = list_directory_files(source, extensions)?;
+    let analyzer = template.shared_analyzer();
+    let mut segments = 0;
+    let mut reused = 0;
+    // First file of the next segment
+    let mut start = 0;
+    while start < files.len() {
+        let number = segments;
+        if let Some(entry) = manifest.segments.get(number) {
+            if entry.documents == 0 {
+                return Err(format!(
+                    "segment {} in {} has no documents, delete it to start over",
+                    entry.file,
+                    checkpoint_dir.display()
+                )
+                .into());
+            }
+            let end = start + entry.documents;
+            if end > files.len() || files[end - 1].0 != entry.last_path {
+                return Err(format!(
+                    "the files in {} changed since the checkpoint in {}, delete it to start over",
+                    source,
+                    checkpoint_dir.display()
+                )
+                .into());
+            }
+            segments += 1;
+            reused += 1;
+            start = end;
+            continue;
+        }
+
+        let batch = &files[start..files.len().min(start + segment_size)];
+        let segment = match limits.max_postings {
+            None => template.with_documents(read_batch(source, batch)?),
+            Some(max_postings) => {
+                // Every document is indexed on its own, and the parts merged once the segment is full,
+                // merging appends postings without analyzing anything again
+                let mut parts = Vec::new();
+                let mut postings = 0;
+                for file in batch {
+                    let part = template.with_documents(read_batch(source, std::slice::from_ref(file))?);
+                    postings += part.chunks().iter().map(|chunk| part.index().chunk_terms(chunk.id).unique as usize).sum::<usize>();
+                    parts.push(part);
+                    if postings >= max_postings {
+                        break;
+                    }
+                }
+                Corpus::merge(&parts.iter().collect::<Vec<&Corpus>>())?
+            }
+        };
+        let documents = segment.documents().len();
+        let last_path = files[start + documents - 1].0.clone();
+        start += documents;
+        let name = format!("segment-{:05}.idx", number);
+        save_corpus(&segment, &checkpoint_dir.join(&name))?;
+        manifest.segments.push(SegmentEntry { file: name, documents, last_path });
+        // Write then rename, so a crash while writing leaves the previous manifest intact
+        let temporary = checkpoint_dir.join(format!("{}.tmp", MANIFEST));
+        fs::write(&temporary, serde_json::to_string_pretty(&manifest)?)?;
+        fs::rename(&temporary, &manifest_path)?;
+        segments += 1;
+    }
+
+    let mut corpus = None;
+    for (number, entry) in manifest.segments[..segments].iter().enumerate() {
+        let segment = load_corpus(&checkpoint_dir.join(&entry.file), Arc::clone(&analyzer))?;
+        match &mut corpus {
+            None => corpus = Some(segment),
+            Some(corpus) => corpus.append(number, &segment)?,
+        }
+    }
+    let corpus = corpus.unwrap_or_else(|| template.with_documents(Vec::new()));
+    Ok(ResumableBuild { corpus, segments, reused })
+}
+
+// Read the files of a segment one at a time
+fn read_batch(source: &str, batch: &[(String, PathBuf)]) -> Result<Vec<Document>, Box<dyn Error>> {
+    let mut documents = Vec::with_capacity(batch.len());
+    for (path, file) in batch {
+        let file = read_file(path.clone(), file.clone())?;
+        let document = Document::new(&file.path, &file.text);
+        documents.push(Document {
+            root: source.to_string(),
+            modified: file.modified,
+            size: Some(file.size),
+            file: Some(file.file),
+            ..document
+        });
+    }
+    Ok(documents)
+}
+
+/// The segment files of a checkpoint directory, in order, as listed in its manifest
+pub fn checkpoint_segments(checkpoint_dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let manifest: Manifest = serde_json::from_str(&fs::read_to_string(checkpoint_dir.join(MANIFEST))?)?;
+    Ok(manifest.segments.iter().map(|entry| checkpoint_dir.join(&entry.file)).collect())
+}
+
+/// Whether the directory holds a checkpoint written by build_resumable
+pub fn is_checkpoint(dir: &Path) -> bool {
+    dir.join(MANIFEST).is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Score;
+
+    #[test]
+    fn test_resume_reuses_saved_segments() {
+        let base = std::env::temp_dir().join(format!("checkpoint_{}", std::process::id()));
+        let source = base.join("docs");
+        let checkpoint = base.join("checkpoint");
+        fs::create_dir_all(&source).unwrap();
+        for (name, text) in [("a.txt", "rust borrow"), ("b.txt", "python garbage"), ("c.txt", "rust python"), ("d.txt", "go")] {
+            fs::write(source.join(name), text).unwrap();
+        }
+        let source = source.to_string_lossy().to_string();
+        let template = Corpus::new(Vec::new(), ChunkingConfig::default());
+
+        let first = build_resumable(&source, &["txt"], &template, &checkpoint, 3).unwrap();
+        assert_eq!((first.segments, first.reused), (2, 0));
+        // Simulate a run interrupted after the first segment
+        let manifest_path = checkpoint.join(MANIFEST);
+        let mut manifest: Manifest = serde_json::from_str(&fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        manifest.segments.truncate(1);
+        fs::write(&manifest_path, serde_json::to_string(&manifest).unwrap()).unwrap();
+
+        let resumed = build_resumable(&source, &["txt"], &template, &checkpoint, 3).unwrap();
+        assert_eq!((resumed.segments, resumed.reused), (2, 1));
+        let scores = |corpus: &Corpus| -> Vec<(String, Score)> {
+            corpus.search("rust").unwrap().iter().map(|r| (corpus.path(r.doc).unwrap().to_string(), r.score)).collect()
+        };
+        assert_eq!(scores(&resumed.corpus), scores(&first.corpus));
+        assert!(build_resumable(&source, &["txt"], &template, &checkpoint, 2).is_err());
+
+        // Every file has two distinct terms or fewer, a limit of two postings gives each its own segment
+        let limits = ResourceLimits::new(1, Some(2)).unwrap();
+        let limited = build_resumable_limited(&source, &["txt"], &template, &base.join("limited"), 3, &limits).unwrap();
+        assert_eq!(limited.segments, 4);
+        assert_eq!(scores(&limited.corpus), scores(&first.corpus));
+
+        // A damaged manifest with an empty segment is an error, not a panic
+        manifest.segments[0].documents = 0;
+        fs::write(&manifest_path, serde_json::to_string(&manifest).unwrap()).unwrap();
+        assert!(build_resumable(&source, &["txt"], &template, &checkpoint, 3).is_err());
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -1,16 +1,221 @@
+use std::fmt;
+use std::ops::{Deref, Range};
+use std::str::FromStr;
+use std::sync::Arc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::corpus::{ChunkId, DocId, Document};
+
 // We derive from the Debug trait and the Clone trait
 // Debug allows us to print the struct with {:?} for debugging
 // Clone allows us to create copies of Chunk instances when needed
-#[derive(Debug, Clone)]
+// Serialize and Deserialize (from serde) let chunks be saved as part of an index file
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Chunk {
-    pub text: String,
-    pub file: String,
+    /// Corpus-wide id, assigned when the corpus is built
+    pub id: ChunkId,
+    /// The document this chunk was cut from
+    pub doc: DocId,
+    /// Position of the chunk within its document
     pub index: usize,
+    pub text: ChunkText,
+}
+
+/// The text of a chunk: a byte range of its document's text, which every chunk of the document shares
+// A 500 byte chunk used to be its own String, a second copy of the whole corpus, more with overlap.
+// Now a chunk is an Arc to the document text (a reference count increment, no copy) and a range.
+// Deref to str means it can be used like a &str: chunk.text.len(), chunk.text.contains("x")
+#[derive(Clone, Default)]
+pub struct ChunkText {
+    source: Arc<str>,
+    range: Range<usize>,
+}
+
+impl ChunkText {
+    /// The range of source, which must lie on character boundaries
+    pub fn new(source: Arc<str>, range: Range<usize>) -> ChunkText {
+        assert!(source.get(range.clone()).is_some(), "chunk range {:?} is not within the text", range);
+        ChunkText { source, range }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source[self.range.clone()]
+    }
+
+    /// Where the chunk starts and ends within its document's text
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    // Point a chunk read from an index file at its document's text, false if its range isn't in it
+    pub(crate) fn resolve(&mut self, source: &Arc<str>) -> bool {
+        if source.get(self.range.clone()).is_none() {
+            return false;
+        }
+        self.source = Arc::clone(source);
+        true
+    }
+}
+
+impl Deref for ChunkText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+// A chunk that doesn't share its text, e.g. one cut from a file that was read again
+impl From<String> for ChunkText {
+    fn from(text: String) -> ChunkText {
+        let range = 0..text.len();
+        ChunkText { source: Arc::from(text), range }
+    }
+}
+
+impl From<&str> for ChunkText {
+    fn from(text: &str) -> ChunkText {
+        ChunkText::from(text.to_string())
+    }
+}
+
+impl fmt::Debug for ChunkText {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for ChunkText {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq for ChunkText {
+    fn eq(&self, other: &ChunkText) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialEq<str> for ChunkText {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for ChunkText {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+// Saved as its range only, the text is in the document already. A loaded chunk has no text until
+// Corpus::share_text points it back into its document's text, which load_corpus does right away
+impl Serialize for ChunkText {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.range.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChunkText {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ChunkText, D::Error> {
+        Ok(ChunkText { source: Arc::from(""), range: Range::deserialize(deserializer)? })
+    }
+}
+
+/// Default chunk size in bytes, 500 chars is roughly 75-100 tokens
+pub const DEFAULT_CHUNK_SIZE: usize = 500;
+
+/// The unit a chunk's size and overlap are measured in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChunkStrategy {
+    /// Fixed number of bytes, moved forward to the next character boundary
+    #[default]
+    Chars,
+    /// Fixed number of whitespace separated words
+    Words,
+    /// Fixed number of lines
+    Lines,
+}
+
+// FromStr lets "words".parse::<ChunkStrategy>() work, which is also what the CLI uses
+impl FromStr for ChunkStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "chars" => Ok(ChunkStrategy::Chars),
+            "words" => Ok(ChunkStrategy::Words),
+            "lines" => Ok(ChunkStrategy::Lines),
+            other => Err(format!("unknown chunk strategy '{}', expected chars, words or lines", other)),
+        }
+    }
+}
+
+impl fmt::Display for ChunkStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ChunkStrategy::Chars => "chars",
+            ChunkStrategy::Words => "words",
+            ChunkStrategy::Lines => "lines",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How files are split into chunks: the strategy picks the unit, size and overlap count units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkingConfig {
+    pub strategy: ChunkStrategy,
+    pub size: usize,
+    /// How many units consecutive chunks share, must be smaller than size
+    pub overlap: usize,
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        ChunkingConfig {
+            strategy: ChunkStrategy::Chars,
+            size: DEFAULT_CHUNK_SIZE,
+            overlap: 0,
+        }
+    }
+}
+
+impl ChunkingConfig {
+    pub fn new(strategy: ChunkStrategy, size: usize, overlap: usize) -> Result<ChunkingConfig, String> {
+        if size == 0 {
+            return Err("chunk size must be greater than zero".to_string());
+        }
+        if overlap >= size {
+            return Err(format!("chunk overlap ({}) must be smaller than chunk size ({})", overlap, size));
+        }
+        Ok(ChunkingConfig { strategy, size, overlap })
+    }
 }
 
 ///
 /// Read a text and chunk it down according to chunk_size
-pub fn chunk_text(text: &str, chunk_size: usize, source_file: &str) -> Vec<Chunk> {
+pub fn chunk_text(text: &str, chunk_size: usize, doc: DocId) -> Vec<Chunk> {
+    chunk_chars(&Arc::from(text), chunk_size, 0, doc)
+}
+
+/// Chunk a text using the strategy, size and overlap from config
+// Chunk ids are local to the text here, chunk_files renumbers them corpus-wide
+pub fn chunk_with_config(text: &str, config: &ChunkingConfig, doc: DocId) -> Vec<Chunk> {
+    chunk_shared(&Arc::from(text), config, doc)
+}
+
+/// chunk_with_config for text that is already shared, the chunks point into it instead of copying it
+pub fn chunk_shared(text: &Arc<str>, config: &ChunkingConfig, doc: DocId) -> Vec<Chunk> {
+    match config.strategy {
+        ChunkStrategy::Chars => chunk_chars(text, config.size, config.overlap, doc),
+        ChunkStrategy::Words => chunk_units(text, &word_starts(text), config, doc),
+        ChunkStrategy::Lines => chunk_units(text, &line_starts(text), config, doc),
+    }
+}
+
+// Byte based chunking, consecutive chunks share `overlap` bytes (rounded to character boundaries)
+fn chunk_chars(text: &Arc<str>, chunk_size: usize, overlap: usize, doc: DocId) -> Vec<Chunk> {
     let mut chunks = Vec::new();
     let mut current_pos = 0;
     let mut index = 0;
@@ -26,20 +231,97 @@ pub fn chunk_text(text: &str, chunk_size: usize, source_file: &str) -> Vec<Chunk
         }
 
         chunks.push(Chunk {
+            id: ChunkId(index as u32),
+            doc,
+            index,
             // we adjust end pos index because slicing in Rust
             // works with Byte Indices, not character indices
-            text: text[current_pos..end_pos].to_string(),
-            file: source_file.to_string(),
-            index,
+            text: ChunkText::new(Arc::clone(text), current_pos..end_pos),
         });
 
-        current_pos = end_pos;
+        if end_pos == text_len {
+            break;
+        }
+
+        // Step back by the overlap, but always move forward at least one byte so we terminate
+        let mut next_pos = std::cmp::max(end_pos.saturating_sub(overlap), current_pos + 1);
+        while !text.is_char_boundary(next_pos) {
+            next_pos += 1;
+        }
+        current_pos = next_pos;
         index += 1;
     }
 
     chunks
 }
 
+// Chunk by counting units (words or lines), given the byte offset where every unit starts
+// A chunk runs until the start of the first unit after it, so whitespace between units is kept
+fn chunk_units(text: &Arc<str>, starts: &[usize], config: &ChunkingConfig, doc: DocId) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let step = config.size - config.overlap;
+    let mut first = 0;
+
+    while first < starts.len() {
+        let last = first + config.size;
+        let start = if first == 0 { 0 } else { starts[first] };
+        let end = if last < starts.len() { starts[last] } else { text.len() };
+
+        chunks.push(Chunk {
+            id: ChunkId(chunks.len() as u32),
+            doc,
+            index: chunks.len(),
+            text: ChunkText::new(Arc::clone(text), start..end),
+        });
+
+        if last >= starts.len() {
+            break;
+        }
+        first += step;
+    }
+
+    chunks
+}
+
+// Byte offsets of the first character of every whitespace separated word
+fn word_starts(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut previous_was_space = true;
+    for (pos, c) in text.char_indices() {
+        if !c.is_whitespace() && previous_was_space {
+            starts.push(pos);
+        }
+        previous_was_space = c.is_whitespace();
+    }
+    starts
+}
+
+// Byte offsets of the first character of every line
+fn line_starts(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut pos = 0;
+    // split_inclusive keeps the '\n' on every line so the lengths add up to the text length
+    for line in text.split_inclusive('\n') {
+        starts.push(pos);
+        pos += line.len();
+    }
+    starts
+}
+
+/// Chunk every document and collect the chunks in document order, numbering them from first_id
+pub fn chunk_files(documents: &[Document], config: &ChunkingConfig, first_id: ChunkId) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = documents
+        .iter()
+        // flat_map turns the Vec<Chunk> of every document into one long iterator of chunks
+        .flat_map(|doc| chunk_shared(&doc.text, config, doc.id))
+        .collect();
+
+    for (offset, chunk) in chunks.iter_mut().enumerate() {
+        chunk.id = ChunkId(first_id.0 + offset as u32);
+    }
+    chunks
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -47,11 +329,51 @@ mod tests {
     #[test]
     fn test_chunk_text_basic() {
         let text = "The quick brown fox jumps over the lazy dog.";
-        let chunks = chunk_text(text, 20, "test.txt");
+        let chunks = chunk_text(text, 20, DocId(0));
 
         assert_eq!(chunks.len(), 3);
         assert_eq!(chunks[0].text, "The quick brown fox ");
         assert_eq!(chunks[1].text, "jumps over the lazy ");
         assert_eq!(chunks[2].text, "dog.");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_chunk_overlap_and_strategies() {
+        let text = "one two three four five";
+
+        let words = ChunkingConfig::new(ChunkStrategy::Words, 2, 1).unwrap();
+        let chunks = chunk_with_config(text, &words, DocId(0));
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.trim()).collect();
+        assert_eq!(texts, vec!["one two", "two three", "three four", "four five"]);
+
+        let chars = ChunkingConfig::new(ChunkStrategy::Chars, 10, 4).unwrap();
+        let chunks = chunk_with_config(text, &chars, DocId(0));
+        assert_eq!(chunks[0].text, "one two th");
+        assert_eq!(chunks[1].text, "o three fo");
+
+        let lines = ChunkingConfig::new(ChunkStrategy::Lines, 2, 0).unwrap();
+        let chunks = chunk_with_config("a\nb\nc\n", &lines, DocId(0));
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].text, "a\nb\n");
+
+        assert!(ChunkingConfig::new(ChunkStrategy::Words, 3, 3).is_err());
+        assert_eq!("lines".parse::<ChunkStrategy>(), Ok(ChunkStrategy::Lines));
+    }
+
+    #[test]
+    fn test_chunks_share_document_text() {
+        use crate::corpus::{Corpus, Document};
+        let chunking = ChunkingConfig::new(ChunkStrategy::Chars, 10, 4).unwrap();
+        let corpus = Corpus::new(vec![Document::new("a.txt", "one two three four five")], chunking);
+        let ranges: Vec<Range<usize>> = corpus.chunks().iter().map(|chunk| chunk.text.range()).collect();
+        assert_eq!(ranges[..2], [0..10, 6..16]);
+
+        // Saved chunks are plain strings, loading points them back into the document text
+        let path = std::env::temp_dir().join(format!("chunk_text_{}.idx", std::process::id()));
+        crate::persist::save_corpus(&corpus, &path).unwrap();
+        let loaded = crate::persist::load_corpus(&path, corpus.shared_analyzer()).unwrap();
+        assert_eq!(loaded.chunks().iter().map(|chunk| chunk.text.range()).collect::<Vec<_>>(), ranges);
+        assert_eq!(loaded.chunks()[1].text, "o three fo");
+        std::fs::remove_file(path).unwrap();
+    }
+}
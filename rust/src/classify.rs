@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use rustc_hash::FxHashMap;
+use crate::analyzer::Analyzer;
+use crate::corpus::Corpus;
+use crate::search::to_f32;
+use crate::tfidf::IdfScheme;
+
+// The analyzer and the IDF statistics that rank documents are also the standard features for
+// sorting them into categories. Two classic baselines, both trained in one pass over the labeled
+// documents: Rocchio compares a text with the average TF-IDF vector of every class, multinomial
+// Naive Bayes picks the class whose word distribution makes the text most likely
+
+/// Which model TextClassifier::train builds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassifierKind {
+    /// Nearest class centroid by cosine similarity of TF-IDF vectors (Rocchio)
+    Centroid,
+    /// Multinomial Naive Bayes over term counts, alpha is the additive (Laplace) smoothing
+    NaiveBayes { alpha: f32 },
+}
+
+enum Model {
+    // One L2-normalized mean TF-IDF vector per label
+    Centroid(Vec<HashMap<String, f32>>),
+    NaiveBayes {
+        log_priors: Vec<f32>,
+        // ln P(term | label) for every term seen with the label in training
+        log_likelihoods: Vec<HashMap<String, f32>>,
+        // ln P(term | label) of the vocabulary terms the label never had
+        log_unseen: Vec<f32>,
+    },
+}
+
+/// A classifier trained on labeled documents of a corpus
+pub struct TextClassifier {
+    labels: Vec<String>,
+    model: Model,
+    idf: FxHashMap<String, f32>,
+    analyzer: Arc<Analyzer>,
+}
+
+impl TextClassifier {
+    /// Train on the documents of the corpus that labels maps from their path to a class
+    /// Unlabeled documents aren't trained on, but they count for the IDF like every other chunk
+    pub fn train(corpus: &Corpus, labels: &HashMap<String, String>, kind: ClassifierKind) -> Result<TextClassifier, Box<dyn Error>> {
+        let mut names: Vec<String> = labels.values().cloned().collect();
+        names.sort();
+        names.dedup();
+        if names.len() < 2 {
+            return Err("training needs documents of at least two labels".into());
+        }
+        let index = corpus.index();
+        let idf: FxHashMap<String, f32> = index
+            .terms()
+            .map(|term| (term.to_string(), to_f32(IdfScheme::Plain.idf(index.num_chunks(), index.doc_freq(term)))))
+            .collect();
+        let mut classifier =
+            TextClassifier { labels: names, model: Model::Centroid(Vec::new()), idf, analyzer: corpus.shared_analyzer() };
+
+        // Term counts of every labeled document, grouped by label
+        let mut examples: Vec<Vec<HashMap<String, u32>>> = vec![Vec::new(); classifier.labels.len()];
+        for document in corpus.documents() {
+            let Some(label) = labels.get(&document.path) else {
+                continue;
+            };
+            let position = classifier.labels.binary_search(label).unwrap();
+            examples[position].push(classifier.counts(&corpus.document_text(document.id)?));
+        }
+        // A label whose paths are all missing from the corpus would have a prior of ln 0
+        let untrained: Vec<&str> =
+            classifier.labels.iter().zip(&examples).filter(|(_, documents)| documents.is_empty()).map(|(label, _)| label.as_str()).collect();
+        if !untrained.is_empty() {
+            return Err(format!("no document of the corpus is labeled {}", untrained.join(", ")).into());
+        }
+
+        classifier.model = match kind {
+            ClassifierKind::Centroid => Model::Centroid(
+                examples
+                    .iter()
+                    .map(|documents| {
+                        let mut centroid: HashMap<String, f32> = HashMap::new();
+                        for counts in documents {
+                            for (term, weight) in classifier.tfidf_vector(counts) {
+                                *centroid.entry(term).or_insert(0.0) += weight;
+                            }
+                        }
+                        // Dividing by the number of documents doesn't change the direction, normalizing is enough
+                        normalize(&mut centroid);
+                        centroid
+                    })
+                    .collect(),
+            ),
+            ClassifierKind::NaiveBayes { alpha } => {
+                let total_documents: usize = examples.iter().map(|documents| documents.len()).sum();
+                let vocabulary: usize = {
+                    let mut terms: Vec<&String> = examples.iter().flatten().flat_map(|counts| counts.keys()).collect();
+                    terms.sort();
+                    terms.dedup();
+                    terms.len()
+                };
+                let log_priors = examples.iter().map(|documents| (documents.len() as f32 / total_documents as f32).ln()).collect();
+                let (log_likelihoods, log_unseen) = examples
+                    .iter()
+                    .map(|documents| {
+                        let mut counts: HashMap<String, f32> = HashMap::new();
+                        for document in documents {
+                            for (term, count) in document {
+                                *counts.entry(term.clone()).or_insert(0.0) += *count as f32;
+                            }
+                        }
+                        // Smoothing gives terms never seen with this label a small probability instead of 0
+                        let total = counts.values().sum::<f32>() + alpha * vocabulary as f32;
+                        let likelihoods =
+                            counts.into_iter().map(|(term, count)| (term, ((count + alpha) / total).ln())).collect();
+                        (likelihoods, (alpha / total).ln())
+                    })
+                    .unzip();
+                Model::NaiveBayes { log_priors, log_likelihoods, log_unseen }
+            }
+        };
+        Ok(classifier)
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The most likely label of the text
+    pub fn predict(&self, text: &str) -> String {
+        self.scores(text).swap_remove(0).0
+    }
+
+    /// A score for every label, highest first: cosine similarity for Centroid, log probability for NaiveBayes
+    pub fn scores(&self, text: &str) -> Vec<(String, f32)> {
+        let counts = self.counts(text);
+        let mut scores: Vec<(String, f32)> = match &self.model {
+            Model::Centroid(centroids) => {
+                let mut vector = self.tfidf_vector(&counts);
+                normalize(&mut vector);
+                centroids
+                    .iter()
+                    .zip(&self.labels)
+                    .map(|(centroid, label)| {
+                        let cosine: f32 = vector.iter().filter_map(|(term, w)| Some(w * centroid.get(term)?)).sum();
+                        (label.clone(), cosine)
+                    })
+                    .collect()
+            }
+            Model::NaiveBayes { log_priors, log_likelihoods, log_unseen } => (0..self.labels.len())
+                .map(|position| {
+                    let (likelihoods, unseen) = (&log_likelihoods[position], log_unseen[position]);
+                    let score: f32 = counts
+                        .iter()
+                        // Terms that aren't in the corpus at all say nothing about any label
+                        .filter(|(term, _)| self.idf.contains_key(*term))
+                        .map(|(term, count)| *count as f32 * likelihoods.get(term).copied().unwrap_or(unseen))
+                        .sum();
+                    (self.labels[position].clone(), log_priors[position] + score)
+                })
+                .collect(),
+        };
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scores
+    }
+
+    /// Fraction of the examples whose label is predicted correctly
+    pub fn accuracy(&self, examples: &[(String, String)]) -> f32 {
+        let correct = examples.iter().filter(|(text, label)| self.predict(text) == *label).count();
+        correct as f32 / examples.len().max(1) as f32
+    }
+
+    fn counts(&self, text: &str) -> HashMap<String, u32> {
+        let mut counts = HashMap::new();
+        for term in self.analyzer.analyze(text) {
+            *counts.entry(term).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // tf / length * idf, the default TF-IDF weighting of the rankers
+    fn tfidf_vector(&self, counts: &HashMap<String, u32>) -> HashMap<String, f32> {
+        let length: u32 = counts.values().sum();
+        counts
+            .iter()
+            .filter_map(|(term, count)| Some((term.clone(), *count as f32 / length as f32 * self.idf.get(term)?)))
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect()
+    }
+}
+
+fn normalize(vector: &mut HashMap<String, f32>) {
+    let norm = vector.values().map(|w| w * w).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.values_mut().for_each(|w| *w /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_classifiers_separate_two_topics() {
+        let training = [
+            ("r1.txt", "rust borrow checker ownership", "rust"),
+            ("r2.txt", "rust lifetimes and borrow rules", "rust"),
+            ("r3.txt", "cargo builds rust crates", "rust"),
+            ("p1.txt", "python garbage collector", "python"),
+            ("p2.txt", "python pip installs packages", "python"),
+            ("p3.txt", "python dynamic typing and garbage", "python"),
+        ];
+        let corpus = Corpus::new(
+            training.iter().map(|(path, text, _)| Document::new(path, text)).collect(),
+            ChunkingConfig::default(),
+        );
+        let labels: HashMap<String, String> =
+            training.iter().map(|(path, _, label)| (path.to_string(), label.to_string())).collect();
+        let test = vec![
+            ("the borrow checker rejects this".to_string(), "rust".to_string()),
+            ("garbage collector pauses".to_string(), "python".to_string()),
+            ("pip packages".to_string(), "python".to_string()),
+        ];
+        for kind in [ClassifierKind::Centroid, ClassifierKind::NaiveBayes { alpha: 1.0 }] {
+            let classifier = TextClassifier::train(&corpus, &labels, kind).unwrap();
+            assert_eq!(classifier.labels(), ["python", "rust"]);
+            assert_eq!(classifier.accuracy(&test), 1.0);
+        }
+        let one_label = HashMap::from([("r1.txt".to_string(), "rust".to_string())]);
+        assert!(TextClassifier::train(&corpus, &one_label, ClassifierKind::Centroid).is_err());
+        let mut missing = labels.clone();
+        missing.insert("gone.txt".to_string(), "go".to_string());
+        let error = TextClassifier::train(&corpus, &missing, ClassifierKind::NaiveBayes { alpha: 1.0 }).err().unwrap();
+        assert_eq!(error.to_string(), "no document of the corpus is labeled go");
+    }
+}
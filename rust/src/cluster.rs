@@ -0,0 +1,129 @@
+use crate::corpus::{ChunkId, Corpus, SplitMix64};
+use crate::tfidf::{unit_vectors, TfIdfParams};
+
+// k-means on TF-IDF vectors groups chunks that use the same distinctive words, which is a quick
+// way to see what a corpus is about before searching it. With vectors of length 1, the closest
+// centroid is the one with the highest dot product (cosine similarity), and each centroid is the
+// normalized mean of its chunks: "spherical" k-means, the usual variant for text
+
+/// Keywords shown per cluster
+const KEYWORDS: usize = 5;
+const MAX_ITERATIONS: usize = 100;
+
+/// A group of similar chunks and the terms with the most weight in its centroid
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub keywords: Vec<String>,
+    pub chunks: Vec<ChunkId>,
+}
+
+/// Group the chunks into at most k clusters, largest first. The same seed gives the same clusters
+pub fn cluster_chunks(corpus: &Corpus, k: usize, seed: u64) -> Vec<Cluster> {
+    let vectors = unit_vectors(corpus, TfIdfParams::default());
+    let points = &vectors.vectors;
+    let k = k.min(points.len());
+    if k == 0 {
+        return Vec::new();
+    }
+    // Centroids are dense, a k x vocabulary matrix, the chunks stay sparse
+    let dot = |centroid: &[f32], point: &[(usize, f32)]| -> f32 { point.iter().map(|(term, w)| centroid[*term] * w).sum() };
+    let dense = |point: &[(usize, f32)]| -> Vec<f32> {
+        let mut centroid = vec![0.0; vectors.terms.len()];
+        point.iter().for_each(|(term, w)| centroid[*term] = *w);
+        centroid
+    };
+
+    // k-means++: every next starting centroid is a chunk picked with probability proportional to
+    // its squared distance from the closest centroid so far, which spreads them over the data
+    let mut rng = SplitMix64(seed);
+    let mut centroids = vec![dense(&points[(rng.next() % points.len() as u64) as usize].1)];
+    while centroids.len() < k {
+        // For unit vectors the squared distance is 2 - 2 * cosine
+        let distances: Vec<f32> = points
+            .iter()
+            .map(|(_, point)| centroids.iter().map(|c| (2.0 - 2.0 * dot(c, point)).max(0.0)).fold(f32::MAX, f32::min))
+            .collect();
+        let total: f32 = distances.iter().sum();
+        if total <= 0.0 {
+            // Fewer distinct chunks than k
+            break;
+        }
+        let mut target = (rng.next() as f64 / u64::MAX as f64) as f32 * total;
+        let chosen = distances.iter().position(|d| {
+            target -= d;
+            target <= 0.0
+        });
+        centroids.push(dense(&points[chosen.unwrap_or(points.len() - 1)].1));
+    }
+
+    let mut assignments = vec![usize::MAX; points.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (assignment, (_, point)) in assignments.iter_mut().zip(points) {
+            let closest = (0..centroids.len())
+                .max_by(|a, b| dot(&centroids[*a], point).partial_cmp(&dot(&centroids[*b], point)).unwrap().then(b.cmp(a)))
+                .unwrap();
+            changed |= *assignment != closest;
+            *assignment = closest;
+        }
+        if !changed {
+            break;
+        }
+        for (cluster, centroid) in centroids.iter_mut().enumerate() {
+            let mut sum = vec![0.0; vectors.terms.len()];
+            for (_, point) in points.iter().zip(&assignments).filter(|(_, a)| **a == cluster).map(|(p, _)| p) {
+                point.iter().for_each(|(term, w)| sum[*term] += w);
+            }
+            let norm = sum.iter().map(|w| w * w).sum::<f32>().sqrt();
+            // A cluster that lost all its chunks keeps its old centroid
+            if norm > 0.0 {
+                *centroid = sum.into_iter().map(|w| w / norm).collect();
+            }
+        }
+    }
+
+    let mut clusters: Vec<Cluster> = centroids
+        .iter()
+        .enumerate()
+        .map(|(cluster, centroid)| {
+            let mut weighted: Vec<(usize, f32)> = centroid.iter().copied().enumerate().filter(|(_, w)| *w > 0.0).collect();
+            weighted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+            Cluster {
+                keywords: weighted.iter().take(KEYWORDS).map(|(term, _)| vectors.terms[*term].clone()).collect(),
+                chunks: points.iter().zip(&assignments).filter(|(_, a)| **a == cluster).map(|((id, _), _)| *id).collect(),
+            }
+        })
+        .filter(|cluster| !cluster.chunks.is_empty())
+        .collect();
+    clusters.sort_by(|a, b| b.chunks.len().cmp(&a.chunks.len()).then(a.chunks.cmp(&b.chunks)));
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_clusters_separate_topics() {
+        let texts = [
+            "rust borrow checker",
+            "python garbage collector",
+            "rust borrow lifetimes",
+            "python garbage pauses",
+            "rust lifetimes borrow",
+            "garbage collector python",
+        ];
+        let documents = texts.iter().enumerate().map(|(i, text)| Document::new(&format!("{}.txt", i), text)).collect();
+        let corpus = Corpus::new(documents, ChunkingConfig::default());
+        let clusters = cluster_chunks(&corpus, 2, 7);
+        assert_eq!(clusters.len(), 2);
+        let mut chunks: Vec<Vec<u32>> = clusters.iter().map(|c| c.chunks.iter().map(|id| id.0).collect()).collect();
+        chunks.sort();
+        assert_eq!(chunks, [vec![0, 2, 4], vec![1, 3, 5]]);
+        assert!(clusters.iter().any(|c| c.keywords.contains(&"borrow".to_string())));
+        assert_eq!(cluster_chunks(&corpus, 2, 7), clusters);
+        assert_eq!(cluster_chunks(&corpus, 10, 7).iter().map(|c| c.chunks.len()).sum::<usize>(), 6);
+    }
+}
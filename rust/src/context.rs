@@ -0,0 +1,89 @@
+use std::error::Error;
+use std::ops::Range;
+use crate::corpus::{ChunkId, Corpus};
+
+// A chunk is sized for scoring, not for reading: 500 characters are often half a paragraph, cut
+// mid-sentence. Chunks are ranges of their document's text, so the text around a hit is still
+// there to show, either a number of neighbouring chunks on each side or a number of characters
+
+/// How much text around a chunk to include
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    /// This many chunks of the same document before and after it
+    Chunks(usize),
+    /// This many characters before and after it
+    Chars(usize),
+}
+
+/// A chunk with the text around it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpandedChunk {
+    pub chunk: ChunkId,
+    /// The chunk and its context, never beyond the start or end of the document
+    pub text: String,
+    /// Where text starts and ends in the document's text
+    pub range: Range<usize>,
+    /// Where the chunk itself is within text, e.g. to highlight it
+    pub chunk_range: Range<usize>,
+}
+
+/// The chunk and its context, with the text read from the file again if the corpus doesn't store it
+pub fn expand_context(corpus: &Corpus, id: ChunkId, context: Context) -> Result<ExpandedChunk, Box<dyn Error>> {
+    let chunk = corpus.chunk(id).ok_or_else(|| format!("no chunk {}", id.0))?;
+    let text = corpus.document_text(chunk.doc)?;
+    let ranges = corpus.chunk_ranges(chunk.doc)?;
+    let position = ranges.iter().position(|(other, _)| *other == id).ok_or_else(|| format!("chunk {} is not in the file anymore", id.0))?;
+    let own = ranges[position].1.clone();
+    let range = match context {
+        Context::Chunks(n) => {
+            // With overlap neighbouring chunks share text, the outermost ones still bound it
+            let neighbours = &ranges[position.saturating_sub(n)..ranges.len().min(position + n + 1)];
+            let start = neighbours.iter().map(|(_, range)| range.start).min().unwrap_or(own.start);
+            let end = neighbours.iter().map(|(_, range)| range.end).max().unwrap_or(own.end);
+            start..end
+        }
+        Context::Chars(n) => {
+            let start = text[..own.start].char_indices().rev().take(n).last().map_or(own.start, |(start, _)| start);
+            let end = own.end + text[own.end..].chars().take(n).map(char::len_utf8).sum::<usize>();
+            start..end
+        }
+    };
+    Ok(ExpandedChunk {
+        chunk: id,
+        text: text[range.clone()].to_string(),
+        chunk_range: own.start - range.start..own.end - range.start,
+        range,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::{ChunkStrategy, ChunkingConfig};
+    use crate::corpus::Document;
+    use crate::index::Index;
+
+    #[test]
+    fn test_expand_context() {
+        let corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "one two three four five six"))
+            .add_document(Document::new("b.txt", "other"))
+            .chunking(ChunkingConfig::new(ChunkStrategy::Words, 2, 0).unwrap())
+            .build()
+            .unwrap();
+        let middle = corpus.chunk_at("a.txt", 1).unwrap().id;
+        let index = Index::new(corpus);
+
+        let expanded = index.expand_context(middle, Context::Chunks(1)).unwrap();
+        assert_eq!(expanded.text, "one two three four five six");
+        assert_eq!(&expanded.text[expanded.chunk_range.clone()], "three four ");
+        // The first chunk has nothing before it
+        let first = index.expand_context(ChunkId(0), Context::Chunks(1)).unwrap();
+        assert_eq!((first.text.as_str(), first.chunk_range), ("one two three four ", 0..8));
+
+        let expanded = index.expand_context(middle, Context::Chars(4)).unwrap();
+        assert_eq!(expanded.text, "two three four five");
+        assert_eq!(expanded.range, 4..23);
+        assert!(index.expand_context(ChunkId(99), Context::Chars(4)).is_err());
+    }
+}
@@ -0,0 +1,1578 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+use crate::analyzer::Analyzer;
+use crate::bm25::{Bm25Params, Bm25Scorer};
+use crate::compress::CompressedText;
+use crate::chunker::{chunk_files, chunk_with_config, Chunk, ChunkText, ChunkingConfig};
+use crate::explain::{explain_rank_diff, RankDiff};
+use crate::vocabulary::Vocabulary;
+use crate::fields::{term_counts, FieldExplanation, FieldIndex, FieldWeights, CODE, DATE, DOCS, TAGS, TITLE};
+use crate::inverted_index::{DfPruning, InvertedIndex};
+use crate::loader::{list_directory_files, load_objects, load_url, load_wikipedia, parse_frontmatter, read_file, read_files, read_text, split_source};
+use crate::stats::CorpusStats;
+use crate::search::{search_lowercase_chunks, search_lowercase_files, Score, SearchOptions, SearchResult, Selection, TimedResults};
+use crate::plan::{plan_query, QueryPlan};
+use crate::progress::{Progress, ProgressCallback, Stage, Tracker};
+use crate::cancel::CancellationToken;
+use crate::limits::{LimitExceeded, ResourceLimits};
+use crate::query::{parse_query, DeadlineScorer, QueryError, RepeatedTermsScorer, TermScorer};
+use crate::tfidf::{rank, score_terms_tfidf, TfIdfParams, TfIdfScorer};
+
+/// Compact document id, assigned when the corpus is built and never reused
+// The tuple struct wrapper keeps doc ids and chunk ids from being mixed up, at no runtime cost
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DocId(pub u32);
+
+/// Compact chunk id, unique across the whole corpus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ChunkId(pub u32);
+
+impl fmt::Display for DocId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for ChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A single loaded text together with where it came from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub id: DocId,
+    /// Name used in results, e.g. "docs/library/os.txt"
+    pub path: String,
+    /// The directory, file or URL that was added to the builder to find this document
+    pub root: String,
+    /// Shared with the chunks of the document, which are ranges of it
+    pub text: Arc<str>,
+    /// Multiplied into the score of every chunk of the document, 1.0 leaves it alone
+    #[serde(default = "default_boost")]
+    pub boost: f32,
+    /// When the file was last modified, used for recency weighting
+    #[serde(default)]
+    pub modified: Option<SystemTime>,
+    /// Size of the file in bytes when it was read, with modified it tells whether the file changed since
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// The file the text was read from, None for documents that didn't come from a file
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Named texts besides the body, e.g. the "docs" and "code" of a source file, see fields.rs
+    /// Indexed per document and only scored by searches with field weights
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// The terms of fields with how often each occurs, kept instead of their text when the
+    /// document's text isn't stored, see IndexOptions::store_text
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub field_terms: BTreeMap<String, Vec<(String, u32)>>,
+    // The text, when IndexOptions::compress_text moved it out of the text field. Not behind the
+    // zstd feature, so every build reads and writes the same index files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) compressed: Option<CompressedText>,
+}
+
+/// What a corpus keeps besides the postings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexOptions {
+    /// Keep the text of documents and chunks, for snippets and highlighting
+    /// Without it, text is read back from the files whenever a result needs it, and the
+    /// substring modes (search_lines, search_chunks) find nothing in documents loaded from files
+    pub store_text: bool,
+    /// Keep stored text zstd compressed and decompress it when a result needs it
+    /// The substring modes find nothing in compressed documents. Ignored without the zstd feature
+    #[serde(default)]
+    pub compress_text: bool,
+    /// Split source files into a "docs" field of comments and strings and a "code" field,
+    /// for documents that don't have fields yet. See loader::split_source
+    #[serde(default)]
+    pub code_fields: bool,
+    /// Read the title, tags and date in the frontmatter of Markdown files into fields
+    #[serde(default)]
+    pub frontmatter: bool,
+}
+
+impl Default for IndexOptions {
+    fn default() -> IndexOptions {
+        IndexOptions { store_text: true, compress_text: false, code_fields: false, frontmatter: false }
+    }
+}
+
+// Index files written before boosts existed have no boost field
+fn default_boost() -> f32 {
+    1.0
+}
+
+/// Recency weighting: a document's scores are halved for every half_life of age
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recency {
+    pub half_life: Duration,
+    /// Ages are measured from this time
+    pub reference: SystemTime,
+}
+
+impl Recency {
+    pub fn from_now(half_life: Duration) -> Recency {
+        Recency { half_life, reference: SystemTime::now() }
+    }
+
+    /// 0.5 ^ (age / half_life), 1.0 for documents modified after the reference time
+    pub fn factor(&self, modified: SystemTime) -> f32 {
+        if self.half_life.is_zero() {
+            return 1.0;
+        }
+        let age = self.reference.duration_since(modified).unwrap_or_default();
+        0.5f32.powf(age.as_secs_f32() / self.half_life.as_secs_f32())
+    }
+}
+
+impl Document {
+    /// A document that wasn't loaded from any particular root
+    // The id is a placeholder until the document is added to a Corpus
+    pub fn new(path: &str, text: &str) -> Document {
+        Document {
+            id: DocId(0),
+            path: path.to_string(),
+            root: String::new(),
+            text: Arc::from(text),
+            boost: 1.0,
+            modified: None,
+            size: None,
+            file: None,
+            fields: BTreeMap::new(),
+            field_terms: BTreeMap::new(),
+            compressed: None,
+        }
+    }
+}
+
+/// Loaded documents together with their chunks
+// Chunking happens exactly once, when the corpus is built, so running many
+// queries against the same corpus never re-chunks the files
+// Documents and chunks are kept sorted by id, so lookups by id are binary searches
+// and removing a document never changes the ids of the others
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Corpus {
+    documents: Vec<Document>,
+    chunks: Vec<Chunk>,
+    doc_ids: HashMap<String, DocId>,
+    chunking: ChunkingConfig,
+    index: InvertedIndex,
+    // Trait objects can't be serialized, a loaded corpus gets its analyzer back from
+    // load_corpus after the fingerprint in the file has been checked against it
+    #[serde(skip)]
+    analyzer: Arc<Analyzer>,
+    // A query-time setting that depends on the current time, so it isn't saved with the index
+    #[serde(skip)]
+    recency: Option<Recency>,
+    #[serde(default)]
+    options: IndexOptions,
+    #[serde(skip)]
+    lowercase: LowercaseText,
+    // Built on the first search with field weights, like the lowercase text
+    #[serde(skip)]
+    field_index: OnceLock<FieldIndex>,
+}
+
+// The substring searches match case-insensitively. Lowercasing every chunk for every query cost
+// more than the search itself, so a corpus lowercases its text once, on the first substring query,
+// and keeps it until the chunks change. It is a second copy of the text, only paid for by corpora
+// that get substring queries. Not saved with the index, a loaded corpus builds it again when needed
+#[derive(Clone, Default)]
+struct LowercaseText {
+    // In the same order as the chunks and the documents
+    chunks: OnceLock<Vec<Box<str>>>,
+    documents: OnceLock<Vec<Box<str>>>,
+}
+
+impl Corpus {
+    /// Build a corpus with the default analyzer
+    pub fn new(documents: Vec<Document>, chunking: ChunkingConfig) -> Corpus {
+        Corpus::with_analyzer(documents, chunking, Arc::new(Analyzer::default()))
+    }
+
+    /// Build a corpus from documents, assigning ids in order, chunking every document
+    /// and indexing every chunk with the analyzer up front
+    pub fn with_analyzer(documents: Vec<Document>, chunking: ChunkingConfig, analyzer: Arc<Analyzer>) -> Corpus {
+        Corpus::index_documents(documents, chunking, analyzer, None, &CancellationToken::new(), None)
+            .expect("a new token is never cancelled and there is no postings limit")
+    }
+
+    // with_analyzer, reporting every analyzed chunk to the progress callback and stopping between
+    // chunks once the token is cancelled or the index holds more than max_postings postings
+    fn index_documents(
+        mut documents: Vec<Document>,
+        chunking: ChunkingConfig,
+        analyzer: Arc<Analyzer>,
+        progress: Option<&(dyn Fn(&Progress) + Send + Sync)>,
+        cancel: &CancellationToken,
+        max_postings: Option<usize>,
+    ) -> Result<Corpus, Box<dyn Error>> {
+        let mut doc_ids = HashMap::new();
+        for (position, document) in documents.iter_mut().enumerate() {
+            document.id = DocId(position as u32);
+            doc_ids.insert(document.path.clone(), document.id);
+        }
+        let chunks = chunk_files(&documents, &chunking, ChunkId(0));
+        // Chunking is quick next to analyzing, so the chunks are the unit of progress
+        let mut tracker = Tracker::new(progress, Stage::Indexing, chunks.len());
+        let mut index = InvertedIndex::default();
+        let (mut bytes, mut postings) = (0, 0);
+        for (done, chunk) in chunks.iter().enumerate() {
+            cancel.check()?;
+            index.add_chunk(chunk, &analyzer);
+            // A chunk adds one posting for each of its distinct terms
+            postings += index.chunk_terms(chunk.id).unique as usize;
+            if let Some(max_postings) = max_postings
+                && postings > max_postings
+            {
+                return Err(LimitExceeded { max_postings }.into());
+            }
+            bytes += chunk.text.len() as u64;
+            tracker.update(done + 1, bytes);
+        }
+        Ok(Corpus {
+            documents,
+            chunks,
+            doc_ids,
+            chunking,
+            index,
+            analyzer,
+            recency: None,
+            options: IndexOptions::default(),
+            lowercase: LowercaseText::default(),
+            field_index: OnceLock::new(),
+        })
+    }
+
+    /// Combine corpora built separately, e.g. shards indexed by parallel jobs, into one
+    // Every shard's document and chunk ids are shifted past the ones already merged, so ids stay
+    // unique and sorted, and the postings are appended rather than re-analyzed
+    pub fn merge(shards: &[&Corpus]) -> Result<Corpus, String> {
+        let Some(first) = shards.first() else {
+            return Err("nothing to merge".to_string());
+        };
+        let mut merged = Corpus {
+            documents: Vec::new(),
+            chunks: Vec::new(),
+            doc_ids: HashMap::new(),
+            chunking: first.chunking,
+            index: InvertedIndex::default(),
+            analyzer: Arc::clone(&first.analyzer),
+            recency: first.recency,
+            options: first.options,
+            lowercase: LowercaseText::default(),
+            field_index: OnceLock::new(),
+        };
+        for (number, shard) in shards.iter().enumerate() {
+            merged.append(number, shard)?;
+        }
+        Ok(merged)
+    }
+
+    /// Add a shard after everything merged so far, as Corpus::merge does, number names it in errors
+    // Lets a merge hold one shard at a time instead of all of them, see checkpoint.rs
+    pub(crate) fn append(&mut self, number: usize, shard: &Corpus) -> Result<(), String> {
+        if shard.chunking != self.chunking {
+            return Err(format!("shard {} was chunked with different settings", number));
+        }
+        if shard.options != self.options {
+            return Err(format!("shard {} was built with different index options", number));
+        }
+        if shard.analyzer.fingerprint() != self.analyzer.fingerprint() {
+            return Err(format!(
+                "shard {} was analyzed with '{}', not '{}'",
+                number,
+                shard.analyzer.describe(),
+                self.analyzer.describe()
+            ));
+        }
+        // One past the highest id so far, ids of removed documents are skipped rather than reused
+        let doc_offset = self.documents.last().map(|d| d.id.0 + 1).unwrap_or(0);
+        let chunk_offset = self.chunks.last().map(|c| c.id.0 + 1).unwrap_or(0);
+        self.append_shifted(shard, doc_offset, chunk_offset)
+    }
+
+    // Add every document and chunk of other with its ids shifted up by the offsets, postings included
+    fn append_shifted(&mut self, other: &Corpus, doc_offset: u32, chunk_offset: u32) -> Result<(), String> {
+        for document in &other.documents {
+            let id = DocId(document.id.0 + doc_offset);
+            if self.doc_ids.insert(document.path.clone(), id).is_some() {
+                return Err(format!("'{}' is in more than one shard", document.path));
+            }
+            self.documents.push(Document { id, ..document.clone() });
+        }
+        self.chunks.extend(other.chunks.iter().map(|chunk| Chunk {
+            id: ChunkId(chunk.id.0 + chunk_offset),
+            doc: DocId(chunk.doc.0 + doc_offset),
+            ..chunk.clone()
+        }));
+        self.index.append(&other.index, chunk_offset);
+        self.lowercase = LowercaseText::default();
+        self.field_index = OnceLock::new();
+        Ok(())
+    }
+
+    /// The same corpus with every document id moved up by doc_offset and every chunk id by chunk_offset
+    // Lets corpora built separately share one id space without re-analyzing anything, see segments.rs
+    pub(crate) fn shifted(&self, doc_offset: u32, chunk_offset: u32) -> Corpus {
+        let mut shifted = Corpus {
+            documents: Vec::with_capacity(self.documents.len()),
+            chunks: Vec::with_capacity(self.chunks.len()),
+            doc_ids: HashMap::new(),
+            index: InvertedIndex::default(),
+            analyzer: Arc::clone(&self.analyzer),
+            lowercase: LowercaseText::default(),
+            field_index: OnceLock::new(),
+            ..*self
+        };
+        // The paths come from one corpus, so none of them can clash
+        shifted.append_shifted(self, doc_offset, chunk_offset).unwrap_or_default();
+        shifted
+    }
+
+    /// A corpus with only the given documents and their chunks, keeping their ids
+    // Used to cut a corpus into shards whose results can be mixed without renumbering
+    pub(crate) fn subset(&self, docs: &HashSet<DocId>) -> Corpus {
+        let documents: Vec<Document> = self.documents.iter().filter(|d| docs.contains(&d.id)).cloned().collect();
+        let chunks: Vec<Chunk> = self.chunks.iter().filter(|c| docs.contains(&c.doc)).cloned().collect();
+        let doc_ids = documents.iter().map(|d| (d.path.clone(), d.id)).collect();
+        let mut index = InvertedIndex::build(&chunks, &self.analyzer);
+        // Keep the vocabulary the full index ended up with, in case it was pruned
+        index.retain_terms(|term| !self.index.postings(term).is_empty());
+        Corpus {
+            documents,
+            chunks,
+            doc_ids,
+            chunking: self.chunking,
+            index,
+            analyzer: Arc::clone(&self.analyzer),
+            recency: self.recency,
+            options: self.options,
+            lowercase: LowercaseText::default(),
+            field_index: OnceLock::new(),
+        }
+    }
+
+    /// A random fraction of the documents, the same one for the same seed
+    // Sampling is by document rather than chunk, so a sampled document keeps all of its chunks.
+    // Ids are kept, judgments and results of the full corpus stay comparable
+    pub fn sample(&self, fraction: f32, seed: u64) -> Result<Corpus, String> {
+        let (sampled, _) = self.split(fraction, seed)?;
+        Ok(sampled)
+    }
+
+    /// Split the documents at random into two corpora, the first with ratio of them, e.g. to tune
+    /// parameters on one part and evaluate on the held-out rest. The same seed gives the same split
+    pub fn split(&self, ratio: f32, seed: u64) -> Result<(Corpus, Corpus), String> {
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(format!("ratio must be between 0 and 1, got {}", ratio));
+        }
+        let mut ids: Vec<DocId> = self.documents.iter().map(|d| d.id).collect();
+        // Fisher-Yates shuffle, every order is equally likely
+        let mut rng = SplitMix64(seed);
+        for i in (1..ids.len()).rev() {
+            ids.swap(i, (rng.next() % (i as u64 + 1)) as usize);
+        }
+        let first = (ratio * ids.len() as f32).round() as usize;
+        let (a, b) = ids.split_at(first);
+        Ok((self.subset(&a.iter().copied().collect()), self.subset(&b.iter().copied().collect())))
+    }
+
+    /// Start building a corpus from any mix of directories, files and URLs
+    pub fn builder() -> CorpusBuilder {
+        CorpusBuilder::default()
+    }
+
+    /// Load every .txt file under a directory and chunk it according to the config
+    pub fn from_directory(directory_path: &str, chunking: ChunkingConfig) -> Result<Corpus, Box<dyn Error>> {
+        Corpus::builder().add_dir(directory_path).chunking(chunking).build()
+    }
+
+    pub fn documents(&self) -> &[Document] {
+        &self.documents
+    }
+
+    /// Documents that were loaded from the given root, as passed to the builder
+    pub fn documents_from<'a>(&'a self, root: &'a str) -> impl Iterator<Item = &'a Document> + 'a {
+        self.documents.iter().filter(move |doc| doc.root == root)
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// Look up a document by id, None if it was never added or has been removed
+    pub fn document(&self, id: DocId) -> Option<&Document> {
+        // binary_search_by_key works because documents stay sorted by id
+        self.documents
+            .binary_search_by_key(&id, |doc| doc.id)
+            .ok()
+            .map(|position| &self.documents[position])
+    }
+
+    pub fn chunk(&self, id: ChunkId) -> Option<&Chunk> {
+        self.chunks
+            .binary_search_by_key(&id, |chunk| chunk.id)
+            .ok()
+            .map(|position| &self.chunks[position])
+    }
+
+    /// Ids of the chunks of the given documents
+    // Chunks are numbered in document order, so the chunks of a document are one run of the
+    // chunk list, found by binary search and added to the bitmap as a range of ids
+    pub fn chunk_ids(&self, docs: &RoaringBitmap) -> RoaringBitmap {
+        let mut chunks = RoaringBitmap::new();
+        for doc in docs {
+            let start = self.chunks.partition_point(|chunk| chunk.doc.0 < doc);
+            let end = self.chunks.partition_point(|chunk| chunk.doc.0 <= doc);
+            if start < end {
+                chunks.insert_range(self.chunks[start].id.0..=self.chunks[end - 1].id.0);
+            }
+        }
+        chunks
+    }
+
+    /// Path of a document, for displaying results
+    pub fn path(&self, id: DocId) -> Option<&str> {
+        self.document(id).map(|doc| doc.path.as_str())
+    }
+
+    /// Id of the document loaded from path
+    pub fn doc_id(&self, path: &str) -> Option<DocId> {
+        self.doc_ids.get(path).copied()
+    }
+
+    /// The chunk at a position within a document, the inverse of eval::result_key
+    pub fn chunk_at(&self, path: &str, index: usize) -> Option<&Chunk> {
+        let doc = self.doc_id(path)?;
+        self.chunks.iter().find(|chunk| chunk.doc == doc && chunk.index == index)
+    }
+
+    /// A new corpus of the documents, chunked and analyzed the way this one was
+    pub fn with_documents(&self, documents: Vec<Document>) -> Corpus {
+        let mut corpus = Corpus::with_analyzer(documents, self.chunking, Arc::clone(&self.analyzer));
+        corpus.set_options(self.options);
+        corpus
+    }
+
+    pub fn options(&self) -> IndexOptions {
+        self.options
+    }
+
+    // Without store_text, the text of every document that can be read back from its file is dropped,
+    // with compress_text the text of every other document is compressed
+    fn set_options(&mut self, mut options: IndexOptions) {
+        if !cfg!(feature = "zstd") {
+            options.compress_text = false;
+        }
+        self.options = options;
+        let analyzer = Arc::clone(&self.analyzer);
+        let mut emptied = HashSet::new();
+        for document in self.documents.iter_mut() {
+            // Before the text is dropped or compressed, there is nothing to split afterwards
+            if options.code_fields && document.fields.is_empty() {
+                let extension = document.path.rsplit_once('.').map_or("", |(_, extension)| extension);
+                if let Some(source) = split_source(&document.text, extension) {
+                    document.fields = BTreeMap::from([(DOCS.to_string(), source.docs), (CODE.to_string(), source.code)]);
+                }
+            }
+            if options.frontmatter
+                && document.path.ends_with(".md")
+                && !document.fields.contains_key(TITLE)
+                && let Some(frontmatter) = parse_frontmatter(&document.text)
+            {
+                let tags = (!frontmatter.tags.is_empty()).then(|| frontmatter.tags.join("\n"));
+                let named = [(TITLE, frontmatter.title), (DATE, frontmatter.date), (TAGS, tags)];
+                document.fields.extend(named.into_iter().filter_map(|(name, value)| Some((name.to_string(), value?))));
+            }
+            if !options.store_text && document.file.is_some() {
+                document.text = Arc::from("");
+                emptied.insert(document.id);
+                // Fields are mostly parts of the text, BM25F only needs their terms. Tags are
+                // metadata the tag filter reads, they stay as they are
+                let fields = std::mem::take(&mut document.fields);
+                for (name, text) in fields {
+                    if name == TAGS {
+                        document.fields.insert(name, text);
+                    } else {
+                        document.field_terms.insert(name, term_counts(&analyzer, &text));
+                    }
+                }
+            } else if options.compress_text {
+                // Compressing in memory only fails if zstd can't allocate, then the text stays as it is
+                #[cfg(feature = "zstd")]
+                if let Ok(compressed) = CompressedText::compress(&document.text) {
+                    document.compressed = Some(compressed);
+                    document.text = Arc::from("");
+                    emptied.insert(document.id);
+                }
+            }
+        }
+        for chunk in self.chunks.iter_mut().filter(|chunk| emptied.contains(&chunk.doc)) {
+            chunk.text = ChunkText::default();
+        }
+        self.lowercase = LowercaseText::default();
+        self.field_index = OnceLock::new();
+    }
+
+    /// The text of a document, read from its file again if the corpus doesn't store text
+    // Cow: borrowed when the text is stored, owned when it had to be read
+    pub fn document_text(&self, id: DocId) -> Result<Cow<'_, str>, Box<dyn Error>> {
+        let document = self.document(id).ok_or_else(|| format!("no document {}", id.0))?;
+        if let Some(compressed) = &document.compressed {
+            return Ok(Cow::Owned(compressed.decompress()?));
+        }
+        match &document.file {
+            Some(file) if !self.options.store_text => Ok(Cow::Owned(read_text(file)?)),
+            _ => Ok(Cow::Borrowed(&document.text)),
+        }
+    }
+
+    /// The text of a chunk, cut from the re-read file if the corpus doesn't store text
+    // Chunking is deterministic, so chunking the file again with the same settings gives the same chunks,
+    // as long as the file hasn't changed since it was indexed
+    pub fn chunk_text(&self, id: ChunkId) -> Result<Cow<'_, str>, Box<dyn Error>> {
+        let chunk = self.chunk(id).ok_or_else(|| format!("no chunk {}", id.0))?;
+        match self.document_text(chunk.doc)? {
+            Cow::Borrowed(_) => Ok(Cow::Borrowed(&chunk.text)),
+            Cow::Owned(text) => {
+                let rechunked = chunk_with_config(&text, &self.chunking, chunk.doc).into_iter().nth(chunk.index);
+                let rechunked = rechunked.ok_or_else(|| format!("chunk {} is not in the file anymore", id.0))?;
+                Ok(Cow::Owned(rechunked.text.to_string()))
+            }
+        }
+    }
+
+    /// Remove a document and its chunks, the ids of all other documents and chunks stay valid
+    pub fn remove_document(&mut self, id: DocId) -> Option<Document> {
+        let position = self.documents.binary_search_by_key(&id, |doc| doc.id).ok()?;
+        let document = self.documents.remove(position);
+        self.doc_ids.remove(&document.path);
+        let removed: HashSet<ChunkId> = self.chunks.iter().filter(|c| c.doc == id).map(|c| c.id).collect();
+        // retain keeps only the chunks for which the closure returns true
+        self.chunks.retain(|chunk| chunk.doc != id);
+        self.index.remove_chunks(&removed);
+        self.lowercase = LowercaseText::default();
+        self.field_index = OnceLock::new();
+        Some(document)
+    }
+
+    /// Postings of the fields of every document, built on first use
+    pub fn field_index(&self) -> &FieldIndex {
+        self.field_index.get_or_init(|| FieldIndex::build(&self.documents, &self.analyzer))
+    }
+
+    /// The chunking settings the chunks were built with
+    pub fn chunking(&self) -> &ChunkingConfig {
+        &self.chunking
+    }
+
+    /// The analyzer used for both chunk text and queries
+    pub fn analyzer(&self) -> &Analyzer {
+        &self.analyzer
+    }
+
+    // The analyzer as the Arc it is shared through, for corpora built or loaded to match this one
+    pub(crate) fn shared_analyzer(&self) -> Arc<Analyzer> {
+        Arc::clone(&self.analyzer)
+    }
+
+    /// Hand the analyzer back to a corpus that was just deserialized
+    pub(crate) fn attach_analyzer(&mut self, analyzer: Arc<Analyzer>) {
+        self.analyzer = analyzer;
+    }
+
+    // Chunks read from an index file only know their range, point them into their document's text.
+    // Chunks of documents whose text isn't stored were saved with an empty range
+    pub(crate) fn share_text(&mut self) -> Result<(), String> {
+        for chunk in self.chunks.iter_mut() {
+            let source = self
+                .documents
+                .binary_search_by_key(&chunk.doc, |doc| doc.id)
+                .map_err(|_| format!("chunk {} belongs to no document", chunk.id.0))?;
+            if !chunk.text.resolve(&self.documents[source].text) {
+                return Err(format!("chunk {} lies outside the text of its document", chunk.id.0));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn index(&self) -> &InvertedIndex {
+        &self.index
+    }
+
+    /// Why TF-IDF and BM25 rank a chunk differently for a query, term by term
+    pub fn explain_rank_diff(&self, query: &str, chunk: ChunkId) -> Result<RankDiff, QueryError> {
+        explain_rank_diff(self, query, chunk)
+    }
+
+    /// How every weighted field adds to a document's BM25F score for a query, the part
+    /// SearchOptions::field_weights adds to the scores of its chunks with the same k1 and b
+    pub fn explain_fields(
+        &self,
+        query: &str,
+        doc: DocId,
+        weights: &FieldWeights,
+        params: &Bm25Params,
+    ) -> Result<FieldExplanation, QueryError> {
+        let terms = self.plan(query)?.query.positive_terms(&Bm25Scorer { corpus: self, params: *params });
+        Ok(self.field_index().explain(doc, &terms, weights, params))
+    }
+
+    /// Drop rare and near-universal terms from the index, returns how many terms were dropped
+    pub fn prune_vocabulary(&mut self, pruning: &DfPruning) -> usize {
+        self.index.prune(pruning)
+    }
+
+    /// Drop every term outside the vocabulary from the index, returns how many terms were dropped
+    // Like pruning, chunk lengths still count the dropped terms, they are what the text is
+    pub fn restrict_vocabulary(&mut self, vocabulary: &Vocabulary) -> usize {
+        let before = self.index.terms().count();
+        self.index.retain_terms(|term| vocabulary.0.contains(term));
+        before - self.index.terms().count()
+    }
+
+    /// Collection statistics for scoring text outside the index, cheap to share between threads
+    pub fn stats(&self) -> Arc<CorpusStats> {
+        Arc::new(CorpusStats::from_index(&self.index, Arc::clone(&self.analyzer)))
+    }
+
+    /// Turn a query into terms with the same analyzer that indexed the chunks
+    pub fn analyze_query(&self, query: &str) -> Vec<String> {
+        self.analyzer.analyze(query)
+    }
+
+    /// Line-based substring search over the original files
+    pub fn search_lines(&self, query: &str) -> Vec<SearchResult> {
+        let lowercase = self.lowercase.documents.get_or_init(|| self.documents.iter().map(|d| d.text.to_lowercase().into()).collect());
+        search_lowercase_files(query, &self.documents, lowercase)
+    }
+
+    /// Substring search over the prebuilt chunks
+    pub fn search_chunks(&self, query: &str) -> Vec<SearchResult> {
+        let lowercase = self.lowercase.chunks.get_or_init(|| self.chunks.iter().map(|c| c.text.to_lowercase().into()).collect());
+        search_lowercase_chunks(query, &self.chunks, lowercase)
+    }
+
+    /// Rank chunks with TF-IDF over the inverted index
+    pub fn score_tfidf(&self, query: &str) -> Vec<SearchResult> {
+        let terms = self.analyze_query(query);
+        self.to_results(score_terms_tfidf(&terms, &self.index), &terms)
+    }
+
+    /// Parse a query with AND / OR / NOT, "phrases" and (groups) and rank the matches with TF-IDF
+    pub fn search(&self, query: &str) -> Result<Vec<SearchResult>, QueryError> {
+        self.search_with(query, &TfIdfScorer::new(self, TfIdfParams::default()))
+    }
+
+    /// Like search, with any scorer, e.g. a Bm25Scorer
+    pub fn search_with(&self, query: &str, scorer: &dyn TermScorer) -> Result<Vec<SearchResult>, QueryError> {
+        self.search_with_options(query, scorer, &SearchOptions::default())
+    }
+
+    /// Like search_with, leaving out the chunks of the excluded documents, e.g. ones the user has already seen
+    // Excluded chunks are dropped before ranking, so they never take a place in the top k
+    pub fn search_excluding(
+        &self,
+        query: &str,
+        scorer: &dyn TermScorer,
+        excluded: &HashSet<DocId>,
+    ) -> Result<Vec<SearchResult>, QueryError> {
+        let query = parse_query(query)?;
+        let terms = query.positive_terms(scorer);
+        let mut scores = query.evaluate(scorer);
+        let excluded = self.chunk_ids(&excluded.iter().map(|doc| doc.0).collect());
+        scores.retain(|chunk, _| !excluded.contains(chunk.0));
+        Ok(self.to_results(self.rank_boosted(scores), &terms))
+    }
+
+    /// Analyzed terms of the non-negated parts of the query that no chunk contains, each once in
+    /// query order. A query with a typo or a word the corpus never uses finds little or nothing,
+    /// these say why
+    pub fn unknown_terms(&self, query: &str) -> Result<Vec<String>, QueryError> {
+        let terms = parse_query(query)?.positive_terms(&TfIdfScorer::new(self, TfIdfParams::default()));
+        Ok(self.missing_terms(&terms))
+    }
+
+    fn missing_terms(&self, terms: &[String]) -> Vec<String> {
+        let mut missing: Vec<String> = Vec::new();
+        for term in terms {
+            if self.index.doc_freq(term) == 0 && !missing.contains(term) {
+                missing.push(term.clone());
+            }
+        }
+        missing
+    }
+
+    /// How the query will be evaluated, with the operands of every AND reordered rarest first
+    pub fn plan(&self, query: &str) -> Result<QueryPlan, QueryError> {
+        Ok(plan_query(&parse_query(query)?, &self.index, &self.analyzer))
+    }
+
+    /// Number of chunks the query matches, counted from the postings without scoring them
+    /// A search also drops matches that score 0, which depends on the scorer: a term in every
+    /// chunk has a TF-IDF idf of 0, so search finds nothing for it while count counts every chunk
+    pub fn count(&self, query: &str) -> Result<usize, QueryError> {
+        Ok(parse_query(query)?.matching_chunks(&self.index, &self.analyzer).len() as usize)
+    }
+
+    /// Like search_with, dropping matches below the thresholds in options
+    pub fn search_with_options(
+        &self,
+        query: &str,
+        scorer: &dyn TermScorer,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>, QueryError> {
+        Ok(self.search_timed(query, scorer, options)?.results)
+    }
+
+    /// Like search_with_options, also reporting whether the time budget cut the search short
+    // The thresholds are applied to the score map, so weak matches never cost a SearchResult
+    pub fn search_timed(&self, query: &str, scorer: &dyn TermScorer, options: &SearchOptions) -> Result<TimedResults, QueryError> {
+        let repeated = RepeatedTermsScorer::new(scorer, options.repeated_terms);
+        let scorer: &dyn TermScorer = &repeated;
+        // The clock starts before parsing, the budget is for the whole query
+        let deadline = options.time_budget.map(|budget| DeadlineScorer::new(scorer, Instant::now() + budget));
+        let scorer: &dyn TermScorer = match &deadline {
+            Some(deadline) => deadline,
+            None => scorer,
+        };
+        let query = self.plan(query)?.query;
+        let terms = query.positive_terms(scorer);
+        let mut scores = query.evaluate_with(scorer, options.summation);
+        if let Some(weights) = &options.field_weights {
+            // Fields belong to documents, a document's BM25F score is added to every chunk of it the
+            // query matched, so fields reorder the matches without matching anything on their own.
+            // BM25 scores them with its own k1 and b, other scorers with the defaults
+            let field_scores = self.field_index().bm25f(&terms, weights, &scorer.bm25_params().unwrap_or_default());
+            for (chunk, score) in scores.iter_mut() {
+                if let Some(field_score) = self.chunk(*chunk).and_then(|chunk| field_scores.get(&chunk.doc)) {
+                    *score += field_score;
+                }
+            }
+        }
+        if let Some(filter) = &options.filter {
+            let allowed = filter.chunks(self);
+            scores.retain(|chunk, _| allowed.contains(chunk.0));
+        }
+        let selected = options.selection.as_ref().map(|selection| self.selected_chunks(selection));
+        if let Some(selected) = &selected {
+            scores.retain(|chunk, _| selected.contains_key(chunk));
+        }
+        if let Some(minimum) = options.minimum_should_match {
+            let mut distinct = terms.clone();
+            distinct.sort();
+            distinct.dedup();
+            let required = minimum.min(distinct.len());
+            // Count, for every chunk, how many of the query terms it contains. Straight from the
+            // postings, a scorer past its deadline returns nothing and would drop every match
+            let mut matched: HashMap<ChunkId, usize> = HashMap::new();
+            for term in &distinct {
+                for posting in self.index.postings(term) {
+                    *matched.entry(posting.chunk).or_insert(0) += 1;
+                }
+            }
+            scores.retain(|chunk, _| matched.get(chunk).copied().unwrap_or(0) >= required);
+        }
+        let mut ranked = self.rank_boosted(scores);
+        if let Some(min_score) = options.min_score {
+            ranked.retain(|(_, score)| *score >= min_score);
+        }
+        let truncated = deadline.is_some_and(|deadline| deadline.exceeded());
+        let mut results = self.to_results(ranked, &terms);
+        if let (Some(selection), Some(selected)) = (&options.selection, &selected) {
+            // Spans are relative to the chunk, the selection to the document. A chunk that overlaps
+            // the selection but matched only outside of it isn't a match within the selection
+            results.retain_mut(|result| {
+                let start = result.chunk.and_then(|chunk| selected.get(&chunk)).copied().unwrap_or(0);
+                let matched = !result.spans.is_empty();
+                result.spans.retain(|span| selection.bytes.start <= start + span.start && start + span.end <= selection.bytes.end);
+                !matched || !result.spans.is_empty()
+            });
+        }
+        Ok(TimedResults { results, truncated, unknown_terms: self.missing_terms(&terms) })
+    }
+
+    /// The selection of the lines first to last of a document, 1-based. Lines past the end select up to it
+    pub fn select_lines(&self, doc: DocId, lines: RangeInclusive<usize>) -> Result<Selection, Box<dyn Error>> {
+        if *lines.start() == 0 || lines.start() > lines.end() {
+            return Err(format!("{}-{} is not a range of lines, they are numbered from 1", lines.start(), lines.end()).into());
+        }
+        let text = self.document_text(doc)?;
+        // Where every line starts, and the end of the text as the start of the line after the last
+        let starts: Vec<usize> = std::iter::once(0).chain(text.match_indices('\n').map(|(newline, _)| newline + 1)).collect();
+        let offset = |line: usize| starts.get(line - 1).copied().unwrap_or(text.len()).min(text.len());
+        // Saturating, --within path:1-18446744073709551615 means to the end too
+        Ok(Selection { doc, bytes: offset(*lines.start())..offset(lines.end().saturating_add(1)) })
+    }
+
+    // The chunks of the selection's document that overlap it, with where each starts in the document
+    fn selected_chunks(&self, selection: &Selection) -> HashMap<ChunkId, usize> {
+        let ranges = self.chunk_ranges(selection.doc).unwrap_or_default();
+        ranges
+            .into_iter()
+            .filter(|(_, range)| range.start < selection.bytes.end && selection.bytes.start < range.end)
+            .map(|(chunk, range)| (chunk, range.start))
+            .collect()
+    }
+
+    /// Where every chunk of a document starts and ends in its text, in chunk order
+    // Chunks whose text isn't stored have no range left, the re-read file is cut again
+    pub fn chunk_ranges(&self, doc: DocId) -> Result<ChunkRanges, Box<dyn Error>> {
+        let start = self.chunks.partition_point(|chunk| chunk.doc < doc);
+        let end = self.chunks.partition_point(|chunk| chunk.doc <= doc);
+        let chunks = &self.chunks[start..end];
+        match self.document_text(doc)? {
+            Cow::Borrowed(_) => Ok(chunks.iter().map(|chunk| (chunk.id, chunk.text.range())).collect()),
+            Cow::Owned(text) => {
+                let rechunked = chunk_with_config(&text, &self.chunking, doc);
+                Ok(chunks.iter().filter_map(|chunk| Some((chunk.id, rechunked.get(chunk.index)?.text.range()))).collect())
+            }
+        }
+    }
+
+    /// search_timed for every query in turn, stopping with Cancelled between queries once the token
+    /// is cancelled. Results of the queries already run are dropped with the rest
+    pub fn search_batch(
+        &self,
+        queries: &[&str],
+        scorer: &dyn TermScorer,
+        options: &SearchOptions,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<TimedResults>, Box<dyn Error>> {
+        let mut batch = Vec::with_capacity(queries.len());
+        for query in queries {
+            cancel.check()?;
+            batch.push(self.search_timed(query, scorer, options)?);
+        }
+        Ok(batch)
+    }
+
+    /// Chunk ids and scores for a query, highest first, without building results
+    // Evaluation runs hundreds of queries per configuration and only needs the ranking
+    pub fn rank_with(&self, query: &str, scorer: &dyn TermScorer) -> Result<Vec<(ChunkId, Score)>, QueryError> {
+        Ok(self.rank_boosted(parse_query(query)?.evaluate(scorer)))
+    }
+
+    /// Set a document's static boost, false if there is no such document
+    pub fn set_boost(&mut self, id: DocId, boost: f32) -> bool {
+        match self.documents.binary_search_by_key(&id, |doc| doc.id) {
+            Ok(position) => {
+                self.documents[position].boost = boost;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Weight scores by document age from now on, None turns recency weighting off
+    pub fn set_recency(&mut self, recency: Option<Recency>) {
+        self.recency = recency;
+    }
+
+    /// What a document's chunk scores are multiplied by: its boost times its recency factor
+    pub fn doc_boost(&self, id: DocId) -> f32 {
+        let Some(document) = self.document(id) else {
+            return 1.0;
+        };
+        let recency = match (self.recency, document.modified) {
+            (Some(recency), Some(modified)) => recency.factor(modified),
+            _ => 1.0,
+        };
+        document.boost * recency
+    }
+
+    // Apply document boosts to the scores, then sort them
+    // Boosts are applied to the final score rather than per term, so they scale a chunk's
+    // relevance without changing which terms matter most within it
+    pub(crate) fn rank_boosted(&self, mut scores: HashMap<ChunkId, Score>) -> Vec<(ChunkId, Score)> {
+        for (chunk, score) in scores.iter_mut() {
+            if let Some(chunk) = self.chunk(*chunk) {
+                *score *= self.doc_boost(chunk.doc) as Score;
+            }
+        }
+        rank(scores)
+    }
+
+    // Turn ranked chunk ids into results, highlighting the query terms
+    pub(crate) fn to_results(&self, ranked: Vec<(ChunkId, Score)>, terms: &[String]) -> Vec<SearchResult> {
+        let term_refs: Vec<&str> = terms.iter().map(|t| t.as_str()).collect();
+        ranked
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let stored = self.chunk(id)?;
+                // A chunk whose text isn't stored gets it back for highlighting, or none if its file is gone
+                let read;
+                let chunk = match self.chunk_text(id) {
+                    Ok(Cow::Owned(text)) => {
+                        read = Chunk { text: text.into(), ..stored.clone() };
+                        &read
+                    }
+                    _ => stored,
+                };
+                let mut result = SearchResult::from_chunk(chunk, score, &term_refs);
+                // The terms are analyzed, so take the spans from the analyzer's tokens instead of
+                // substring matches: a stemmed "run" then marks "running" but not "prune"
+                result.spans = self
+                    .analyzer
+                    .tokens(&chunk.text)
+                    .into_iter()
+                    .filter(|token| terms.contains(&token.text))
+                    .map(|token| token.start..token.end)
+                    .collect();
+                Some(result)
+            })
+            .collect()
+    }
+}
+
+// SplitMix64, a tiny seeded random number generator, good enough for shuffling and
+// stable across platforms and versions, which matters more here than statistical quality
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+// Where the builder should read documents from, resolved in CorpusBuilder::build
+enum Source {
+    Dir(PathBuf),
+    File(PathBuf),
+    Url(String),
+    Objects(String),
+    Wikipedia(PathBuf, Option<usize>),
+    Text(Document),
+}
+
+/// Chunk ids with where the chunks start and end in their document's text, see Corpus::chunk_ranges
+pub type ChunkRanges = Vec<(ChunkId, Range<usize>)>;
+
+/// Decides whether a document is left out of the corpus, see CorpusBuilder::exclude_if
+// Send + Sync so a builder can be moved to another thread, e.g. by async_api::build
+pub type DocumentFilter = Box<dyn Fn(&Document) -> bool + Send + Sync>;
+
+/// Rewrites a document's text before it is chunked, see CorpusBuilder::transform
+// Cow: a transform with nothing to change returns the text it was given without copying it
+pub type TextTransform = Box<dyn for<'a> Fn(&'a str) -> Cow<'a, str> + Send + Sync>;
+
+/// Collects document sources so one corpus can span several roots
+// The builder methods take self by value and return it, which is what allows chaining:
+// Corpus::builder().add_dir("docs").add_file("notes.txt").build()
+#[derive(Default)]
+pub struct CorpusBuilder {
+    sources: Vec<Source>,
+    chunking: ChunkingConfig,
+    analyzer: Arc<Analyzer>,
+    /// File extensions picked up by add_dir, "txt" when empty
+    extensions: Vec<String>,
+    pruning: Option<DfPruning>,
+    vocabulary: Option<Vocabulary>,
+    filters: Vec<DocumentFilter>,
+    transforms: Vec<TextTransform>,
+    progress: Option<ProgressCallback>,
+    cancel: CancellationToken,
+    limits: ResourceLimits,
+    options: IndexOptions,
+}
+
+impl CorpusBuilder {
+    /// Add every .txt file (or every file with one of the extensions) under a directory, recursively
+    pub fn add_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sources.push(Source::Dir(path.into()));
+        self
+    }
+
+    /// Add a single file, whatever its extension
+    pub fn add_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sources.push(Source::File(path.into()));
+        self
+    }
+
+    /// Add a document downloaded over HTTP(S), requires the `http` feature
+    pub fn add_url(mut self, url: &str) -> Self {
+        self.sources.push(Source::Url(url.to_string()));
+        self
+    }
+
+    /// Add every object under an s3://bucket/prefix URL with one of the extensions, requires the `s3` feature
+    pub fn add_objects(mut self, url: &str) -> Self {
+        self.sources.push(Source::Objects(url.to_string()));
+        self
+    }
+
+    /// Add the articles of a Wikipedia XML or CirrusSearch JSON dump, at most max_articles of them,
+    /// requires the `wikipedia` feature
+    pub fn add_wikipedia(mut self, path: impl Into<PathBuf>, max_articles: Option<usize>) -> Self {
+        self.sources.push(Source::Wikipedia(path.into(), max_articles));
+        self
+    }
+
+    /// Add an in-memory document
+    pub fn add_document(mut self, document: Document) -> Self {
+        self.sources.push(Source::Text(document));
+        self
+    }
+
+    pub fn chunking(mut self, chunking: ChunkingConfig) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// Which file extensions add_dir loads, e.g. ["rs", "py"] for a codebase
+    pub fn extensions<S: Into<String>, I: IntoIterator<Item = S>>(mut self, extensions: I) -> Self {
+        self.extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn analyzer(mut self, analyzer: Analyzer) -> Self {
+        self.analyzer = Arc::new(analyzer);
+        self
+    }
+
+    /// Prune the vocabulary by document frequency once everything is indexed
+    pub fn df_pruning(mut self, pruning: DfPruning) -> Self {
+        self.pruning = Some(pruning);
+        self
+    }
+
+    /// Keep only the terms of a fixed vocabulary once everything is indexed, after pruning
+    pub fn vocabulary(mut self, vocabulary: Vocabulary) -> Self {
+        self.vocabulary = Some(vocabulary);
+        self
+    }
+
+    /// What the corpus stores besides the postings, see IndexOptions
+    pub fn index_options(mut self, options: IndexOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Leave out every document the filter returns true for, e.g. drafts or generated files
+    /// Filters run after loading, so they can look at the path and the text
+    pub fn exclude_if<F: Fn(&Document) -> bool + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Rewrite the text of every document before it is chunked, e.g. to strip boilerplate or redact
+    /// emails. Transforms run in the order they were added, after the exclude_if filters
+    /// A document whose text a transform changed keeps its text even without store_text,
+    /// its file doesn't have the transformed text to read back
+    pub fn transform<F>(mut self, transform: F) -> Self
+    where
+        F: for<'a> Fn(&'a str) -> Cow<'a, str> + Send + Sync + 'static,
+    {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Call back with the progress of loading and indexing a few times a second, see progress.rs
+    pub fn on_progress<F: Fn(&Progress) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Stop loading or indexing with a Cancelled error once the token is cancelled
+    pub fn cancel_on(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Bound the files open at once and the postings in memory, see limits.rs
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Read every source in the order it was added and chunk the result
+    pub fn build(self) -> Result<Corpus, Box<dyn Error>> {
+        let mut documents = Vec::new();
+        let extensions: Vec<&str> = if self.extensions.is_empty() {
+            vec!["txt"]
+        } else {
+            self.extensions.iter().map(|e| e.as_str()).collect()
+        };
+
+        // Directories are listed before anything is read, so the progress has a total from the start
+        let mut listings = Vec::new();
+        for source in &self.sources {
+            if let Source::Dir(dir) = source {
+                listings.push(list_directory_files(&dir.to_string_lossy(), &extensions)?);
+            }
+        }
+        let total = self.sources.len() - listings.len() + listings.iter().map(Vec::len).sum::<usize>();
+        let mut listings = listings.into_iter();
+        let mut tracker = Tracker::new(self.progress.as_deref(), Stage::Loading, total);
+        let (mut done, mut bytes) = (0, 0);
+
+        for source in self.sources {
+            self.cancel.check()?;
+            let loaded = documents.len();
+            match source {
+                Source::Dir(dir) => {
+                    let root = dir.to_string_lossy().to_string();
+                    read_files(&listings.next().unwrap_or_default(), self.limits.max_open_files, |file| {
+                        self.cancel.check()?;
+                        done += 1;
+                        bytes += file.text.len() as u64;
+                        tracker.update(done, bytes);
+                        let document = Document::new(&file.path, &file.text);
+                        documents.push(Document {
+                            root: root.clone(),
+                            modified: file.modified,
+                            size: Some(file.size),
+                            file: Some(file.file),
+                            ..document
+                        });
+                        Ok(())
+                    })?;
+                    continue;
+                }
+                Source::File(file) => {
+                    let path = file.to_string_lossy().to_string();
+                    let file = read_file(path.clone(), file)?;
+                    documents.push(Document {
+                        root: path.clone(),
+                        modified: file.modified,
+                        size: Some(file.size),
+                        file: Some(file.file),
+                        ..Document::new(&path, &file.text)
+                    });
+                }
+                Source::Url(url) => {
+                    let text = load_url(&url)?;
+                    documents.push(Document { root: url.clone(), ..Document::new(&url, &text) });
+                }
+                Source::Objects(url) => documents.extend(load_objects(&url, &extensions, self.limits.max_open_files)?),
+                Source::Wikipedia(path, max_articles) => documents.extend(load_wikipedia(&path, max_articles)?),
+                Source::Text(document) => documents.push(document),
+            }
+            // Every other source is one step, however many documents it had
+            done += 1;
+            bytes += documents[loaded..].iter().map(|document| document.text.len() as u64).sum::<u64>();
+            tracker.update(done, bytes);
+        }
+        documents.retain(|document| !self.filters.iter().any(|exclude| exclude(document)));
+        for document in documents.iter_mut() {
+            let text = self.transforms.iter().fold(Cow::Borrowed(&*document.text), |text, transform| match transform(&text) {
+                Cow::Owned(changed) => Cow::Owned(changed),
+                Cow::Borrowed(same) if same.as_ptr() == text.as_ptr() && same.len() == text.len() => text,
+                // A part of the text, e.g. trimmed, is a change too
+                Cow::Borrowed(part) => Cow::Owned(part.to_string()),
+            });
+            if let Cow::Owned(text) = text {
+                document.text = Arc::from(text);
+                document.file = None;
+            }
+        }
+
+        let mut corpus = Corpus::index_documents(
+            documents,
+            self.chunking,
+            self.analyzer,
+            self.progress.as_deref(),
+            &self.cancel,
+            self.limits.max_postings,
+        )?;
+        if let Some(pruning) = self.pruning {
+            corpus.prune_vocabulary(&pruning);
+        }
+        if let Some(vocabulary) = &self.vocabulary {
+            corpus.restrict_vocabulary(vocabulary);
+        }
+        corpus.set_options(self.options);
+        Ok(corpus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::chunker::ChunkStrategy;
+    use crate::bm25::{Bm25Params, Bm25Scorer};
+
+    #[test]
+    fn test_corpus_chunks_once_and_answers_many_queries() {
+        let files = vec![
+            Document::new("a.txt", "rust ownership and borrowing"),
+            Document::new("b.txt", "python garbage collection"),
+        ];
+        let chunking = ChunkingConfig::new(ChunkStrategy::Chars, 100, 0).unwrap();
+        let corpus = Corpus::new(files, chunking);
+        let chunk_count = corpus.chunks().len();
+
+        assert_eq!(corpus.search_chunks("rust").len(), 1);
+        assert_eq!(corpus.search_chunks("python").len(), 1);
+        assert_eq!(corpus.score_tfidf("garbage")[0].doc, corpus.doc_id("b.txt").unwrap());
+        // Queries borrow the chunks, they never rebuild them
+        assert_eq!(corpus.chunks().len(), chunk_count);
+    }
+
+    #[test]
+    fn test_builder_spans_multiple_roots() {
+        let base = std::env::temp_dir().join(format!("corpus_builder_{}", std::process::id()));
+        let docs = base.join("docs");
+        let wiki = base.join("wiki");
+        fs::create_dir_all(&docs).unwrap();
+        fs::create_dir_all(&wiki).unwrap();
+        fs::write(docs.join("a.txt"), "rust docs").unwrap();
+        fs::write(wiki.join("b.txt"), "rust wiki").unwrap();
+        fs::write(base.join("notes.md"), "rust notes").unwrap();
+
+        let corpus = Corpus::builder()
+            .add_dir(&docs)
+            .add_dir(&wiki)
+            .add_file(base.join("notes.md"))
+            .add_document(Document::new("inline", "rust inline"))
+            .build()
+            .unwrap();
+
+        assert_eq!(corpus.documents().len(), 4);
+        assert_eq!(corpus.search_chunks("rust").len(), 4);
+        let wiki_root = wiki.to_string_lossy().to_string();
+        let from_wiki: Vec<&Document> = corpus.documents_from(&wiki_root).collect();
+        assert_eq!(from_wiki.len(), 1);
+        assert_eq!(&*from_wiki[0].text, "rust wiki");
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_ids_survive_document_removal() {
+        let files = vec![
+            Document::new("a.txt", "alpha"),
+            Document::new("b.txt", "beta"),
+            Document::new("c.txt", "gamma"),
+        ];
+        let mut corpus = Corpus::new(files, ChunkingConfig::default());
+        let c = corpus.doc_id("c.txt").unwrap();
+        let b = corpus.doc_id("b.txt").unwrap();
+
+        let removed = corpus.remove_document(b).unwrap();
+        assert_eq!(removed.path, "b.txt");
+        assert!(corpus.document(b).is_none());
+        assert_eq!(corpus.doc_id("b.txt"), None);
+        // c keeps its id and its chunk is still found through the result's ids
+        let results = corpus.search_chunks("gamma");
+        assert_eq!(results[0].doc, c);
+        assert_eq!(corpus.chunk(results[0].chunk.unwrap()).unwrap().text, "gamma");
+    }
+
+    #[test]
+    fn test_substring_search_lowercases_once() {
+        let files = vec![Document::new("a.txt", "Rust\nBORROW checker"), Document::new("b.txt", "rust and Python")];
+        let mut corpus = Corpus::new(files, ChunkingConfig::default());
+        assert_eq!(corpus.search_chunks("RUST").len(), 2);
+        let lines = corpus.search_lines("borrow");
+        assert_eq!((lines[0].line, lines[0].highlights[0].as_str()), (Some(2), "BORROW checker"));
+        // Removing a document drops the lowercase text, the next query sees the corpus as it is now
+        corpus.remove_document(DocId(0));
+        assert_eq!(corpus.search_chunks("rust").len(), 1);
+        assert!(corpus.search_lines("borrow").is_empty());
+    }
+
+    #[test]
+    fn test_search_with_query_syntax() {
+        let files = vec![
+            Document::new("a.txt", "rust borrow checker"),
+            Document::new("b.txt", "rust garbage collector"),
+            Document::new("c.txt", "python garbage collector"),
+        ];
+        let corpus = Corpus::new(files, ChunkingConfig::default());
+        let paths = |results: Vec<SearchResult>| -> Vec<String> {
+            results.iter().map(|r| corpus.path(r.doc).unwrap().to_string()).collect()
+        };
+
+        assert_eq!(paths(corpus.search("rust AND garbage").unwrap()), vec!["b.txt"]);
+        assert_eq!(paths(corpus.search("garbage -python").unwrap()), vec!["b.txt"]);
+        assert_eq!(paths(corpus.search("\"borrow checker\"").unwrap()), vec!["a.txt"]);
+        assert_eq!(corpus.search("\"borrow checker\"").unwrap()[0].spans, vec![5..11, 12..19]);
+        assert!(corpus.search("(rust").is_err());
+    }
+
+    #[test]
+    fn test_proximity_queries() {
+        let files = vec![
+            Document::new("exact.txt", "the quick fox jumps"),
+            Document::new("near.txt", "the quick brown fox jumps"),
+            Document::new("far.txt", "quick as ever the old grey fox"),
+            Document::new("other.txt", "nothing to see"),
+        ];
+        let corpus = Corpus::new(files, ChunkingConfig::default());
+        let paths = |query: &str| -> Vec<String> {
+            let results = corpus.search(query).unwrap();
+            results.iter().map(|r| corpus.path(r.doc).unwrap().to_string()).collect()
+        };
+
+        assert_eq!(paths("\"quick fox\""), vec!["exact.txt"]);
+        // With slop the closer match ranks first
+        assert_eq!(paths("\"quick fox\"~1"), vec!["exact.txt", "near.txt"]);
+        assert_eq!(paths("\"quick fox\"~10").len(), 3);
+    }
+
+    #[test]
+    fn test_merge_shards() {
+        let whole = Corpus::new(
+            vec![
+                Document::new("a.txt", "rust borrow checker"),
+                Document::new("b.txt", "rust garbage"),
+                Document::new("c.txt", "python garbage collector"),
+            ],
+            ChunkingConfig::default(),
+        );
+        let first = Corpus::new(vec![Document::new("a.txt", "rust borrow checker")], ChunkingConfig::default());
+        let second = Corpus::new(
+            vec![Document::new("b.txt", "rust garbage"), Document::new("c.txt", "python garbage collector")],
+            ChunkingConfig::default(),
+        );
+
+        let merged = Corpus::merge(&[&first, &second]).unwrap();
+        assert_eq!(merged.index().doc_freq("rust"), 2);
+        assert_eq!(merged.path(merged.chunk(ChunkId(2)).unwrap().doc), Some("c.txt"));
+        // Scores over the merged corpus are the same as if it had been built in one go
+        let scores = |corpus: &Corpus| -> Vec<(String, Score)> {
+            corpus.search("garbage rust").unwrap().iter().map(|r| (corpus.path(r.doc).unwrap().to_string(), r.score)).collect()
+        };
+        assert_eq!(scores(&merged), scores(&whole));
+        assert!(Corpus::merge(&[&first, &first]).is_err());
+    }
+
+    #[test]
+    fn test_sample_and_split_are_reproducible() {
+        let documents = (0..20).map(|i| Document::new(&format!("{}.txt", i), "rust")).collect();
+        let corpus = Corpus::new(documents, ChunkingConfig::default());
+        let paths = |corpus: &Corpus| -> Vec<String> { corpus.documents().iter().map(|d| d.path.clone()).collect() };
+
+        let (train, test) = corpus.split(0.75, 7).unwrap();
+        assert_eq!((train.documents().len(), test.documents().len()), (15, 5));
+        assert!(paths(&test).iter().all(|path| train.doc_id(path).is_none()));
+        assert_eq!(paths(&corpus.split(0.75, 7).unwrap().1), paths(&test));
+        assert_ne!(paths(&corpus.split(0.75, 8).unwrap().1), paths(&test));
+
+        assert_eq!(corpus.sample(0.1, 7).unwrap().documents().len(), 2);
+        assert!(corpus.sample(1.5, 7).is_err());
+    }
+
+    #[test]
+    fn test_boost_and_recency() {
+        let now = SystemTime::now();
+        let old = Document { modified: Some(now - Duration::from_secs(10 * 86_400)), ..Document::new("old.txt", "rust borrow") };
+        let new = Document { modified: Some(now), ..Document::new("new.txt", "rust borrow") };
+        let mut corpus = Corpus::new(vec![old, new, Document::new("other.txt", "python")], ChunkingConfig::default());
+        let top = |corpus: &Corpus| corpus.path(corpus.search("rust").unwrap()[0].doc).unwrap().to_string();
+        let score = corpus.search("rust").unwrap()[0].score;
+
+        // Equal matches, the boost decides
+        assert!(corpus.set_boost(DocId(0), 2.0));
+        assert_eq!(top(&corpus), "old.txt");
+        assert!((corpus.search("rust").unwrap()[0].score - 2.0 * score).abs() < 1e-6);
+
+        // Ten days old with a ten day half-life: 2.0 * 0.5 is 1.0, tied with new.txt again
+        corpus.set_recency(Some(Recency { half_life: Duration::from_secs(10 * 86_400), reference: now }));
+        assert!((corpus.doc_boost(DocId(0)) - 1.0).abs() < 1e-6);
+        assert_eq!(corpus.doc_boost(DocId(1)), 1.0);
+        assert!(!corpus.set_boost(DocId(9), 2.0));
+    }
+
+    #[test]
+    fn test_exclusion_filters() {
+        let corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "rust borrow"))
+            .add_document(Document::new("draft.txt", "rust draft"))
+            .add_document(Document::new("b.txt", "rust rust python"))
+            .add_document(Document::new("c.txt", "python"))
+            .exclude_if(|doc| doc.path.starts_with("draft"))
+            .build()
+            .unwrap();
+        assert_eq!(corpus.documents().len(), 3);
+        assert!(corpus.doc_id("draft.txt").is_none());
+
+        let scorer = TfIdfScorer::new(&corpus, TfIdfParams::default());
+        let seen: HashSet<DocId> = [corpus.doc_id("b.txt").unwrap()].into_iter().collect();
+        let results = corpus.search_excluding("rust", &scorer, &seen).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(corpus.path(results[0].doc), Some("a.txt"));
+    }
+
+    #[test]
+    fn test_text_transforms_run_before_chunking() {
+        let corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "mail alice@example.com about rust"))
+            .add_document(Document::new("b.txt", "nothing to redact"))
+            .transform(|text| {
+                let words: Vec<&str> = text.split(' ').collect();
+                if words.iter().any(|word| word.contains('@')) {
+                    Cow::Owned(words.iter().map(|w| if w.contains('@') { "[email]" } else { w }).collect::<Vec<_>>().join(" "))
+                } else {
+                    Cow::Borrowed(text)
+                }
+            })
+            .add_document(Document::new("c.txt", "  padded  "))
+            .transform(|text| Cow::Owned(text.replace("rust", "Rust")))
+            .transform(|text| Cow::Borrowed(text.trim()))
+            .build()
+            .unwrap();
+        assert_eq!(&*corpus.documents()[0].text, "mail [email] about Rust");
+        assert_eq!(&*corpus.documents()[2].text, "padded");
+        assert!(corpus.search_chunks("alice").is_empty());
+        assert_eq!(corpus.search("[email]").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_search_options_thresholds() {
+        let corpus = Corpus::new(
+            vec![
+                Document::new("all.txt", "rust borrow checker"),
+                Document::new("two.txt", "rust borrow"),
+                Document::new("one.txt", "rust"),
+                Document::new("none.txt", "python"),
+            ],
+            ChunkingConfig::default(),
+        );
+        let scorer = Bm25Scorer { corpus: &corpus, params: Bm25Params::default() };
+        let paths = |options: SearchOptions| -> Vec<String> {
+            let results = corpus.search_with_options("rust borrow checker", &scorer, &options).unwrap();
+            results.iter().map(|r| corpus.path(r.doc).unwrap().to_string()).collect()
+        };
+        assert_eq!(paths(SearchOptions::default()).len(), 3);
+        assert_eq!(paths(SearchOptions { minimum_should_match: Some(2), ..Default::default() }), ["all.txt", "two.txt"]);
+        assert_eq!(paths(SearchOptions { minimum_should_match: Some(5), ..Default::default() }), ["all.txt"]);
+        let weakest = corpus.search_with("rust borrow checker", &scorer).unwrap()[2].score;
+        assert_eq!(paths(SearchOptions { min_score: Some(weakest + 1e-3), ..Default::default() }).len(), 2);
+    }
+
+    #[test]
+    fn test_time_budget_truncates() {
+        let corpus = Corpus::new(
+            vec![Document::new("a.txt", "rust borrow"), Document::new("b.txt", "python")],
+            ChunkingConfig::default(),
+        );
+        let scorer = TfIdfScorer::new(&corpus, TfIdfParams::default());
+        let search = |budget: u64| {
+            let options = SearchOptions { time_budget: Some(Duration::from_secs(budget)), ..Default::default() };
+            corpus.search_timed("rust python", &scorer, &options).unwrap()
+        };
+        // A zero budget has run out before the first term is looked up
+        let expired = search(0);
+        assert!(expired.truncated);
+        assert!(expired.results.is_empty());
+        let complete = search(60);
+        assert!(!complete.truncated);
+        assert_eq!(complete.results.len(), 2);
+        assert!(complete.unknown_terms.is_empty());
+
+        // Terms the index has never seen are reported, negated ones don't matter
+        let options = SearchOptions::default();
+        let typo = corpus.search_timed("rust borow borow -java", &scorer, &options).unwrap();
+        assert_eq!((typo.results.len(), typo.unknown_terms.as_slice()), (1, ["borow".to_string()].as_slice()));
+        assert_eq!(corpus.unknown_terms("\"pyton\" OR rust").unwrap(), ["pyton"]);
+    }
+
+    #[test]
+    fn test_count_matches_search() {
+        let corpus = Corpus::new(
+            vec![
+                Document::new("a.txt", "rust borrow checker"),
+                Document::new("b.txt", "the checker of borrow rules"),
+                Document::new("c.txt", "python garbage"),
+            ],
+            ChunkingConfig::default(),
+        );
+        for query in ["rust python", "borrow AND checker", "borrow -rust", "\"borrow checker\"", "\"borrow checker\"~3", "-rust", "missing"] {
+            let searched = corpus.search(query).unwrap().len();
+            assert_eq!(corpus.count(query).unwrap(), searched, "{}", query);
+        }
+        // A term in every chunk is counted, but TF-IDF scores it 0 and search leaves it out
+        let everywhere = Corpus::new(vec![Document::new("a.txt", "rust"), Document::new("b.txt", "rust code")], ChunkingConfig::default());
+        assert_eq!((everywhere.count("rust").unwrap(), everywhere.search("rust").unwrap().len()), (2, 0));
+        let bm25 = Bm25Scorer { corpus: &everywhere, params: Bm25Params::default() };
+        assert_eq!(everywhere.search_with("rust", &bm25).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_index_without_stored_text() {
+        let dir = std::env::temp_dir().join(format!("corpus_store_text_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "first chunk about rust. second chunk about borrowing").unwrap();
+        let corpus = Corpus::builder()
+            .add_dir(&dir)
+            .add_document(Document::new("inline.txt", "inline rust"))
+            .chunking(ChunkingConfig::new(ChunkStrategy::Words, 4, 0).unwrap())
+            .index_options(IndexOptions { store_text: false, ..IndexOptions::default() })
+            .build()
+            .unwrap();
+
+        // File text is gone from memory, the inline document can't be re-read so it keeps its text
+        assert!(corpus.chunks().iter().filter(|c| c.doc == DocId(0)).all(|c| c.text.is_empty()));
+        assert_eq!(&*corpus.document(DocId(1)).unwrap().text, "inline rust");
+        let results = corpus.search("borrowing").unwrap();
+        assert_eq!(corpus.chunk_text(results[0].chunk.unwrap()).unwrap(), "second chunk about borrowing");
+        assert_eq!(results[0].highlights, ["second chunk about borrowing"]);
+        assert!(corpus.document_text(DocId(0)).unwrap().starts_with("first chunk"));
+
+        // Fields aren't kept as text either, only their terms, which still score
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "// parse the request\nfn handle() {}").unwrap();
+        let code = Corpus::builder()
+            .add_dir(dir.join("src"))
+            .extensions(["rs"])
+            .index_options(IndexOptions { store_text: false, code_fields: true, ..IndexOptions::default() })
+            .build()
+            .unwrap();
+        let document = code.document(DocId(0)).unwrap();
+        assert!(document.fields.is_empty());
+        assert!(document.field_terms[DOCS].contains(&("request".to_string(), 1)));
+        assert!(!serde_json::to_string(&code).unwrap().contains("parse the request"));
+        let scores = code.field_index().bm25f(&["request".to_string()], &"docs=1".parse().unwrap(), &Bm25Params::default());
+        assert!(scores.contains_key(&DocId(0)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_within_selection() {
+        let corpus = Corpus::builder()
+            .add_document(Document::new("lib.rs", "fn parse rust\nfn lex rust rust\nfn emit\nfn check rust"))
+            .add_document(Document::new("other.rs", "rust rust rust"))
+            .chunking(ChunkingConfig::new(ChunkStrategy::Lines, 1, 0).unwrap())
+            .build()
+            .unwrap();
+        let scorer = TfIdfScorer::new(&corpus, TfIdfParams::default());
+        let lib = corpus.doc_id("lib.rs").unwrap();
+        let selection = corpus.select_lines(lib, 2..=3).unwrap();
+        assert_eq!(selection.bytes, 14..39);
+        let options = SearchOptions { selection: Some(selection), ..Default::default() };
+        let results = corpus.search_timed("rust", &scorer, &options).unwrap().results;
+        assert_eq!(results.len(), 1);
+        assert_eq!(corpus.chunk(results[0].chunk.unwrap()).unwrap().index, 1);
+        assert_eq!(results[0].spans, [7..11, 12..16]);
+
+        // A selection that ends halfway through a chunk keeps the chunk, but not the matches after its end
+        let options = SearchOptions { selection: Some(Selection { doc: lib, bytes: 14..25 }), ..Default::default() };
+        assert_eq!(corpus.search_timed("rust", &scorer, &options).unwrap().results[0].spans, vec![7..11]);
+        // The chunk overlaps a selection of "fn lex", but its matches are all after it
+        let options = SearchOptions { selection: Some(Selection { doc: lib, bytes: 14..20 }), ..Default::default() };
+        assert!(corpus.search_timed("rust", &scorer, &options).unwrap().results.is_empty());
+        assert_eq!(corpus.select_lines(lib, 4..=9).unwrap().bytes, 39..52);
+        assert_eq!(corpus.select_lines(lib, 4..=usize::MAX).unwrap().bytes, 39..52);
+        assert!(corpus.select_lines(lib, 0..=1).is_err());
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use crate::corpus::{ChunkId, Corpus};
+use crate::query::{QueryError, TermScorer};
+
+/// Relevance judgments: query id -> result key -> graded relevance (0 = not relevant)
+// BTreeMap instead of HashMap so the written file comes out sorted and diffs cleanly
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Qrels {
+    judgments: BTreeMap<String, BTreeMap<String, u32>>,
+}
+
+impl Qrels {
+    /// Parse the TREC qrels format: `query_id iteration result_key relevance`, one judgment per line
+    /// The result key may contain spaces, e.g. a path, it is everything between iteration and relevance
+    pub fn parse(text: &str) -> Result<Qrels, Box<dyn Error>> {
+        let mut qrels = Qrels::default();
+        for (number, line) in text.lines().enumerate() {
+            match trec_fields(line).as_deref() {
+                Some([]) => continue,
+                Some([query, _iteration, key, relevance]) => {
+                    let relevance = relevance
+                        .parse()
+                        .map_err(|_| format!("line {}: relevance {:?} is not a number", number + 1, relevance))?;
+                    qrels.judge(query, key, relevance);
+                }
+                _ => return Err(format!("line {}: expected 4 fields", number + 1).into()),
+            }
+        }
+        Ok(qrels)
+    }
+
+    /// Load a qrels file, a missing file is an empty set of judgments
+    pub fn load(path: &Path) -> Result<Qrels, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Qrels::default());
+        }
+        Qrels::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        Ok(fs::write(path, self.to_trec())?)
+    }
+
+    pub fn to_trec(&self) -> String {
+        let mut out = String::new();
+        for (query, judgments) in &self.judgments {
+            for (key, relevance) in judgments {
+                // The iteration column is unused by every tool that reads qrels, TREC convention is 0
+                out.push_str(&format!("{} 0 {} {}\n", query, key, relevance));
+            }
+        }
+        out
+    }
+
+    pub fn judge(&mut self, query: &str, key: &str, relevance: u32) {
+        self.judgments.entry(query.to_string()).or_default().insert(key.to_string(), relevance);
+    }
+
+    /// The judged relevance, None if this result hasn't been judged for the query
+    pub fn relevance(&self, query: &str, key: &str) -> Option<u32> {
+        self.judgments.get(query)?.get(key).copied()
+    }
+
+    /// Every judgment for one query
+    pub fn judgments(&self, query: &str) -> Option<&BTreeMap<String, u32>> {
+        self.judgments.get(query)
+    }
+}
+
+// The four fields of a qrels line, an empty slice for a blank line. The result key is everything
+// between the second field and the last, so a path with spaces in it reads back as written
+fn trec_fields(line: &str) -> Option<Vec<&str>> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Some(Vec::new());
+    }
+    let (query, rest) = line.split_once(char::is_whitespace)?;
+    let (second, rest) = rest.trim_start().split_once(char::is_whitespace)?;
+    let (key, relevance) = rest.trim().rsplit_once(char::is_whitespace)?;
+    Some(vec![query, second, key.trim_end(), relevance])
+}
+
+/// Subtopic judgments for measuring diversity: query id -> result key -> the subtopics it covers
+// A query like "jaguar" has several meanings or aspects, ten results about the car answer it
+// worse than results that cover the car, the animal and the OS, however relevant each one is
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubtopicQrels {
+    judgments: BTreeMap<String, BTreeMap<String, BTreeSet<String>>>,
+    /// Every subtopic of a query, also those no judged result covers
+    subtopics: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SubtopicQrels {
+    /// Parse the TREC diversity qrels format: `query_id subtopic result_key relevance`. A result
+    /// covers the subtopic when relevance is above 0
+    pub fn parse(text: &str) -> Result<SubtopicQrels, Box<dyn Error>> {
+        let mut qrels = SubtopicQrels::default();
+        for (number, line) in text.lines().enumerate() {
+            match trec_fields(line).as_deref() {
+                Some([]) => continue,
+                Some([query, subtopic, key, relevance]) => {
+                    let relevance: u32 = relevance
+                        .parse()
+                        .map_err(|_| format!("line {}: relevance {:?} is not a number", number + 1, relevance))?;
+                    qrels.subtopics.entry(query.to_string()).or_default().insert(subtopic.to_string());
+                    let covered = qrels.judgments.entry(query.to_string()).or_default().entry(key.to_string()).or_default();
+                    if relevance > 0 {
+                        covered.insert(subtopic.to_string());
+                    }
+                }
+                _ => return Err(format!("line {}: expected 4 fields", number + 1).into()),
+            }
+        }
+        Ok(qrels)
+    }
+
+    pub fn load(path: &Path) -> Result<SubtopicQrels, Box<dyn Error>> {
+        SubtopicQrels::parse(&fs::read_to_string(path)?)
+    }
+
+    /// The subtopics a result covers, empty if it wasn't judged or covers none
+    pub fn covered(&self, query: &str, key: &str) -> BTreeSet<String> {
+        self.judgments.get(query).and_then(|judgments| judgments.get(key)).cloned().unwrap_or_default()
+    }
+
+    /// The number of subtopics of a query, 0 if it has no subtopic judgments
+    pub fn count(&self, query: &str) -> usize {
+        self.subtopics.get(query).map_or(0, BTreeSet::len)
+    }
+
+    /// What every judged result of a query covers
+    pub fn judged(&self, query: &str) -> Vec<BTreeSet<String>> {
+        self.judgments.get(query).map(|judgments| judgments.values().cloned().collect()).unwrap_or_default()
+    }
+}
+
+/// Read a query set, one query per line as `id<TAB>query text`
+/// Lines without a tab get their line number as id, blank lines and lines starting with # are skipped
+pub fn load_queries(path: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let queries = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| match line.split_once('\t') {
+            Some((id, query)) => (id.trim().to_string(), query.trim().to_string()),
+            None => ((number + 1).to_string(), line.trim().to_string()),
+        })
+        .collect();
+    Ok(queries)
+}
+
+/// The key a chunk is judged under, `path#chunk index`
+// Chunk ids are renumbered when a corpus is rebuilt, path and position in the file are not,
+// so judgments stay valid as long as the files and the chunking settings don't change
+pub fn result_key(corpus: &Corpus, id: ChunkId) -> Option<String> {
+    let chunk = corpus.chunk(id)?;
+    Some(format!("{}#{}", corpus.path(chunk.doc)?, chunk.index))
+}
+
+/// Standard ranking metrics averaged over a query set, all computed on the top k results
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Metrics {
+    pub ndcg: f32,
+    pub map: f32,
+    pub precision: f32,
+    pub mrr: f32,
+    /// Number of queries that had judgments and were averaged over
+    pub queries: usize,
+}
+
+// All metric functions take the judged relevance of every ranked result in order,
+// unjudged results count as 0 (not relevant), as trec_eval does
+
+/// Fraction of the first k results that are relevant
+pub fn precision_at(relevances: &[u32], k: usize) -> f32 {
+    if k == 0 {
+        return 0.0;
+    }
+    relevances.iter().take(k).filter(|r| **r > 0).count() as f32 / k as f32
+}
+
+/// 1 / rank of the first relevant result, 0 if there is none
+pub fn reciprocal_rank(relevances: &[u32]) -> f32 {
+    relevances.iter().position(|r| *r > 0).map(|i| 1.0 / (i + 1) as f32).unwrap_or(0.0)
+}
+
+/// Mean of the precision at every relevant result, relevant documents never retrieved count as 0
+pub fn average_precision(relevances: &[u32], total_relevant: usize) -> f32 {
+    if total_relevant == 0 {
+        return 0.0;
+    }
+    let mut found = 0;
+    let mut sum = 0.0;
+    for (i, relevance) in relevances.iter().enumerate() {
+        if *relevance > 0 {
+            found += 1;
+            sum += found as f32 / (i + 1) as f32;
+        }
+    }
+    sum / total_relevant as f32
+}
+
+/// Discounted cumulative gain of the ranking divided by that of the best possible ranking
+// Gains are graded (2^rel - 1), so a highly relevant result at rank 1 beats a marginal one
+pub fn ndcg_at(relevances: &[u32], judged: &[u32], k: usize) -> f32 {
+    let dcg = |rels: &[u32]| -> f32 {
+        rels.iter()
+            .take(k)
+            .enumerate()
+            .map(|(i, r)| (2f32.powi(*r as i32) - 1.0) / (i as f32 + 2.0).log2())
+            .sum()
+    };
+    let mut ideal = judged.to_vec();
+    ideal.sort_unstable_by(|a, b| b.cmp(a));
+    let ideal_dcg = dcg(&ideal);
+    if ideal_dcg == 0.0 { 0.0 } else { dcg(relevances) / ideal_dcg }
+}
+
+// alpha-NDCG (Clarke et al., 2008) counts a subtopic less every time it comes up again: a result
+// gains 1 for every subtopic it covers, times (1 - alpha) for every result above it that covered
+// the same subtopic. alpha 0 is plain NDCG with binary relevance, alpha 1 only rewards what's new
+fn alpha_dcg(coverage: &[BTreeSet<String>], alpha: f32, k: usize) -> f32 {
+    let mut seen: HashMap<&str, i32> = HashMap::new();
+    let mut dcg = 0.0;
+    for (i, covered) in coverage.iter().take(k).enumerate() {
+        let mut gain = 0.0;
+        for subtopic in covered {
+            let times = seen.entry(subtopic).or_default();
+            gain += (1.0 - alpha).powi(*times);
+            *times += 1;
+        }
+        dcg += gain / (i as f32 + 2.0).log2();
+    }
+    dcg
+}
+
+/// alpha-NDCG of a ranking, given the subtopics each ranked result covers and those of every judged result
+// The best ranking is NP-hard to find, it's approximated greedily as usual: at every rank, the
+// judged result that adds the most gain
+pub fn alpha_ndcg_at(coverage: &[BTreeSet<String>], judged: &[BTreeSet<String>], alpha: f32, k: usize) -> f32 {
+    let mut left: Vec<&BTreeSet<String>> = judged.iter().collect();
+    let mut ideal: Vec<BTreeSet<String>> = Vec::new();
+    while ideal.len() < k {
+        let gain = |candidate: &BTreeSet<String>| {
+            let seen = |subtopic| ideal.iter().filter(|covered| covered.contains(subtopic)).count() as i32;
+            candidate.iter().map(|subtopic| (1.0 - alpha).powi(seen(subtopic))).sum::<f32>()
+        };
+        let Some((best, _)) = left.iter().enumerate().map(|(i, candidate)| (i, gain(candidate))).max_by(|a, b| a.1.total_cmp(&b.1)) else {
+            break;
+        };
+        ideal.push(left.swap_remove(best).clone());
+    }
+    let ideal_dcg = alpha_dcg(&ideal, alpha, k);
+    if ideal_dcg == 0.0 { 0.0 } else { alpha_dcg(coverage, alpha, k) / ideal_dcg }
+}
+
+/// Fraction of a query's subtopics covered by the first k results
+pub fn subtopic_recall_at(coverage: &[BTreeSet<String>], subtopics: usize, k: usize) -> f32 {
+    if subtopics == 0 {
+        return 0.0;
+    }
+    let covered: BTreeSet<&String> = coverage.iter().take(k).flatten().collect();
+    covered.len() as f32 / subtopics as f32
+}
+
+/// Diversity metrics averaged over a query set, computed on the top k results
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Diversity {
+    pub alpha_ndcg: f32,
+    pub subtopic_recall: f32,
+    /// Number of queries that had subtopic judgments and were averaged over
+    pub queries: usize,
+}
+
+/// Run every query with subtopic judgments and average alpha-NDCG and subtopic recall over the top k
+pub fn evaluate_diversity(
+    corpus: &Corpus,
+    queries: &[(String, String)],
+    qrels: &SubtopicQrels,
+    scorer: &dyn TermScorer,
+    k: usize,
+    alpha: f32,
+) -> Result<Diversity, QueryError> {
+    let mut total = Diversity::default();
+    for (id, query) in queries {
+        let subtopics = qrels.count(id);
+        if subtopics == 0 {
+            continue;
+        }
+        let coverage: Vec<BTreeSet<String>> = corpus
+            .rank_with(query, scorer)?
+            .into_iter()
+            .take(k)
+            .map(|(chunk, _)| result_key(corpus, chunk).map(|key| qrels.covered(id, &key)).unwrap_or_default())
+            .collect();
+        total.alpha_ndcg += alpha_ndcg_at(&coverage, &qrels.judged(id), alpha, k);
+        total.subtopic_recall += subtopic_recall_at(&coverage, subtopics, k);
+        total.queries += 1;
+    }
+    if total.queries > 0 {
+        total.alpha_ndcg /= total.queries as f32;
+        total.subtopic_recall /= total.queries as f32;
+    }
+    Ok(total)
+}
+
+/// Run every judged query with the scorer and average the metrics over the top k results
+pub fn evaluate(
+    corpus: &Corpus,
+    queries: &[(String, String)],
+    qrels: &Qrels,
+    scorer: &dyn TermScorer,
+    k: usize,
+) -> Result<Metrics, QueryError> {
+    let mut total = Metrics::default();
+    for (id, query) in queries {
+        // Queries nobody judged would only drag every configuration towards 0 equally
+        let Some(judgments) = qrels.judgments(id) else {
+            continue;
+        };
+        let relevances: Vec<u32> = corpus
+            .rank_with(query, scorer)?
+            .into_iter()
+            .take(k)
+            .map(|(chunk, _)| result_key(corpus, chunk).and_then(|key| judgments.get(&key).copied()).unwrap_or(0))
+            .collect();
+        let judged: Vec<u32> = judgments.values().copied().collect();
+
+        total.ndcg += ndcg_at(&relevances, &judged, k);
+        total.map += average_precision(&relevances, judged.iter().filter(|r| **r > 0).count());
+        total.precision += precision_at(&relevances, k);
+        total.mrr += reciprocal_rank(&relevances);
+        total.queries += 1;
+    }
+    if total.queries > 0 {
+        let n = total.queries as f32;
+        total.ndcg /= n;
+        total.map /= n;
+        total.precision /= n;
+        total.mrr /= n;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qrels_round_trip() {
+        let mut qrels = Qrels::parse("q1 0 a.txt#0 2\n\nq1 0 b.txt#3 0\n").unwrap();
+        qrels.judge("q2", "c.txt#1", 1);
+
+        assert_eq!(qrels.relevance("q1", "a.txt#0"), Some(2));
+        assert_eq!(qrels.relevance("q1", "c.txt#1"), None);
+        assert_eq!(Qrels::parse(&qrels.to_trec()).unwrap(), qrels);
+        // Paths with spaces in them read back as written
+        qrels.judge("q3", "My Documents/notes  2024.txt#0", 1);
+        assert_eq!(Qrels::parse(&qrels.to_trec()).unwrap(), qrels);
+        assert!(Qrels::parse("q1 0 a.txt#0").is_err());
+        assert!(Qrels::parse("q1 0 a.txt#0 yes").is_err());
+    }
+
+    #[test]
+    fn test_metrics() {
+        let ranking = [0, 2, 0, 1];
+        assert_eq!(precision_at(&ranking, 4), 0.5);
+        assert_eq!(reciprocal_rank(&ranking), 0.5);
+        // Relevant at ranks 2 and 4, a third relevant document was never found
+        assert_eq!(average_precision(&ranking, 3), (1.0 / 2.0 + 2.0 / 4.0) / 3.0);
+        assert_eq!(ndcg_at(&[2, 1], &[1, 2], 10), 1.0);
+        assert!(ndcg_at(&[1, 2], &[1, 2], 10) < 1.0);
+        assert_eq!(ndcg_at(&[0, 0], &[0], 10), 0.0);
+    }
+
+    #[test]
+    fn test_diversity_metrics() {
+        let qrels = SubtopicQrels::parse("q1 1 car.txt#0 1\nq1 1 car.txt#1 1\nq1 2 cat.txt#0 1\nq1 3 os.txt#0 0\n").unwrap();
+        assert_eq!(qrels.count("q1"), 3);
+        assert_eq!(qrels.covered("q1", "car.txt#1").len(), 1);
+        let ranking = |keys: &[&str]| -> Vec<BTreeSet<String>> { keys.iter().map(|key| qrels.covered("q1", key)).collect() };
+        let redundant = ranking(&["car.txt#0", "car.txt#1", "cat.txt#0"]);
+        let diverse = ranking(&["car.txt#0", "cat.txt#0", "car.txt#1"]);
+        let judged = qrels.judged("q1");
+
+        // The same results, plain NDCG can't tell them apart but alpha-NDCG can
+        assert_eq!(alpha_ndcg_at(&diverse, &judged, 0.5, 10), 1.0);
+        assert!(alpha_ndcg_at(&redundant, &judged, 0.5, 10) < 1.0);
+        assert_eq!(alpha_ndcg_at(&redundant, &judged, 0.0, 10), 1.0);
+        // The OS subtopic is never covered
+        assert_eq!(subtopic_recall_at(&diverse, 3, 2), 2.0 / 3.0);
+        assert_eq!(subtopic_recall_at(&redundant, 3, 2), 1.0 / 3.0);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::bm25::{idf_bm25, Bm25Params, Bm25Scorer};
+use crate::corpus::{ChunkId, Corpus};
+use crate::query::{parse_query, QueryError, TermScorer};
+use crate::search::Score;
+use crate::tfidf::{IdfScheme, TfIdfParams, TfIdfScorer};
+
+/// How one query term contributes to a chunk's score under both rankers
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermExplanation {
+    pub term: String,
+    /// Occurrences of the term in the chunk
+    pub tf: u32,
+    /// Chunks containing the term
+    pub df: usize,
+    /// tf / chunk length, TF-IDF's term frequency
+    pub tfidf_tf: Score,
+    pub tfidf_idf: Score,
+    pub tfidf: Score,
+    /// tf * (k1 + 1) / (tf + k1 * length_norm), BM25's saturating term frequency
+    pub bm25_tf: Score,
+    pub bm25_idf: Score,
+    pub bm25: Score,
+}
+
+/// Why TF-IDF and BM25 put one chunk at different ranks for a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct RankDiff {
+    pub chunk: ChunkId,
+    /// 1-based rank, None if the chunk doesn't match the query at all
+    pub tfidf_rank: Option<usize>,
+    pub bm25_rank: Option<usize>,
+    pub chunk_len: u32,
+    pub avg_len: Score,
+    /// 1 - b + b * chunk_len / avg_len, above 1 BM25 penalizes the chunk for being long
+    pub length_norm: Score,
+    pub params: Bm25Params,
+    pub terms: Vec<TermExplanation>,
+}
+
+impl RankDiff {
+    pub fn tfidf_score(&self) -> Score {
+        self.terms.iter().map(|t| t.tfidf).sum()
+    }
+
+    pub fn bm25_score(&self) -> Score {
+        self.terms.iter().map(|t| t.bm25).sum()
+    }
+
+    /// Plain-text explanation for the terminal
+    pub fn render(&self) -> String {
+        let rank = |r: Option<usize>| r.map(|r| format!("#{}", r)).unwrap_or_else(|| "unranked".to_string());
+        let mut out = format!(
+            "chunk {}: TF-IDF {} ({:.4}), BM25 {} ({:.4})\n",
+            self.chunk,
+            rank(self.tfidf_rank),
+            self.tfidf_score(),
+            rank(self.bm25_rank),
+            self.bm25_score()
+        );
+        let verdict = match (self.tfidf_rank, self.bm25_rank) {
+            (Some(t), Some(b)) if b < t => "BM25 ranks it higher",
+            (Some(t), Some(b)) if b > t => "BM25 ranks it lower",
+            (Some(_), Some(_)) => "both rank it the same",
+            _ => "it doesn't match the query",
+        };
+        out.push_str(&format!("{}\n\n", verdict));
+
+        for t in &self.terms {
+            out.push_str(&format!("  {}  tf {}  df {}\n", t.term, t.tf, t.df));
+            out.push_str(&format!(
+                "    TF-IDF  {:.4} = tf/len {:.4} x idf {:.4}\n",
+                t.tfidf, t.tfidf_tf, t.tfidf_idf
+            ));
+            out.push_str(&format!("    BM25    {:.4} = tf' {:.4} x idf {:.4}\n", t.bm25, t.bm25_tf, t.bm25_idf));
+            if t.tf > 1 {
+                // A single occurrence gives the baseline both formulas are compared against
+                let single = (self.params.k1 + 1.0) / (1.0 + self.params.k1 * self.length_norm);
+                out.push_str(&format!(
+                    "    saturation: {} occurrences count {}x in TF-IDF but only {:.2}x in BM25\n",
+                    t.tf,
+                    t.tf,
+                    t.bm25_tf / single
+                ));
+            }
+        }
+
+        let relative = self.chunk_len as Score / self.avg_len;
+        out.push_str(&format!(
+            "\nlength: {} terms, {:.2}x the average of {:.1}\n",
+            self.chunk_len, relative, self.avg_len
+        ));
+        out.push_str("  TF-IDF divides tf by the full length, BM25 only scales k1 by ");
+        out.push_str(&format!("{:.2}", self.length_norm));
+        out.push_str(if self.length_norm > 1.0 {
+            ", a milder penalty for being long\n"
+        } else if self.length_norm < 1.0 {
+            ", a milder boost for being short\n"
+        } else {
+            ", no effect at average length\n"
+        });
+        out
+    }
+}
+
+/// Compare the TF-IDF and BM25 rank of a chunk for a query, term by term
+// Both rankers use their default settings, the same ones `search --mode tfidf` and `--mode bm25` use
+pub fn explain_rank_diff(corpus: &Corpus, query: &str, chunk: ChunkId) -> Result<RankDiff, QueryError> {
+    let tfidf = TfIdfScorer::new(corpus, TfIdfParams::default());
+    let params = Bm25Params::default();
+    let bm25 = Bm25Scorer { corpus, params };
+    let rank_of = |scorer: &dyn TermScorer| -> Result<Option<usize>, QueryError> {
+        Ok(corpus.rank_with(query, scorer)?.iter().position(|(id, _)| *id == chunk).map(|i| i + 1))
+    };
+    let tfidf_rank = rank_of(&tfidf)?;
+    let bm25_rank = rank_of(&bm25)?;
+
+    let index = corpus.index();
+    let chunk_len = index.chunk_len(chunk);
+    let avg_len = index.avg_len();
+    let length_norm = 1.0 - params.b + params.b * chunk_len as Score / avg_len;
+
+    let mut terms = parse_query(query)?.positive_terms(&tfidf);
+    terms.sort();
+    terms.dedup();
+    let terms = terms
+        .into_iter()
+        .filter_map(|term| {
+            let tf = index.positions(&term, chunk).len() as u32;
+            if tf == 0 {
+                return None;
+            }
+            let df = index.doc_freq(&term);
+            let tfidf_tf = tf as Score / chunk_len as Score;
+            let tfidf_idf = IdfScheme::Plain.idf(index.num_chunks(), df);
+            let bm25_tf = tf as Score * (params.k1 + 1.0) / (tf as Score + params.k1 * length_norm);
+            let bm25_idf = idf_bm25(index.num_chunks(), df);
+            Some(TermExplanation {
+                term,
+                tf,
+                df,
+                tfidf_tf,
+                tfidf_idf,
+                tfidf: tfidf_tf * tfidf_idf,
+                bm25_tf,
+                bm25_idf,
+                bm25: bm25_tf * bm25_idf,
+            })
+        })
+        .collect();
+
+    Ok(RankDiff { chunk, tfidf_rank, bm25_rank, chunk_len, avg_len, length_norm, params, terms })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_explain_rank_diff() {
+        let files = vec![
+            Document::new("short.txt", "rust"),
+            Document::new("repeated.txt", "rust rust rust rust rust rust and some more words here"),
+            Document::new("other.txt", "python"),
+            Document::new("more.txt", "go"),
+        ];
+        let corpus = Corpus::new(files, ChunkingConfig::default());
+
+        let diff = explain_rank_diff(&corpus, "rust", ChunkId(1)).unwrap();
+        assert_eq!(diff.terms.len(), 1);
+        assert_eq!(diff.terms[0].tf, 6);
+        let indexed = crate::bm25::term_scores_bm25("rust", corpus.index(), &Bm25Params::default());
+        assert!((diff.bm25_score() - indexed[1].1).abs() < 1e-6);
+        let text = diff.render();
+        assert!(text.contains("saturation: 6 occurrences"));
+        assert!(text.contains("length: 11 terms"));
+
+        let unmatched = explain_rank_diff(&corpus, "rust", ChunkId(2)).unwrap();
+        assert_eq!(unmatched.tfidf_rank, None);
+        assert!(unmatched.terms.is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use arrow::array::{ArrayRef, Float32Array, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use crate::corpus::{ChunkId, Corpus};
+use crate::eval::result_key;
+use crate::search::{to_f32, Score, SearchResult};
+use crate::similarity::Edge;
+
+// Columnar files load straight into pandas, polars or DuckDB with the right types,
+// without the quoting and float parsing problems of CSV
+
+/// Results of a query set as one table: query, rank, path, chunk_index, line, score
+/// Rank is 1-based, chunk_index is null for line search and line is null for chunk search
+pub fn results_batch(corpus: &Corpus, runs: &[(String, Vec<SearchResult>)]) -> Result<RecordBatch, ArrowError> {
+    let rows = runs.iter().flat_map(|(query, results)| results.iter().enumerate().map(move |(i, r)| (query, i, r)));
+    let (mut queries, mut ranks, mut paths, mut chunk_indexes, mut lines, mut scores) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (query, position, result) in rows {
+        queries.push(query.as_str());
+        ranks.push(position as u32 + 1);
+        paths.push(corpus.path(result.doc).unwrap_or_default());
+        chunk_indexes.push(result.chunk.and_then(|id| corpus.chunk(id)).map(|chunk| chunk.index as u32));
+        lines.push(result.line.map(|line| line as u32));
+        scores.push(to_f32(result.score));
+    }
+    let schema = Schema::new(vec![
+        Field::new("query", DataType::Utf8, false),
+        Field::new("rank", DataType::UInt32, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("chunk_index", DataType::UInt32, true),
+        Field::new("line", DataType::UInt32, true),
+        Field::new("score", DataType::Float32, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(queries)),
+        Arc::new(UInt32Array::from(ranks)),
+        Arc::new(StringArray::from(paths)),
+        Arc::new(UInt32Array::from(chunk_indexes)),
+        Arc::new(UInt32Array::from(lines)),
+        Arc::new(Float32Array::from(scores)),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+/// A sparse chunk-term matrix, e.g. from tfidf::document_term_matrix, as one row per non-zero weight:
+/// chunk_id, path, chunk_index, term, weight
+// Long format instead of one column per term: a real vocabulary has far too many terms for columns,
+// and pivoting on the Python side is one call
+pub fn matrix_batch(corpus: &Corpus, entries: &[(ChunkId, String, Score)]) -> Result<RecordBatch, ArrowError> {
+    let chunk = |id: ChunkId| corpus.chunk(id);
+    // A term in every chunk can weigh 0 under some idf variants; that is no entry of a sparse matrix
+    let entries: Vec<&(ChunkId, String, Score)> = entries.iter().filter(|(_, _, weight)| to_f32(*weight) != 0.0).collect();
+    let schema = Schema::new(vec![
+        Field::new("chunk_id", DataType::UInt32, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("chunk_index", DataType::UInt32, false),
+        Field::new("term", DataType::Utf8, false),
+        Field::new("weight", DataType::Float32, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(entries.iter().map(|(id, _, _)| id.0))),
+        Arc::new(StringArray::from_iter_values(
+            entries.iter().map(|(id, _, _)| chunk(*id).and_then(|c| corpus.path(c.doc)).unwrap_or_default()),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            entries.iter().map(|(id, _, _)| chunk(*id).map(|c| c.index as u32).unwrap_or_default()),
+        )),
+        Arc::new(StringArray::from_iter_values(entries.iter().map(|(_, term, _)| term.as_str()))),
+        Arc::new(Float32Array::from_iter_values(entries.iter().map(|(_, _, weight)| to_f32(*weight)))),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+/// A chunk similarity edge list, e.g. from similarity::similarity_edges, as one row per edge:
+/// source, target (both path#index) and similarity
+pub fn edges_batch(corpus: &Corpus, edges: &[Edge]) -> Result<RecordBatch, ArrowError> {
+    let key = |id: ChunkId| result_key(corpus, id).unwrap_or_default();
+    let schema = Schema::new(vec![
+        Field::new("source", DataType::Utf8, false),
+        Field::new("target", DataType::Utf8, false),
+        Field::new("similarity", DataType::Float32, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(edges.iter().map(|edge| key(edge.source)))),
+        Arc::new(StringArray::from_iter_values(edges.iter().map(|edge| key(edge.target)))),
+        Arc::new(Float32Array::from_iter_values(edges.iter().map(|edge| edge.similarity))),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+/// Write a table as Parquet if the path ends in .parquet, as an Arrow IPC file otherwise
+pub fn write_batch(batch: &RecordBatch, path: &Path) -> Result<(), Box<dyn Error>> {
+    let file = File::create(path)?;
+    if path.extension().is_some_and(|ext| ext == "parquet") {
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), None)?;
+        writer.write(batch)?;
+        writer.close()?;
+    } else {
+        let mut writer = FileWriter::try_new(file, &batch.schema())?;
+        writer.write(batch)?;
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+    use crate::tfidf::{document_term_matrix, TfIdfParams};
+
+    #[test]
+    fn test_export_round_trip() {
+        let corpus = Corpus::new(
+            vec![Document::new("a.txt", "rust borrow rust"), Document::new("b.txt", "python borrow")],
+            ChunkingConfig::default(),
+        );
+        let matrix = matrix_batch(&corpus, &document_term_matrix(&corpus, TfIdfParams::default())).unwrap();
+        // One row per distinct term of every chunk, except "borrow" which is in both and weighs 0
+        assert_eq!(matrix.num_rows(), 2);
+        let runs = vec![("q1".to_string(), corpus.search("rust").unwrap()), ("q2".to_string(), corpus.search_lines("borrow"))];
+        let results = results_batch(&corpus, &runs).unwrap();
+        assert_eq!(results.num_rows(), 3);
+
+        let base = std::env::temp_dir().join(format!("export_{}", std::process::id()));
+        let (ipc, parquet) = (base.with_extension("arrow"), base.with_extension("parquet"));
+        write_batch(&matrix, &ipc).unwrap();
+        write_batch(&results, &parquet).unwrap();
+
+        let read: Vec<RecordBatch> = FileReader::try_new(File::open(&ipc).unwrap(), None).unwrap().map(|b| b.unwrap()).collect();
+        assert_eq!(read, vec![matrix]);
+        let read: Vec<RecordBatch> = ParquetRecordBatchReaderBuilder::try_new(File::open(&parquet).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(|b| b.unwrap())
+            .collect();
+        assert_eq!(read, vec![results]);
+        std::fs::remove_file(ipc).unwrap();
+        std::fs::remove_file(parquet).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use std::error::Error;
+use std::sync::Arc;
+use crate::index::{Index, Snapshot};
+use crate::query::parse_query;
+use crate::search::{Score, SearchResult};
+use crate::shard::{search_with_stats, Scoring};
+use crate::stats::CorpusStats;
+
+// Scores from separate indexes can't simply be merged. Each idf is computed from its own index's
+// N and df: a term that is rare in the docs but everywhere in the code scores high in one and
+// near zero in the other, whatever the hits are worth. There are two ways around that. Rank fusion
+// ignores the scores and merges by rank, 1 / (60 + rank) is the reciprocal rank fusion of Cormack
+// et al., which works for any indexes, even ones with different analyzers. Global statistics
+// score every index with N, df and average length summed over all of them, the scores then are
+// those of one index holding every document, like the shards of a ShardedCorpus. That needs one
+// analyzer for all, a term has to be the same term everywhere for its df to add up
+
+/// The k of reciprocal rank fusion, large enough that the first few ranks of a list don't swamp the rest
+const RRF_K: Score = 60.0;
+
+/// An index to search, with the name its results are attributed to
+#[derive(Clone)]
+pub struct IndexRef {
+    pub name: String,
+    pub index: Index,
+}
+
+impl IndexRef {
+    pub fn new(name: &str, index: Index) -> IndexRef {
+        IndexRef { name: name.to_string(), index }
+    }
+}
+
+/// How scores of different indexes are made comparable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Harmonize {
+    /// Merge by rank within each index, the score is the reciprocal rank fusion
+    RankFusion,
+    /// Score every index with the statistics of all of them together
+    GlobalStats,
+}
+
+/// A hit of a federated search, with where it came from
+#[derive(Debug, Clone)]
+pub struct FederatedResult {
+    /// Name of the index the hit came from
+    pub index: String,
+    pub path: String,
+    /// 1-based rank of the hit within its own index
+    pub rank: usize,
+    /// The score results are merged by, comparable across indexes
+    pub score: Score,
+    /// The hit as its index returned it, ids refer to that index. Its score is the score the
+    /// federated one was derived from
+    pub result: SearchResult,
+}
+
+/// The top k hits of a query over several indexes, best first
+pub fn search_federated(
+    query: &str,
+    indexes: &[IndexRef],
+    k: usize,
+    scoring: Scoring,
+    harmonize: Harmonize,
+) -> Result<Vec<FederatedResult>, Box<dyn Error>> {
+    let parsed = parse_query(query).map_err(|e| e.render(query))?;
+    // One snapshot per index for the whole search, an update in between can't skew the statistics
+    let snapshots: Vec<Arc<Snapshot>> = indexes.iter().map(|index| index.index.snapshot()).collect();
+    let global = match harmonize {
+        Harmonize::RankFusion => None,
+        Harmonize::GlobalStats => Some(global_stats(indexes, &snapshots, query)?),
+    };
+
+    let mut merged = Vec::new();
+    for (index, snapshot) in indexes.iter().zip(&snapshots) {
+        let own;
+        let stats = match &global {
+            Some(stats) => stats,
+            None => {
+                own = CorpusStats::combined(&[snapshot.index()], &snapshot.analyze_query(query), snapshot.shared_analyzer());
+                &own
+            }
+        };
+        for (position, result) in search_with_stats(snapshot, &parsed, stats, scoring, k).into_iter().enumerate() {
+            let score = match harmonize {
+                Harmonize::RankFusion => 1.0 / (RRF_K + (position + 1) as Score),
+                Harmonize::GlobalStats => result.score,
+            };
+            merged.push(FederatedResult {
+                index: index.name.clone(),
+                path: snapshot.path(result.doc).unwrap_or("?").to_string(),
+                rank: position + 1,
+                score,
+                result,
+            });
+        }
+    }
+    // Ties, which rank fusion makes of every rank, go to the index listed first
+    merged.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.rank.cmp(&b.rank)));
+    merged.truncate(k);
+    Ok(merged)
+}
+
+fn global_stats(indexes: &[IndexRef], snapshots: &[Arc<Snapshot>], query: &str) -> Result<CorpusStats, Box<dyn Error>> {
+    let Some(first) = snapshots.first() else {
+        return Ok(CorpusStats::default());
+    };
+    for (index, snapshot) in indexes.iter().zip(snapshots) {
+        if snapshot.analyzer().fingerprint() != first.analyzer().fingerprint() {
+            return Err(format!(
+                "index {} was built with another analyzer than {}, global statistics need the same one, use rank fusion",
+                index.name, indexes[0].name
+            )
+            .into());
+        }
+    }
+    let inverted: Vec<_> = snapshots.iter().map(|snapshot| snapshot.index()).collect();
+    Ok(CorpusStats::combined(&inverted, &first.analyze_query(query), first.shared_analyzer()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::{Analyzer, LightStemmer, Lowercase, WhitespaceTokenizer};
+    use crate::bm25::{Bm25Params, Bm25Scorer};
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::{Corpus, Document};
+
+    #[test]
+    fn test_search_federated() {
+        let docs = Index::new(Corpus::new(
+            vec![Document::new("guide.txt", "rust borrow checker"), Document::new("intro.txt", "python interpreter")],
+            ChunkingConfig::default(),
+        ));
+        let code = Index::new(Corpus::new(
+            vec![Document::new("main.rs", "rust rust borrow"), Document::new("lib.rs", "rust code"), Document::new("build.rs", "rust")],
+            ChunkingConfig::default(),
+        ));
+        let indexes = [IndexRef::new("docs", docs.clone()), IndexRef::new("code", code)];
+        let bm25 = Scoring::Bm25(Bm25Params::default());
+
+        let fused = search_federated("borrow", &indexes, 10, bm25, Harmonize::RankFusion).unwrap();
+        let summary = |results: &[FederatedResult]| -> Vec<(String, String, usize)> {
+            results.iter().map(|r| (r.index.clone(), r.path.clone(), r.rank)).collect()
+        };
+        assert_eq!(summary(&fused), [("docs".into(), "guide.txt".into(), 1), ("code".into(), "main.rs".into(), 1)]);
+        assert_eq!(fused[0].score, fused[1].score);
+
+        // Scored as one collection of five chunks, where "borrow" is in two of them
+        let global = search_federated("borrow", &indexes, 1, bm25, Harmonize::GlobalStats).unwrap();
+        let one = Corpus::new(
+            vec![
+                Document::new("guide.txt", "rust borrow checker"),
+                Document::new("intro.txt", "python interpreter"),
+                Document::new("main.rs", "rust rust borrow"),
+                Document::new("lib.rs", "rust code"),
+                Document::new("build.rs", "rust"),
+            ],
+            ChunkingConfig::default(),
+        );
+        let expected = one.search_with("borrow", &Bm25Scorer { corpus: &one, params: Bm25Params::default() }).unwrap();
+        assert_eq!(global.len(), 1);
+        assert_eq!(global[0].path, one.path(expected[0].doc).unwrap());
+        assert!((global[0].score - expected[0].score).abs() < 1e-4);
+
+        // Different analyzers only merge by rank
+        let stemming = Arc::new(Analyzer::new(WhitespaceTokenizer).with_filter(Lowercase).with_filter(LightStemmer));
+        let stemmed = Corpus::with_analyzer(vec![Document::new("w.txt", "borrowing")], ChunkingConfig::default(), stemming);
+        let mixed = [IndexRef::new("docs", docs), IndexRef::new("wiki", Index::new(stemmed))];
+        assert!(search_federated("borrow", &mixed, 10, bm25, Harmonize::GlobalStats).is_err());
+        assert_eq!(search_federated("borrow", &mixed, 10, bm25, Harmonize::RankFusion).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use rustc_hash::FxHashMap;
+use crate::analyzer::Analyzer;
+use crate::bm25::{idf_bm25, Bm25Params};
+use crate::corpus::{DocId, Document};
+use crate::search::Score;
+
+// Fields are named texts of a document next to its body: the comments and the code of a source
+// file. They are indexed per document, not per chunk, and scored with BM25F (Robertson, Zaragoza
+// and Taylor 2004): a term's frequency in every field is length normalized by that field's own
+// average length and weighted by the field's boost, the weighted frequencies are added up, and
+// the sum is saturated once, like a single BM25 tf. Summing per-field BM25 scores instead would
+// saturate every field separately and reward a term for being spread over many fields
+
+/// Comments, doc comments and string literals of a source file, see loader::split_source
+pub const DOCS: &str = "docs";
+/// The rest of a source file
+pub const CODE: &str = "code";
+/// The title in the frontmatter of a Markdown file, see loader::parse_frontmatter
+pub const TITLE: &str = "title";
+/// Frontmatter tags, one per line
+pub const TAGS: &str = "tags";
+/// The frontmatter date as written
+pub const DATE: &str = "date";
+
+/// The boost of every field BM25F scores, fields without one are left out
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldWeights(pub Vec<(String, Score)>);
+
+impl FromStr for FieldWeights {
+    type Err = String;
+
+    /// Comma separated name=boost pairs, e.g. "docs=2,code=0.5"
+    fn from_str(s: &str) -> Result<FieldWeights, String> {
+        let mut weights = Vec::new();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (name, boost) = pair.split_once('=').ok_or_else(|| format!("'{}': expected FIELD=BOOST", pair))?;
+            let boost: Score = boost.trim().parse().map_err(|_| format!("'{}': {} is not a number", pair, boost))?;
+            if !(boost.is_finite() && boost >= 0.0) {
+                return Err(format!("'{}': the boost must be 0 or more", pair));
+            }
+            weights.push((name.trim().to_string(), boost));
+        }
+        Ok(FieldWeights(weights))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct FieldPostings {
+    /// Term -> (document, term frequency in this field of the document)
+    postings: FxHashMap<String, Vec<(DocId, u32)>>,
+    /// Number of terms in this field of every document that has it
+    lengths: HashMap<DocId, u32>,
+    total_length: u64,
+}
+
+impl FieldPostings {
+    fn avg_len(&self) -> Score {
+        if self.lengths.is_empty() { 0.0 } else { self.total_length as Score / self.lengths.len() as Score }
+    }
+}
+
+/// Postings of every field of every document
+#[derive(Debug, Clone, Default)]
+pub struct FieldIndex {
+    fields: BTreeMap<String, FieldPostings>,
+    documents: usize,
+}
+
+impl FieldIndex {
+    /// Analyze the fields of the documents with the analyzer of their body
+    pub fn build(documents: &[Document], analyzer: &Analyzer) -> FieldIndex {
+        let mut index = FieldIndex { fields: BTreeMap::new(), documents: documents.len() };
+        for document in documents {
+            // Fields whose text wasn't stored come already counted
+            let analyzed = document.fields.iter().map(|(name, text)| (name, term_counts(analyzer, text)));
+            let counted = document.field_terms.iter().map(|(name, counts)| (name, counts.clone()));
+            for (name, counts) in analyzed.chain(counted) {
+                let field = index.fields.entry(name.clone()).or_default();
+                let length: u32 = counts.iter().map(|(_, tf)| tf).sum();
+                for (term, tf) in counts {
+                    field.postings.entry(term).or_default().push((document.id, tf));
+                }
+                field.lengths.insert(document.id, length);
+                field.total_length += length as u64;
+            }
+        }
+        index
+    }
+
+    /// Names of the fields any document has, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fields.keys().map(String::as_str)
+    }
+
+    /// BM25F score of every document that has one of the terms in a weighted field
+    pub fn bm25f(&self, terms: &[String], weights: &FieldWeights, params: &Bm25Params) -> HashMap<DocId, Score> {
+        let mut scores: HashMap<DocId, Score> = HashMap::new();
+        for term in &distinct(terms) {
+            let frequencies = self.frequencies(term, weights, params);
+            // A document counts once for the idf however many fields have the term
+            let idf = idf_bm25(self.documents, frequencies.len());
+            for (doc, tf) in frequencies {
+                *scores.entry(doc).or_insert(0.0) += idf * saturate(tf, params);
+            }
+        }
+        scores.retain(|_, score| *score > 0.0);
+        scores
+    }
+
+    /// How every field adds to the BM25F score of one document, for tuning the field boosts
+    pub fn explain(&self, doc: DocId, terms: &[String], weights: &FieldWeights, params: &Bm25Params) -> FieldExplanation {
+        let mut explanation = FieldExplanation { doc, params: *params, terms: Vec::new() };
+        for term in distinct(terms) {
+            let mut fields = Vec::new();
+            for (name, boost) in &weights.0 {
+                let Some(field) = self.fields.get(name) else {
+                    continue;
+                };
+                let Some(&(_, tf)) = field.postings.get(&term).and_then(|postings| postings.iter().find(|(id, _)| *id == doc)) else {
+                    continue;
+                };
+                let length = field.lengths[&doc];
+                let avg_len = field.avg_len();
+                let weighted_tf = boost * tf as Score / (1.0 - params.b + params.b * length as Score / avg_len);
+                fields.push(FieldContribution { field: name.clone(), tf, length, avg_len, boost: *boost, weighted_tf });
+            }
+            if fields.is_empty() {
+                continue;
+            }
+            let df = self.frequencies(&term, weights, params).len();
+            let idf = idf_bm25(self.documents, df);
+            let weighted_tf = fields.iter().map(|field| field.weighted_tf).sum();
+            let score = idf * saturate(weighted_tf, params);
+            explanation.terms.push(FieldTermExplanation { term, df, idf, fields, weighted_tf, score });
+        }
+        explanation
+    }
+
+    // Weighted, length normalized frequency of the term in every document, over all fields
+    fn frequencies(&self, term: &str, weights: &FieldWeights, params: &Bm25Params) -> HashMap<DocId, Score> {
+        let mut frequencies: HashMap<DocId, Score> = HashMap::new();
+        for (name, boost) in &weights.0 {
+            let Some(field) = self.fields.get(name) else {
+                continue;
+            };
+            let avg_len = field.avg_len();
+            for (doc, tf) in field.postings.get(term).map(Vec::as_slice).unwrap_or_default() {
+                let length = field.lengths[doc] as Score;
+                let length_norm = 1.0 - params.b + params.b * length / avg_len;
+                *frequencies.entry(*doc).or_insert(0.0) += boost * *tf as Score / length_norm;
+            }
+        }
+        frequencies
+    }
+}
+
+/// Every term of the analyzed text with how often it occurs, sorted by term
+pub(crate) fn term_counts(analyzer: &Analyzer, text: &str) -> Vec<(String, u32)> {
+    let mut counts: FxHashMap<String, u32> = FxHashMap::default();
+    for term in analyzer.analyze(text) {
+        *counts.entry(term).or_insert(0) += 1;
+    }
+    let mut counts: Vec<(String, u32)> = counts.into_iter().collect();
+    counts.sort_unstable();
+    counts
+}
+
+fn distinct(terms: &[String]) -> Vec<String> {
+    let mut distinct = terms.to_vec();
+    distinct.sort();
+    distinct.dedup();
+    distinct
+}
+
+// The summed field frequencies are saturated once, like the tf of plain BM25
+fn saturate(tf: Score, params: &Bm25Params) -> Score {
+    tf * (params.k1 + 1.0) / (params.k1 + tf)
+}
+
+/// What one field adds to a term's frequency in a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldContribution {
+    pub field: String,
+    /// Occurrences of the term in the field
+    pub tf: u32,
+    /// Terms in the field of this document, and on average over the documents that have the field
+    pub length: u32,
+    pub avg_len: Score,
+    pub boost: Score,
+    /// boost * tf / (1 - b + b * length / avg_len)
+    pub weighted_tf: Score,
+}
+
+/// One query term's part of a BM25F score
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldTermExplanation {
+    pub term: String,
+    /// Documents with the term in any weighted field
+    pub df: usize,
+    pub idf: Score,
+    pub fields: Vec<FieldContribution>,
+    /// The sum of the fields' weighted frequencies, what gets saturated
+    pub weighted_tf: Score,
+    pub score: Score,
+}
+
+/// The BM25F score of a document broken down by term and field, see FieldIndex::explain
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldExplanation {
+    pub doc: DocId,
+    pub params: Bm25Params,
+    pub terms: Vec<FieldTermExplanation>,
+}
+
+impl FieldExplanation {
+    pub fn score(&self) -> Score {
+        self.terms.iter().map(|term| term.score).sum()
+    }
+
+    /// Plain-text explanation for the terminal, headed by the document's name
+    pub fn render(&self, name: &str) -> String {
+        let mut out = format!("{}: BM25F {:.4} (k1 {}, b {})\n", name, self.score(), self.params.k1, self.params.b);
+        for term in &self.terms {
+            out.push_str(&format!("  {}  df {}  idf {:.4}  = {:.4}\n", term.term, term.df, term.idf, term.score));
+            for field in &term.fields {
+                out.push_str(&format!(
+                    "    {:<8} tf {}  length {} (avg {:.1})  boost {}  -> {:.4}\n",
+                    field.field, field.tf, field.length, field.avg_len, field.boost, field.weighted_tf
+                ));
+            }
+            // How much of the weighted frequency survives saturation shows whether a higher boost can still help
+            let saturated = saturate(term.weighted_tf, &self.params);
+            out.push_str(&format!(
+                "    weighted tf {:.4} saturates to {:.4}, at most {:.1}\n",
+                term.weighted_tf,
+                saturated,
+                self.params.k1 + 1.0
+            ));
+        }
+        if self.terms.is_empty() {
+            out.push_str("  no query term is in a weighted field\n");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: u32, docs: &str, code: &str) -> Document {
+        let fields = BTreeMap::from([(DOCS.to_string(), docs.to_string()), (CODE.to_string(), code.to_string())]);
+        Document { id: DocId(id), fields, ..Document::new(&format!("{}.rs", id), "") }
+    }
+
+    #[test]
+    fn test_bm25f_weights_fields() {
+        let documents = vec![
+            document(0, "parse a request", "fn handle"),
+            document(1, "handle a request", "fn parse"),
+            document(2, "unrelated", "fn main"),
+        ];
+        let index = FieldIndex::build(&documents, &Analyzer::default());
+        assert_eq!(index.names().collect::<Vec<_>>(), ["code", "docs"]);
+        let terms = vec!["parse".to_string()];
+        let params = Bm25Params::default();
+        let docs_first = index.bm25f(&terms, &"docs=2,code=1".parse().unwrap(), &params);
+        assert!(docs_first[&DocId(0)] > docs_first[&DocId(1)]);
+        let code_first = index.bm25f(&terms, &"docs=1,code=2".parse().unwrap(), &params);
+        assert!(code_first[&DocId(1)] > code_first[&DocId(0)]);
+        // A field without a weight isn't scored
+        assert_eq!(index.bm25f(&terms, &"docs=1".parse().unwrap(), &params).len(), 1);
+        assert!("docs".parse::<FieldWeights>().is_err());
+
+        let explanation = index.explain(DocId(0), &terms, &"docs=2,code=1".parse().unwrap(), &params);
+        assert!((explanation.score() - docs_first[&DocId(0)]).abs() < 1e-6);
+        let parse = &explanation.terms[0];
+        assert_eq!((parse.df, parse.fields.len(), parse.fields[0].field.as_str(), parse.fields[0].tf), (2, 1, "docs", 1));
+        assert!(explanation.render("0.rs").contains("docs     tf 1  length 3 (avg 2.3)  boost 2"));
+    }
+}
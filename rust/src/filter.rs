@@ -0,0 +1,89 @@
+use std::path::Path;
+use std::time::SystemTime;
+use roaring::RoaringBitmap;
+use crate::corpus::{Corpus, Document};
+use crate::fields::TAGS;
+
+// A filter is evaluated to the set of documents it allows, as a roaring bitmap of their ids, and
+// then to the ids of their chunks. Combining filters is bitmap and, or and and-not, and keeping a
+// scored chunk is a bitmap lookup, instead of a pass over the results for every condition
+
+/// Which documents a search may return results from, by their metadata
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocFilter {
+    /// Documents whose path starts with the prefix
+    PathPrefix(String),
+    /// Documents whose path has this extension, without the dot
+    Extension(String),
+    /// Documents with this frontmatter tag, ignoring case
+    Tag(String),
+    /// Documents last modified at or after this time, documents without a time don't match
+    ModifiedSince(SystemTime),
+    /// The documents with these ids, e.g. the ones a previous search found
+    Documents(RoaringBitmap),
+    /// Documents every filter allows, all documents for no filters
+    And(Vec<DocFilter>),
+    /// Documents at least one filter allows
+    Or(Vec<DocFilter>),
+    Not(Box<DocFilter>),
+}
+
+impl DocFilter {
+    /// Ids of the documents the filter allows
+    pub fn documents(&self, corpus: &Corpus) -> RoaringBitmap {
+        let matching = |allowed: &dyn Fn(&Document) -> bool| {
+            corpus.documents().iter().filter(|doc| allowed(doc)).map(|doc| doc.id.0).collect()
+        };
+        match self {
+            DocFilter::PathPrefix(prefix) => matching(&|doc| doc.path.starts_with(prefix.as_str())),
+            DocFilter::Extension(extension) => {
+                matching(&|doc| Path::new(&doc.path).extension().is_some_and(|e| e == extension.as_str()))
+            }
+            DocFilter::Tag(tag) => matching(&|doc| {
+                doc.fields.get(TAGS).is_some_and(|tags| tags.lines().any(|t| t.eq_ignore_ascii_case(tag)))
+            }),
+            DocFilter::ModifiedSince(time) => matching(&|doc| doc.modified.is_some_and(|modified| modified >= *time)),
+            DocFilter::Documents(ids) => ids.clone(),
+            DocFilter::And(filters) => {
+                filters.iter().fold(matching(&|_| true), |allowed: RoaringBitmap, filter| allowed & filter.documents(corpus))
+            }
+            DocFilter::Or(filters) => filters.iter().fold(RoaringBitmap::new(), |allowed, filter| allowed | filter.documents(corpus)),
+            DocFilter::Not(filter) => matching(&|_| true) - filter.documents(corpus),
+        }
+    }
+
+    /// Ids of the chunks of the documents the filter allows
+    pub fn chunks(&self, corpus: &Corpus) -> RoaringBitmap {
+        corpus.chunk_ids(&self.documents(corpus))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+
+    #[test]
+    fn test_filters_combine_as_sets() {
+        let corpus = Corpus::new(
+            vec![
+                Document::new("docs/a.md", "rust borrow checker"),
+                Document::new("docs/b.txt", "rust lifetimes"),
+                Document {
+                    fields: [(TAGS.to_string(), "Python\nrust".to_string())].into(),
+                    ..Document::new("notes/c.md", "rust and python")
+                },
+            ],
+            ChunkingConfig::default(),
+        );
+        let docs = DocFilter::PathPrefix("docs/".to_string());
+        let markdown = DocFilter::Extension("md".to_string());
+        let ids = |filter: &DocFilter| filter.chunks(&corpus).iter().collect::<Vec<u32>>();
+        assert_eq!(ids(&docs), [0, 1]);
+        assert_eq!(ids(&DocFilter::And(vec![docs.clone(), markdown.clone()])), [0]);
+        assert_eq!(ids(&DocFilter::Or(vec![docs.clone(), markdown])), [0, 1, 2]);
+        assert_eq!(ids(&DocFilter::Not(Box::new(docs))), [2]);
+        assert_eq!(ids(&DocFilter::Tag("python".to_string())), [2]);
+        assert_eq!(ids(&DocFilter::ModifiedSince(SystemTime::UNIX_EPOCH)), Vec::<u32>::new());
+    }
+}
@@ -0,0 +1,583 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tonic::transport::Server;
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Response, Status};
+use crate::async_api::{AsyncError, AsyncIndex};
+use crate::bm25::{idf_bm25, Bm25Params, Bm25Scorer};
+use crate::cache::ResultCache;
+use crate::cancel::CancellationToken;
+use crate::persist::fnv1a;
+use crate::corpus::{Corpus, Document};
+use crate::metrics::{serve_metrics, ServerMetrics};
+use crate::ratelimit::{client_key, RateLimiter};
+use crate::filter::DocFilter;
+use crate::index::{Index, WeakIndex};
+use crate::registry::IndexRegistry;
+use crate::search::{to_f32, Score, SearchOptions, SearchResult};
+use crate::tfidf::{IdfScheme, TfIdfParams, TfIdfScorer};
+
+/// Messages and service traits generated from proto/tfidf.proto by build.rs
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("tfidf");
+}
+
+use proto::ranker_server::{Ranker, RankerServer};
+
+/// Search responses a server keeps by default, see RankerService::cache_size
+pub const DEFAULT_CACHE_SIZE: usize = 1024;
+
+/// Bounds on what clients may ask of a server, by default none
+// For a server open to anyone: a flood of requests or a query of a megabyte of terms is refused
+// before it takes the blocking pool from everybody else
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ServerLimits {
+    /// Requests a second per client address, with bursts of up to burst requests
+    pub requests_per_second: Option<f64>,
+    pub burst: u32,
+    /// Longest query in characters, for Search and Explain
+    pub max_query_length: Option<usize>,
+    /// Most hits a Search may ask for
+    pub max_top: Option<usize>,
+}
+
+/// Result caches by index name, each with the index its responses came from
+type Caches = HashMap<String, (WeakIndex, Arc<ResultCache<proto::SearchResponse>>)>;
+
+/// The Ranker service over a shared index, and the named indexes of a registry
+// Every RPC takes one snapshot, so a search never sees half of a concurrent Index call
+#[derive(Clone)]
+pub struct RankerService {
+    index: AsyncIndex,
+    registry: Arc<IndexRegistry>,
+    metrics: Arc<ServerMetrics>,
+    cache_size: usize,
+    caches: Arc<Mutex<Caches>>,
+    limits: ServerLimits,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    admin: bool,
+}
+
+impl RankerService {
+    pub fn new(index: AsyncIndex, metrics: Arc<ServerMetrics>) -> RankerService {
+        metrics.observe_index(&index.index().snapshot());
+        RankerService {
+            index,
+            registry: Arc::new(IndexRegistry::new()),
+            metrics,
+            cache_size: DEFAULT_CACHE_SIZE,
+            caches: Arc::new(Mutex::new(HashMap::new())),
+            limits: ServerLimits::default(),
+            rate_limiter: None,
+            admin: false,
+        }
+    }
+
+    /// Keep at most this many search responses per index for repeated requests, 0 turns the cache off
+    pub fn cache_size(mut self, capacity: usize) -> Self {
+        self.cache_size = capacity;
+        self.caches = Arc::new(Mutex::new(HashMap::new()));
+        self
+    }
+
+    /// Serve the indexes of a registry too, requests pick one by name
+    pub fn registry(mut self, registry: Arc<IndexRegistry>) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Accept Reload and Close requests for the indexes of the registry, refused by default
+    pub fn admin(mut self, enabled: bool) -> Self {
+        self.admin = enabled;
+        self
+    }
+
+    fn check_admin(&self) -> Result<(), Status> {
+        if self.admin {
+            Ok(())
+        } else {
+            Err(Status::permission_denied("the server wasn't started with --admin"))
+        }
+    }
+
+    // The index gauges in the metrics only describe the default index
+    fn route(&self, name: &str) -> Result<AsyncIndex, Status> {
+        if name.is_empty() {
+            return Ok(self.index.clone());
+        }
+        let index = self.registry.get(name).ok_or_else(|| {
+            Status::not_found(format!("no index {}, the server has {}", name, self.registry.names().join(", ")))
+        })?;
+        Ok(AsyncIndex::from_index(index))
+    }
+
+    // An index closed and opened again under the same name starts over at generation 0, so a
+    // cache is only reused for the very index it was filled from. The cache only holds a weak
+    // handle to it, a closed index is freed once its last request is done, and its cache
+    // with the next one created
+    fn cache(&self, name: &str, index: &Index) -> Arc<ResultCache<proto::SearchResponse>> {
+        let mut caches = self.caches.lock().unwrap();
+        match caches.get(name) {
+            Some((cached, cache)) if cached.upgrade().is_some_and(|cached| cached.ptr_eq(index)) => Arc::clone(cache),
+            _ => {
+                caches.retain(|_, (cached, _)| cached.upgrade().is_some());
+                let cache = Arc::new(ResultCache::new(self.cache_size));
+                caches.insert(name.to_string(), (index.downgrade(), Arc::clone(&cache)));
+                cache
+            }
+        }
+    }
+
+    pub fn limits(mut self, limits: ServerLimits) -> Result<Self, String> {
+        self.rate_limiter = match limits.requests_per_second {
+            Some(per_second) => Some(Arc::new(RateLimiter::new(per_second, limits.burst)?)),
+            None => None,
+        };
+        self.limits = limits;
+        Ok(self)
+    }
+
+    // Clients are told apart by IP address, requests without one, e.g. over a Unix socket, share a bucket
+    fn admit<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let Some(limiter) = &self.rate_limiter else {
+            return Ok(());
+        };
+        let client = request.remote_addr().map(|addr| client_key(addr.ip())).unwrap_or_default();
+        limiter.check(&client, Instant::now()).map_err(|wait| {
+            self.metrics.observe_rejected("rate");
+            let mut status = Status::resource_exhausted(format!("too many requests, retry in {} ms", wait.as_millis().max(1)));
+            // Whole seconds, like the HTTP header of the same name
+            if let Ok(seconds) = wait.as_secs_f64().ceil().to_string().parse() {
+                status.metadata_mut().insert("retry-after", seconds);
+            }
+            status
+        })
+    }
+
+    fn check_query(&self, query: &str) -> Result<(), Status> {
+        match self.limits.max_query_length {
+            Some(max) if query.chars().count() > max => {
+                self.metrics.observe_rejected("query_length");
+                Err(Status::invalid_argument(format!("the query is longer than {} characters", max)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Serve the Ranker service on addr until the process is stopped, with the indexes of registry
+/// by name, and Prometheus metrics at http://metrics_addr/metrics if given. Reload and Close
+/// requests are only accepted with admin
+pub async fn serve(
+    index: AsyncIndex,
+    registry: Arc<IndexRegistry>,
+    addr: SocketAddr,
+    metrics_addr: Option<SocketAddr>,
+    cache_size: usize,
+    limits: ServerLimits,
+    admin: bool,
+) -> Result<(), AsyncError> {
+    let metrics = Arc::new(ServerMetrics::new()?);
+    let service =
+        RankerService::new(index, Arc::clone(&metrics)).registry(registry).cache_size(cache_size).limits(limits)?.admin(admin);
+    let grpc = async {
+        Server::builder().add_service(RankerServer::new(service)).serve(addr).await?;
+        Ok::<_, AsyncError>(())
+    };
+    match metrics_addr {
+        // try_join stops the server as soon as either listener fails, e.g. because its port is taken
+        Some(metrics_addr) => tokio::try_join!(grpc, serve_metrics(metrics, metrics_addr)).map(|_| ()),
+        None => grpc.await,
+    }
+}
+
+fn internal(error: AsyncError) -> Status {
+    Status::internal(error.to_string())
+}
+
+// The same request against the same generation always gets the same hits, so the pair names a
+// response. These are gRPC response metadata named after the HTTP caching headers, there is no
+// HTTP server here: a gRPC client or interceptor holding an etag can compare it instead of hits,
+// and no-cache tells it to check back rather than reuse it, the next Index call may change it at
+// any moment. FNV-1a rather than DefaultHasher, whose output may change between Rust releases
+fn cache_metadata(response: &mut Response<proto::SearchResponse>, key: &str, hit: bool) {
+    let etag = format!("\"{}-{:016x}\"", response.get_ref().generation, fnv1a(key.as_bytes()));
+    let metadata = response.metadata_mut();
+    // Quotes, digits and hex are all ASCII, so the values always parse
+    if let Ok(etag) = MetadataValue::try_from(etag) {
+        metadata.insert("etag", etag);
+    }
+    metadata.insert("cache-control", MetadataValue::from_static("no-cache"));
+    metadata.insert("x-cache", MetadataValue::from_static(if hit { "hit" } else { "miss" }));
+}
+
+// Results carry ids, clients only know documents by path
+fn to_hit(corpus: &Corpus, result: &SearchResult) -> Option<proto::Hit> {
+    let chunk = corpus.chunk(result.chunk?)?;
+    Some(proto::Hit {
+        path: corpus.path(chunk.doc)?.to_string(),
+        chunk_index: chunk.index as u32,
+        // The protocol carries f32 whatever precision the scores were computed in
+        score: to_f32(result.score),
+        text: corpus.chunk_text(chunk.id).map(|text| text.into_owned()).unwrap_or_default(),
+        highlights: result.highlights.clone(),
+    })
+}
+
+#[tonic::async_trait]
+impl Ranker for RankerService {
+    async fn index(&self, request: Request<proto::IndexRequest>) -> Result<Response<proto::IndexResponse>, Status> {
+        self.admit(&request)?;
+        let request = request.into_inner();
+        let index = self.route(&request.index)?;
+        let name = request.index.clone();
+        let documents: Vec<Document> = request.documents.iter().map(|d| Document::new(&d.path, &d.text)).collect();
+        // Indexing is the slow part, it runs on the blocking pool while searches keep using the old snapshot
+        let (started_at, corpus) = index
+            .read(move |current| {
+                let added = current.with_documents(documents);
+                let corpus = if request.append { Corpus::merge(&[current, &added]) } else { Ok(added) };
+                (current.generation(), corpus)
+            })
+            .await
+            .map_err(internal)?;
+        let corpus = corpus.map_err(Status::invalid_argument)?;
+        let (documents, chunks) = (corpus.documents().len() as u64, corpus.chunks().len() as u64);
+        // An Index call that finished in the meantime would be lost by the swap, so refuse instead
+        let generation = index
+            .index()
+            .replace_if(started_at, corpus)
+            .ok_or_else(|| Status::aborted("the index changed during the call, retry"))?;
+        if name.is_empty() {
+            self.metrics.observe_index(&index.index().snapshot());
+        }
+        Ok(Response::new(proto::IndexResponse { generation, documents, chunks }))
+    }
+
+    async fn search(&self, request: Request<proto::SearchRequest>) -> Result<Response<proto::SearchResponse>, Status> {
+        self.admit(&request)?;
+        let request = request.into_inner();
+        self.check_query(&request.query)?;
+        let top = if request.top == 0 { 10 } else { request.top as usize };
+        if let Some(max) = self.limits.max_top
+            && top > max
+        {
+            self.metrics.observe_rejected("top");
+            return Err(Status::invalid_argument(format!("top is at most {}", max)));
+        }
+        let ranker = request.ranking().as_str_name().to_lowercase();
+        let started = Instant::now();
+        // Every field of the request is a parameter of the search, so its debug form is the key
+        let index = self.route(&request.index)?;
+        let cache = self.cache(&request.index, index.index());
+        let key = format!("{:?}", request);
+        if let Some(cached) = cache.get(index.index().snapshot().generation(), &key) {
+            self.metrics.observe_cache(true);
+            self.metrics.observe_query(&ranker, started.elapsed(), true);
+            let mut response = Response::new(cached);
+            cache_metadata(&mut response, &key, true);
+            return Ok(response);
+        }
+        self.metrics.observe_cache(false);
+        let response = index
+            .read(move |snapshot| {
+                let corpus: &Corpus = snapshot;
+                // Paths that aren't in the index (anymore) are left out of the filter
+                let within = request.within_paths.iter().filter_map(|path| corpus.doc_id(path)).map(|id| id.0).collect();
+                let options = SearchOptions {
+                    filter: (!request.within_paths.is_empty()).then_some(DocFilter::Documents(within)),
+                    ..SearchOptions::default()
+                };
+                let results = match request.ranking() {
+                    proto::Ranking::Tfidf => {
+                        corpus.search_with_options(&request.query, &TfIdfScorer::new(corpus, TfIdfParams::default()), &options)
+                    }
+                    proto::Ranking::Bm25 => {
+                        let defaults = Bm25Params::default();
+                        let params = Bm25Params {
+                            k1: request.k1.map_or(defaults.k1, |k1| k1 as Score),
+                            b: request.b.map_or(defaults.b, |b| b as Score),
+                        };
+                        corpus.search_with_options(&request.query, &Bm25Scorer { corpus, params }, &options)
+                    }
+                };
+                let hits = results.map_err(|e| e.render(&request.query))?.iter().take(top).filter_map(|r| to_hit(corpus, r)).collect();
+                // The query parsed for the search, so it parses here too
+                let unknown_terms = corpus.unknown_terms(&request.query).unwrap_or_default();
+                Ok::<_, String>(proto::SearchResponse { generation: snapshot.generation(), hits, unknown_terms })
+            })
+            .await
+            .map_err(internal)?;
+        self.metrics.observe_query(&ranker, started.elapsed(), response.is_ok());
+        let response = response.map_err(Status::invalid_argument)?;
+        // Filed under the generation it was computed from, which a concurrent Index call may already have replaced
+        cache.insert(response.generation, key.clone(), response.clone());
+        let mut response = Response::new(response);
+        cache_metadata(&mut response, &key, false);
+        Ok(response)
+    }
+
+    async fn explain(&self, request: Request<proto::ExplainRequest>) -> Result<Response<proto::ExplainResponse>, Status> {
+        self.admit(&request)?;
+        let request = request.into_inner();
+        self.check_query(&request.query)?;
+        let response = self
+            .route(&request.index)?
+            .read(move |corpus| {
+                let chunk = corpus
+                    .chunk_at(&request.path, request.chunk_index as usize)
+                    .ok_or_else(|| Status::not_found(format!("no chunk {}#{}", request.path, request.chunk_index)))?;
+                let diff = corpus
+                    .explain_rank_diff(&request.query, chunk.id)
+                    .map_err(|e| Status::invalid_argument(e.render(&request.query)))?;
+                let terms = diff
+                    .terms
+                    .iter()
+                    .map(|t| proto::TermExplanation {
+                        term: t.term.clone(),
+                        tf: t.tf,
+                        df: t.df as u64,
+                        tfidf_tf: to_f32(t.tfidf_tf),
+                        tfidf_idf: to_f32(t.tfidf_idf),
+                        tfidf: to_f32(t.tfidf),
+                        bm25_tf: to_f32(t.bm25_tf),
+                        bm25_idf: to_f32(t.bm25_idf),
+                        bm25: to_f32(t.bm25),
+                    })
+                    .collect();
+                Ok::<_, Status>(proto::ExplainResponse {
+                    tfidf_rank: diff.tfidf_rank.map(|r| r as u32),
+                    bm25_rank: diff.bm25_rank.map(|r| r as u32),
+                    chunk_len: diff.chunk_len,
+                    avg_len: to_f32(diff.avg_len),
+                    length_norm: to_f32(diff.length_norm),
+                    terms,
+                    rendered: diff.render(),
+                })
+            })
+            .await
+            .map_err(internal)??;
+        Ok(Response::new(response))
+    }
+
+    async fn get_document(
+        &self,
+        request: Request<proto::GetDocumentRequest>,
+    ) -> Result<Response<proto::GetDocumentResponse>, Status> {
+        self.admit(&request)?;
+        let request = request.into_inner();
+        let path = request.path;
+        let response = self
+            .route(&request.index)?
+            .read(move |snapshot| {
+                let document = snapshot
+                    .doc_id(&path)
+                    .and_then(|id| snapshot.document(id))
+                    .ok_or_else(|| Status::not_found(format!("no document {}", path)))?;
+                Ok::<_, Status>(proto::GetDocumentResponse {
+                    generation: snapshot.generation(),
+                    path: document.path.clone(),
+                    root: document.root.clone(),
+                    text: snapshot.document_text(document.id).map_err(|e| Status::unavailable(e.to_string()))?.into_owned(),
+                    chunks: snapshot.chunks().iter().filter(|chunk| chunk.doc == document.id).count() as u32,
+                })
+            })
+            .await
+            .map_err(internal)??;
+        Ok(Response::new(response))
+    }
+
+    async fn stats(&self, request: Request<proto::StatsRequest>) -> Result<Response<proto::StatsResponse>, Status> {
+        self.admit(&request)?;
+        let request = request.into_inner();
+        let response = self
+            .route(&request.index)?
+            .read(move |snapshot| {
+                let index = snapshot.index();
+                let n = index.num_chunks();
+                let terms = request
+                    .terms
+                    .iter()
+                    .flat_map(|text| snapshot.analyze_query(text))
+                    .map(|term| {
+                        let df = index.doc_freq(&term);
+                        proto::TermStats {
+                            df: df as u64,
+                            collection_freq: index.collection_freq(&term),
+                            idf_tfidf: to_f32(IdfScheme::Plain.idf(n, df)),
+                            idf_bm25: if df == 0 { 0.0 } else { to_f32(idf_bm25(n, df)) },
+                            term,
+                        }
+                    })
+                    .collect();
+                proto::StatsResponse {
+                    generation: snapshot.generation(),
+                    documents: snapshot.documents().len() as u64,
+                    chunks: n as u64,
+                    vocabulary: index.terms().count() as u64,
+                    avg_chunk_len: to_f32(index.avg_len()),
+                    analyzer: snapshot.analyzer().describe(),
+                    terms,
+                }
+            })
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(response))
+    }
+
+    async fn reload(&self, request: Request<proto::ReloadRequest>) -> Result<Response<proto::ReloadResponse>, Status> {
+        self.admit(&request)?;
+        self.check_admin()?;
+        let name = request.into_inner().index;
+        let index = self.registry.get(&name).ok_or_else(|| Status::not_found(format!("no index {}", name)))?;
+        let registry = Arc::clone(&self.registry);
+        // Loading reads every source again, the blocking pool does that while searches use the old snapshot.
+        // Tonic drops this future when the client cancels or disconnects, the guard then stops the load.
+        // Box<dyn Error> isn't Send, only its message crosses threads
+        let cancel = CancellationToken::new();
+        let guard = cancel.clone().drop_guard();
+        let generation = tokio::task::spawn_blocking(move || registry.reload(&name, &cancel).map_err(|e| e.to_string()))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::failed_precondition)?;
+        guard.disarm();
+        // Responses cached for the old generation go stale on their own
+        let snapshot = index.snapshot();
+        Ok(Response::new(proto::ReloadResponse {
+            generation,
+            documents: snapshot.documents().len() as u64,
+            chunks: snapshot.chunks().len() as u64,
+        }))
+    }
+
+    async fn close(&self, request: Request<proto::CloseRequest>) -> Result<Response<proto::CloseResponse>, Status> {
+        self.admit(&request)?;
+        self.check_admin()?;
+        let name = request.into_inner().index;
+        self.registry.close(&name).ok_or_else(|| Status::not_found(format!("no index {}", name)))?;
+        self.caches.lock().unwrap().remove(&name);
+        Ok(Response::new(proto::CloseResponse {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+
+    fn document(path: &str, text: &str) -> proto::Document {
+        proto::Document { path: path.to_string(), text: text.to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_index_search_explain_and_stats() {
+        let metrics = Arc::new(ServerMetrics::new().unwrap());
+        let registry = Arc::new(IndexRegistry::new());
+        let code = Corpus::new(vec![Document::new("main.rs", "fn main rust"), Document::new("lib.rs", "pub mod")], ChunkingConfig::default());
+        registry.insert("code", Index::new(code)).unwrap();
+        let service = RankerService::new(AsyncIndex::new(Corpus::new(Vec::new(), ChunkingConfig::default())), Arc::clone(&metrics))
+            .registry(registry);
+        let indexed = service
+            .index(Request::new(proto::IndexRequest {
+                documents: vec![document("a.txt", "rust borrow checker"), document("b.txt", "python garbage collector")],
+                append: false,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((indexed.generation, indexed.documents), (1, 2));
+        let appended = service
+            .index(Request::new(proto::IndexRequest { documents: vec![document("c.txt", "rust rust")], append: true, ..Default::default() }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(appended.documents, 3);
+
+        let search = |query: &str, ranking| proto::SearchRequest { query: query.to_string(), ranking: ranking as i32, ..Default::default() };
+        let found = service.search(Request::new(search("rust", proto::Ranking::Bm25))).await.unwrap().into_inner();
+        assert_eq!(found.hits.iter().map(|h| h.path.as_str()).collect::<Vec<_>>(), ["c.txt", "a.txt"]);
+        assert!(found.unknown_terms.is_empty());
+        let typo = service.search(Request::new(search("rust borow", proto::Ranking::Bm25))).await.unwrap().into_inner();
+        assert_eq!(typo.unknown_terms, ["borow"]);
+        let again = service.search(Request::new(search("rust", proto::Ranking::Bm25))).await.unwrap();
+        assert_eq!(again.metadata().get("x-cache").unwrap(), "hit");
+        assert_eq!(again.into_inner(), found);
+        let within = proto::SearchRequest { within_paths: vec!["a.txt".to_string()], ..search("rust", proto::Ranking::Bm25) };
+        let drilled = service.search(Request::new(within)).await.unwrap().into_inner();
+        assert_eq!(drilled.hits.iter().map(|h| h.path.as_str()).collect::<Vec<_>>(), ["a.txt"]);
+        let error = service.search(Request::new(search("(rust", proto::Ranking::Tfidf))).await.unwrap_err();
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+        assert!(metrics.render().contains(r#"search_queries_total{outcome="error",ranker="tfidf"} 1"#));
+
+        let explained = service
+            .explain(Request::new(proto::ExplainRequest { query: "rust".to_string(), path: "a.txt".to_string(), chunk_index: 0, ..Default::default() }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(explained.bm25_rank, Some(2));
+        assert_eq!(explained.terms[0].df, 2);
+
+        let document = service
+            .get_document(Request::new(proto::GetDocumentRequest { path: "c.txt".to_string(), ..Default::default() }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((document.text.as_str(), document.chunks), ("rust rust", 1));
+
+        let stats = service.stats(Request::new(proto::StatsRequest { terms: vec!["Rust".to_string()], ..Default::default() })).await.unwrap().into_inner();
+        assert_eq!((stats.documents, stats.terms[0].term.as_str(), stats.terms[0].collection_freq), (3, "rust", 3));
+
+        // Indexing invalidates the cached response
+        let more = proto::IndexRequest { documents: vec![proto::Document { path: "d.txt".to_string(), text: "rust".to_string() }], append: true, ..Default::default() };
+        service.index(Request::new(more)).await.unwrap();
+        let after = service.search(Request::new(search("rust", proto::Ranking::Bm25))).await.unwrap();
+        assert_eq!(after.metadata().get("x-cache").unwrap(), "miss");
+        assert_eq!((after.get_ref().generation, after.get_ref().hits.len()), (3, 3));
+        assert!(metrics.render().contains(r#"search_cache_requests_total{result="hit"} 1"#));
+
+        // Named indexes are searched on their own
+        let in_code = proto::SearchRequest { index: "code".to_string(), ..search("rust", proto::Ranking::Bm25) };
+        let found = service.search(Request::new(in_code)).await.unwrap().into_inner();
+        assert_eq!((found.generation, found.hits.iter().map(|h| h.path.as_str()).collect::<Vec<_>>()), (0, vec!["main.rs"]));
+        let missing = proto::SearchRequest { index: "wiki".to_string(), ..search("rust", proto::Ranking::Bm25) };
+        assert_eq!(service.search(Request::new(missing)).await.unwrap_err().code(), tonic::Code::NotFound);
+
+        // Reload and Close need --admin, closing drops the index and its cache
+        let close = || Request::new(proto::CloseRequest { index: "code".to_string() });
+        assert_eq!(service.close(close()).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        let admin = service.clone().admin(true);
+        let reload = Request::new(proto::ReloadRequest { index: "code".to_string() });
+        assert_eq!(admin.reload(reload).await.unwrap_err().code(), tonic::Code::FailedPrecondition);
+        let loader: crate::registry::Loader = Arc::new(|_| Ok(Corpus::new(vec![Document::new("n.txt", "notes")], ChunkingConfig::default())));
+        admin.registry.open("notes", loader).unwrap();
+        let reloaded = admin.reload(Request::new(proto::ReloadRequest { index: "notes".to_string() })).await.unwrap().into_inner();
+        assert_eq!((reloaded.generation, reloaded.documents), (1, 1));
+        assert!(admin.caches.lock().unwrap().contains_key("code"));
+        admin.close(close()).await.unwrap();
+        assert!(!admin.caches.lock().unwrap().contains_key("code"));
+        assert_eq!(admin.close(close()).await.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_server_limits() {
+        let metrics = Arc::new(ServerMetrics::new().unwrap());
+        let corpus = Corpus::new(vec![Document::new("a.txt", "rust borrow checker"), Document::new("b.txt", "python")], ChunkingConfig::default());
+        let limits = ServerLimits { requests_per_second: Some(0.001), burst: 3, max_query_length: Some(10), max_top: Some(5) };
+        let service = RankerService::new(AsyncIndex::new(corpus), Arc::clone(&metrics)).limits(limits).unwrap();
+        let search = |query: &str, top| Request::new(proto::SearchRequest { query: query.to_string(), top, ..Default::default() });
+
+        let long = service.search(search("rust borrow checker", 0)).await.unwrap_err();
+        assert_eq!(long.code(), tonic::Code::InvalidArgument);
+        assert_eq!(service.search(search("rust", 6)).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        assert_eq!(service.search(search("rust", 5)).await.unwrap().into_inner().hits.len(), 1);
+        // The burst is spent and the next token is 1000 seconds away
+        let limited = service.search(search("rust", 5)).await.unwrap_err();
+        assert_eq!(limited.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(limited.metadata().get("retry-after").unwrap(), "1000");
+        assert!(metrics.render().contains(r#"requests_rejected_total{limit="rate"} 1"#));
+    }
+}
@@ -0,0 +1,277 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use crate::chunker::{Chunk, ChunkText};
+use crate::context::{expand_context, Context, ExpandedChunk};
+use crate::retrieve::{retrieve_for_prompt, Passage};
+use crate::corpus::{ChunkId, Corpus, DocId, Document};
+use crate::query::QueryError;
+use crate::vocabulary::{vocabulary, write_vocabulary};
+
+/// An immutable view of the corpus at one point in time
+// Readers hold an Arc<Snapshot>, so a snapshot lives for as long as any query still uses it,
+// even after the index has moved on to a newer one
+pub struct Snapshot {
+    generation: u64,
+    corpus: Corpus,
+}
+
+impl Snapshot {
+    /// Increases by one every time the index is replaced or updated
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+// Deref lets a snapshot be used wherever a &Corpus is expected: snapshot.search_chunks("...")
+impl Deref for Snapshot {
+    type Target = Corpus;
+
+    fn deref(&self) -> &Corpus {
+        &self.corpus
+    }
+}
+
+struct Shared {
+    current: RwLock<Arc<Snapshot>>,
+    // Serializes writers so two copy-on-write updates can't both start from the same snapshot
+    writer: Mutex<()>,
+}
+
+/// A corpus that can be queried from many threads while it is being updated
+// Cloning an Index is cheap and every clone refers to the same shared snapshot
+#[derive(Clone)]
+pub struct Index {
+    shared: Arc<Shared>,
+}
+
+/// A handle that doesn't keep its index alive, see Index::downgrade
+#[derive(Clone)]
+pub struct WeakIndex {
+    shared: Weak<Shared>,
+}
+
+impl WeakIndex {
+    /// The index, if any handle to it is still around
+    pub fn upgrade(&self) -> Option<Index> {
+        self.shared.upgrade().map(|shared| Index { shared })
+    }
+}
+
+/// A read-only handle to an index, suitable for handing to query threads
+#[derive(Clone)]
+pub struct IndexReader {
+    shared: Arc<Shared>,
+}
+
+impl Index {
+    pub fn new(corpus: Corpus) -> Index {
+        let snapshot = Arc::new(Snapshot { generation: 0, corpus });
+        Index {
+            shared: Arc::new(Shared {
+                current: RwLock::new(snapshot),
+                writer: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// A new index over the current snapshots of several shard indexes, see Corpus::merge
+    pub fn merge(indexes: &[Index]) -> Result<Index, String> {
+        let snapshots: Vec<Arc<Snapshot>> = indexes.iter().map(|index| index.snapshot()).collect();
+        let corpora: Vec<&Corpus> = snapshots.iter().map(|snapshot| &snapshot.corpus).collect();
+        Ok(Index::new(Corpus::merge(&corpora)?))
+    }
+
+    pub fn reader(&self) -> IndexReader {
+        IndexReader { shared: Arc::clone(&self.shared) }
+    }
+
+    /// Whether both handles refer to the same index, rather than two that may hold equal corpora
+    pub fn ptr_eq(&self, other: &Index) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    /// A handle to remember the index by without keeping it alive, e.g. in a cache keyed on it
+    pub fn downgrade(&self) -> WeakIndex {
+        WeakIndex { shared: Arc::downgrade(&self.shared) }
+    }
+
+    /// The current snapshot
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        current(&self.shared)
+    }
+
+    /// A copy of a stored document of the current snapshot, text included
+    /// Text that isn't stored (see IndexOptions) is read from the file, and left empty if that fails
+    // Copies, because a reference couldn't outlive the snapshot it points into
+    pub fn get_document(&self, id: DocId) -> Option<Document> {
+        let snapshot = self.snapshot();
+        let document = snapshot.document(id)?;
+        // Stored text is shared, not copied
+        let text = match snapshot.document_text(id) {
+            Ok(Cow::Borrowed(_)) => Arc::clone(&document.text),
+            Ok(Cow::Owned(text)) => text.into(),
+            Err(_) => Arc::from(""),
+        };
+        Some(Document { text, ..document.clone() })
+    }
+
+    /// Like get_document, looking the document up by path
+    pub fn get_document_by_path(&self, path: &str) -> Option<Document> {
+        self.get_document(self.snapshot().doc_id(path)?)
+    }
+
+    /// A copy of a chunk of the current snapshot, its doc field leads to the document
+    pub fn get_chunk(&self, id: ChunkId) -> Option<Chunk> {
+        let snapshot = self.snapshot();
+        let chunk = snapshot.chunk(id)?;
+        let text = match snapshot.chunk_text(id) {
+            Ok(Cow::Borrowed(_)) => chunk.text.clone(),
+            Ok(Cow::Owned(text)) => text.into(),
+            Err(_) => ChunkText::default(),
+        };
+        Some(Chunk { text, ..chunk.clone() })
+    }
+
+    /// A chunk of the current snapshot with the text around it, see context.rs
+    pub fn expand_context(&self, id: ChunkId, context: Context) -> Result<ExpandedChunk, Box<dyn Error>> {
+        expand_context(&self.snapshot(), id, context)
+    }
+
+    /// Passages of the current snapshot for an LLM prompt, see retrieve.rs
+    pub fn retrieve_for_prompt(&self, query: &str, max_total_tokens: usize) -> Result<Vec<Passage>, Box<dyn Error>> {
+        retrieve_for_prompt(&self.snapshot(), query, max_total_tokens)
+    }
+
+    /// Number of chunks the query matches in the current snapshot, see Corpus::count
+    pub fn count(&self, query: &str) -> Result<usize, QueryError> {
+        self.snapshot().count(query)
+    }
+
+    /// Write every term of the current snapshot with its df, cf and idf as TSV, see vocabulary.rs
+    pub fn export_vocabulary(&self, path: &Path) -> io::Result<()> {
+        write_vocabulary(&vocabulary(&self.snapshot()), BufWriter::new(File::create(path)?))
+    }
+
+    /// Swap in a freshly built corpus, e.g. after a background reindex
+    // Queries that already hold the previous snapshot finish against it undisturbed
+    pub fn replace(&self, corpus: Corpus) -> u64 {
+        let _writer = self.shared.writer.lock().unwrap();
+        publish(&self.shared, corpus)
+    }
+
+    /// Swap in a corpus built from the snapshot of generation expected, None without swapping if
+    /// another update was published in the meantime
+    // The check and the swap happen under the writer lock, two callers that both built on the
+    // same snapshot can't both succeed and lose one of the updates
+    pub fn replace_if(&self, expected: u64, corpus: Corpus) -> Option<u64> {
+        let _writer = self.shared.writer.lock().unwrap();
+        (current(&self.shared).generation == expected).then(|| publish(&self.shared, corpus))
+    }
+
+    /// Copy-on-write update: the change is applied to a copy of the current corpus,
+    /// which is then published atomically
+    pub fn update<F>(&self, change: F) -> u64
+    where
+        F: FnOnce(&mut Corpus),
+    {
+        let _writer = self.shared.writer.lock().unwrap();
+        let mut corpus = current(&self.shared).corpus.clone();
+        change(&mut corpus);
+        publish(&self.shared, corpus)
+    }
+}
+
+impl IndexReader {
+    /// The current snapshot, every query should use a single snapshot from start to end
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        current(&self.shared)
+    }
+}
+
+fn current(shared: &Shared) -> Arc<Snapshot> {
+    // The read lock is only held long enough to clone the Arc, never during a query
+    Arc::clone(&shared.current.read().unwrap())
+}
+
+fn publish(shared: &Shared, corpus: Corpus) -> u64 {
+    let mut current = shared.current.write().unwrap();
+    let generation = current.generation + 1;
+    *current = Arc::new(Snapshot { generation, corpus });
+    generation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use std::thread;
+
+    fn corpus(text: &str) -> Corpus {
+        Corpus::new(vec![Document::new("doc.txt", text)], ChunkingConfig::default())
+    }
+
+    #[test]
+    fn test_readers_keep_their_snapshot_during_updates() {
+        let index = Index::new(corpus("old rust text"));
+        let reader = index.reader();
+        let before = reader.snapshot();
+
+        let generation = index.update(|corpus| {
+            let id = corpus.doc_id("doc.txt").unwrap();
+            corpus.remove_document(id);
+        });
+        assert_eq!(generation, 1);
+        // A corpus built from generation 0 would drop the update, so it isn't swapped in
+        assert_eq!(index.replace_if(0, corpus("stale")), None);
+        assert_eq!(index.replace_if(1, corpus("old rust text")), Some(2));
+        assert_eq!(index.replace(corpus("")), 3);
+
+        // The snapshot taken before the update is unchanged, new snapshots see the update
+        assert_eq!(before.search_chunks("rust").len(), 1);
+        assert_eq!(reader.snapshot().search_chunks("rust").len(), 0);
+    }
+
+    #[test]
+    fn test_queries_from_other_threads_while_reindexing() {
+        let index = Index::new(corpus("rust"));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = index.reader();
+                // move transfers ownership of the reader into the thread
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let snapshot = reader.snapshot();
+                        // Every snapshot is internally consistent: one document, one matching chunk
+                        assert_eq!(snapshot.search_chunks("rust").len(), 1);
+                    }
+                })
+            })
+            .collect();
+
+        for i in 0..10 {
+            index.replace(corpus(&format!("rust version {}", i)));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(index.snapshot().generation(), 10);
+    }
+
+    #[test]
+    fn test_get_document_and_chunk() {
+        let index = Index::new(corpus("stored rust text"));
+        let document = index.get_document_by_path("doc.txt").unwrap();
+        assert_eq!(&*document.text, "stored rust text");
+        assert_eq!(index.get_document(document.id).unwrap().path, "doc.txt");
+        let chunk = index.get_chunk(ChunkId(0)).unwrap();
+        assert_eq!((chunk.doc, chunk.text.as_str()), (document.id, "stored rust text"));
+        assert!(index.get_chunk(ChunkId(1)).is_none());
+        assert!(index.get_document_by_path("other.txt").is_none());
+    }
+}
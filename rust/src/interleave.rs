@@ -0,0 +1,199 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::corpus::SplitMix64;
+use crate::search::SearchResult;
+
+// Judging results takes an expert and a lot of time, clicking on the result you wanted takes
+// neither. Team-draft interleaving (Radlinski et al., 2008) merges the rankings of two rankers
+// into one list, the way captains pick teams: a coin decides who picks first in each round, and
+// each ranker then adds its best result that isn't in the list yet. The user only ever sees one
+// list, and every click goes to the ranker that contributed the result. Over many queries, the
+// ranker whose results are chosen more often is the one users prefer, with far fewer queries
+// than comparing click rates of two separate rankings would take
+
+/// Which ranker contributed a result to an interleaved list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Team {
+    A,
+    B,
+}
+
+/// Two rankings merged into one, with the team of every result
+pub struct Interleaved {
+    pub results: Vec<SearchResult>,
+    pub teams: Vec<Team>,
+}
+
+/// Merge the rankings a and b into at most top results by team draft. The same seed gives the same list
+pub fn team_draft(a: &[SearchResult], b: &[SearchResult], top: usize, seed: u64) -> Interleaved {
+    let mut rng = SplitMix64(seed);
+    let mut interleaved = Interleaved { results: Vec::new(), teams: Vec::new() };
+    // A result is the same whichever ranker found it, the scores differ
+    let mut taken = HashSet::new();
+    let (mut next_a, mut next_b) = (0, 0);
+    let (mut picks_a, mut picks_b) = (0, 0);
+    while interleaved.results.len() < top {
+        // Skip what the other team already picked
+        while next_a < a.len() && taken.contains(&(a[next_a].doc, a[next_a].chunk, a[next_a].line)) {
+            next_a += 1;
+        }
+        while next_b < b.len() && taken.contains(&(b[next_b].doc, b[next_b].chunk, b[next_b].line)) {
+            next_b += 1;
+        }
+        let a_left = next_a < a.len();
+        let b_left = next_b < b.len();
+        // The team with fewer picks goes next, a tie is decided by the coin. A team with
+        // nothing left to pick passes its turn
+        let team = match (a_left, b_left) {
+            (false, false) => break,
+            (true, false) => Team::A,
+            (false, true) => Team::B,
+            _ if picks_a != picks_b => if picks_a < picks_b { Team::A } else { Team::B },
+            _ => if rng.next() & 1 == 0 { Team::A } else { Team::B },
+        };
+        let result = match team {
+            Team::A => {
+                picks_a += 1;
+                next_a += 1;
+                &a[next_a - 1]
+            }
+            Team::B => {
+                picks_b += 1;
+                next_b += 1;
+                &b[next_b - 1]
+            }
+        };
+        taken.insert((result.doc, result.chunk, result.line));
+        interleaved.results.push(result.clone());
+        interleaved.teams.push(team);
+    }
+    interleaved
+}
+
+impl Interleaved {
+    /// Clicks per team, for the 0-based positions that were clicked. Unknown positions are ignored
+    pub fn credit(&self, clicks: &[usize]) -> (usize, usize) {
+        let team = |team| clicks.iter().filter(|click| self.teams.get(**click) == Some(&team)).count();
+        (team(Team::A), team(Team::B))
+    }
+}
+
+/// The outcome of one interleaved query, a line of an interleaving log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    pub query: String,
+    /// The names of the two rankers, e.g. "tfidf" and "bm25 k1=1.2 b=0.75"
+    pub a: String,
+    pub b: String,
+    /// The clicked results, as `path#chunk index`
+    pub clicks: Vec<String>,
+    pub credit_a: usize,
+    pub credit_b: usize,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl Outcome {
+    pub fn new(query: &str, a: &str, b: &str, clicks: Vec<String>, credit: (usize, usize)) -> Outcome {
+        Outcome {
+            query: query.to_string(),
+            a: a.to_string(),
+            b: b.to_string(),
+            clicks,
+            credit_a: credit.0,
+            credit_b: credit.1,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+        }
+    }
+
+    /// Append the outcome to a JSONL log, created if it doesn't exist yet
+    pub fn record(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Every outcome of a log, in the order they were recorded
+pub fn read_outcomes(path: &Path) -> Result<Vec<Outcome>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let mut outcomes = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if !line.trim().is_empty() {
+            outcomes.push(serde_json::from_str(line).map_err(|e| format!("{} line {}: {}", path.display(), number + 1, e))?);
+        }
+    }
+    Ok(outcomes)
+}
+
+/// Preferences over a log: the queries each ranker won, the ties, and the queries nothing was clicked for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tally {
+    pub wins: BTreeMap<String, usize>,
+    pub ties: usize,
+    pub no_clicks: usize,
+}
+
+pub fn tally(outcomes: &[Outcome]) -> Tally {
+    let mut tally = Tally::default();
+    for outcome in outcomes {
+        // By name, so logs whose sessions put the rankers on different teams still add up
+        if outcome.credit_a + outcome.credit_b == 0 {
+            tally.no_clicks += 1;
+            continue;
+        }
+        match outcome.credit_a.cmp(&outcome.credit_b) {
+            Ordering::Greater => *tally.wins.entry(outcome.a.clone()).or_default() += 1,
+            Ordering::Less => *tally.wins.entry(outcome.b.clone()).or_default() += 1,
+            Ordering::Equal => tally.ties += 1,
+        }
+    }
+    tally
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::{Corpus, Document};
+
+    #[test]
+    fn test_team_draft_credits_clicks() {
+        let corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "rust rust rust borrow"))
+            .add_document(Document::new("b.txt", "rust garbage collector with a long tail of other words"))
+            .add_document(Document::new("c.txt", "rust"))
+            .add_document(Document::new("d.txt", "python interpreter"))
+            .build()
+            .unwrap();
+        let ranking = corpus.search("rust").unwrap();
+        let reversed: Vec<SearchResult> = ranking.iter().rev().cloned().collect();
+        for seed in 0..8 {
+            let interleaved = team_draft(&ranking, &reversed, 10, seed);
+            // Every result once, and the teams take turns picking
+            assert_eq!(interleaved.results.len(), 3);
+            let picks_a = interleaved.teams.iter().filter(|team| **team == Team::A).count();
+            assert!(picks_a == 1 || picks_a == 2);
+            // The first result is the top of whichever ranker picked first
+            let first = if interleaved.teams[0] == Team::A { &ranking[0] } else { &reversed[0] };
+            assert_eq!(interleaved.results[0].chunk, first.chunk);
+            let credit = interleaved.credit(&[0, 7]);
+            assert_eq!(credit, if interleaved.teams[0] == Team::A { (1, 0) } else { (0, 1) });
+        }
+        assert_eq!(team_draft(&ranking, &[], 2, 0).teams, [Team::A, Team::A]);
+
+        let outcomes = [
+            Outcome::new("rust", "tfidf", "bm25", vec!["a.txt#0".to_string()], (1, 0)),
+            Outcome::new("borrow", "bm25", "tfidf", vec!["a.txt#0".to_string()], (1, 0)),
+            Outcome::new("python", "tfidf", "bm25", Vec::new(), (0, 0)),
+        ];
+        let tally = tally(&outcomes);
+        assert_eq!((tally.wins["tfidf"], tally.wins["bm25"], tally.ties, tally.no_clicks), (1, 1, 0, 1));
+    }
+}
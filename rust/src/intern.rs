@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use rustc_hash::FxHashMap;
+
+// A symbol table: every distinct string is stored once and referred to by a small integer.
+// Hashing and comparing a u32 is much cheaper than hashing a string, and a structure keyed by
+// symbols (a Vec indexed by them) holds one copy of each string however often it occurs.
+// The maps use FxHash, a few multiplications per word instead of SipHash's rounds: terms come
+// from the corpus being indexed, not from someone trying to make lookups collide
+
+/// Id of an interned string, only meaningful for the Interner that returned it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(pub u32);
+
+/// Assigns every distinct string a Symbol, in the order they are first seen
+// Strings are never removed, a symbol stays valid as long as the interner. An owner whose strings
+// come and go rebuilds the interner instead, see InvertedIndex
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    // The map and the list share the same allocation for every string
+    ids: FxHashMap<Arc<str>, Symbol>,
+    strings: Vec<Arc<str>>,
+}
+
+impl Interner {
+    /// The symbol of the string, adding it if it is new
+    pub fn intern(&mut self, text: &str) -> Symbol {
+        if let Some(symbol) = self.ids.get(text) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.strings.len() as u32);
+        let text: Arc<str> = Arc::from(text);
+        self.strings.push(Arc::clone(&text));
+        self.ids.insert(text, symbol);
+        symbol
+    }
+
+    /// The symbol of the string, None if it was never interned
+    pub fn get(&self, text: &str) -> Option<Symbol> {
+        self.ids.get(text).copied()
+    }
+
+    /// The string of a symbol from this interner
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.strings[symbol.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Every symbol with its string, in symbol order
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, &str)> {
+        self.strings.iter().enumerate().map(|(i, text)| (Symbol(i as u32), &**text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_and_resolve() {
+        let mut interner = Interner::default();
+        let rust = interner.intern("rust");
+        let borrow = interner.intern("borrow");
+        assert_eq!(interner.intern("rust"), rust);
+        assert_eq!((rust, borrow), (Symbol(0), Symbol(1)));
+        assert_eq!(interner.resolve(borrow), "borrow");
+        assert_eq!(interner.get("python"), None);
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.iter().map(|(_, text)| text).collect::<Vec<_>>(), ["rust", "borrow"]);
+    }
+}
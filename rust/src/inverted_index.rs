@@ -0,0 +1,456 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use crate::analyzer::Analyzer;
+use crate::chunker::Chunk;
+use crate::corpus::ChunkId;
+use crate::intern::{Interner, Symbol};
+use crate::search::Score;
+use crate::tfidf::{IdfScheme, TfScheme};
+
+/// One occurrence list entry: a chunk that contains the term, how often and where
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Posting {
+    pub chunk: ChunkId,
+    pub tf: u32,
+    /// Token positions of every occurrence, in increasing order, used by phrase and proximity queries
+    pub positions: Vec<u32>,
+}
+
+/// Which terms to drop from the vocabulary based on how many chunks they occur in
+// The same idea as scikit-learn's min_df / max_df: terms in one or two chunks are mostly typos
+// and terms in nearly every chunk carry almost no information but have the longest postings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DfPruning {
+    /// Drop terms that occur in fewer chunks than this
+    pub min_df: usize,
+    /// Drop terms that occur in more than this fraction of all chunks, 1.0 keeps everything
+    pub max_df_ratio: f32,
+}
+
+impl Default for DfPruning {
+    fn default() -> DfPruning {
+        DfPruning { min_df: 1, max_df_ratio: 1.0 }
+    }
+}
+
+/// Per-chunk term counts, what the different length normalizations divide by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ChunkTerms {
+    /// Number of terms, after analysis
+    pub len: u32,
+    /// Number of distinct terms
+    pub unique: u32,
+    /// Term frequency of the most frequent term
+    pub max_tf: u32,
+}
+
+/// Maps every analyzed term to the chunks containing it
+// This is what makes scoring fast: instead of scanning every chunk's text for every
+// query term, we look the term up once and only visit the chunks that contain it.
+// Terms are interned: each is stored once and its postings list sits at its symbol in a Vec, so
+// the per-chunk counting hashes small integers and a term's String is kept once however often it
+// occurs. The analyzer still hands over a String per token, interning doesn't save that one.
+// Removing chunks or terms leaves dead symbols behind; they are compacted away once they
+// outnumber the live ones, so an index that keeps changing doesn't grow without bound
+#[derive(Debug, Clone, Default)]
+pub struct InvertedIndex {
+    terms: Interner,
+    /// Postings of every term by symbol, empty for terms that were removed or pruned
+    postings: Vec<Vec<Posting>>,
+    /// Term counts of every chunk
+    chunks: BTreeMap<ChunkId, ChunkTerms>,
+    total_length: u64,
+    total_unique: u64,
+    cache: StatsCache,
+}
+
+/// Statistics over the whole index that queries reuse: the IDF of every term and cosine norms
+// They take a pass over every postings list to compute, so they are kept between queries instead
+// of being computed per query, and dropped by every method that changes the index
+#[derive(Default)]
+pub(crate) struct StatsCache {
+    // IDF of every term by symbol
+    idf: RwLock<HashMap<IdfScheme, Arc<Vec<Score>>>>,
+    pub(crate) cosine: RwLock<HashMap<(TfScheme, IdfScheme), Arc<CosineNorms>>>,
+}
+
+/// Euclidean length of every chunk's TF-IDF vector, see tfidf::cosine_norms
+pub(crate) type CosineNorms = HashMap<ChunkId, Score>;
+
+impl StatsCache {
+    fn clear(&mut self) {
+        self.idf.get_mut().unwrap().clear();
+        self.cosine.get_mut().unwrap().clear();
+    }
+}
+
+// A clone has the same terms and chunks, so it shares the tables computed so far
+impl Clone for StatsCache {
+    fn clone(&self) -> StatsCache {
+        StatsCache { idf: RwLock::new(self.idf.read().unwrap().clone()), cosine: RwLock::new(self.cosine.read().unwrap().clone()) }
+    }
+}
+
+impl fmt::Debug for StatsCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StatsCache")
+    }
+}
+
+// Saved as a term -> postings map like before interning, so index files don't change format
+#[derive(Serialize)]
+struct IndexRef<'a> {
+    postings: HashMap<&'a str, &'a [Posting]>,
+    chunks: &'a BTreeMap<ChunkId, ChunkTerms>,
+    total_length: u64,
+    total_unique: u64,
+}
+
+#[derive(Deserialize)]
+struct StoredIndex {
+    postings: HashMap<String, Vec<Posting>>,
+    chunks: BTreeMap<ChunkId, ChunkTerms>,
+    total_length: u64,
+    total_unique: u64,
+}
+
+impl Serialize for InvertedIndex {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        IndexRef {
+            postings: self.live_terms().map(|(symbol, term)| (term, self.postings_of(symbol))).collect(),
+            chunks: &self.chunks,
+            total_length: self.total_length,
+            total_unique: self.total_unique,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for InvertedIndex {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<InvertedIndex, D::Error> {
+        let stored = StoredIndex::deserialize(deserializer)?;
+        let mut index = InvertedIndex {
+            chunks: stored.chunks,
+            total_length: stored.total_length,
+            total_unique: stored.total_unique,
+            ..InvertedIndex::default()
+        };
+        // Sorted, so loading the same file always gives the same symbols
+        let mut postings: Vec<(String, Vec<Posting>)> = stored.postings.into_iter().collect();
+        postings.sort_by(|a, b| a.0.cmp(&b.0));
+        for (term, list) in postings {
+            let symbol = index.intern(&term);
+            index.postings[symbol.0 as usize] = list;
+        }
+        Ok(index)
+    }
+}
+
+impl InvertedIndex {
+    /// Analyze every chunk and record its terms
+    pub fn build(chunks: &[Chunk], analyzer: &Analyzer) -> InvertedIndex {
+        let mut index = InvertedIndex::default();
+        for chunk in chunks {
+            index.add_chunk(chunk, analyzer);
+        }
+        index
+    }
+
+    // Chunks must be added in increasing id order, which keeps every postings list sorted by chunk
+    pub fn add_chunk(&mut self, chunk: &Chunk, analyzer: &Analyzer) {
+        let tokens = analyzer.tokens(&chunk.text);
+        let mut positions: FxHashMap<Symbol, Vec<u32>> = FxHashMap::default();
+        for token in &tokens {
+            // entry() inserts an empty list the first time a term is seen, then we add to it
+            let symbol = self.intern(&token.text);
+            positions.entry(symbol).or_default().push(token.position);
+        }
+        let terms = ChunkTerms {
+            len: tokens.len() as u32,
+            unique: positions.len() as u32,
+            max_tf: positions.values().map(|p| p.len() as u32).max().unwrap_or(0),
+        };
+        self.cache.clear();
+        for (symbol, positions) in positions {
+            let tf = positions.len() as u32;
+            self.postings[symbol.0 as usize].push(Posting { chunk: chunk.id, tf, positions });
+        }
+        self.chunks.insert(chunk.id, terms);
+        self.total_length += terms.len as u64;
+        self.total_unique += terms.unique as u64;
+    }
+
+    /// Forget the given chunks, dropping terms that no longer occur anywhere
+    pub fn remove_chunks(&mut self, ids: &HashSet<ChunkId>) {
+        self.cache.clear();
+        for postings in &mut self.postings {
+            postings.retain(|p| !ids.contains(&p.chunk));
+        }
+        for id in ids {
+            if let Some(terms) = self.chunks.remove(id) {
+                self.total_length -= terms.len as u64;
+                self.total_unique -= terms.unique as u64;
+            }
+        }
+        self.compact();
+    }
+
+    /// Add every posting of another index, with its chunk ids shifted up by offset
+    // The other index's chunks must all end up above this index's chunks after shifting, so
+    // appending keeps every postings list sorted. Document frequencies add up by construction
+    pub fn append(&mut self, other: &InvertedIndex, offset: u32) {
+        self.cache.clear();
+        let shift = |id: ChunkId| ChunkId(id.0 + offset);
+        for (symbol, term) in other.live_terms() {
+            let shifted = other.postings_of(symbol).iter().map(|p| Posting { chunk: shift(p.chunk), ..p.clone() });
+            let mine = self.intern(term);
+            self.postings[mine.0 as usize].extend(shifted);
+        }
+        self.chunks.extend(other.chunks.iter().map(|(id, terms)| (shift(*id), *terms)));
+        self.total_length += other.total_length;
+        self.total_unique += other.total_unique;
+    }
+
+    /// Keep only the terms the closure returns true for
+    pub fn retain_terms<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        self.cache.clear();
+        for (symbol, term) in self.terms.iter() {
+            if !keep(term) {
+                self.postings[symbol.0 as usize] = Vec::new();
+            }
+        }
+        self.compact();
+    }
+
+    /// Drop terms whose document frequency is outside the limits, returns how many were dropped
+    // Chunk lengths are left alone: a chunk doesn't get shorter because its words became unsearchable
+    pub fn prune(&mut self, pruning: &DfPruning) -> usize {
+        self.cache.clear();
+        let max_df = pruning.max_df_ratio * self.num_chunks() as f32;
+        let mut dropped = 0;
+        for postings in self.postings.iter_mut().filter(|postings| !postings.is_empty()) {
+            if postings.len() < pruning.min_df || postings.len() as f32 > max_df {
+                *postings = Vec::new();
+                dropped += 1;
+            }
+        }
+        self.compact();
+        dropped
+    }
+
+    /// Terms in at least min_df_ratio of the chunks with their document frequency, the most common
+    /// first: candidates for stopwords of this corpus
+    // Unlike prune nothing is dropped, "python" in a Python corpus may be noise or may be the point
+    pub fn frequent_terms(&self, min_df_ratio: f32) -> Vec<(&str, usize)> {
+        let min_df = min_df_ratio * self.num_chunks() as f32;
+        let mut terms: Vec<(&str, usize)> =
+            self.terms().map(|term| (term, self.doc_freq(term))).filter(|(_, df)| *df as f32 >= min_df).collect();
+        terms.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        terms
+    }
+
+    /// Chunks containing the term, empty if the term is unknown
+    pub fn postings(&self, term: &str) -> &[Posting] {
+        self.terms.get(term).map(|symbol| self.postings_of(symbol)).unwrap_or(&[])
+    }
+
+    /// Positions of the term in one chunk, empty if it doesn't occur there
+    pub fn positions(&self, term: &str, chunk: ChunkId) -> &[u32] {
+        let postings = self.postings(term);
+        // Postings are sorted by chunk id, so we can binary search instead of scanning
+        match postings.binary_search_by_key(&chunk, |p| p.chunk) {
+            Ok(i) => &postings[i].positions,
+            Err(_) => &[],
+        }
+    }
+
+    /// IDF of the term under the scheme, looked up in a table of every term's IDF
+    /// The table is computed on the first call for each scheme and kept until the index changes
+    pub fn idf(&self, term: &str, scheme: IdfScheme) -> Score {
+        let Some(symbol) = self.terms.get(term) else {
+            return scheme.idf(self.num_chunks(), 0);
+        };
+        let cached = self.cache.idf.read().unwrap().get(&scheme).cloned();
+        let table = cached.unwrap_or_else(|| {
+            let n = self.num_chunks();
+            let table = Arc::new(self.postings.iter().map(|postings| scheme.idf(n, postings.len())).collect::<Vec<Score>>());
+            self.cache.idf.write().unwrap().insert(scheme, Arc::clone(&table));
+            table
+        });
+        table[symbol.0 as usize]
+    }
+
+    pub(crate) fn cache(&self) -> &StatsCache {
+        &self.cache
+    }
+
+    /// Number of chunks that contain the term
+    pub fn doc_freq(&self, term: &str) -> usize {
+        self.postings(term).len()
+    }
+
+    /// Total number of occurrences of the term across all chunks
+    pub fn collection_freq(&self, term: &str) -> u64 {
+        self.postings(term).iter().map(|p| p.tf as u64).sum()
+    }
+
+    pub fn chunk_len(&self, id: ChunkId) -> u32 {
+        self.chunk_terms(id).len
+    }
+
+    pub fn chunk_terms(&self, id: ChunkId) -> ChunkTerms {
+        self.chunks.get(&id).copied().unwrap_or_default()
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Average number of terms per chunk
+    pub fn avg_len(&self) -> Score {
+        if self.chunks.is_empty() {
+            return 0.0;
+        }
+        self.total_length as Score / self.chunks.len() as Score
+    }
+
+    /// Average number of distinct terms per chunk
+    pub fn avg_unique(&self) -> Score {
+        if self.chunks.is_empty() {
+            return 0.0;
+        }
+        self.total_unique as Score / self.chunks.len() as Score
+    }
+
+    /// Every distinct term in the index, in no particular order
+    pub fn terms(&self) -> impl Iterator<Item = &str> {
+        self.live_terms().map(|(_, term)| term)
+    }
+
+    // Interned terms can outlive their postings, only the ones still in some chunk count
+    fn live_terms(&self) -> impl Iterator<Item = (Symbol, &str)> {
+        self.terms.iter().filter(|(symbol, _)| !self.postings_of(*symbol).is_empty())
+    }
+
+    fn postings_of(&self, symbol: Symbol) -> &[Posting] {
+        &self.postings[symbol.0 as usize]
+    }
+
+    // Re-intern the live terms once most symbols are dead, the interner itself never forgets one.
+    // Symbols don't leave the index and the tables keyed by them are cleared on every change
+    fn compact(&mut self) {
+        let dead = self.postings.iter().filter(|postings| postings.is_empty()).count();
+        if dead <= self.postings.len() / 2 {
+            return;
+        }
+        let terms = std::mem::take(&mut self.terms);
+        let mut postings = std::mem::take(&mut self.postings);
+        for (symbol, term) in terms.iter() {
+            let list = std::mem::take(&mut postings[symbol.0 as usize]);
+            if !list.is_empty() {
+                let symbol = self.intern(term);
+                self.postings[symbol.0 as usize] = list;
+            }
+        }
+    }
+
+    // The symbol of the term, with a postings list for it
+    fn intern(&mut self, term: &str) -> Symbol {
+        let symbol = self.terms.intern(term);
+        if self.postings.len() < self.terms.len() {
+            self.postings.resize_with(self.terms.len(), Vec::new);
+        }
+        symbol
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::DocId;
+    use crate::analyzer::Stopwords;
+
+    #[test]
+    fn test_build_and_remove() {
+        let chunks = vec![
+            Chunk { id: ChunkId(0), doc: DocId(0), index: 0, text: "Rust rust borrow".into() },
+            Chunk { id: ChunkId(1), doc: DocId(1), index: 0, text: "python".into() },
+        ];
+        let mut index = InvertedIndex::build(&chunks, &Analyzer::default());
+
+        assert_eq!(index.postings("rust"), &[Posting { chunk: ChunkId(0), tf: 2, positions: vec![0, 1] }]);
+        assert_eq!(index.positions("borrow", ChunkId(0)), &[2]);
+        assert_eq!(index.doc_freq("python"), 1);
+        assert_eq!(index.avg_len(), 2.0);
+        assert_eq!(index.chunk_terms(ChunkId(0)), ChunkTerms { len: 3, unique: 2, max_tf: 2 });
+
+        // "rust" and "borrow" are in 1 of 2 chunks, "python" too, so only a ratio below 0.5 drops anything
+        assert_eq!(index.clone().prune(&DfPruning { min_df: 2, max_df_ratio: 1.0 }), 3);
+        assert_eq!(index.clone().prune(&DfPruning { min_df: 1, max_df_ratio: 0.4 }), 3);
+        assert_eq!(index.clone().prune(&DfPruning::default()), 0);
+
+        index.remove_chunks(&HashSet::from([ChunkId(1)]));
+        assert_eq!(index.doc_freq("python"), 0);
+        assert_eq!(index.num_chunks(), 1);
+        assert_eq!(index.avg_len(), 3.0);
+    }
+
+    #[test]
+    fn test_interned_terms_round_trip() {
+        let chunks = vec![
+            Chunk { id: ChunkId(0), doc: DocId(0), index: 0, text: "rust borrow".into() },
+            Chunk { id: ChunkId(1), doc: DocId(1), index: 0, text: "python rust".into() },
+        ];
+        let mut index = InvertedIndex::build(&chunks, &Analyzer::default());
+        index.remove_chunks(&HashSet::from([ChunkId(1)]));
+        let mut terms: Vec<&str> = index.terms().collect();
+        terms.sort();
+        assert_eq!(terms, ["borrow", "rust"]);
+
+        // Saved as a plain term -> postings map, without the removed term
+        let json = serde_json::to_value(&index).unwrap();
+        assert_eq!(json["postings"].as_object().unwrap().len(), 2);
+        let loaded: InvertedIndex = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.postings("rust"), index.postings("rust"));
+        assert_eq!(loaded.doc_freq("python"), 0);
+        assert_eq!(loaded.avg_len(), 2.0);
+
+        // Once most symbols are dead they are dropped, re-adding a term gives it a fresh symbol
+        index.remove_chunks(&HashSet::from([ChunkId(0)]));
+        assert_eq!(index.terms.len(), 0);
+        index.add_chunk(&chunks[1], &Analyzer::default());
+        assert_eq!(index.terms.len(), 2);
+        assert_eq!(index.doc_freq("python"), 1);
+    }
+
+    #[test]
+    fn test_idf_cache_follows_changes() {
+        let chunk = |id: u32, text: &str| Chunk { id: ChunkId(id), doc: DocId(id), index: 0, text: text.into() };
+        let analyzer = Analyzer::default();
+        let mut index = InvertedIndex::build(&[chunk(0, "rust"), chunk(1, "python")], &analyzer);
+        assert_eq!(index.idf("rust", IdfScheme::Plain), Score::ln(2.0));
+        assert_eq!(index.idf("go", IdfScheme::Plain), 0.0);
+        // Adding a chunk with the term changes both N and df, the cached table must not be reused
+        index.add_chunk(&chunk(2, "rust"), &analyzer);
+        assert_eq!(index.idf("rust", IdfScheme::Plain), Score::ln(1.5));
+        index.remove_chunks(&HashSet::from([ChunkId(0)]));
+        assert_eq!(index.idf("rust", IdfScheme::Plain), Score::ln(2.0));
+    }
+
+    #[test]
+    fn test_frequent_terms_as_stopwords() {
+        let chunk = |id: u32, text: &str| Chunk { id: ChunkId(id), doc: DocId(id), index: 0, text: text.into() };
+        let chunks = [chunk(0, "python error in parser"), chunk(1, "python error"), chunk(2, "python lexer"), chunk(3, "go")];
+        let index = InvertedIndex::build(&chunks, &Analyzer::default());
+        let frequent = index.frequent_terms(0.5);
+        assert_eq!(frequent, [("python", 3), ("error", 2)]);
+
+        let list: String = frequent.iter().map(|(term, _)| format!("{}\n", term)).collect();
+        let analyzer = Analyzer::default().with_filter(Stopwords::parse(&format!("# proposed\n{}", list)));
+        let index = InvertedIndex::build(&chunks, &analyzer);
+        assert_eq!((index.doc_freq("python"), index.doc_freq("parser")), (0, 1));
+    }
+}
@@ -0,0 +1,64 @@
+pub mod tfidf;
+pub mod smart;
+pub mod bm25;
+pub mod ltr;
+pub mod chunker;
+pub mod search;
+pub mod loader;
+pub mod normalize;
+pub mod corpus;
+pub mod progress;
+pub mod cancel;
+pub mod limits;
+pub mod index;
+pub mod shard;
+pub mod analyzer;
+pub mod persist;
+pub mod storage;
+pub mod inverted_index;
+pub mod vocabulary;
+pub mod intern;
+pub mod kernel;
+pub mod query;
+pub mod plan;
+pub mod rewrite;
+pub mod filter;
+pub mod boosts;
+pub mod fields;
+pub mod eval;
+pub mod querylog;
+pub mod interleave;
+pub mod stats;
+pub mod explain;
+pub mod checkpoint;
+pub mod refresh;
+pub mod segments;
+pub mod percolate;
+pub mod summarize;
+pub mod context;
+pub mod retrieve;
+pub mod mcp;
+pub mod cache;
+pub mod ratelimit;
+pub mod registry;
+pub mod federate;
+pub mod classify;
+pub mod cluster;
+pub mod similarity;
+#[cfg(feature = "async")]
+pub mod async_api;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "grpc")]
+pub mod metrics;
+#[cfg(feature = "arrow")]
+pub mod export;
+#[cfg(any(feature = "ndarray", feature = "polars"))]
+pub mod matrix;
+pub mod compress;
+#[cfg(feature = "s3")]
+pub mod objects;
+#[cfg(feature = "wikipedia")]
+pub mod wikipedia;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
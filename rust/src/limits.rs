@@ -0,0 +1,77 @@
+use std::error::Error;
+use std::fmt;
+
+// Bounds for indexing on small machines. Loading opens at most max_open_files files (or object
+// downloads) at once, whatever the size of the directory. Postings are what an index spends its
+// memory on, one per distinct term of every chunk: a CorpusBuilder that grows past max_postings
+// stops with LimitExceeded instead of taking the machine down, and checkpoint::build_resumable
+// writes its segment out and starts the next one, so only one segment is ever being indexed
+
+/// Files read at once unless ResourceLimits says otherwise
+pub const DEFAULT_OPEN_FILES: usize = 16;
+
+/// Upper bounds on what loading and indexing hold at once, see CorpusBuilder::limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Files open at the same time while loading, object downloads in flight for s3:// sources
+    pub max_open_files: usize,
+    /// Postings held in memory before a segment is written out, None for no bound
+    pub max_postings: Option<usize>,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> ResourceLimits {
+        ResourceLimits { max_open_files: DEFAULT_OPEN_FILES, max_postings: None }
+    }
+}
+
+impl ResourceLimits {
+    pub fn new(max_open_files: usize, max_postings: Option<usize>) -> Result<ResourceLimits, String> {
+        if max_open_files == 0 {
+            return Err("at least one file has to be open to load anything".to_string());
+        }
+        if max_postings == Some(0) {
+            return Err("the postings limit must be at least 1".to_string());
+        }
+        Ok(ResourceLimits { max_open_files, max_postings })
+    }
+}
+
+/// The error of a CorpusBuilder whose index grew past ResourceLimits::max_postings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub max_postings: usize,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the index grew past {} postings, raise the limit or build with a checkpoint to index in segments",
+            self.max_postings
+        )
+    }
+}
+
+impl Error for LimitExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::{Corpus, Document};
+
+    #[test]
+    fn test_postings_limit_stops_the_build() {
+        assert!(ResourceLimits::new(0, None).is_err());
+        let builder = || {
+            Corpus::builder()
+                .add_document(Document::new("a.txt", "rust borrow checker"))
+                .add_document(Document::new("b.txt", "python garbage collector rust"))
+        };
+        // Three distinct terms in one chunk and four in the other
+        let exactly = builder().limits(ResourceLimits::new(1, Some(7)).unwrap()).build().unwrap();
+        assert_eq!(exactly.documents().len(), 2);
+        let error = builder().limits(ResourceLimits::new(1, Some(6)).unwrap()).build().err().unwrap();
+        assert_eq!(error.downcast_ref::<LimitExceeded>(), Some(&LimitExceeded { max_postings: 6 }));
+    }
+}
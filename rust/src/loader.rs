@@ -1,15 +1,58 @@
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::ffi::OsStr;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+/// A file read by load_directory_files
+#[derive(Debug, Clone)]
+pub struct LoadedFile {
+    pub path: String,
+    /// Where the file is on disk, path is only the part shown in results
+    pub file: PathBuf,
+    pub text: String,
+    /// Last modification time, None where the filesystem doesn't record it
+    pub modified: Option<SystemTime>,
+    /// Size of the file in bytes when it was read
+    pub size: u64,
+}
 
 pub fn load_directory(directory_path: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    load_directory_with_extensions(directory_path, &["txt"])
+}
+
+/// Load every file under a directory whose extension is one of extensions, e.g. &["rs", "py"]
+pub fn load_directory_with_extensions(
+    directory_path: &str,
+    extensions: &[&str],
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let files = load_directory_files(directory_path, extensions)?;
+    Ok(files.into_iter().map(|file| (file.path, file.text)).collect())
+}
+
+/// Like load_directory_with_extensions, keeping the modification time of every file
+pub fn load_directory_files(directory_path: &str, extensions: &[&str]) -> Result<Vec<LoadedFile>, Box<dyn Error>> {
+    // collect on an iterator of Results stops at the first error and returns it
+    list_directory_files(directory_path, extensions)?.into_iter().map(|(path, file)| read_file(path, file)).collect()
+}
+
+/// The files load_directory_files would load, as (path shown in results, file on disk), without reading them
+// Lets callers that index a huge directory in batches keep only one batch of text in memory
+pub fn list_directory_files(directory_path: &str, extensions: &[&str]) -> Result<Vec<(String, PathBuf)>, Box<dyn Error>> {
     // Create a mutable vector to store all files from directory and subdirectories
-    let mut files: Vec<(String, String)> = Vec::new();
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
     // Start recursive loading from the root directory path
     // The ? operator handles the error, if directory doesn't exist or we don't have permission,
     // then the function returns early with the error
-    load_directory_recursive(Path::new(directory_path), &mut files)?;
+    load_directory_recursive(Path::new(directory_path), extensions, &mut files)?;
+    // read_dir returns entries in whatever order the filesystem keeps them, sorting by path
+    // gives documents the same ids, and equal scores the same order, on every machine
+    files.sort_by(|a, b| a.0.cmp(&b.0));
     // Rust has implicit return - unlike C++ or C# where semicolon and return is mandatory,
     // in Rust no semicolon means "return this value"
     Ok(files)
@@ -18,7 +61,7 @@ pub fn load_directory(directory_path: &str) -> Result<Vec<(String, String)>, Box
 // Recursive helper function that does the actual directory traversal
 // Takes a Path reference and a mutable reference to the files vector
 // Returns Result<(), Box<dyn Error>> - either success (empty tuple) or error
-fn load_directory_recursive(dir: &Path, files: &mut Vec<(String, String)>) -> Result<(), Box<dyn Error>> {
+fn load_directory_recursive(dir: &Path, extensions: &[&str], files: &mut Vec<(String, PathBuf)>) -> Result<(), Box<dyn Error>> {
     // Read the directory of the path, the ? operator handles the error, if directory doesn't exist or
     // We do not have permission, then the function returns early
     let entries = fs::read_dir(dir)?; // entries is an iterator of Result<DirEntry, std::io::Error>
@@ -32,16 +75,16 @@ fn load_directory_recursive(dir: &Path, files: &mut Vec<(String, String)>) -> Re
         if path.is_dir() {
             // If this is a subdirectory, recursively process it
             // This allows us to find files in nested folders
-            load_directory_recursive(&path, files)?;
+            load_directory_recursive(&path, extensions, files)?;
         } else {
-            // Check if this file has a .txt extension
-            let extension: Option<&OsStr> = path.extension(); // None if no extension exists
+            // Check if this file has one of the wanted extensions
+            let extension: Option<&OsStr> = text_extension(&path); // None if no extension exists
 
             // and_then is used for chaining operations that might fail
             // so what we are doing is checking if extension is valid using and_then
-            // then we call to_str to change &OsStr to Option<&str> which we can compare to Some("txt")
-            // The reason we do not use == "txt" is because we do not have a &str, but an Option<&str>
-            if extension.and_then(|s| s.to_str()) == Some("txt") {
+            // then we call to_str to change &OsStr to Option<&str> which we can look up in extensions
+            // is_some_and is false for None, so files without an extension are skipped
+            if extension.and_then(|s| s.to_str()).is_some_and(|ext| extensions.contains(&ext)) {
                 // Include relative path from root directory for better context
                 // This gives us paths like "subdir/file.txt" instead of just "file.txt"
                 let filename = path
@@ -50,10 +93,7 @@ fn load_directory_recursive(dir: &Path, files: &mut Vec<(String, String)>) -> Re
                     .to_string_lossy() // Convert Path to String, handling any non-UTF8 characters
                     .to_string(); // Convert from Cow<str> to owned String
 
-                // fs::read_to_string returns io::Result<String>, io::Result<String> is a type alias for Result<String, io::Error>
-                // The ? operator propagates errors to the caller, if we skip ?, then we would have to handle Ok() and Err() here
-                let contents = fs::read_to_string(&path)?;
-                files.push((filename, contents));
+                files.push((filename, path));
             }
         }
     }
@@ -63,6 +103,298 @@ fn load_directory_recursive(dir: &Path, files: &mut Vec<(String, String)>) -> Re
     Ok(())
 }
 
+/// Extensions of the compressed files read_text decompresses
+pub const COMPRESSED_EXTENSIONS: [&str; 4] = ["gz", "zst", "xz", "bz2"];
+
+// The extension of the text inside: "txt" for both notes.txt and dump.txt.gz
+// Compressed files are only picked up by builds that can decompress them, so a stray backup.txt.gz
+// in a directory doesn't fail a build that never read it before
+fn text_extension(path: &Path) -> Option<&OsStr> {
+    let extension = path.extension()?;
+    let compressed = extension.to_str().is_some_and(|extension| COMPRESSED_EXTENSIONS.contains(&extension));
+    if compressed && cfg!(feature = "compressed") {
+        Path::new(path.file_stem()?).extension()
+    } else {
+        Some(extension)
+    }
+}
+
+/// A reader of a file, decompressing it if it ends in .gz, .zst, .xz or .bz2 and the `compressed` feature is on
+// Large dumps (Wikipedia, Common Crawl extracts) are nearly always distributed compressed, and
+// decompressing while reading saves unpacking a copy many times the size on disk first
+#[cfg(feature = "compressed")]
+pub fn open_text(file: &Path) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let reader = fs::File::open(file)?;
+    // Every decoder is a Read, whatever the format the text is read the same way
+    Ok(match file.extension().and_then(|extension| extension.to_str()) {
+        // Multi because concatenated archives are still one valid archive, and dumps use that
+        Some("gz") => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        Some("zst") => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        Some("xz") => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+        Some("bz2") => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        _ => Box::new(reader),
+    })
+}
+
+/// A reader of a file
+#[cfg(not(feature = "compressed"))]
+pub fn open_text(file: &Path) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    Ok(Box::new(fs::File::open(file)?))
+}
+
+/// The text of a file, decompressed like open_text does
+pub fn read_text(file: &Path) -> Result<String, Box<dyn Error>> {
+    let mut text = String::new();
+    open_text(file)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Read one file found by list_directory_files
+pub fn read_file(path: String, file: PathBuf) -> Result<LoadedFile, Box<dyn Error>> {
+    // read_text returns Result<String, Box<dyn Error>>, whatever went wrong: a missing file, a corrupt archive or bytes that aren't UTF-8
+    // The ? operator propagates errors to the caller, if we skip ?, then we would have to handle Ok() and Err() here
+    let text = read_text(&file)?;
+    let metadata = fs::metadata(&file)?;
+    // ok() turns the Result into an Option, a missing mtime only disables recency weighting
+    let modified = metadata.modified().ok();
+    Ok(LoadedFile { path, file, text, modified, size: metadata.len() })
+}
+
+/// Read files found by list_directory_files on up to max_open_files threads at once, handing
+/// them to each in the order they were listed. Stops at the first file that can't be read and at
+/// the first error each returns
+// Reading is mostly waiting on the disk, so a few threads read much faster than one, but every
+// one of them holds a file open. The threads take the next file from a shared counter and send
+// it back with its position, files that arrive early wait in pending until it's their turn. A
+// thread only starts on a file less than max_open_files past the last one handed on, so one slow
+// file can't let the others pile up in pending, at most max_open_files files are held at once
+pub fn read_files<F>(files: &[(String, PathBuf)], max_open_files: usize, mut each: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(LoadedFile) -> Result<(), Box<dyn Error>>,
+{
+    let window = max_open_files.max(1);
+    let next = AtomicUsize::new(0);
+    // Files handed to each so far, usize::MAX once reading stopped
+    let handed = (Mutex::new(0), Condvar::new());
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(window);
+        for _ in 0..window.min(files.len().max(1)) {
+            let sender = sender.clone();
+            let (next, handed) = (&next, &handed);
+            scope.spawn(move || loop {
+                let position = next.fetch_add(1, Ordering::Relaxed);
+                let Some((path, file)) = files.get(position) else {
+                    break;
+                };
+                let (count, turn) = handed;
+                let count = turn.wait_while(count.lock().unwrap(), |count| *count != usize::MAX && position >= *count + window).unwrap();
+                if *count == usize::MAX {
+                    break;
+                }
+                drop(count);
+                // Box<dyn Error> can't be sent between threads, the message is all that's reported
+                let read = read_file(path.clone(), file.clone()).map_err(|e| format!("{}: {}", file.display(), e));
+                // The receiver is gone once reading stopped early, there's nobody left to read for
+                if sender.send((position, read)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        let (count, turn) = &handed;
+        let hand_on = move || -> Result<(), Box<dyn Error>> {
+            let mut pending = BTreeMap::new();
+            let mut expected = 0;
+            for (position, read) in receiver {
+                pending.insert(position, read);
+                while let Some(read) = pending.remove(&expected) {
+                    each(read?)?;
+                    expected += 1;
+                    *count.lock().unwrap() = expected;
+                    turn.notify_all();
+                }
+            }
+            Ok(())
+        };
+        let result = hand_on();
+        // Returning from hand_on dropped the receiver, the threads still waiting for their turn stop too
+        *count.lock().unwrap() = usize::MAX;
+        turn.notify_all();
+        result
+    })
+}
+
+/// The prose and the code of a source file, see split_source
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceFields {
+    /// Comments, doc comments and string literals, one per line
+    pub docs: String,
+    /// Everything else, with the comments and strings blanked out
+    pub code: String,
+}
+
+// How comments and strings look in a language: (line comment, has /* block */ comments, string quotes)
+fn comment_syntax(extension: &str) -> Option<(&'static str, bool, &'static [&'static str])> {
+    match extension {
+        "rs" | "c" | "h" | "cc" | "cpp" | "hpp" | "java" | "cs" | "go" | "swift" | "kt" | "scala" => Some(("//", true, &["\""])),
+        "js" | "jsx" | "ts" | "tsx" | "php" => Some(("//", true, &["\"", "'", "`"])),
+        // Python's triple quoted docstrings come first, so they aren't read as an empty string and a quote
+        "py" => Some(("#", false, &["\"\"\"", "'''", "\"", "'"])),
+        "rb" | "sh" | "pl" | "r" | "toml" | "yaml" | "yml" => Some(("#", false, &["\"", "'"])),
+        "sql" | "lua" | "hs" => Some(("--", false, &["\"", "'"])),
+        _ => None,
+    }
+}
+
+/// Split source code into prose and code by the file's extension, None for unknown languages
+// A small lexer, not a parser: it knows where comments and strings start and end in each
+// language, which is all it takes to tell the words written for people from the code.
+// Comment markers (//, ///, /*, #) are left out of the docs, escaped quotes don't end strings
+pub fn split_source(text: &str, extension: &str) -> Option<SourceFields> {
+    let (line_comment, block_comments, quotes) = comment_syntax(extension)?;
+    let mut fields = SourceFields::default();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (docs, end) = if rest.starts_with(line_comment) {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            // Doc comment markers are the comment marker repeated or followed by ! (///, //!, ##)
+            (rest[..end].trim_start_matches(|c: char| line_comment.contains(c) || c == '!'), end)
+        } else if block_comments && rest.starts_with("/*") {
+            let end = rest[2..].find("*/").map_or(rest.len(), |end| end + 4);
+            (rest[..end].trim_start_matches(['/', '*', '!']).trim_end_matches(['*', '/']), end)
+        } else if let Some(quote) = quotes.iter().find(|quote| rest.starts_with(**quote)) {
+            let body = &rest[quote.len()..];
+            let mut chars = body.char_indices();
+            let mut close = None;
+            while let Some((i, c)) = chars.next() {
+                if c == '\\' {
+                    // The escaped character can't end the string
+                    chars.next();
+                } else if body[i..].starts_with(quote) {
+                    close = Some(i);
+                    break;
+                }
+            }
+            match close {
+                Some(i) => (&body[..i], quote.len() * 2 + i),
+                // A string that is never closed runs to the end of the file
+                None => (body, rest.len()),
+            }
+        } else {
+            // A char literal like '"' is code, its quote must not start a string
+            let length = if c == '\'' { char_literal(rest).unwrap_or(1) } else { c.len_utf8() };
+            fields.code.push_str(&rest[..length]);
+            rest = &rest[length..];
+            continue;
+        };
+        let docs = docs.trim();
+        if !docs.is_empty() {
+            fields.docs.push_str(docs);
+            fields.docs.push('\n');
+        }
+        // Keep the lines of the code where they were, a comment still ends its line
+        fields.code.extend(rest[..end].chars().map(|c| if c == '\n' { '\n' } else { ' ' }));
+        rest = &rest[end..];
+    }
+    Some(fields)
+}
+
+// Byte length of a char literal at the start of text, 'x' or '\n', None for anything else ('a lifetimes)
+fn char_literal(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().skip(1);
+    if chars.next()?.1 == '\\' {
+        chars.next()?;
+    }
+    let (i, close) = chars.next()?;
+    (close == '\'').then_some(i + 1)
+}
+
+/// Metadata at the top of a Markdown file, see parse_frontmatter
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frontmatter {
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    /// As written, e.g. "2024-05-01"
+    pub date: Option<String>,
+}
+
+/// The YAML (between --- lines) or TOML (between +++ lines) frontmatter of a Markdown file
+// Not a YAML or TOML parser: only the flat keys title, tags and date are read, which is what
+// static site generators put there. Tags can be a [a, b] list, a YAML "- a" list or one string
+pub fn parse_frontmatter(text: &str) -> Option<Frontmatter> {
+    let mut lines = text.lines();
+    let fence = lines.next()?.trim_end();
+    let separator = match fence {
+        "---" => ':',
+        "+++" => '=',
+        _ => return None,
+    };
+    let unquote = |value: &str| value.trim().trim_matches(['"', '\'']).to_string();
+    let mut frontmatter = Frontmatter::default();
+    let mut in_tags = false;
+    for line in lines {
+        if line.trim_end() == fence {
+            return Some(frontmatter);
+        }
+        // The items of a YAML block list under tags:
+        if let Some(item) = line.trim_start().strip_prefix("- ").filter(|_| in_tags) {
+            frontmatter.tags.push(unquote(item));
+            continue;
+        }
+        in_tags = false;
+        let Some((key, value)) = line.split_once(separator) else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "title" => frontmatter.title = Some(unquote(value)),
+            "date" => frontmatter.date = Some(unquote(value)),
+            "tags" if value.is_empty() => in_tags = true,
+            "tags" => {
+                let list = value.strip_prefix('[').and_then(|list| list.strip_suffix(']')).unwrap_or(value);
+                frontmatter.tags.extend(list.split(',').map(unquote).filter(|tag| !tag.is_empty()));
+            }
+            _ => {}
+        }
+    }
+    // No closing fence, the --- was a horizontal rule
+    None
+}
+
+/// Download a text document over HTTP(S)
+#[cfg(feature = "http")]
+pub fn load_url(url: &str) -> Result<String, Box<dyn Error>> {
+    // ureq follows redirects and returns an error for non-2xx responses
+    let mut response = ureq::get(url).call()?;
+    Ok(response.body_mut().read_to_string()?)
+}
+
+/// Without the `http` feature there is no HTTP client compiled in, so URLs are rejected
+#[cfg(not(feature = "http"))]
+pub fn load_url(url: &str) -> Result<String, Box<dyn Error>> {
+    Err(format!("cannot load {}: rebuild with `--features http` to load URLs", url).into())
+}
+
+/// Download every object under an s3://bucket/prefix URL, see objects::load_objects
+#[cfg(feature = "s3")]
+pub use crate::objects::load_objects;
+
+/// Without the `s3` feature there is no object store client compiled in
+#[cfg(not(feature = "s3"))]
+pub fn load_objects(url: &str, _extensions: &[&str], _in_flight: usize) -> Result<Vec<crate::corpus::Document>, Box<dyn Error>> {
+    Err(format!("cannot load {}: rebuild with `--features s3` to load from object storage", url).into())
+}
+
+/// The articles of a Wikipedia dump, see wikipedia::load_wikipedia
+#[cfg(feature = "wikipedia")]
+pub use crate::wikipedia::load_wikipedia;
+
+/// Without the `wikipedia` feature there is no XML parser compiled in
+#[cfg(not(feature = "wikipedia"))]
+pub fn load_wikipedia(path: &Path, _max_articles: Option<usize>) -> Result<Vec<crate::corpus::Document>, Box<dyn Error>> {
+    Err(format!("cannot load {}: rebuild with `--features wikipedia` to load Wikipedia dumps", path.display()).into())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,12 +406,12 @@ mod tests {
         match load_directory("data") {
             Ok(files) => {
                 // Should find multiple .txt files in the Python documentation
-                assert!(files.len() > 0, "Should find at least some .txt files");
+                assert!(!files.is_empty(), "Should find at least some .txt files");
 
                 // Check that all loaded files have .txt extension in their names
                 for (filename, content) in &files {
                     assert!(filename.ends_with(".txt"), "All files should be .txt files");
-                    assert!(content.len() > 0, "Files should not be empty");
+                    assert!(!content.is_empty(), "Files should not be empty");
                 }
 
                 println!("Successfully loaded {} files", files.len());
@@ -90,4 +422,56 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_split_source_into_docs_and_code() {
+        let rust = "/// Parses a request\nfn parse(q: &str) -> bool {\n    q == \"GET \\\"x\\\"\" || q.contains('\"') // quoted\n}\n";
+        let fields = split_source(rust, "rs").unwrap();
+        assert_eq!(fields.docs, "Parses a request\nGET \\\"x\\\"\nquoted\n");
+        assert!(fields.code.contains("fn parse(q: &str) -> bool {") && fields.code.contains("contains('\"')"));
+        assert_eq!(fields.code.lines().count(), rust.lines().count());
+
+        let python = "def area(r):\n    \"\"\"Area of a circle\"\"\"\n    return 3.14 * r * r  # approximately\n";
+        let fields = split_source(python, "py").unwrap();
+        assert_eq!(fields.docs, "Area of a circle\napproximately\n");
+        assert!(!fields.code.contains("circle"));
+        assert_eq!(split_source("plain text", "txt"), None);
+    }
+
+    #[cfg(feature = "compressed")]
+    #[test]
+    fn test_read_compressed_files() {
+        use std::io::Write;
+        let dir = std::env::temp_dir().join(format!("compressed_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let text = "rust borrow checker\n";
+        let mut gz = flate2::write::GzEncoder::new(fs::File::create(dir.join("a.txt.gz")).unwrap(), flate2::Compression::default());
+        gz.write_all(text.as_bytes()).unwrap();
+        gz.finish().unwrap();
+        fs::write(dir.join("b.txt.zst"), zstd::encode_all(text.as_bytes(), 0).unwrap()).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(fs::File::create(dir.join("c.txt.xz")).unwrap(), 6);
+        xz.write_all(text.as_bytes()).unwrap();
+        xz.finish().unwrap();
+        fs::write(dir.join("d.md.gz"), b"not listed").unwrap();
+
+        let files = load_directory_files(&dir.to_string_lossy(), &["txt"]).unwrap();
+        let names: Vec<&str> = files.iter().map(|file| file.file.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, ["a.txt.gz", "b.txt.zst", "c.txt.xz"]);
+        assert!(files.iter().all(|file| file.text == text));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_frontmatter() {
+        let yaml = "---\ntitle: \"Borrowing\"\ndate: 2024-05-01\ntags:\n  - rust\n  - memory\n---\n# Borrowing\n";
+        let frontmatter = parse_frontmatter(yaml).unwrap();
+        assert_eq!(frontmatter.title.as_deref(), Some("Borrowing"));
+        assert_eq!(frontmatter.date.as_deref(), Some("2024-05-01"));
+        assert_eq!(frontmatter.tags, ["rust", "memory"]);
+
+        let toml = "+++\ntitle = 'GC'\ntags = [\"python\", \"memory\"]\n+++\ntext";
+        assert_eq!(parse_frontmatter(toml).unwrap().tags, ["python", "memory"]);
+        assert_eq!(parse_frontmatter("---\nno closing fence"), None);
+        assert_eq!(parse_frontmatter("# Just markdown"), None);
+    }
 }
\ No newline at end of file
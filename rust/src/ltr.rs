@@ -0,0 +1,100 @@
+use crate::bm25::{idf_bm25, term_scores_bm25, Bm25Params};
+use crate::corpus::{ChunkId, Corpus};
+use crate::query::min_spread;
+use crate::search::{to_f32, Score};
+use crate::tfidf::term_scores_tfidf;
+
+/// Names of the values features() returns, in order, feature i + 1 in SVMrank files
+pub const FEATURE_NAMES: [&str; 8] = [
+    "tf_sum",
+    "idf_sum",
+    "tfidf",
+    "bm25",
+    "chunk_len",
+    "term_coverage",
+    "proximity",
+    "path_match",
+];
+
+/// Learning-to-rank features of one chunk for a query's analyzed terms
+// These are the signals the lexical scorers already compute, exported separately so a
+// reranker can learn how to weigh them instead of relying on one fixed formula
+pub fn features(corpus: &Corpus, terms: &[String], chunk: ChunkId) -> Vec<f32> {
+    let index = corpus.index();
+    // Duplicate query terms would count twice in every sum
+    let mut unique: Vec<&String> = terms.iter().collect();
+    unique.sort();
+    unique.dedup();
+
+    let score_of =
+        |scores: Vec<(ChunkId, Score)>| scores.into_iter().find(|(id, _)| *id == chunk).map(|(_, s)| to_f32(s)).unwrap_or(0.0);
+    let mut tf_sum = 0.0;
+    let mut idf_sum = 0.0;
+    let mut tfidf = 0.0;
+    let mut bm25 = 0.0;
+    let mut matched = Vec::new();
+    for term in &unique {
+        let positions = index.positions(term, chunk);
+        idf_sum += to_f32(idf_bm25(index.num_chunks(), index.doc_freq(term)));
+        if positions.is_empty() {
+            continue;
+        }
+        tf_sum += positions.len() as f32;
+        tfidf += score_of(term_scores_tfidf(term, index));
+        bm25 += score_of(term_scores_bm25(term, index, &Bm25Params::default()));
+        matched.push(positions.iter().map(|p| *p as i64).collect::<Vec<i64>>());
+    }
+
+    // 1 when the matched terms sit next to each other, falling towards 0 the further apart they are
+    let proximity = match min_spread(&matched) {
+        Some(spread) if !matched.is_empty() => 1.0 / (1.0 + (spread - (matched.len() as i64 - 1)).max(0) as f32),
+        _ => 0.0,
+    };
+    let coverage = if unique.is_empty() { 0.0 } else { matched.len() as f32 / unique.len() as f32 };
+    let path_terms: Vec<String> = corpus
+        .chunk(chunk)
+        .and_then(|c| corpus.path(c.doc))
+        .map(|path| corpus.analyze_query(&path.replace(['/', '\\', '.', '_', '-'], " ")))
+        .unwrap_or_default();
+    let path_match = if unique.is_empty() {
+        0.0
+    } else {
+        unique.iter().filter(|t| path_terms.contains(t)).count() as f32 / unique.len() as f32
+    };
+
+    vec![tf_sum, idf_sum, tfidf, bm25, index.chunk_len(chunk) as f32, coverage, proximity, path_match]
+}
+
+/// One line of SVMrank / LightGBM (libsvm ranking) input: `label qid:N 1:v 2:v ... # comment`
+pub fn svmrank_line(label: u32, qid: usize, features: &[f32], comment: &str) -> String {
+    let values: Vec<String> = features.iter().enumerate().map(|(i, v)| format!("{}:{}", i + 1, v)).collect();
+    format!("{} qid:{} {} # {}", label, qid, values.join(" "), comment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_features() {
+        let files = vec![
+            Document::new("rust.txt", "the quick fox"),
+            Document::new("b.txt", "quick and then a fox"),
+            Document::new("c.txt", "nothing"),
+        ];
+        let corpus = Corpus::new(files, ChunkingConfig::default());
+        let terms = corpus.analyze_query("quick fox rust");
+
+        let near = features(&corpus, &terms, ChunkId(0));
+        let far = features(&corpus, &terms, ChunkId(1));
+        assert_eq!(near.len(), FEATURE_NAMES.len());
+        assert_eq!(near[0], 2.0);
+        assert_eq!(near[6], 1.0);
+        assert!(far[6] < near[6]);
+        // "rust" is only in the path of the first document
+        assert!(near[7] > 0.0 && far[7] == 0.0);
+        assert_eq!(svmrank_line(2, 1, &[1.0, 0.5], "q1 rust.txt#0"), "2 qid:1 1:1 2:0.5 # q1 rust.txt#0");
+    }
+}
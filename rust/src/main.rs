@@ -1,8 +1,1891 @@
-mod tfidf;
-mod chunker;
-mod search;
-mod loader;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use rust::analyzer::{Analyzer, CodeTokenizer, DropNumbers, Emoji, EmojiMode, FoldWidth, LengthFilter, LightStemmer, Lowercase, Stopwords, WhitespaceTokenizer};
+use rust::checkpoint::{build_resumable_limited, checkpoint_segments, is_checkpoint};
+use rust::cluster::cluster_chunks;
+use rust::context::Context;
+use rust::chunker::{ChunkStrategy, ChunkingConfig, DEFAULT_CHUNK_SIZE};
+use rust::corpus::{ChunkId, Corpus, DocId, IndexOptions, Recency};
+use rust::bm25::{idf_bm25, Bm25Params, Bm25Scorer};
+use rust::boosts::{boost_prefix, Boosts};
+use rust::cancel::CancellationToken;
+use rust::eval::{evaluate, evaluate_diversity, load_queries, result_key, Diversity, Metrics, Qrels, SubtopicQrels};
+use rust::index::Index;
+use rust::interleave::{read_outcomes, tally, team_draft, Outcome};
+use rust::inverted_index::DfPruning;
+use rust::limits::{ResourceLimits, DEFAULT_OPEN_FILES};
+use rust::normalize::{clean_whitespace, dehyphenate};
+use rust::progress;
+use rust::filter::DocFilter;
+use rust::mcp::McpServer;
+use rust::ltr::{features, svmrank_line, FEATURE_NAMES};
+use rust::querylog::{self, read_log, LoggedQuery, QueryLog};
+use rust::query::{parse_query, RepeatedTerms, Summation, TermScorer};
+use rust::refresh::refresh;
+use rust::retrieve::{pack_for_prompt, render_passages};
+use rust::rewrite::{BoostedScorer, RewriteRules, Rewritten};
+use rust::shard::{Scoring, ShardedCorpus};
+use rust::similarity::{similarity_edges, write_edges_csv};
+use rust::smart::parse_smart;
+use rust::summarize::summarize_results;
+use rust::vocabulary::{vocabulary, write_vocabulary, Vocabulary};
+use rust::tfidf::{IdfScheme, LengthNorm, TfIdfParams, TfIdfScorer, TfScheme};
+use rust::persist::{load_corpus, save_corpus};
+use rust::search::{normalize_scores, sort_results, Facets, Normalization, Score, SearchOptions, SearchResult, SearchResults, Selection, SortOrder, TimedResults};
+
+#[derive(Parser)]
+#[command(about = "Lexical search over a folder of .txt files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Search a directory of .txt files, or an index saved with `build`
+    Search {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// The query text
+        query: String,
+        /// How to search the corpus
+        #[arg(long, value_enum, default_value_t = SearchMode::Tfidf)]
+        mode: SearchMode,
+        /// Maximum number of results to print
+        #[arg(short = 'k', long, default_value_t = 10)]
+        top: usize,
+        /// How to print the results
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        /// Also report scores rescaled with minmax, zscore or percentile, computed over all results
+        #[arg(long)]
+        normalize: Option<Normalization>,
+        /// Also count all results by file extension and top-level directory, with --format text or json
+        #[arg(long)]
+        facets: bool,
+        /// Order of the printed results: score, path (document order) or modified (newest first)
+        /// The top k are still chosen by score
+        #[arg(long, default_value_t = SortOrder::Score)]
+        sort: SortOrder,
+        /// Show this many sentences of every result's document, the ones with the most weight for the query
+        #[arg(long, default_value_t = 0)]
+        summary: usize,
+        /// Print how a ranked query will be evaluated, on stderr before the results
+        #[arg(long)]
+        explain_plan: bool,
+        /// Print what every field adds to the BM25F score of each shown result, on stderr, needs --field-weight
+        #[arg(long)]
+        explain_fields: bool,
+        /// Append the query, the ranker, the latency and the top results to this JSONL file, see replay
+        #[arg(long, value_name = "FILE")]
+        log_queries: Option<String>,
+        #[command(flatten)]
+        scoring: ScoringArgs,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Print the best passages for a query with their sources, packed into a token budget for an LLM prompt
+    Retrieve {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// The query text
+        query: String,
+        /// Most tokens all passages may take up together, estimated at four characters per token
+        #[arg(long, default_value_t = 2000)]
+        max_tokens: usize,
+        /// Chunks of context to add before and after every hit
+        #[arg(long, default_value_t = 1)]
+        context_chunks: usize,
+        /// How to rank the chunks, tfidf or bm25
+        #[arg(long, value_enum, default_value_t = SearchMode::Bm25)]
+        mode: SearchMode,
+        /// Number of ranked chunks to build passages from
+        #[arg(short = 'k', long, default_value_t = 50)]
+        top: usize,
+        /// Print the passages as prompt text, or as JSON with their paths, lines and scores
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        #[command(flatten)]
+        scoring: ScoringArgs,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Run the queries of a search --log-queries file again and compare the results with the logged ones
+    Replay {
+        /// The query log
+        log: String,
+        /// Directory to load .txt files from, or a saved index file, e.g. a new build of the logged one
+        source: String,
+        /// How to search the corpus
+        #[arg(long, value_enum, default_value_t = SearchMode::Tfidf)]
+        mode: SearchMode,
+        /// Number of results compared per query
+        #[arg(short = 'k', long, default_value_t = 10)]
+        top: usize,
+        /// Log the replayed searches to this file, to compare the next change against
+        #[arg(long, value_name = "FILE")]
+        log_queries: Option<String>,
+        #[command(flatten)]
+        scoring: ScoringArgs,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Show the top results for every query in a query set and record relevance labels as qrels
+    Judge {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// Query set, one `id<TAB>query` per line
+        queries: String,
+        /// Qrels file to write, judgments already in it are kept and not asked again
+        qrels: String,
+        /// Number of results to judge per query
+        #[arg(short = 'k', long, default_value_t = 10)]
+        top: usize,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Interleave the TF-IDF and BM25 rankings of each query and record which results are picked,
+    /// to find out which ranker users prefer
+    Interleave {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// Interleaving log to append to, its tally is printed at the end
+        log: String,
+        /// Query set, one `id<TAB>query` per line, queries are typed in when not given
+        #[arg(long)]
+        queries: Option<String>,
+        /// Number of results shown per query
+        #[arg(short = 'k', long, default_value_t = 10)]
+        top: usize,
+        /// Seed for the coin that decides which ranker picks first, the clock by default
+        #[arg(long)]
+        seed: Option<u64>,
+        #[command(flatten)]
+        scoring: ScoringArgs,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Evaluate a grid of BM25 k1/b values and TF-IDF schemes against judged queries, printing CSV
+    Sweep {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// Query set, one `id<TAB>query` per line
+        queries: String,
+        /// Relevance judgments in TREC qrels format, e.g. written by `judge`
+        qrels: String,
+        /// BM25 k1 values to try
+        #[arg(long, value_delimiter = ',', default_value = "0.5,0.9,1.2,1.5,2.0")]
+        k1: Vec<Score>,
+        /// BM25 b values to try
+        #[arg(long, value_delimiter = ',', default_value = "0,0.25,0.5,0.75,1")]
+        b: Vec<Score>,
+        /// TF-IDF weightings in SMART notation to try, next to the built-in grid
+        #[arg(long, value_delimiter = ',', default_value = "lnc.ltc,ltc.ltc,lnn.ltn,bnn.ntc,Lnu.ltc")]
+        smart: Vec<String>,
+        /// Metric the best configuration is picked by
+        #[arg(long, value_enum, default_value_t = Metric::Ndcg)]
+        metric: Metric,
+        /// Cutoff for every metric
+        #[arg(short = 'k', long, default_value_t = 10)]
+        top: usize,
+        /// Subtopic judgments in TREC diversity qrels format (query subtopic key relevance), adds
+        /// alpha-NDCG and subtopic recall columns
+        #[arg(long, value_name = "FILE")]
+        subtopics: Option<String>,
+        /// How much alpha-NDCG discounts a subtopic every time it comes up again, 0 to 1
+        #[arg(long, default_value_t = 0.5)]
+        alpha: f32,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Print learning-to-rank features of the top BM25 results for every query, in SVMrank format
+    Features {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// Query set, one `id<TAB>query` per line
+        queries: String,
+        /// Relevance judgments used as labels, unjudged results are labeled 0
+        #[arg(long)]
+        qrels: Option<String>,
+        /// Number of results per query to export
+        #[arg(short = 'k', long, default_value_t = 100)]
+        top: usize,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Show the TF-IDF and BM25 rankings for a query side by side
+    Compare {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// The query text
+        query: String,
+        /// Number of results per ranker
+        #[arg(short = 'k', long, default_value_t = 10)]
+        top: usize,
+        /// Explain term by term why the rankers disagree about one chunk, given as path#chunk index
+        #[arg(long)]
+        explain: Option<String>,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Show how a term is distributed over the corpus and what each IDF scheme makes of it
+    TermStats {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// The term, run through the analyzer like a query
+        term: String,
+        /// Number of chunks with the highest term frequency to list
+        #[arg(short = 'k', long, default_value_t = 10)]
+        top: usize,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Propose stopwords for this corpus, the terms that occur in the most chunks, and write them
+    /// to a file --stopwords-file reads
+    Stopwords {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// Propose terms that occur in at least this fraction of the chunks
+        #[arg(long, default_value_t = 0.5)]
+        min_df_ratio: f32,
+        /// Propose at most this many terms, the most common ones
+        #[arg(long)]
+        max: Option<usize>,
+        /// File to write the list to
+        #[arg(short, long)]
+        output: Option<String>,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Write every term with its df, cf and idf as TSV, for --vocabulary or for comparing term spaces
+    Vocabulary {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// File to write the TSV to, stdout by default
+        #[arg(short, long)]
+        output: Option<String>,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Group the chunks into clusters of similar TF-IDF vectors and print each cluster's keywords
+    Cluster {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// Number of clusters
+        #[arg(short = 'k', long, default_value_t = 5)]
+        k: usize,
+        /// Seed for choosing the starting centroids, the same seed gives the same clusters
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Number of chunks to list per cluster
+        #[arg(long, default_value_t = 5)]
+        show: usize,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Write the cosine similarities between chunks as an edge list, for graph tools
+    Similar {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// Where to write, CSV unless it ends in .parquet or .arrow, which need the arrow feature
+        /// Without it the CSV is printed
+        #[arg(long)]
+        output: Option<String>,
+        /// Only the most similar chunks of every chunk, instead of every pair
+        #[arg(long)]
+        neighbors: Option<usize>,
+        /// Leave out pairs less similar than this
+        #[arg(long, default_value_t = 0.1)]
+        min_similarity: f32,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Combine index files built separately, e.g. one per shard of a large corpus, into one
+    Merge {
+        /// Where to write the merged index
+        output: String,
+        /// The index files to merge, all built with the same chunking and analyzer options
+        #[arg(required = true)]
+        shards: Vec<String>,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Chunk a directory of .txt files and save the result as an index file
+    Build {
+        /// Directory to load .txt files from
+        dir: String,
+        /// Where to write the index
+        output: String,
+        /// Save progress in this directory, segment by segment, and resume from it if it exists
+        #[arg(long)]
+        checkpoint: Option<String>,
+        /// Documents per checkpoint segment
+        #[arg(long, default_value_t = 1000)]
+        segment_size: usize,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Update an index file with the files of its directory that were added, changed or deleted since
+    Refresh {
+        /// The index file, written back in place
+        index: String,
+        /// The directory the index was built from, as it was given to build
+        dir: String,
+        /// File extensions to look for, as given to build
+        #[arg(long = "ext", default_value = "txt")]
+        extensions: Vec<String>,
+        /// Stop reading changed files after this many seconds and save what was done, a later refresh continues
+        #[arg(long)]
+        time_budget_secs: Option<u64>,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Write the TF-IDF chunk-term matrix, or the results of a query set, as Parquet or Arrow IPC
+    #[cfg(feature = "arrow")]
+    Export {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// Where to write, a .parquet extension writes Parquet, anything else an Arrow IPC file
+        output: String,
+        /// Export the top results of every query in this query set instead of the matrix
+        #[arg(long)]
+        queries: Option<String>,
+        /// Ranker for --queries
+        #[arg(long, value_enum, default_value_t = SearchMode::Tfidf)]
+        mode: SearchMode,
+        /// Results per query
+        #[arg(short = 'k', long, default_value_t = 100)]
+        top: usize,
+        #[command(flatten)]
+        scoring: ScoringArgs,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Print what an index stores: its layout, the postings of a term or the fields of a document
+    DumpIndex {
+        /// Directory to load .txt files from, a saved index file, or a `build --checkpoint` directory
+        source: String,
+        /// Print the postings of this term with their positions, run through the analyzer like a query
+        #[arg(long)]
+        term: Option<String>,
+        /// Print the stored fields, chunks and terms of this document, given as id or path
+        #[arg(long)]
+        doc: Option<String>,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Serve search and fetch_document tools to LLM agents over the Model Context Protocol, on stdin and stdout
+    Mcp {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+    /// Serve the corpus over gRPC, see proto/tfidf.proto, documents can be added with the Index call
+    #[cfg(feature = "grpc")]
+    Serve {
+        /// Directory to load .txt files from, or a saved index file
+        source: String,
+        /// Also serve another corpus under a name requests can pick it by, e.g. --index code=src/
+        #[arg(long = "index", value_name = "NAME=SOURCE")]
+        indexes: Vec<String>,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        addr: std::net::SocketAddr,
+        /// Also serve Prometheus metrics at http://<address>/metrics
+        #[arg(long)]
+        metrics_addr: Option<std::net::SocketAddr>,
+        /// Search responses to keep for repeated requests until the index changes, 0 turns the cache off
+        #[arg(long, default_value_t = rust::grpc::DEFAULT_CACHE_SIZE)]
+        cache_size: usize,
+        /// Requests a second each client address may send, unlimited by default
+        #[arg(long)]
+        rate_limit: Option<f64>,
+        /// Requests a client may send at once before the rate limit applies
+        #[arg(long, default_value_t = 10)]
+        burst: u32,
+        /// Refuse queries longer than this many characters
+        #[arg(long)]
+        max_query_length: Option<usize>,
+        /// Refuse searches asking for more hits than this
+        #[arg(long)]
+        max_top: Option<usize>,
+        /// Accept Reload and Close requests for the --index indexes, anyone who can connect may send them
+        #[arg(long)]
+        admin: bool,
+        #[command(flatten)]
+        chunking: ChunkingArgs,
+        #[command(flatten)]
+        analyzer: AnalyzerArgs,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SearchMode {
+    /// Case-insensitive substring match per line
+    Lines,
+    /// Case-insensitive substring match per chunk
+    Chunks,
+    /// Chunks ranked by TF-IDF, the query may use AND, OR, NOT / -, "phrases", "near words"~N and (groups)
+    Tfidf,
+    /// Like tfidf, ranked by BM25
+    Bm25,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LengthNormKind {
+    /// Raw term frequency, long chunks win by having more words
+    None,
+    /// Term frequency divided by chunk length
+    Length,
+    /// Between the two, see --pivot-slope
+    Pivoted,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Metric {
+    Ndcg,
+    Map,
+    Precision,
+    Mrr,
+    /// Needs --subtopics
+    AlphaNdcg,
+    /// Needs --subtopics
+    SubtopicRecall,
+}
+
+impl Metric {
+    fn name(&self) -> &'static str {
+        match self {
+            Metric::Ndcg => "ndcg",
+            Metric::Map => "map",
+            Metric::Precision => "precision",
+            Metric::Mrr => "mrr",
+            Metric::AlphaNdcg => "alpha-ndcg",
+            Metric::SubtopicRecall => "subtopic-recall",
+        }
+    }
+
+    fn of(&self, metrics: &Metrics, diversity: &Diversity) -> f32 {
+        match self {
+            Metric::Ndcg => metrics.ndcg,
+            Metric::Map => metrics.map,
+            Metric::Precision => metrics.precision,
+            Metric::Mrr => metrics.mrr,
+            Metric::AlphaNdcg => diversity.alpha_ndcg,
+            Metric::SubtopicRecall => diversity.subtopic_recall,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// Human readable, with highlighted lines
+    Text,
+    /// A JSON array with paths, scores and match spans, for editors and other tools
+    Json,
+    /// path:line:column: line, like ripgrep --column, for editor tooling (needs --mode lines)
+    Grep,
+}
+
+/// One result as printed by --format json
+// #[serde(flatten)] inlines the SearchResult fields next to the path instead of nesting them
+#[derive(Serialize)]
+struct JsonResult<'a> {
+    path: &'a str,
+    /// Position of the chunk within its document
+    chunk_index: Option<usize>,
+    #[serde(flatten)]
+    result: &'a SearchResult,
+    /// Sentences chosen by --summary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<&'a [String]>,
+}
+
+/// Options of the ranked search modes
+#[derive(Args)]
+struct ScoringArgs {
+    /// How TF-IDF corrects term frequency for chunk length, only used with --mode tfidf
+    #[arg(long, value_enum, default_value_t = LengthNormKind::Length)]
+    length_norm: LengthNormKind,
+    /// Slope of pivoted length normalization, 0 ignores length and 1 divides by it fully
+    #[arg(long, default_value_t = 0.25)]
+    pivot_slope: Score,
+    /// TF-IDF weighting in SMART notation, e.g. lnc.ltc, replaces --length-norm
+    #[arg(long)]
+    smart: Option<String>,
+    /// BM25 term frequency saturation, only used with --mode bm25
+    #[arg(long, default_value_t = Bm25Params::default().k1)]
+    k1: Score,
+    /// BM25 length normalization, 0 to 1, only used with --mode bm25
+    #[arg(long, default_value_t = Bm25Params::default().b)]
+    b: Score,
+    /// Split the corpus into this many shards and query them in parallel, uses the default weighting
+    #[arg(long, default_value_t = 1)]
+    shards: usize,
+    /// Multiply the scores of documents under a path by a factor, e.g. docs/=2 or old.txt=0.5, repeatable
+    #[arg(long, value_name = "PATH=FACTOR")]
+    boost: Vec<String>,
+    /// Multiply the scores of documents by weights from a CSV (path,weight) or JSON ({"path": weight}) file,
+    /// e.g. PageRank, --boost multiplies these weights
+    #[arg(long, value_name = "FILE")]
+    boosts_file: Option<String>,
+    /// Halve the scores of documents for every this many days since they were last modified
+    #[arg(long)]
+    half_life_days: Option<f32>,
+    /// Drop results scoring below this
+    #[arg(long)]
+    min_score: Option<Score>,
+    /// Drop chunks containing fewer than this many of the distinct query terms
+    #[arg(long)]
+    minimum_should_match: Option<usize>,
+    /// Stop scoring after this many milliseconds and print the best results found so far
+    #[arg(long)]
+    time_budget_ms: Option<u64>,
+    /// Add up term scores with compensated (Kahan) summation, for exact comparisons with other implementations
+    #[arg(long)]
+    compensated_sum: bool,
+    /// How a term the query repeats counts: count every repetition, dedupe, or cap:N repetitions
+    #[arg(long, default_value_t = RepeatedTerms::Count)]
+    repeated_terms: RepeatedTerms,
+    /// Only return chunks of documents whose path starts with this, repeatable
+    #[arg(long, value_name = "PREFIX")]
+    path: Vec<String>,
+    /// Only return chunks of Markdown documents with this frontmatter tag, needs --frontmatter, repeatable
+    #[arg(long)]
+    tag: Vec<String>,
+    /// Only search part of one document, lines as PATH:FIRST-LAST or bytes as PATH@START-END, with
+    /// the path as results print it
+    #[arg(long, value_name = "PATH:LINES")]
+    within: Option<String>,
+    /// Add the BM25F score over document fields, e.g. docs=2 with --code-fields, repeatable
+    #[arg(long, value_name = "FIELD=BOOST")]
+    field_weight: Vec<String>,
+    /// Rewrite ranked queries with the rules in this file before parsing them, e.g. k8s => kubernetes
+    #[arg(long, value_name = "FILE")]
+    rewrite_rules: Option<String>,
+}
+
+impl ScoringArgs {
+    fn tfidf_params(&self) -> Result<TfIdfParams, String> {
+        if let Some(notation) = &self.smart {
+            return parse_smart(notation);
+        }
+        let norm = match self.length_norm {
+            LengthNormKind::None => LengthNorm::None,
+            LengthNormKind::Length => LengthNorm::Length,
+            LengthNormKind::Pivoted => LengthNorm::Pivoted { slope: self.pivot_slope },
+        };
+        Ok(TfIdfParams { norm, ..TfIdfParams::default() })
+    }
+
+    /// The rules of --rewrite-rules, none without it
+    fn rewrite_rules(&self) -> Result<RewriteRules, Box<dyn Error>> {
+        self.rewrite_rules.as_ref().map_or(Ok(RewriteRules::default()), |path| RewriteRules::load(Path::new(path)))
+    }
+
+    fn bm25_params(&self) -> Bm25Params {
+        Bm25Params { k1: self.k1, b: self.b }
+    }
+
+    // Any of the --path prefixes and any of the --tag tags
+    fn filter(&self) -> Option<DocFilter> {
+        let paths = DocFilter::Or(self.path.iter().map(|prefix| DocFilter::PathPrefix(prefix.clone())).collect());
+        let tags = DocFilter::Or(self.tag.iter().map(|tag| DocFilter::Tag(tag.clone())).collect());
+        match (self.path.is_empty(), self.tag.is_empty()) {
+            (true, true) => None,
+            (false, true) => Some(paths),
+            (true, false) => Some(tags),
+            (false, false) => Some(DocFilter::And(vec![paths, tags])),
+        }
+    }
+
+    fn search_options(&self) -> Result<SearchOptions, String> {
+        let options = SearchOptions {
+            min_score: self.min_score,
+            minimum_should_match: self.minimum_should_match,
+            time_budget: self.time_budget_ms.map(Duration::from_millis),
+            summation: if self.compensated_sum { Summation::Compensated } else { Summation::Naive },
+            repeated_terms: self.repeated_terms,
+            filter: self.filter(),
+            // Finding the document needs the corpus, see selection
+            selection: None,
+            field_weights: if self.field_weight.is_empty() {
+                None
+            } else {
+                Some(self.field_weight.join(",").parse().map_err(|e| format!("--field-weight {}", e))?)
+            },
+        };
+        // Shards only return their top k, thresholds over those would silently drop other shards' matches
+        if self.shards > 1 && options != SearchOptions::default() {
+            return Err(
+                "--min-score, --minimum-should-match, --time-budget-ms, --compensated-sum, --repeated-terms, --path, --tag and --field-weight don't work with --shards"
+                    .to_string(),
+            );
+        }
+        Ok(options)
+    }
+
+    /// The part of a document --within selects, None without it
+    fn selection(&self, corpus: &Corpus) -> Result<Option<Selection>, Box<dyn Error>> {
+        let Some(within) = &self.within else {
+            return Ok(None);
+        };
+        let usage = "--within expects PATH:FIRST-LAST for lines or PATH@START-END for bytes";
+        let (path, range, lines) = match within.rsplit_once('@') {
+            Some((path, range)) => (path, range, false),
+            None => within.rsplit_once(':').map(|(path, range)| (path, range, true)).ok_or(usage)?,
+        };
+        let (start, end) = range.split_once('-').ok_or(usage)?;
+        let (start, end): (usize, usize) = (start.trim().parse().map_err(|_| usage)?, end.trim().parse().map_err(|_| usage)?);
+        let doc = corpus.doc_id(path).ok_or_else(|| format!("--within: {} is not in the index", path))?;
+        if lines {
+            Ok(Some(corpus.select_lines(doc, start..=end)?))
+        } else if start <= end {
+            Ok(Some(Selection { doc, bytes: start..end }))
+        } else {
+            Err(format!("--within: {}-{} is not a range of bytes", start, end).into())
+        }
+    }
+
+    /// Set the document boosts and recency weighting asked for on the command line
+    fn apply_boosts(&self, corpus: &mut Corpus) -> Result<(), Box<dyn Error>> {
+        if let Some(file) = &self.boosts_file {
+            let boosts = Boosts::load(Path::new(file))?;
+            let missing = boosts.apply(corpus);
+            // A sidecar is usually computed for a bigger or older corpus, that's worth knowing but not an error
+            if !missing.is_empty() {
+                eprintln!("{}: {} of {} paths are not in the corpus, e.g. {}", file, missing.len(), boosts.0.len(), missing[0]);
+            }
+        }
+        for boost in &self.boost {
+            let (prefix, factor) = boost.rsplit_once('=').ok_or_else(|| format!("--boost {}: expected PATH=FACTOR", boost))?;
+            let factor: f32 = factor.parse().map_err(|_| format!("--boost {}: {} is not a number", boost, factor))?;
+            if !(factor.is_finite() && factor >= 0.0) {
+                return Err(format!("--boost {}: the factor must be 0 or more", boost).into());
+            }
+            if boost_prefix(corpus, prefix, factor) == 0 {
+                return Err(format!("--boost {}: no document path starts with {}", boost, prefix).into());
+            }
+        }
+        if let Some(days) = self.half_life_days {
+            if !(days.is_finite() && days > 0.0) {
+                return Err("--half-life-days must be more than 0".into());
+            }
+            corpus.set_recency(Some(Recency::from_now(Duration::from_secs_f32(days * 86_400.0))));
+        }
+        Ok(())
+    }
+}
+
+/// Loading and chunking options shared by every command that builds a corpus
+#[derive(Args)]
+struct ChunkingArgs {
+    /// Unit the chunk size and overlap are measured in: chars, words or lines
+    #[arg(long, default_value_t = ChunkStrategy::Chars)]
+    chunk_strategy: ChunkStrategy,
+    /// Chunk size, in units of the chunk strategy
+    #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+    chunk_size: usize,
+    /// Units shared by consecutive chunks
+    #[arg(long, default_value_t = 0)]
+    chunk_overlap: usize,
+    /// File extensions to load from directories, e.g. --ext rs --ext py
+    #[arg(long = "ext", default_value = "txt")]
+    extensions: Vec<String>,
+    /// Drop terms that occur in fewer chunks than this from the index
+    #[arg(long, default_value_t = 1)]
+    min_df: usize,
+    /// Drop terms that occur in more than this fraction of chunks from the index
+    #[arg(long, default_value_t = 1.0)]
+    max_df_ratio: f32,
+    /// Keep only the terms listed in this file, e.g. written by `vocabulary` for another index
+    #[arg(long, value_name = "FILE")]
+    vocabulary: Option<String>,
+    /// Leave out documents whose path starts with this, e.g. --exclude docs/drafts/, repeatable
+    #[arg(long, value_name = "PATH")]
+    exclude: Vec<String>,
+    /// Keep only the postings, not the text, and read the files again to show results
+    /// Makes saved indexes much smaller, --mode lines and chunks need the text
+    #[arg(long)]
+    no_store_text: bool,
+    /// Store the text zstd compressed, needs the zstd feature, --mode lines and chunks need uncompressed text
+    #[arg(long)]
+    compress_text: bool,
+    /// Collapse runs of whitespace, normalize line endings and drop control characters before chunking
+    #[arg(long)]
+    clean_whitespace: bool,
+    /// Join words hyphenated across line breaks, e.g. in text extracted from PDFs, before chunking
+    #[arg(long)]
+    dehyphenate: bool,
+    /// Read the title, tags and date in the frontmatter of Markdown files, see --tag and --field-weight title=2
+    #[arg(long)]
+    frontmatter: bool,
+    /// Split source files into a docs field of comments and strings and a code field, see --field-weight
+    #[arg(long)]
+    code_fields: bool,
+    /// How to report loading and indexing progress: a bar on a terminal, JSON lines on stderr, or nothing
+    #[arg(long, value_enum, default_value_t = ProgressKind::Auto)]
+    progress: ProgressKind,
+    /// Files read at the same time, object downloads in flight for s3:// sources
+    #[arg(long, default_value_t = DEFAULT_OPEN_FILES)]
+    max_open_files: usize,
+    /// Fail instead of holding more postings than this in memory, with --checkpoint start a new segment
+    #[arg(long)]
+    max_postings: Option<usize>,
+    /// The source is a Wikipedia XML or CirrusSearch JSON dump, needs the wikipedia feature
+    #[arg(long)]
+    wikipedia: bool,
+    /// Index only the first this many articles of a --wikipedia dump
+    #[arg(long, requires = "wikipedia")]
+    max_articles: Option<usize>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ProgressKind {
+    /// A bar when stderr is a terminal, nothing otherwise
+    Auto,
+    Bar,
+    /// One JSON object per update, for scripts and CI logs
+    Json,
+    None,
+}
+
+impl ChunkingArgs {
+    fn to_config(&self) -> Result<ChunkingConfig, String> {
+        ChunkingConfig::new(self.chunk_strategy, self.chunk_size, self.chunk_overlap)
+    }
+
+    fn limits(&self) -> Result<ResourceLimits, String> {
+        ResourceLimits::new(self.max_open_files, self.max_postings)
+    }
+}
+
+/// Analyzer options, a saved index must be searched with the options it was built with
+#[derive(Args)]
+struct AnalyzerArgs {
+    /// How text is split into tokens
+    #[arg(long, value_enum, default_value_t = TokenizerKind::Whitespace)]
+    tokenizer: TokenizerKind,
+    /// Keep the original case of terms instead of lowercasing them
+    #[arg(long)]
+    keep_case: bool,
+    /// Drop common English stopwords
+    #[arg(long)]
+    stopwords: bool,
+    /// Strip plural, -ing and -ed suffixes
+    #[arg(long)]
+    stem: bool,
+    /// Drop tokens with fewer characters than this
+    #[arg(long, default_value_t = 1)]
+    min_token_len: usize,
+    /// Drop tokens with more characters than this, e.g. base64 blobs
+    #[arg(long)]
+    max_token_len: Option<usize>,
+    /// Drop tokens that are pure numbers
+    #[arg(long)]
+    drop_numbers: bool,
+    /// Fold full-width letters, digits and punctuation to ASCII before tokenizing
+    #[arg(long)]
+    fold_width: bool,
+    /// Strip emoji, or make every emoji a searchable word of its own
+    #[arg(long, value_enum)]
+    emoji: Option<EmojiKind>,
+    /// Drop the terms listed in this file, one per line, e.g. written by `stopwords`
+    #[arg(long, value_name = "FILE")]
+    stopwords_file: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EmojiKind {
+    Strip,
+    Name,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TokenizerKind {
+    /// Split on whitespace, for prose
+    Whitespace,
+    /// Split identifiers on camelCase and snake_case and keep operators, for source code
+    Code,
+}
+
+impl AnalyzerArgs {
+    fn to_analyzer(&self) -> Result<Analyzer, Box<dyn Error>> {
+        let mut analyzer = match self.tokenizer {
+            TokenizerKind::Whitespace => Analyzer::new(WhitespaceTokenizer),
+            TokenizerKind::Code => Analyzer::new(CodeTokenizer),
+        };
+        if self.fold_width {
+            analyzer = analyzer.with_char_filter(FoldWidth);
+        }
+        match self.emoji {
+            Some(EmojiKind::Strip) => analyzer = analyzer.with_char_filter(Emoji::new(EmojiMode::Strip)),
+            Some(EmojiKind::Name) => analyzer = analyzer.with_char_filter(Emoji::new(EmojiMode::Name)),
+            None => {}
+        }
+        if !self.keep_case {
+            analyzer = analyzer.with_filter(Lowercase);
+        }
+        if self.stopwords {
+            analyzer = analyzer.with_filter(Stopwords::english());
+        }
+        if self.stem {
+            analyzer = analyzer.with_filter(LightStemmer);
+        }
+        // Only add the length filter when it does something, so default indexes keep their fingerprint
+        if self.min_token_len > 1 || self.max_token_len.is_some() {
+            analyzer = analyzer.with_filter(LengthFilter::new(self.min_token_len, self.max_token_len.unwrap_or(usize::MAX)));
+        }
+        if self.drop_numbers {
+            analyzer = analyzer.with_filter(DropNumbers);
+        }
+        // Last, the list holds terms as they come out of the analyzer, stemmed and lowercased
+        if let Some(file) = &self.stopwords_file {
+            let stopwords = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+            analyzer = analyzer.with_filter(Stopwords::parse(&stopwords));
+        }
+        Ok(analyzer)
+    }
+}
 
 fn main() {
-    println!("Hello, world!");
+    let cli = Cli::parse();
+
+    if let Err(e) = run(cli) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    match cli.command {
+        Command::Search {
+            source,
+            query,
+            mode,
+            top,
+            format,
+            normalize,
+            facets,
+            sort,
+            summary,
+            explain_plan,
+            explain_fields,
+            log_queries,
+            scoring,
+            chunking,
+            analyzer,
+        } => {
+            let field_weights = scoring.search_options()?.field_weights;
+            if explain_fields && (field_weights.is_none() || !matches!(mode, SearchMode::Tfidf | SearchMode::Bm25)) {
+                return Err("--explain-fields needs --field-weight and --mode tfidf or bm25".into());
+            }
+            let rules = scoring.rewrite_rules()?;
+            let mut corpus = open_corpus(&source, &chunking, &analyzer)?;
+            scoring.apply_boosts(&mut corpus)?;
+            let rewritten = rewrite_query(&rules, mode, &query).query;
+            // On stderr, so --format json output stays parseable
+            if explain_plan && matches!(mode, SearchMode::Tfidf | SearchMode::Bm25) {
+                if rewritten != query {
+                    eprintln!("rewritten to: {}", rewritten);
+                }
+                eprint!("{}", corpus.plan(&rewritten).map_err(|e| e.render(&rewritten))?);
+            }
+            let started = Instant::now();
+            let TimedResults { mut results, truncated, unknown_terms } = rank_query(&corpus, &query, mode, &scoring, &rules, top)?;
+            if let Some(log) = log_queries {
+                let entry = LoggedQuery::new(&query, &ranker_name(mode, &scoring), started.elapsed(), &corpus, &results, top);
+                QueryLog::open(Path::new(&log))?.record(&entry)?;
+            }
+            if let Some(method) = normalize {
+                normalize_scores(&mut results, method);
+            }
+            let facets = facets.then(|| SearchResults::new(&corpus, &results).facets());
+            // Only the top k by score are reordered, the rest stay behind them and aren't printed
+            let shown = top.min(results.len());
+            sort_results(&corpus, &mut results[..shown], sort);
+            let summaries = match summary {
+                0 => Vec::new(),
+                n => summarize_results(&corpus, &results[..shown], &rewritten, n),
+            };
+            if let Some(weights) = field_weights.filter(|_| explain_fields) {
+                // The k1 and b the search scored the fields with
+                let params = if matches!(mode, SearchMode::Bm25) { scoring.bm25_params() } else { Bm25Params::default() };
+                for result in &results[..shown] {
+                    let explanation = corpus.explain_fields(&rewritten, result.doc, &weights, &params).map_err(|e| e.render(&rewritten))?;
+                    eprint!("{}", explanation.render(corpus.path(result.doc).unwrap_or("?")));
+                }
+            }
+            match format {
+                OutputFormat::Text => {
+                    print_results(&corpus, &results, top, &summaries);
+                    if let Some(facets) = &facets {
+                        print_facets(facets);
+                    }
+                    if truncated {
+                        println!("time budget exceeded, results may be incomplete");
+                    }
+                    if !unknown_terms.is_empty() {
+                        println!("not in the index: {}", unknown_terms.join(", "));
+                    }
+                }
+                OutputFormat::Json => {
+                    // The truncated flag is only printed when a budget was set, so it's never a surprise field
+                    let truncated = scoring.time_budget_ms.map(|_| truncated);
+                    print_json(&corpus, &results, top, &summaries, facets.as_ref(), truncated, &unknown_terms)?;
+                }
+                OutputFormat::Grep => {
+                    if !matches!(mode, SearchMode::Lines) {
+                        return Err("--format grep needs --mode lines, chunks have no line numbers".into());
+                    }
+                    print_grep(&corpus, &results, top);
+                }
+            }
+        }
+        Command::Retrieve { source, query, max_tokens, context_chunks, mode, top, format, scoring, chunking, analyzer } => {
+            if !matches!(mode, SearchMode::Tfidf | SearchMode::Bm25) {
+                return Err("retrieve needs --mode tfidf or bm25".into());
+            }
+            let rules = scoring.rewrite_rules()?;
+            let mut corpus = open_corpus(&source, &chunking, &analyzer)?;
+            scoring.apply_boosts(&mut corpus)?;
+            let mut results = rank_query(&corpus, &query, mode, &scoring, &rules, top)?.results;
+            results.truncate(top);
+            let passages = pack_for_prompt(&corpus, &results, max_tokens, Context::Chunks(context_chunks))?;
+            match format {
+                OutputFormat::Text => print!("{}", render_passages(&passages)),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&passages)?),
+                OutputFormat::Grep => return Err("--format grep needs line search, retrieve prints passages".into()),
+            }
+        }
+        Command::Replay { log, source, mode, top, log_queries, scoring, chunking, analyzer } => {
+            let logged = read_log(Path::new(&log))?;
+            let rules = scoring.rewrite_rules()?;
+            let mut corpus = open_corpus(&source, &chunking, &analyzer)?;
+            scoring.apply_boosts(&mut corpus)?;
+            let ranker = ranker_name(mode, &scoring);
+            let new_log = log_queries.map(|path| QueryLog::open(Path::new(&path))).transpose()?;
+            println!("overlap  first    ms (logged)  query");
+            let (mut overlap, mut changed, mut latency, mut logged_latency) = (0.0, 0, 0.0, 0.0);
+            for entry in &logged {
+                let started = Instant::now();
+                let results = rank_query(&corpus, &entry.query, mode, &scoring, &rules, top)?.results;
+                let replayed = LoggedQuery::new(&entry.query, &ranker, started.elapsed(), &corpus, &results, top);
+                // The logged top k may be longer or shorter than this run's, compare the same number of results
+                let logged_top = LoggedQuery { results: entry.results.iter().take(top).cloned().collect(), ..entry.clone() };
+                let comparison = querylog::compare(&logged_top, &replayed);
+                println!(
+                    "{:>7.2}  {:<7}  {:>6.2} ({:.2})  {}",
+                    comparison.overlap,
+                    if comparison.same_first { "same" } else { "changed" },
+                    replayed.latency_ms,
+                    entry.latency_ms,
+                    entry.query
+                );
+                overlap += comparison.overlap;
+                changed += usize::from(!comparison.same_first);
+                latency += replayed.latency_ms;
+                logged_latency += entry.latency_ms;
+                if let Some(new_log) = &new_log {
+                    new_log.record(&replayed)?;
+                }
+            }
+            if !logged.is_empty() {
+                let n = logged.len() as f64;
+                println!(
+                    "{} queries: mean overlap {:.2}, first result changed for {} ({:.1}%), mean latency {:.2} ms (logged {:.2} ms)",
+                    logged.len(),
+                    overlap as f64 / n,
+                    changed,
+                    changed as f64 / n * 100.0,
+                    latency / n,
+                    logged_latency / n
+                );
+            }
+        }
+        Command::Judge { source, queries, qrels, top, chunking, analyzer } => {
+            let corpus = open_corpus(&source, &chunking, &analyzer)?;
+            judge(&corpus, &load_queries(Path::new(&queries))?, Path::new(&qrels), top)?;
+        }
+        Command::Interleave { source, log, queries, top, seed, scoring, chunking, analyzer } => {
+            let mut corpus = open_corpus(&source, &chunking, &analyzer)?;
+            scoring.apply_boosts(&mut corpus)?;
+            let queries = queries.map(|path| load_queries(Path::new(&path))).transpose()?;
+            let seed = seed.unwrap_or_else(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64));
+            interleave(&corpus, queries.as_deref(), Path::new(&log), &scoring, top, seed)?;
+        }
+        Command::Sweep { source, queries, qrels, k1, b, smart, metric, top, subtopics, alpha, chunking, analyzer } => {
+            if matches!(metric, Metric::AlphaNdcg | Metric::SubtopicRecall) && subtopics.is_none() {
+                return Err(format!("--metric {} needs --subtopics", metric.name()).into());
+            }
+            if !(0.0..=1.0).contains(&alpha) {
+                return Err("--alpha must be between 0 and 1".into());
+            }
+            let corpus = open_corpus(&source, &chunking, &analyzer)?;
+            let queries = load_queries(Path::new(&queries))?;
+            let qrels = Qrels::load(Path::new(&qrels))?;
+            let subtopics = subtopics.map(|path| SubtopicQrels::load(Path::new(&path))).transpose()?;
+            let grid = Grid { k1, b, smart };
+            sweep(&corpus, &queries, &qrels, subtopics.as_ref().map(|subtopics| (subtopics, alpha)), &grid, metric, top)?;
+        }
+        Command::Features { source, queries, qrels, top, chunking, analyzer } => {
+            let corpus = open_corpus(&source, &chunking, &analyzer)?;
+            let qrels = match qrels {
+                Some(path) => Qrels::load(Path::new(&path))?,
+                None => Qrels::default(),
+            };
+            export_features(&corpus, &load_queries(Path::new(&queries))?, &qrels, top)?;
+        }
+        Command::Compare { source, query, top, explain, chunking, analyzer } => {
+            let corpus = open_corpus(&source, &chunking, &analyzer)?;
+            compare(&corpus, &query, top, explain.as_deref())?;
+        }
+        Command::TermStats { source, term, top, chunking, analyzer } => {
+            let corpus = open_corpus(&source, &chunking, &analyzer)?;
+            print_term_stats(&corpus, &term, top);
+        }
+        Command::Stopwords { source, min_df_ratio, max, output, chunking, analyzer } => {
+            if !(min_df_ratio > 0.0 && min_df_ratio <= 1.0) {
+                return Err("--min-df-ratio must be above 0 and at most 1".into());
+            }
+            let corpus = open_corpus(&source, &chunking, &analyzer)?;
+            let index = corpus.index();
+            let mut terms = index.frequent_terms(min_df_ratio);
+            terms.truncate(max.unwrap_or(usize::MAX));
+            for (term, df) in &terms {
+                println!("{}\t{}\t{:.2}", term, df, *df as f32 / index.num_chunks() as f32);
+            }
+            if let Some(output) = output {
+                // Comments say where the list came from, Stopwords::parse skips them
+                let mut text = format!("# terms in at least {} of {} chunks of {}\n", min_df_ratio, index.num_chunks(), source);
+                for (term, _) in &terms {
+                    text.push_str(term);
+                    text.push('\n');
+                }
+                fs::write(&output, text)?;
+                eprintln!("{} stopwords written to {}, review them before indexing with --stopwords-file", terms.len(), output);
+            }
+        }
+        Command::Vocabulary { source, output, chunking, analyzer } => {
+            let index = Index::new(open_corpus(&source, &chunking, &analyzer)?);
+            match output {
+                Some(output) => index.export_vocabulary(Path::new(&output))?,
+                None => write_vocabulary(&vocabulary(&index.snapshot()), io::stdout().lock())?,
+            }
+        }
+        Command::Cluster { source, k, seed, show, chunking, analyzer } => {
+            let corpus = open_corpus(&source, &chunking, &analyzer)?;
+            for (number, cluster) in cluster_chunks(&corpus, k, seed).iter().enumerate() {
+                println!("cluster {} ({} chunks): {}", number + 1, cluster.chunks.len(), cluster.keywords.join(", "));
+                for chunk in cluster.chunks.iter().take(show) {
+                    println!("    {}", result_key(&corpus, *chunk).unwrap_or_default());
+                }
+            }
+        }
+        Command::Similar { source, output, neighbors, min_similarity, chunking, analyzer } => {
+            if !(min_similarity.is_finite() && min_similarity > 0.0) {
+                return Err("--min-similarity must be above 0".into());
+            }
+            let corpus = open_corpus(&source, &chunking, &analyzer)?;
+            let edges = similarity_edges(&corpus, neighbors, min_similarity);
+            match output {
+                None => write_edges_csv(&corpus, &edges, io::stdout().lock())?,
+                Some(output) if output.ends_with(".parquet") || output.ends_with(".arrow") => {
+                    #[cfg(feature = "arrow")]
+                    rust::export::write_batch(&rust::export::edges_batch(&corpus, &edges)?, Path::new(&output))?;
+                    #[cfg(not(feature = "arrow"))]
+                    return Err(format!("writing {} needs a build with the arrow feature", output).into());
+                }
+                Some(output) => write_edges_csv(&corpus, &edges, io::BufWriter::new(std::fs::File::create(&output)?))?,
+            }
+            eprintln!("{} edges", edges.len());
+        }
+        Command::Merge { output, shards, analyzer } => {
+            let analyzer = Arc::new(analyzer.to_analyzer()?);
+            let mut indexes = Vec::new();
+            for shard in &shards {
+                indexes.push(Index::new(load_corpus(Path::new(shard), Arc::clone(&analyzer))?));
+            }
+            let merged = Index::merge(&indexes)?.snapshot();
+            save_corpus(&merged, Path::new(&output))?;
+            println!("Merged {} shards: {} documents, {} chunks", shards.len(), merged.documents().len(), merged.chunks().len());
+        }
+        Command::Build { dir, output, checkpoint: Some(checkpoint), segment_size, chunking, analyzer } => {
+            if !chunking.exclude.is_empty() || chunking.clean_whitespace || chunking.dehyphenate || chunking.wikipedia {
+                return Err("--exclude, --clean-whitespace, --dehyphenate and --wikipedia don't work with --checkpoint".into());
+            }
+            let template = Corpus::builder()
+                .chunking(chunking.to_config()?)
+                .analyzer(analyzer.to_analyzer()?)
+                .index_options(IndexOptions {
+                    store_text: !chunking.no_store_text,
+                    compress_text: chunking.compress_text,
+                    code_fields: chunking.code_fields,
+                    frontmatter: chunking.frontmatter,
+                })
+                .build()?;
+            let extensions: Vec<&str> = chunking.extensions.iter().map(|e| e.as_str()).collect();
+            let limits = chunking.limits()?;
+            let build = build_resumable_limited(&dir, &extensions, &template, Path::new(&checkpoint), segment_size, &limits)?;
+            let mut corpus = build.corpus;
+            corpus.prune_vocabulary(&DfPruning { min_df: chunking.min_df, max_df_ratio: chunking.max_df_ratio });
+            if let Some(vocabulary) = &chunking.vocabulary {
+                corpus.restrict_vocabulary(&Vocabulary::load(Path::new(vocabulary))?);
+            }
+            save_corpus(&corpus, Path::new(&output))?;
+            println!(
+                "Indexed {} documents into {} chunks, {} of {} segments from the checkpoint",
+                corpus.documents().len(),
+                corpus.chunks().len(),
+                build.reused,
+                build.segments
+            );
+        }
+        Command::Build { dir, output, checkpoint: None, chunking, analyzer, .. } => {
+            let corpus = open_corpus(&dir, &chunking, &analyzer)?;
+            save_corpus(&corpus, Path::new(&output))?;
+            println!("Indexed {} documents into {} chunks", corpus.documents().len(), corpus.chunks().len());
+        }
+        Command::Refresh { index, dir, extensions, time_budget_secs, analyzer } => {
+            let mut corpus = load_corpus(Path::new(&index), Arc::new(analyzer.to_analyzer()?))?;
+            let extensions: Vec<&str> = extensions.iter().map(|e| e.as_str()).collect();
+            let report = refresh(&mut corpus, &dir, &extensions, time_budget_secs.map(Duration::from_secs))?;
+            save_corpus(&corpus, Path::new(&index))?;
+            println!(
+                "{} unchanged, {} updated, {} added, {} removed",
+                report.unchanged, report.updated, report.added, report.removed
+            );
+            if report.pending > 0 {
+                println!("Out of time, {} changed files are left for the next refresh", report.pending);
+            }
+        }
+        #[cfg(feature = "arrow")]
+        Command::Export { source, output, queries, mode, top, scoring, chunking, analyzer } => {
+            let mut corpus = open_corpus(&source, &chunking, &analyzer)?;
+            scoring.apply_boosts(&mut corpus)?;
+            let batch = match queries {
+                None => rust::export::matrix_batch(&corpus, &rust::tfidf::document_term_matrix(&corpus, scoring.tfidf_params()?))?,
+                Some(queries) => {
+                    let mut runs = Vec::new();
+                    for (id, query) in load_queries(Path::new(&queries))? {
+                        let mut results = match mode {
+                            SearchMode::Lines => corpus.search_lines(&query),
+                            SearchMode::Chunks => corpus.search_chunks(&query),
+                            SearchMode::Tfidf => corpus
+                                .search_with(&query, &TfIdfScorer::new(&corpus, scoring.tfidf_params()?))
+                                .map_err(|e| e.render(&query))?,
+                            SearchMode::Bm25 => corpus
+                                .search_with(&query, &Bm25Scorer { corpus: &corpus, params: scoring.bm25_params() })
+                                .map_err(|e| e.render(&query))?,
+                        };
+                        results.truncate(top);
+                        runs.push((id, results));
+                    }
+                    rust::export::results_batch(&corpus, &runs)?
+                }
+            };
+            rust::export::write_batch(&batch, Path::new(&output))?;
+            println!("Wrote {} rows to {}", batch.num_rows(), output);
+        }
+        Command::DumpIndex { source, term, doc, chunking, analyzer } => {
+            // A checkpoint is loaded segment by segment, to show which ids each segment ended up with
+            let (corpus, segments) = if is_checkpoint(Path::new(&source)) {
+                let analyzer = Arc::new(analyzer.to_analyzer()?);
+                let mut segments = Vec::new();
+                for file in checkpoint_segments(Path::new(&source))? {
+                    let segment = load_corpus(&file, Arc::clone(&analyzer))?;
+                    segments.push((file.display().to_string(), segment));
+                }
+                let corpus = Corpus::merge(&segments.iter().map(|(_, segment)| segment).collect::<Vec<&Corpus>>())?;
+                let sizes = segments.iter().map(|(name, segment)| (name.clone(), segment.documents().len())).collect();
+                (corpus, sizes)
+            } else {
+                let corpus = open_corpus(&source, &chunking, &analyzer)?;
+                let sizes = vec![(source.clone(), corpus.documents().len())];
+                (corpus, sizes)
+            };
+            print_index_layout(&corpus, &segments);
+            if let Some(term) = term {
+                print_postings(&corpus, &term);
+            }
+            if let Some(doc) = doc {
+                print_document(&corpus, &doc)?;
+            }
+        }
+        Command::Mcp { source, chunking, analyzer } => {
+            let server = McpServer::new(Index::new(open_corpus(&source, &chunking, &analyzer)?));
+            // stdout carries the protocol, anything for people goes to stderr
+            eprintln!("Serving MCP tools on stdin and stdout");
+            server.serve(io::stdin().lock(), io::stdout().lock())?;
+        }
+        #[cfg(feature = "grpc")]
+        Command::Serve { source, indexes, addr, metrics_addr, cache_size, rate_limit, burst, max_query_length, max_top, admin, chunking, analyzer } => {
+            let limits = rust::grpc::ServerLimits { requests_per_second: rate_limit, burst, max_query_length, max_top };
+            let index = rust::async_api::AsyncIndex::new(open_corpus(&source, &chunking, &analyzer)?);
+            let registry = Arc::new(rust::registry::IndexRegistry::new());
+            // Named indexes are loaded with the same chunking and analyzer options as the default one
+            let options = Arc::new((chunking, analyzer));
+            for named in &indexes {
+                let (name, source) = named.split_once('=').filter(|(name, _)| !name.is_empty()).ok_or_else(|| format!("--index {} isn't NAME=SOURCE", named))?;
+                let (source, options) = (source.to_string(), Arc::clone(&options));
+                let named = registry.open(name, Arc::new(move |cancel| open_corpus_with(&source, &options.0, &options.1, Some(cancel))))?;
+                eprintln!("Serving {} chunks as index {}", named.snapshot().chunks().len(), name);
+            }
+            eprintln!("Serving {} chunks on {}", index.index().snapshot().chunks().len(), addr);
+            // The CLI is synchronous everywhere else, so the runtime only exists for this command
+            let runtime = tokio::runtime::Runtime::new()?;
+            runtime.block_on(rust::grpc::serve(index, registry, addr, metrics_addr, cache_size, limits, admin)).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Load a saved index if source is a file, otherwise load and chunk the directory or s3:// prefix
+fn open_corpus(source: &str, chunking: &ChunkingArgs, analyzer: &AnalyzerArgs) -> Result<Corpus, Box<dyn Error>> {
+    open_corpus_with(source, chunking, analyzer, None)
+}
+
+// A build stops once cancel is cancelled, without one it stops on Ctrl-C
+fn open_corpus_with(
+    source: &str,
+    chunking: &ChunkingArgs,
+    analyzer: &AnalyzerArgs,
+    cancel: Option<&CancellationToken>,
+) -> Result<Corpus, Box<dyn Error>> {
+    let path = Path::new(source);
+    if chunking.compress_text && !cfg!(feature = "zstd") {
+        return Err("--compress-text needs a build with the zstd feature".into());
+    }
+    let vocabulary = chunking.vocabulary.as_ref().map(|file| Vocabulary::load(Path::new(file))).transpose()?;
+    if path.is_file() && !chunking.wikipedia {
+        let mut corpus = load_corpus(path, Arc::new(analyzer.to_analyzer()?))?;
+        // The index was built with whatever it was built with, excluded documents are taken out after loading
+        let excluded: Vec<DocId> = corpus
+            .documents()
+            .iter()
+            .filter(|doc| chunking.exclude.iter().any(|prefix| doc.path.starts_with(prefix.as_str())))
+            .map(|doc| doc.id)
+            .collect();
+        for id in excluded {
+            corpus.remove_document(id);
+        }
+        if let Some(vocabulary) = &vocabulary {
+            corpus.restrict_vocabulary(vocabulary);
+        }
+        Ok(corpus)
+    } else {
+        let mut builder = if chunking.wikipedia {
+            Corpus::builder().add_wikipedia(source, chunking.max_articles)
+        } else if source.starts_with("s3://") {
+            Corpus::builder().add_objects(source)
+        } else {
+            Corpus::builder().add_dir(source)
+        };
+        // Before the whitespace cleanup, which would join the lines a broken word spans
+        if chunking.dehyphenate {
+            builder = builder.transform(dehyphenate);
+        }
+        if chunking.clean_whitespace {
+            builder = builder.transform(clean_whitespace);
+        }
+        builder = match chunking.progress {
+            ProgressKind::Auto if io::stderr().is_terminal() => builder.on_progress(progress::bar()),
+            ProgressKind::Bar => builder.on_progress(progress::bar()),
+            ProgressKind::Json => builder.on_progress(progress::json_lines()),
+            ProgressKind::Auto | ProgressKind::None => builder,
+        };
+        if let Some(vocabulary) = vocabulary {
+            builder = builder.vocabulary(vocabulary);
+        }
+        let builder = builder
+            .extensions(chunking.extensions.clone())
+            .chunking(chunking.to_config()?)
+            .analyzer(analyzer.to_analyzer()?)
+            .limits(chunking.limits()?)
+            .df_pruning(DfPruning { min_df: chunking.min_df, max_df_ratio: chunking.max_df_ratio })
+            .index_options(IndexOptions {
+                store_text: !chunking.no_store_text,
+                compress_text: chunking.compress_text,
+                code_fields: chunking.code_fields,
+                frontmatter: chunking.frontmatter,
+            })
+            .exclude_if({
+                let prefixes = chunking.exclude.clone();
+                move |doc| prefixes.iter().any(|prefix| doc.path.starts_with(prefix.as_str()))
+            });
+        if let Some(cancel) = cancel {
+            return builder.cancel_on(cancel.clone()).build();
+        }
+        let builder = builder.cancel_on(interrupt_token());
+        BUILDING.store(true, Ordering::Relaxed);
+        let corpus = builder.build();
+        BUILDING.store(false, Ordering::Relaxed);
+        corpus
+    }
+}
+
+static BUILDING: AtomicBool = AtomicBool::new(false);
+
+// Ctrl-C during a build stops it at the next file or chunk, so the command ends with "the
+// operation was cancelled" instead of being killed halfway through writing. Anywhere else, or
+// pressed a second time, it exits the way it would without a handler
+fn interrupt_token() -> CancellationToken {
+    static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+    TOKEN
+        .get_or_init(|| {
+            let token = CancellationToken::new();
+            let handler = token.clone();
+            let installed = ctrlc::set_handler(move || {
+                if BUILDING.load(Ordering::Relaxed) && !handler.is_cancelled() {
+                    handler.cancel();
+                } else {
+                    process::exit(130);
+                }
+            });
+            if let Err(e) = installed {
+                eprintln!("Ctrl-C won't cancel indexing: {}", e);
+            }
+            token
+        })
+        .clone()
+}
+
+// Rank a query the way --mode and the scoring options say, for search and replay
+fn rank_query(
+    corpus: &Corpus,
+    query: &str,
+    mode: SearchMode,
+    scoring: &ScoringArgs,
+    rules: &RewriteRules,
+    top: usize,
+) -> Result<TimedResults, Box<dyn Error>> {
+    let Rewritten { query, boosts } = rewrite_query(rules, mode, query);
+    let query = query.as_str();
+    if !boosts.is_empty() && scoring.shards > 1 {
+        return Err("boost rules don't work with --shards".into());
+    }
+    let mut options = scoring.search_options()?;
+    options.selection = scoring.selection(corpus)?;
+    if options.selection.is_some() && scoring.shards > 1 {
+        return Err("--within doesn't work with --shards".into());
+    }
+    // Substring search has no terms, every query is known to it
+    let untimed = |results| TimedResults { results, truncated: false, unknown_terms: Vec::new() };
+    let sharded = |results| Ok(TimedResults { unknown_terms: corpus.unknown_terms(query)?, ..untimed(results) });
+    let ranked = match mode {
+        SearchMode::Lines => Ok(untimed(corpus.search_lines(query))),
+        SearchMode::Chunks => Ok(untimed(corpus.search_chunks(query))),
+        // Sharded search returns only the top k, normalized scores are relative to those
+        SearchMode::Tfidf if scoring.shards > 1 => ShardedCorpus::new(corpus, scoring.shards).search(query, Scoring::TfIdf, top).and_then(sharded),
+        SearchMode::Bm25 if scoring.shards > 1 => {
+            ShardedCorpus::new(corpus, scoring.shards).search(query, Scoring::Bm25(scoring.bm25_params()), top).and_then(sharded)
+        }
+        SearchMode::Tfidf => {
+            let scorer = TfIdfScorer::new(corpus, scoring.tfidf_params()?);
+            corpus.search_timed(query, &BoostedScorer::new(&scorer, &boosts), &options)
+        }
+        SearchMode::Bm25 => {
+            let scorer = Bm25Scorer { corpus, params: scoring.bm25_params() };
+            corpus.search_timed(query, &BoostedScorer::new(&scorer, &boosts), &options)
+        }
+    };
+    Ok(ranked.map_err(|e| e.render(query))?)
+}
+
+// Rules are written in the query language, substring search would look for their ORs literally
+fn rewrite_query(rules: &RewriteRules, mode: SearchMode, query: &str) -> Rewritten {
+    match mode {
+        SearchMode::Lines | SearchMode::Chunks => Rewritten { query: query.to_string(), boosts: Vec::new() },
+        SearchMode::Tfidf | SearchMode::Bm25 => rules.rewrite(query),
+    }
+}
+
+// How a query log names the ranker, with the parameters that change its ranking
+fn ranker_name(mode: SearchMode, scoring: &ScoringArgs) -> String {
+    let name = match mode {
+        SearchMode::Lines => return "lines".to_string(),
+        SearchMode::Chunks => return "chunks".to_string(),
+        SearchMode::Tfidf => scoring.smart.as_ref().map_or("tfidf".to_string(), |smart| format!("tfidf {}", smart)),
+        SearchMode::Bm25 => format!("bm25 k1={} b={}", scoring.k1, scoring.b),
+    };
+    match scoring.repeated_terms {
+        RepeatedTerms::Count => name,
+        repeated => format!("{} repeated={}", name, repeated),
+    }
+}
+
+// summaries is empty without --summary, otherwise it has one entry per printed result
+fn print_results(corpus: &Corpus, results: &[SearchResult], top: usize, summaries: &[Vec<String>]) {
+    for (position, result) in results.iter().take(top).enumerate() {
+        let path = corpus.path(result.doc).unwrap_or("?");
+        match (result.chunk.and_then(|id| corpus.chunk(id)), result.line) {
+            (_, Some(line)) => println!("{}:{}: {}", path, line, result.highlights.join(" ")),
+            (Some(chunk), None) => {
+                match result.normalized {
+                    Some(normalized) => println!("{:.4} ({:.4})  {} #{}", result.score, normalized, path, chunk.index),
+                    None => println!("{:.4}  {} #{}", result.score, path, chunk.index),
+                }
+                for highlight in &result.highlights {
+                    println!("    {}", highlight);
+                }
+            }
+            (None, None) => println!("{:.4}  {}", result.score, path),
+        }
+        if let Some(summary) = summaries.get(position).filter(|summary| !summary.is_empty()) {
+            // Sentences can span wrapped lines, print each summary on one
+            let words: Vec<&str> = summary.iter().flat_map(|sentence| sentence.split_whitespace()).collect();
+            println!("    summary: {}", words.join(" "));
+        }
+    }
+    println!("{} results", results.len());
+}
+
+fn print_facets(facets: &Facets) {
+    let counts = |counts: &BTreeMap<String, usize>| -> String {
+        counts.iter().map(|(key, count)| format!("{} {}", key, count)).collect::<Vec<_>>().join(", ")
+    };
+    println!("by extension: {}", counts(&facets.extensions));
+    println!("by directory: {}", counts(&facets.directories));
+}
+
+/// Search output with --format json, facets and truncated only with --facets and --time-budget-ms
+// Always an object with unknown_terms, possibly empty, so its shape doesn't depend on the query
+#[derive(Serialize)]
+struct JsonSearchOutput<'a> {
+    results: Vec<JsonResult<'a>>,
+    /// Query terms no chunk contains, see Corpus::unknown_terms
+    unknown_terms: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    facets: Option<&'a Facets>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+}
+
+fn print_json(
+    corpus: &Corpus,
+    results: &[SearchResult],
+    top: usize,
+    summaries: &[Vec<String>],
+    facets: Option<&Facets>,
+    truncated: Option<bool>,
+    unknown_terms: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let results: Vec<JsonResult> = results
+        .iter()
+        .take(top)
+        .enumerate()
+        .map(|(position, result)| JsonResult {
+            path: corpus.path(result.doc).unwrap_or("?"),
+            chunk_index: result.chunk.and_then(|id| corpus.chunk(id)).map(|chunk| chunk.index),
+            result,
+            summary: summaries.get(position).map(|summary| summary.as_slice()),
+        })
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&JsonSearchOutput { results, unknown_terms, facets, truncated })?);
+    Ok(())
+}
+
+fn print_grep(corpus: &Corpus, results: &[SearchResult], top: usize) {
+    for result in results.iter().take(top) {
+        let (Some(line), Some(text)) = (result.line, result.highlights.first()) else {
+            continue;
+        };
+        // Columns are 1-based byte offsets of the first match, the same as ripgrep reports them
+        let column = result.spans.first().map(|span| span.start + 1).unwrap_or(1);
+        println!("{}:{}:{}: {}", corpus.path(result.doc).unwrap_or("?"), line, column, text);
+    }
+}
+
+/// Ask for a label for every unjudged result, reading one answer per line from stdin
+// Reading whole lines instead of raw key presses keeps this usable from scripts: pipe the labels in
+fn judge(corpus: &Corpus, queries: &[(String, String)], qrels_path: &Path, top: usize) -> Result<(), Box<dyn Error>> {
+    let mut qrels = Qrels::load(qrels_path)?;
+    let mut lines = io::stdin().lock().lines();
+    println!("Label each result 0-9 (0 = not relevant), enter or s to skip, q to save and quit");
+
+    for (id, query) in queries {
+        let results = match corpus.search(query) {
+            Ok(results) => results,
+            Err(e) => {
+                eprintln!("skipping query {}: {}", id, e.render(query));
+                continue;
+            }
+        };
+        for result in results.iter().take(top) {
+            let Some(key) = result.chunk.and_then(|chunk| result_key(corpus, chunk)) else {
+                continue;
+            };
+            if qrels.relevance(id, &key).is_some() {
+                continue;
+            }
+            println!("\n[{}] {}\n{:.4}  {}", id, query, result.score, key);
+            for highlight in &result.highlights {
+                println!("    {}", highlight);
+            }
+            print!("> ");
+            io::stdout().flush()?;
+
+            // End of input behaves like q, so a script that runs out of labels still saves
+            let answer = lines.next().transpose()?.unwrap_or_else(|| "q".to_string());
+            match answer.trim() {
+                "q" => {
+                    qrels.save(qrels_path)?;
+                    return Ok(());
+                }
+                "" | "s" => {}
+                label => match label.parse() {
+                    Ok(relevance) if relevance <= 9 => {
+                        qrels.judge(id, &key, relevance);
+                        // Save after every label, an interrupted session loses nothing
+                        qrels.save(qrels_path)?;
+                    }
+                    _ => println!("not a label, skipped"),
+                },
+            }
+        }
+    }
+    qrels.save(qrels_path)?;
+    Ok(())
+}
+
+/// Show the interleaved TF-IDF and BM25 results of each query and log the picks, then print the tally
+// The user isn't told which ranker a result came from, so the picks say which results are wanted
+// and not which ranker is liked
+fn interleave(corpus: &Corpus, queries: Option<&[(String, String)]>, log: &Path, scoring: &ScoringArgs, top: usize, seed: u64) -> Result<(), Box<dyn Error>> {
+    let (a, b) = (ranker_name(SearchMode::Tfidf, scoring), ranker_name(SearchMode::Bm25, scoring));
+    let rules = scoring.rewrite_rules()?;
+    let mut lines = io::stdin().lock().lines();
+    let mut queries = queries.map(|queries| queries.iter().map(|(_, query)| query.clone()));
+    println!("Pick the results you would open by number, e.g. 1 3, enter for none, q to quit");
+
+    for round in 0u64.. {
+        let query = match &mut queries {
+            Some(queries) => queries.next(),
+            None => {
+                print!("\nquery> ");
+                io::stdout().flush()?;
+                lines.next().transpose()?.filter(|query| !query.trim().is_empty() && query.trim() != "q")
+            }
+        };
+        let Some(query) = query else {
+            break;
+        };
+        let rank = |mode| rank_query(corpus, &query, mode, scoring, &rules, top).map(|ranked| ranked.results);
+        let (ranking_a, ranking_b) = match (rank(SearchMode::Tfidf), rank(SearchMode::Bm25)) {
+            (Ok(ranking_a), Ok(ranking_b)) => (ranking_a, ranking_b),
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("skipping {}: {}", query, e);
+                continue;
+            }
+        };
+        let interleaved = team_draft(&ranking_a, &ranking_b, top, seed.wrapping_add(round));
+        if interleaved.results.is_empty() {
+            println!("no results for {}", query);
+            continue;
+        }
+        println!("\n{}", query);
+        for (number, result) in interleaved.results.iter().enumerate() {
+            let key = result.chunk.and_then(|chunk| result_key(corpus, chunk)).unwrap_or_default();
+            println!("{:>3}. {}", number + 1, key);
+            for highlight in result.highlights.iter().take(2) {
+                println!("       {}", highlight);
+            }
+        }
+        print!("pick> ");
+        io::stdout().flush()?;
+        // End of input behaves like q, as in judge
+        let answer = lines.next().transpose()?.unwrap_or_else(|| "q".to_string());
+        if answer.trim() == "q" {
+            break;
+        }
+        let picked: Vec<usize> = answer
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter_map(|number| number.parse::<usize>().ok())
+            .filter(|number| (1..=interleaved.results.len()).contains(number))
+            .map(|number| number - 1)
+            .collect();
+        let clicks = picked.iter().filter_map(|&position| interleaved.results[position].chunk.and_then(|chunk| result_key(corpus, chunk))).collect();
+        Outcome::new(&query, &a, &b, clicks, interleaved.credit(&picked)).record(log)?;
+    }
+
+    // Nothing was recorded yet if the first query was quit
+    let tally = if log.exists() { tally(&read_outcomes(log)?) } else { Default::default() };
+    println!();
+    for ranker in [&a, &b] {
+        println!("{}: preferred for {} queries", ranker, tally.wins.get(ranker).unwrap_or(&0));
+    }
+    println!("ties: {}, no picks: {}", tally.ties, tally.no_clicks);
+    Ok(())
+}
+
+/// The configurations a sweep tries besides the fixed TF-IDF grid
+struct Grid {
+    k1: Vec<Score>,
+    b: Vec<Score>,
+    smart: Vec<String>,
+}
+
+/// Evaluate every configuration, print one CSV row each to stdout and the best one to stderr
+// The report goes to stderr so `sweep ... > results.csv` captures only the CSV
+fn sweep(
+    corpus: &Corpus,
+    queries: &[(String, String)],
+    qrels: &Qrels,
+    subtopics: Option<(&SubtopicQrels, f32)>,
+    grid: &Grid,
+    metric: Metric,
+    top: usize,
+) -> Result<(), Box<dyn Error>> {
+    // Box<dyn TermScorer> lets BM25 and TF-IDF configurations live in the same list
+    let mut configs: Vec<(String, Box<dyn TermScorer>)> = Vec::new();
+    for &k1 in &grid.k1 {
+        for &b in &grid.b {
+            configs.push((format!("bm25,{},{},,,,", k1, b), Box::new(Bm25Scorer { corpus, params: Bm25Params { k1, b } })));
+        }
+    }
+    let norms = [
+        ("none", LengthNorm::None),
+        ("length", LengthNorm::Length),
+        ("pivoted-0.25", LengthNorm::Pivoted { slope: 0.25 }),
+        ("pivoted-0.5", LengthNorm::Pivoted { slope: 0.5 }),
+    ];
+    for (tf_name, tf) in [("raw", TfScheme::Raw), ("log", TfScheme::Log)] {
+        for (idf_name, idf) in [("plain", IdfScheme::Plain), ("smooth", IdfScheme::Smooth)] {
+            for (norm_name, norm) in norms {
+                let scorer = TfIdfScorer::new(corpus, TfIdfParams { tf, idf, norm, ..TfIdfParams::default() });
+                configs.push((format!("tfidf,,,{},{},{},", tf_name, idf_name, norm_name), Box::new(scorer)));
+            }
+        }
+    }
+    for notation in &grid.smart {
+        let scorer = TfIdfScorer::new(corpus, parse_smart(notation)?);
+        configs.push((format!("tfidf,,,,,,{}", notation), Box::new(scorer)));
+    }
+
+    let diversity_columns = if subtopics.is_some() { format!(",alpha-ndcg@{k},s-recall@{k}", k = top) } else { String::new() };
+    println!("scorer,k1,b,tf,idf,norm,smart,ndcg@{k},map@{k},p@{k},mrr@{k}{}", diversity_columns, k = top);
+    let mut best: Option<(f32, String)> = None;
+    for (name, scorer) in &configs {
+        let metrics = evaluate(corpus, queries, qrels, scorer.as_ref(), top)?;
+        if metrics.queries == 0 {
+            return Err("none of the queries have judgments in the qrels file".into());
+        }
+        print!("{},{:.4},{:.4},{:.4},{:.4}", name, metrics.ndcg, metrics.map, metrics.precision, metrics.mrr);
+        let diversity = match subtopics {
+            Some((subtopics, alpha)) => {
+                let diversity = evaluate_diversity(corpus, queries, subtopics, scorer.as_ref(), top, alpha)?;
+                if diversity.queries == 0 {
+                    return Err("none of the queries have judgments in the subtopics file".into());
+                }
+                print!(",{:.4},{:.4}", diversity.alpha_ndcg, diversity.subtopic_recall);
+                diversity
+            }
+            None => Diversity::default(),
+        };
+        println!();
+        let value = metric.of(&metrics, &diversity);
+        if best.as_ref().is_none_or(|(best_value, _)| value > *best_value) {
+            best = Some((value, name.clone()));
+        }
+    }
+    if let Some((value, name)) = best {
+        eprintln!("best: {} = {:.4} (scorer,k1,b,tf,idf,norm,smart: {})", metric.name(), value, name);
+    }
+    Ok(())
+}
+
+fn export_features(corpus: &Corpus, queries: &[(String, String)], qrels: &Qrels, top: usize) -> Result<(), Box<dyn Error>> {
+    // Comment lines are skipped by SVMrank and LightGBM, so the header documents the columns for free
+    let names: Vec<String> = FEATURE_NAMES.iter().enumerate().map(|(i, name)| format!("{}:{}", i + 1, name)).collect();
+    println!("# {}", names.join(" "));
+    let scorer = Bm25Scorer { corpus, params: Bm25Params::default() };
+    // SVMrank needs numeric query ids, the query set's own id goes into the comment
+    for (qid, (id, query)) in queries.iter().enumerate() {
+        let terms = parse_query(query).map_err(|e| e.render(query))?.positive_terms(&scorer);
+        for (chunk, _) in corpus.rank_with(query, &scorer)?.into_iter().take(top) {
+            let Some(key) = result_key(corpus, chunk) else {
+                continue;
+            };
+            let label = qrels.relevance(id, &key).unwrap_or(0);
+            println!("{}", svmrank_line(label, qid + 1, &features(corpus, &terms, chunk), &format!("{} {}", id, key)));
+        }
+    }
+    Ok(())
+}
+
+fn print_term_stats(corpus: &Corpus, text: &str, top: usize) {
+    let index = corpus.index();
+    let n = index.num_chunks();
+    // One word can analyze to several terms (identifiers, synonyms) or to none (stopwords)
+    let terms = corpus.analyze_query(text);
+    if terms.is_empty() {
+        println!("'{}' produces no terms with this analyzer", text);
+    }
+    for term in terms {
+        let df = index.doc_freq(&term);
+        println!("term: {}", term);
+        println!("  document frequency:   {} of {} chunks", df, n);
+        println!("  collection frequency: {}", index.collection_freq(&term));
+        if df == 0 {
+            println!("  not in the index");
+            continue;
+        }
+        println!("  idf ln(N/df):                         {:.4}", IdfScheme::Plain.idf(n, df));
+        println!("  idf ln(1 + N/df):                     {:.4}", IdfScheme::Smooth.idf(n, df));
+        println!("  idf bm25 ln(1 + (N-df+.5)/(df+.5)):   {:.4}", idf_bm25(n, df));
+
+        let mut postings = index.postings(&term).to_vec();
+        postings.sort_by(|a, b| b.tf.cmp(&a.tf).then(a.chunk.cmp(&b.chunk)));
+        println!("  top chunks by tf:");
+        for posting in postings.iter().take(top) {
+            let key = result_key(corpus, posting.chunk).unwrap_or_else(|| posting.chunk.to_string());
+            println!("    {:>4}  {} ({} terms)", posting.tf, key, index.chunk_len(posting.chunk));
+        }
+    }
+}
+
+// Settings, sizes and the documents and chunks of every segment, in id order
+fn print_index_layout(corpus: &Corpus, segments: &[(String, usize)]) {
+    let index = corpus.index();
+    println!("documents: {}", corpus.documents().len());
+    println!("chunks:    {} ({:.1} terms on average)", index.num_chunks(), index.avg_len());
+    println!("terms:     {}", index.terms().count());
+    println!("chunking:  {:?}", corpus.chunking());
+    println!("analyzer:  {} (fingerprint {:016x})", corpus.analyzer().describe(), corpus.analyzer().fingerprint());
+    println!("options:   {:?}", corpus.options());
+    println!("segments:  {}", segments.len());
+    let mut start = 0;
+    for (name, size) in segments {
+        let documents = &corpus.documents()[start..start + size];
+        start += size;
+        let (Some(first), Some(last)) = (documents.first(), documents.last()) else {
+            println!("  {}: empty", name);
+            continue;
+        };
+        // Chunks are in document order, so a segment's chunks are those of its first to last document
+        let chunks: Vec<ChunkId> =
+            corpus.chunks().iter().filter(|c| c.doc >= first.id && c.doc <= last.id).map(|c| c.id).collect();
+        let chunk_range = match (chunks.first(), chunks.last()) {
+            (Some(a), Some(b)) => format!("chunks {}-{}", a.0, b.0),
+            _ => "no chunks".to_string(),
+        };
+        println!("  {}: {} documents, ids {}-{}, {}", name, size, first.id.0, last.id.0, chunk_range);
+    }
+}
+
+// Every posting of the terms the text analyzes to, in chunk id order
+fn print_postings(corpus: &Corpus, text: &str) {
+    let index = corpus.index();
+    let terms = corpus.analyze_query(text);
+    if terms.is_empty() {
+        println!("'{}' produces no terms with this analyzer", text);
+    }
+    for term in terms {
+        let postings = index.postings(&term);
+        println!("term: {} (df {}, cf {})", term, postings.len(), index.collection_freq(&term));
+        for posting in postings {
+            let key = result_key(corpus, posting.chunk).unwrap_or_else(|| "?".to_string());
+            println!("  chunk {} {}: tf {}, positions {:?}", posting.chunk.0, key, posting.tf, posting.positions);
+        }
+    }
+}
+
+// The stored fields of a document, then every chunk with its terms, frequencies and positions
+fn print_document(corpus: &Corpus, doc: &str) -> Result<(), Box<dyn Error>> {
+    let id = match doc.parse::<u32>() {
+        Ok(id) => DocId(id),
+        Err(_) => corpus.doc_id(doc).ok_or_else(|| format!("no document with path '{}'", doc))?,
+    };
+    let document = corpus.document(id).ok_or_else(|| format!("no document with id {}", id.0))?;
+    println!("document: {}", id.0);
+    println!("  path:     {}", document.path);
+    println!("  root:     {}", document.root);
+    println!("  file:     {}", document.file.as_ref().map(|f| f.display().to_string()).unwrap_or_else(|| "-".to_string()));
+    println!("  boost:    {}", document.boost);
+    println!("  modified: {:?}", document.modified);
+    println!("  text:     {} bytes stored", document.text.len());
+    let index = corpus.index();
+    for chunk in corpus.chunks().iter().filter(|chunk| chunk.doc == id) {
+        let terms = index.chunk_terms(chunk.id);
+        println!("  chunk {} (#{}): {} terms, {} distinct", chunk.id.0, chunk.index, terms.len, terms.unique);
+        // The index is inverted, so a chunk's terms are found by checking every term's postings
+        let mut vector: Vec<(&str, &[u32])> =
+            index.terms().map(|term| (term, index.positions(term, chunk.id))).filter(|(_, p)| !p.is_empty()).collect();
+        vector.sort();
+        for (term, positions) in vector {
+            println!("    {}: tf {}, positions {:?}", term, positions.len(), positions);
+        }
+    }
+    Ok(())
+}
+
+fn compare(corpus: &Corpus, query: &str, top: usize, explain: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let tfidf = TfIdfScorer::new(corpus, TfIdfParams::default());
+    let bm25 = Bm25Scorer { corpus, params: Bm25Params::default() };
+    let tfidf_ranked = corpus.rank_with(query, &tfidf).map_err(|e| e.render(query))?;
+    let bm25_ranked = corpus.rank_with(query, &bm25).map_err(|e| e.render(query))?;
+    let key = |id| result_key(corpus, id).unwrap_or_default();
+
+    println!("rank  {:<40} bm25", "tfidf");
+    for rank in 0..top.min(tfidf_ranked.len().max(bm25_ranked.len())) {
+        let column = |ranked: &[(ChunkId, Score)]| {
+            ranked.get(rank).map(|(id, score)| format!("{:.4} {}", score, key(*id))).unwrap_or_default()
+        };
+        println!("{:>4}  {:<40} {}", rank + 1, column(&tfidf_ranked), column(&bm25_ranked));
+    }
+
+    if let Some(wanted) = explain {
+        let chunk = corpus
+            .chunks()
+            .iter()
+            .find(|chunk| key(chunk.id) == wanted)
+            .ok_or_else(|| format!("no chunk {}, expected path#chunk index as listed above", wanted))?;
+        println!("\n{}", corpus.explain_rank_diff(query, chunk.id)?.render());
+    }
+    Ok(())
 }
@@ -0,0 +1,84 @@
+use crate::corpus::Corpus;
+use crate::search::to_f32;
+use crate::tfidf::{document_term_matrix, TfIdfParams};
+#[cfg(feature = "ndarray")]
+use crate::corpus::ChunkId;
+#[cfg(feature = "ndarray")]
+use ndarray::Array2;
+#[cfg(feature = "polars")]
+use polars::prelude::{df, DataFrame, PolarsResult};
+
+// The same weights as tfidf::document_term_matrix, in the containers numerical Rust code expects,
+// so the rankers can be compared with linear algebra (cosine similarity, SVD) without leaving Rust
+
+/// The TF-IDF matrix with one row per chunk and one column per term
+#[cfg(feature = "ndarray")]
+pub struct DenseMatrix {
+    pub weights: Array2<f32>,
+    /// The chunk of every row, in id order
+    pub chunks: Vec<ChunkId>,
+    /// The term of every column, sorted
+    pub terms: Vec<String>,
+}
+
+/// Dense TF-IDF matrix, zero where a chunk doesn't contain a term
+// chunks x vocabulary floats, fine for fixtures and samples, too big for a real corpus
+#[cfg(feature = "ndarray")]
+pub fn dense_matrix(corpus: &Corpus, params: TfIdfParams) -> DenseMatrix {
+    let chunks: Vec<ChunkId> = corpus.chunks().iter().map(|chunk| chunk.id).collect();
+    let mut terms: Vec<String> = corpus.index().terms().map(str::to_string).collect();
+    terms.sort();
+    let mut weights = Array2::zeros((chunks.len(), terms.len()));
+    for (chunk, term, weight) in document_term_matrix(corpus, params) {
+        // Both lists are sorted, so every entry's row and column are binary searches
+        if let (Ok(row), Ok(column)) = (chunks.binary_search(&chunk), terms.binary_search(&term)) {
+            weights[[row, column]] = to_f32(weight);
+        }
+    }
+    DenseMatrix { weights, chunks, terms }
+}
+
+/// Sparse TF-IDF matrix as a DataFrame with columns chunk_id, term and weight
+#[cfg(feature = "polars")]
+pub fn matrix_frame(corpus: &Corpus, params: TfIdfParams) -> PolarsResult<DataFrame> {
+    let entries = document_term_matrix(corpus, params);
+    df!(
+        "chunk_id" => entries.iter().map(|(chunk, _, _)| chunk.0).collect::<Vec<u32>>(),
+        "term" => entries.iter().map(|(_, term, _)| term.as_str()).collect::<Vec<&str>>(),
+        "weight" => entries.iter().map(|(_, _, weight)| to_f32(*weight)).collect::<Vec<f32>>(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+
+    fn corpus() -> Corpus {
+        Corpus::new(
+            vec![Document::new("a.txt", "rust borrow rust"), Document::new("b.txt", "python borrow")],
+            ChunkingConfig::default(),
+        )
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn test_dense_matrix() {
+        let matrix = dense_matrix(&corpus(), TfIdfParams::default());
+        assert_eq!(matrix.terms, ["borrow", "python", "rust"]);
+        assert_eq!(matrix.weights.dim(), (2, 3));
+        // "borrow" is in every chunk, so its idf and weight are 0, "rust" is 2 of 3 terms of chunk 0
+        assert_eq!(matrix.weights[[0, 0]], 0.0);
+        assert_eq!(matrix.weights[[1, 2]], 0.0);
+        assert!((matrix.weights[[0, 2]] - 2.0 / 3.0 * 2f32.ln()).abs() < 1e-6);
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn test_matrix_frame() {
+        let frame = matrix_frame(&corpus(), TfIdfParams::default()).unwrap();
+        assert_eq!(frame.shape(), (4, 3));
+        assert_eq!(frame.get_column_names(), ["chunk_id", "term", "weight"]);
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::{self, BufRead, Write};
+use serde_json::{json, Value};
+use crate::bm25::{Bm25Params, Bm25Scorer};
+use crate::index::Index;
+use crate::tfidf::{TfIdfParams, TfIdfScorer};
+
+// The Model Context Protocol is how LLM agents find and call tools: JSON-RPC 2.0 messages, one per
+// line on stdin and stdout, with the server describing its tools and their JSON Schema arguments in
+// tools/list and running them in tools/call. Two tools are enough for retrieval: search returns the
+// best chunks with the paths of their documents, fetch_document the whole text of one of them.
+// Nothing here needs an async runtime, a client starts the server and talks to it over a pipe
+
+/// The protocol revisions this server speaks, newest last
+const PROTOCOL_VERSIONS: [&str; 3] = ["2024-11-05", "2025-03-26", "2025-06-18"];
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Answers MCP requests with searches of an index
+pub struct McpServer {
+    index: Index,
+}
+
+impl McpServer {
+    pub fn new(index: Index) -> McpServer {
+        McpServer { index }
+    }
+
+    /// Read requests from input, one per line, and write the responses to output until input ends
+    pub fn serve<R: BufRead, W: Write>(&self, input: R, mut output: W) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Value>(&line) {
+                Ok(message) => self.handle(&message),
+                Err(e) => Some(error(Value::Null, PARSE_ERROR, &e.to_string())),
+            };
+            if let Some(response) = response {
+                // One line per message, a client reads until the newline
+                writeln!(output, "{}", response)?;
+                output.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The response to one message, None for notifications, which have no id and get no answer
+    pub fn handle(&self, message: &Value) -> Option<Value> {
+        let id = message.get("id")?.clone();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let result = match message.get("method").and_then(Value::as_str) {
+            Some("initialize") => {
+                // Agree on the client's revision if it is one we speak, otherwise offer our newest
+                let requested = params.get("protocolVersion").and_then(Value::as_str);
+                let version = requested.filter(|v| PROTOCOL_VERSIONS.contains(v)).unwrap_or(PROTOCOL_VERSIONS[PROTOCOL_VERSIONS.len() - 1]);
+                json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": { "name": "tfidf", "version": env!("CARGO_PKG_VERSION") },
+                })
+            }
+            Some("ping") => json!({}),
+            Some("tools/list") => json!({ "tools": tools() }),
+            Some("tools/call") => match self.call(&params) {
+                Ok(result) => result,
+                Err(message) => return Some(error(id, INVALID_PARAMS, &message)),
+            },
+            Some(method) => return Some(error(id, METHOD_NOT_FOUND, &format!("unknown method {}", method))),
+            None => return Some(error(id, METHOD_NOT_FOUND, "no method")),
+        };
+        Some(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    // A tool that fails, e.g. on a query that doesn't parse, answers with isError so the model can
+    // read the message and try again. Only calls to tools that don't exist are protocol errors
+    fn call(&self, params: &Value) -> Result<Value, String> {
+        let arguments = params.get("arguments").cloned().unwrap_or(Value::Null);
+        let output = match params.get("name").and_then(Value::as_str) {
+            Some("search") => self.search(&arguments),
+            Some("fetch_document") => self.fetch_document(&arguments),
+            Some(name) => return Err(format!("unknown tool {}", name)),
+            None => return Err("tools/call needs the name of a tool".to_string()),
+        };
+        let (text, is_error) = match output {
+            Ok(text) => (text, false),
+            Err(message) => (message, true),
+        };
+        Ok(json!({ "content": [{ "type": "text", "text": text }], "isError": is_error }))
+    }
+
+    fn search(&self, arguments: &Value) -> Result<String, String> {
+        let query = arguments.get("query").and_then(Value::as_str).ok_or("search needs a query")?;
+        let top = arguments.get("top_k").and_then(Value::as_u64).unwrap_or(10) as usize;
+        let corpus = self.index.snapshot();
+        let results = match arguments.get("ranking").and_then(Value::as_str).unwrap_or("bm25") {
+            "bm25" => corpus.search_with(query, &Bm25Scorer { corpus: &corpus, params: Bm25Params::default() }),
+            "tfidf" => corpus.search_with(query, &TfIdfScorer::new(&corpus, TfIdfParams::default())),
+            other => return Err(format!("unknown ranking {}, expected bm25 or tfidf", other)),
+        };
+        let results = results.map_err(|e| e.render(query))?;
+        if results.is_empty() {
+            return Ok(format!("No results for {}", query));
+        }
+        let mut text = String::new();
+        for (position, result) in results.iter().take(top).enumerate() {
+            let Some(chunk) = result.chunk.and_then(|id| corpus.chunk(id)) else {
+                continue;
+            };
+            let chunk_text = corpus.chunk_text(chunk.id).map_err(|e| e.to_string())?;
+            let path = corpus.path(result.doc).unwrap_or("?");
+            text.push_str(&format!("{}. {} #{} (score {:.4})\n{}\n\n", position + 1, path, chunk.index, result.score, chunk_text.trim()));
+        }
+        Ok(text)
+    }
+
+    fn fetch_document(&self, arguments: &Value) -> Result<String, String> {
+        let path = arguments.get("path").and_then(Value::as_str).ok_or("fetch_document needs a path")?;
+        let corpus = self.index.snapshot();
+        let doc = corpus.doc_id(path).ok_or_else(|| format!("no document {}, use a path search returned", path))?;
+        Ok(corpus.document_text(doc).map_err(|e| e.to_string())?.into_owned())
+    }
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+// What tools/list tells the model, the descriptions are all it knows about the tools
+fn tools() -> Value {
+    json!([
+        {
+            "name": "search",
+            "description": "Search the indexed documents. Returns the best matching chunks, best first, each with the path of its document. Queries may use AND, OR, NOT, \"phrases\" and (groups).",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "The search query" },
+                    "top_k": { "type": "integer", "minimum": 1, "description": "Most chunks to return, 10 by default" },
+                    "ranking": { "type": "string", "enum": ["bm25", "tfidf"], "description": "How to rank, bm25 by default" }
+                },
+                "required": ["query"]
+            }
+        },
+        {
+            "name": "fetch_document",
+            "description": "The whole text of a document, by a path search returned.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "The path of the document" }
+                },
+                "required": ["path"]
+            }
+        }
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::{Corpus, Document};
+
+    #[test]
+    fn test_mcp_session() {
+        let corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "rust borrow checker"))
+            .add_document(Document::new("b.txt", "python interpreter"))
+            .build()
+            .unwrap();
+        let server = McpServer::new(Index::new(corpus));
+        let requests = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{}}}"#,
+            r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"search","arguments":{"query":"borrow"}}}"#,
+            r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"fetch_document","arguments":{"path":"b.txt"}}}"#,
+            r#"{"jsonrpc":"2.0","id":5,"method":"tools/call","params":{"name":"search","arguments":{"query":"(rust"}}}"#,
+            r#"{"jsonrpc":"2.0","id":6,"method":"tools/call","params":{"name":"delete"}}"#,
+            "not json",
+        ];
+        let mut output = Vec::new();
+        server.serve(requests.join("\n").as_bytes(), &mut output).unwrap();
+        let responses: Vec<Value> = String::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        // The notification gets no response
+        assert_eq!(responses.len(), 7);
+        assert_eq!(responses[0]["result"]["protocolVersion"], "2025-03-26");
+        assert_eq!(responses[1]["result"]["tools"][0]["name"], "search");
+        let found = responses[2]["result"]["content"][0]["text"].as_str().unwrap();
+        assert!(found.starts_with("1. a.txt #0") && found.contains("rust borrow checker"));
+        assert_eq!(responses[3]["result"]["content"][0]["text"], "python interpreter");
+        assert_eq!(responses[4]["result"]["isError"], true);
+        assert_eq!(responses[5]["error"]["code"], INVALID_PARAMS);
+        assert_eq!((responses[6]["id"].clone(), responses[6]["error"]["code"].clone()), (Value::Null, json!(PARSE_ERROR)));
+    }
+}
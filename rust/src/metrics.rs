@@ -0,0 +1,119 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use tokio::net::TcpListener;
+use crate::async_api::AsyncError;
+use crate::index::Snapshot;
+
+/// Counters and histograms of a running server, exported in the Prometheus text format
+// Names follow the Prometheus conventions, unit suffixes and _total for counters, so standard dashboards apply
+pub struct ServerMetrics {
+    registry: Registry,
+    queries: IntCounterVec,
+    latency: HistogramVec,
+    cache: IntCounterVec,
+    rejected: IntCounterVec,
+    documents: IntGauge,
+    chunks: IntGauge,
+    terms: IntGauge,
+    generation: IntGauge,
+}
+
+impl ServerMetrics {
+    pub fn new() -> Result<ServerMetrics, prometheus::Error> {
+        let registry = Registry::new();
+        let queries = IntCounterVec::new(
+            Opts::new("search_queries_total", "Search requests by ranker and outcome"),
+            &["ranker", "outcome"],
+        )?;
+        // Scoring a small corpus takes microseconds, the default buckets start at 5ms
+        let latency = HistogramVec::new(
+            HistogramOpts::new("search_latency_seconds", "Time to parse, score and rank a query, by ranker")
+                .buckets(prometheus::exponential_buckets(0.00005, 2.0, 16)?),
+            &["ranker"],
+        )?;
+        let cache = IntCounterVec::new(Opts::new("search_cache_requests_total", "Search requests by result cache outcome"), &["result"])?;
+        let rejected = IntCounterVec::new(Opts::new("requests_rejected_total", "Requests refused by a server limit, by limit"), &["limit"])?;
+        let documents = IntGauge::new("index_documents", "Documents in the served index")?;
+        let chunks = IntGauge::new("index_chunks", "Chunks in the served index")?;
+        let terms = IntGauge::new("index_terms", "Distinct terms in the served index")?;
+        let generation = IntGauge::new("index_generation", "Number of times the served index was replaced")?;
+
+        registry.register(Box::new(queries.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(cache.clone()))?;
+        registry.register(Box::new(rejected.clone()))?;
+        registry.register(Box::new(documents.clone()))?;
+        registry.register(Box::new(chunks.clone()))?;
+        registry.register(Box::new(terms.clone()))?;
+        registry.register(Box::new(generation.clone()))?;
+        Ok(ServerMetrics { registry, queries, latency, cache, rejected, documents, chunks, terms, generation })
+    }
+
+    /// Record one search, ok is false for queries that failed to parse
+    pub fn observe_query(&self, ranker: &str, elapsed: Duration, ok: bool) {
+        let outcome = if ok { "ok" } else { "error" };
+        self.queries.with_label_values(&[ranker, outcome]).inc();
+        self.latency.with_label_values(&[ranker]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Record whether a search was answered from the result cache
+    pub fn observe_cache(&self, hit: bool) {
+        self.cache.with_label_values(&[if hit { "hit" } else { "miss" }]).inc();
+    }
+
+    /// Record a request refused by the rate limit, or by the query_length or top limit
+    pub fn observe_rejected(&self, limit: &str) {
+        self.rejected.with_label_values(&[limit]).inc();
+    }
+
+    /// Update the index size gauges, call it whenever a new snapshot is published
+    pub fn observe_index(&self, snapshot: &Snapshot) {
+        self.documents.set(snapshot.documents().len() as i64);
+        self.chunks.set(snapshot.chunks().len() as i64);
+        self.terms.set(snapshot.index().terms().count() as i64);
+        self.generation.set(snapshot.generation() as i64);
+    }
+
+    /// Every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = Vec::new();
+        // Encoding into a Vec can't fail and every metric name is valid, so there is nothing to report
+        TextEncoder::new().encode(&self.registry.gather(), &mut out).unwrap_or_default();
+        String::from_utf8(out).unwrap_or_default()
+    }
+}
+
+/// Serve GET /metrics on addr until the process is stopped
+pub async fn serve_metrics(metrics: Arc<ServerMetrics>, addr: SocketAddr) -> Result<(), AsyncError> {
+    let app = Router::new().route("/metrics", get(move || async move { metrics.render() }));
+    axum::serve(TcpListener::bind(addr).await?, app).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::{Corpus, Document};
+    use crate::index::Index;
+
+    #[test]
+    fn test_render_counts_queries_and_index_size() {
+        let metrics = ServerMetrics::new().unwrap();
+        metrics.observe_query("bm25", Duration::from_millis(2), true);
+        metrics.observe_query("bm25", Duration::from_millis(3), true);
+        metrics.observe_query("tfidf", Duration::from_millis(1), false);
+        let index = Index::new(Corpus::new(vec![Document::new("a.txt", "rust borrow")], ChunkingConfig::default()));
+        metrics.observe_index(&index.snapshot());
+
+        let text = metrics.render();
+        assert!(text.contains(r#"search_queries_total{outcome="ok",ranker="bm25"} 2"#));
+        assert!(text.contains(r#"search_queries_total{outcome="error",ranker="tfidf"} 1"#));
+        assert!(text.contains(r#"search_latency_seconds_count{ranker="bm25"} 2"#));
+        assert!(text.contains("index_terms 2"));
+    }
+}
@@ -0,0 +1,97 @@
+use std::borrow::Cow;
+
+// Text transforms for CorpusBuilder::transform. Raw documentation dumps and extracted text carry
+// a lot that isn't words: Windows line endings, byte order marks, form feeds, runs of spaces
+// left over from tables and indentation. None of it makes a token, but the character based
+// chunk strategies count all of it, so a chunk of mostly padding holds only a few words
+
+/// Collapse every run of spaces and tabs to one space and every run of more than one blank line
+/// to one blank line, turn \r\n and \r into \n, drop control characters and byte order marks, and
+/// trim the start and end of the text and of every line. Returns the text as it is if it's clean
+pub fn clean_whitespace(text: &str) -> Cow<'_, str> {
+    let mut cleaned = String::with_capacity(text.len());
+    // Whitespace is only written out once the next visible character shows what it separates
+    let mut spaces = false;
+    let mut newlines = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // \r\n is one line break, a lone \r (old Mac OS) is one too
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => newlines += 1,
+            c if c.is_whitespace() => spaces = true,
+            // U+FEFF is a byte order mark at the start of a file and a zero width space anywhere else
+            c if c.is_control() || c == '\u{feff}' => {}
+            c => {
+                if !cleaned.is_empty() {
+                    // A blank line separates paragraphs, more of them separate nothing more
+                    if newlines > 0 {
+                        cleaned.push_str(if newlines > 1 { "\n\n" } else { "\n" });
+                    } else if spaces {
+                        cleaned.push(' ');
+                    }
+                }
+                cleaned.push(c);
+                spaces = false;
+                newlines = 0;
+            }
+        }
+    }
+    if cleaned == text { Cow::Borrowed(text) } else { Cow::Owned(cleaned) }
+}
+
+/// Join words hyphenated across a line break, "infor-\nmation" becomes "information", and drop
+/// soft hyphens (U+00AD). Returns the text as it is if there is nothing to repair
+// Text extracted from PDFs and scanned books keeps the hyphens typesetting added at line ends,
+// and both halves of a broken word end up in the vocabulary as words nobody searches for.
+// Only a letter, a hyphen, a line break and a lowercase letter are joined: "Rust-\nBelt" and
+// "2023-\n2024" keep their hyphen. Compounds broken at their own hyphen ("well-\nknown") can't
+// be told apart from typesetting and lose it. The joined word takes the place of the line break
+pub fn dehyphenate(text: &str) -> Cow<'_, str> {
+    let mut repaired = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find(['-', '\u{ad}']) {
+        repaired.push_str(&rest[..i]);
+        let hyphen = if rest[i..].starts_with('-') { '-' } else { '\u{ad}' };
+        let after = &rest[i + hyphen.len_utf8()..];
+        // Spaces before the line break and the indentation of the next line don't matter
+        let line_end = after.trim_start_matches([' ', '\t']);
+        let next_line = line_end.strip_prefix("\r\n").or_else(|| line_end.strip_prefix('\n'));
+        let continued = next_line
+            .map(|line| line.trim_start_matches([' ', '\t']))
+            .filter(|line| line.starts_with(char::is_lowercase) && repaired.ends_with(char::is_alphabetic));
+        match continued {
+            Some(line) => rest = line,
+            None => {
+                // A soft hyphen is invisible unless a line breaks at it
+                if hyphen == '-' {
+                    repaired.push('-');
+                }
+                rest = after;
+            }
+        }
+    }
+    repaired.push_str(rest);
+    // Every repair makes the text shorter
+    if repaired.len() == text.len() { Cow::Borrowed(text) } else { Cow::Owned(repaired) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_whitespace() {
+        let raw = "\u{feff}Title\r\n\r\n\r\n  indented\t\ttext  \rnext\x0cpage\x07\n";
+        assert_eq!(clean_whitespace(raw), "Title\n\nindented text\nnext page");
+        assert!(matches!(clean_whitespace("already\n\nclean text"), Cow::Borrowed(_)));
+        assert_eq!(clean_whitespace(" \n\t "), "");
+    }
+
+    #[test]
+    fn test_dehyphenate() {
+        let extracted = "the infor-\n  mation retrie-  \r\nval book, Rust-\nBelt 2023-\n2024, hyphen\u{ad}ation";
+        assert_eq!(dehyphenate(extracted), "the information retrieval book, Rust-\nBelt 2023-\n2024, hyphenation");
+        assert!(matches!(dehyphenate("well-known - and\n- listed"), Cow::Borrowed(_)));
+    }
+}
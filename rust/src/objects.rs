@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::future;
+use std::io;
+use std::sync::Arc;
+use std::time::SystemTime;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectMeta, ObjectStore, PutPayload};
+use tokio::runtime::{Builder, Runtime};
+use crate::corpus::Document;
+use crate::storage::StorageBackend;
+
+// Corpora and indexes in S3 or anything that speaks its API (MinIO, R2, Ceph), through the
+// object_store crate. Listing a prefix is one paged request, downloading is one GET per object,
+// and with thousands of small objects the round trips are the whole cost, so several GETs run at
+// once, ResourceLimits::max_open_files of them. object_store is async, the rest of the crate isn't: every call runs on a
+// small runtime of its own, so none of this can be called from inside another tokio runtime.
+// Credentials, region and endpoint come from the usual AWS_* environment variables, e.g.
+// AWS_ENDPOINT=http://localhost:9000 and AWS_ALLOW_HTTP=true for a local MinIO
+
+// "s3://bucket/some/prefix" -> ("bucket", "some/prefix")
+fn parse_url(url: &str) -> Result<(&str, &str), String> {
+    let rest = url.strip_prefix("s3://").ok_or_else(|| format!("'{}' is not an s3:// URL", url))?;
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        return Err(format!("'{}' has no bucket", url));
+    }
+    Ok((bucket, prefix.trim_end_matches('/')))
+}
+
+/// A client for one bucket, configured from the environment
+pub fn s3_store(bucket: &str) -> Result<Arc<dyn ObjectStore>, Box<dyn Error>> {
+    Ok(Arc::new(AmazonS3Builder::from_env().with_bucket_name(bucket).build()?))
+}
+
+fn runtime() -> io::Result<Runtime> {
+    Builder::new_current_thread().enable_all().build()
+}
+
+/// Download every object under an s3://bucket/prefix URL whose extension is one of extensions,
+/// at most in_flight at a time
+/// Documents are named by their object key and sorted by it, like the files of a directory
+pub fn load_objects(url: &str, extensions: &[&str], in_flight: usize) -> Result<Vec<Document>, Box<dyn Error>> {
+    let (bucket, prefix) = parse_url(url)?;
+    let store = s3_store(bucket)?;
+    runtime()?.block_on(load_from_store(store.as_ref(), prefix, url, extensions, in_flight))
+}
+
+/// Like load_objects, from any object store
+pub async fn load_from_store(
+    store: &dyn ObjectStore,
+    prefix: &str,
+    root: &str,
+    extensions: &[&str],
+    in_flight: usize,
+) -> Result<Vec<Document>, Box<dyn Error>> {
+    let prefix = (!prefix.is_empty()).then(|| ObjectPath::from(prefix));
+    let wanted = |meta: &ObjectMeta| meta.location.extension().is_some_and(|extension| extensions.contains(&extension));
+    let objects: Vec<ObjectMeta> = store.list(prefix.as_ref()).try_filter(|meta| future::ready(wanted(meta))).try_collect().await?;
+    // buffer_unordered keeps in_flight downloads going and yields them as they finish
+    let mut documents: Vec<Document> = stream::iter(objects)
+        .map(|meta| async move {
+            let bytes = store.get(&meta.location).await?.bytes().await?;
+            let path = meta.location.to_string();
+            let text = String::from_utf8(bytes.to_vec()).map_err(|_| format!("{} is not UTF-8 text", path))?;
+            Ok::<Document, Box<dyn Error>>(Document {
+                root: root.to_string(),
+                modified: Some(SystemTime::from(meta.last_modified)),
+                size: Some(meta.size),
+                ..Document::new(&path, &text)
+            })
+        })
+        .buffer_unordered(in_flight.max(1))
+        .try_collect()
+        .await?;
+    documents.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(documents)
+}
+
+/// Saved indexes as objects in an object store, see storage::StorageBackend
+pub struct ObjectStoreBackend {
+    store: Arc<dyn ObjectStore>,
+    runtime: Runtime,
+}
+
+impl ObjectStoreBackend {
+    pub fn new(store: Arc<dyn ObjectStore>) -> io::Result<ObjectStoreBackend> {
+        Ok(ObjectStoreBackend { store, runtime: runtime()? })
+    }
+
+    /// A bucket configured from the environment, see s3_store
+    pub fn s3(bucket: &str) -> Result<ObjectStoreBackend, Box<dyn Error>> {
+        Ok(ObjectStoreBackend::new(s3_store(bucket)?)?)
+    }
+}
+
+fn to_io(e: object_store::Error) -> io::Error {
+    match e {
+        object_store::Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, e),
+        e => io::Error::other(e),
+    }
+}
+
+impl StorageBackend for ObjectStoreBackend {
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let bytes = self.runtime.block_on(async { self.store.get(&ObjectPath::from(key)).await?.bytes().await });
+        Ok(bytes.map_err(to_io)?.to_vec())
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        // A single PUT is atomic, readers see the old object or the new one
+        let payload = PutPayload::from(bytes.to_vec());
+        self.runtime.block_on(self.store.put(&ObjectPath::from(key), payload)).map_err(to_io)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match self.runtime.block_on(self.store.delete(&ObjectPath::from(key))).map_err(to_io) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        // Object stores list by whole path segments, "shards/0" is under "shards" but not under "sha",
+        // so list the directory the prefix is in and keep the keys that start with it
+        let directory = prefix.rsplit_once('/').map(|(directory, _)| ObjectPath::from(directory));
+        let objects: Vec<ObjectMeta> = self.runtime.block_on(self.store.list(directory.as_ref()).try_collect()).map_err(to_io)?;
+        let mut keys: Vec<String> = objects.into_iter().map(|meta| meta.location.to_string()).filter(|key| key.starts_with(prefix)).collect();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn test_load_from_object_store() {
+        assert_eq!(parse_url("s3://corpus/docs/").unwrap(), ("corpus", "docs"));
+        assert!(parse_url("https://corpus/docs").is_err());
+
+        let backend = ObjectStoreBackend::new(Arc::new(InMemory::new())).unwrap();
+        backend.put("docs/b.txt", b"python garbage collector").unwrap();
+        backend.put("docs/nested/a.txt", b"rust borrow checker").unwrap();
+        backend.put("docs/c.md", b"not loaded").unwrap();
+        backend.put("other/d.txt", b"not under the prefix").unwrap();
+        assert_eq!(backend.list("docs/n").unwrap(), ["docs/nested/a.txt"]);
+
+        let documents = backend.runtime.block_on(load_from_store(backend.store.as_ref(), "docs", "s3://corpus/docs", &["txt"], 2)).unwrap();
+        let paths: Vec<&str> = documents.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, ["docs/b.txt", "docs/nested/a.txt"]);
+        assert_eq!(documents[1].text.as_ref(), "rust borrow checker");
+        assert_eq!(documents[1].size, Some(19));
+    }
+}
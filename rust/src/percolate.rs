@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use crate::analyzer::Token;
+use crate::corpus::ChunkId;
+use crate::query::{parse_query, Query, QueryError, TermScorer};
+use crate::search::Score;
+use crate::shard::Scoring;
+use crate::stats::CorpusStats;
+
+// Search turned around: instead of running one query against many stored documents, many stored
+// queries are run against one new document. For alerting on a stream, e.g. "tell me whenever a
+// document about rust borrow checking arrives". Running every standing query on every document
+// doesn't scale, so the queries are indexed by their terms like documents are, and a document is
+// only scored against the queries that share at least one term with it
+
+/// A standing query the document matched, with its score
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub id: String,
+    pub score: Score,
+}
+
+struct StandingQuery {
+    id: String,
+    query: Query,
+    threshold: Score,
+}
+
+/// Standing queries to match incoming documents against
+/// Documents are scored with the statistics of an existing corpus, see stats::score_document
+pub struct Percolator {
+    stats: Arc<CorpusStats>,
+    scoring: Scoring,
+    queries: Vec<StandingQuery>,
+    // Term -> positions in queries of the queries with that term outside a NOT
+    by_term: HashMap<String, Vec<usize>>,
+}
+
+impl Percolator {
+    pub fn new(stats: Arc<CorpusStats>, scoring: Scoring) -> Percolator {
+        Percolator { stats, scoring, queries: Vec::new(), by_term: HashMap::new() }
+    }
+
+    /// Register a query under an id, replacing any query already registered under it
+    /// Documents match it when they score at least threshold
+    pub fn register(&mut self, id: &str, query: &str, threshold: Score) -> Result<(), QueryError> {
+        let query = parse_query(query)?;
+        self.queries.retain(|standing| standing.id != id);
+        self.queries.push(StandingQuery { id: id.to_string(), query, threshold });
+        self.reindex();
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, id: &str) -> bool {
+        let before = self.queries.len();
+        self.queries.retain(|standing| standing.id != id);
+        self.reindex();
+        self.queries.len() < before
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    fn reindex(&mut self) {
+        let analyzer = AnalyzerOnly(&self.stats);
+        self.by_term.clear();
+        for (position, standing) in self.queries.iter().enumerate() {
+            let terms: HashSet<String> = standing.query.positive_terms(&analyzer).into_iter().collect();
+            for term in terms {
+                self.by_term.entry(term).or_default().push(position);
+            }
+        }
+    }
+
+    /// The standing queries the text matches at or above their threshold, highest score first
+    pub fn percolate(&self, text: &str) -> Vec<Alert> {
+        let document = DocumentScorer::new(text, &self.stats, self.scoring);
+        let mut candidates: Vec<usize> =
+            document.positions.keys().flat_map(|term| self.by_term.get(term)).flatten().copied().collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut alerts: Vec<Alert> = candidates
+            .into_iter()
+            .filter_map(|position| {
+                let standing = &self.queries[position];
+                // The document is the only "chunk", so a match is an entry for ChunkId(0)
+                let score = *standing.query.evaluate(&document).get(&ChunkId(0))?;
+                (score >= standing.threshold).then(|| Alert { id: standing.id.clone(), score })
+            })
+            .collect();
+        alerts.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap().then_with(|| a.id.cmp(&b.id)));
+        alerts
+    }
+}
+
+// Analysis without any postings, enough for Query::positive_terms
+struct AnalyzerOnly<'a>(&'a CorpusStats);
+
+impl TermScorer for AnalyzerOnly<'_> {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        self.0.analyzer().tokens(text)
+    }
+
+    fn score_term(&self, _term: &str) -> Vec<(ChunkId, Score)> {
+        Vec::new()
+    }
+
+    fn positions(&self, _term: &str, _chunk: ChunkId) -> Vec<u32> {
+        Vec::new()
+    }
+}
+
+// A single document as a one-chunk index, so standing queries are evaluated exactly like searches
+struct DocumentScorer<'a> {
+    stats: &'a CorpusStats,
+    scoring: Scoring,
+    positions: HashMap<String, Vec<u32>>,
+    length: Score,
+}
+
+impl<'a> DocumentScorer<'a> {
+    fn new(text: &str, stats: &'a CorpusStats, scoring: Scoring) -> DocumentScorer<'a> {
+        let tokens = stats.analyzer().tokens(text);
+        let length = tokens.len() as Score;
+        let mut positions: HashMap<String, Vec<u32>> = HashMap::new();
+        for token in tokens {
+            positions.entry(token.text).or_default().push(token.position);
+        }
+        DocumentScorer { stats, scoring, positions, length }
+    }
+}
+
+impl TermScorer for DocumentScorer<'_> {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        self.stats.analyzer().tokens(text)
+    }
+
+    fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)> {
+        let Some(positions) = self.positions.get(term) else {
+            return Vec::new();
+        };
+        // An empty corpus has no average length, treat the document as average
+        let avg_dl = if self.stats.avg_dl > 0.0 { self.stats.avg_dl } else { self.length };
+        let tf = positions.len() as Score;
+        let score = self.scoring.posting_score(tf, self.length, self.stats.n_docs, self.stats.doc_freq(term), avg_dl);
+        vec![(ChunkId(0), score)]
+    }
+
+    fn positions(&self, term: &str, _chunk: ChunkId) -> Vec<u32> {
+        self.positions.get(term).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bm25::Bm25Params;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::{Corpus, Document};
+    use crate::stats::score_document;
+
+    #[test]
+    fn test_percolate_matches_standing_queries() {
+        let files = vec![
+            Document::new("a.txt", "rust borrow checker"),
+            Document::new("b.txt", "rust garbage"),
+            Document::new("c.txt", "python garbage collector"),
+        ];
+        let stats = Corpus::new(files, ChunkingConfig::default()).stats();
+        let mut percolator = Percolator::new(Arc::clone(&stats), Scoring::Bm25(Bm25Params::default()));
+        percolator.register("borrowing", "rust AND borrow", 0.0).unwrap();
+        percolator.register("gc", "\"garbage collector\"", 0.0).unwrap();
+        percolator.register("python", "python -rust", 0.0).unwrap();
+        percolator.register("strict", "borrow", 100.0).unwrap();
+        assert!(percolator.register("broken", "(rust", 0.0).is_err());
+
+        let text = "the borrow checker in rust has no garbage collector";
+        let alerts = percolator.percolate(text);
+        let ids: Vec<&str> = alerts.iter().map(|alert| alert.id.as_str()).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&"borrowing") && ids.contains(&"gc"));
+        // A single-term query scores exactly what score_document gives
+        percolator.register("strict", "borrow", 0.0).unwrap();
+        let strict = percolator.percolate(text).into_iter().find(|alert| alert.id == "strict").unwrap();
+        assert!((strict.score - score_document("borrow", text, &stats).bm25).abs() < 1e-6);
+
+        assert!(percolator.unregister("gc"));
+        assert_eq!(percolator.len(), 3);
+        assert!(percolator.percolate("python snakes").iter().any(|alert| alert.id == "python"));
+    }
+}
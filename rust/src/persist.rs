@@ -0,0 +1,211 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::analyzer::Analyzer;
+use crate::corpus::Corpus;
+use crate::storage::StorageBackend;
+
+/// Version of the on-disk index format, bumped whenever the layout changes
+pub const FORMAT_VERSION: u32 = 4;
+
+// Every saved index starts with a single header line:
+//   TFIDX <format version> <analyzer fingerprint> <checksum of the body>
+// followed by the analyzer description and the corpus as JSON
+const MAGIC: &str = "TFIDX";
+
+#[derive(Serialize)]
+struct IndexFileRef<'a> {
+    analyzer: String,
+    corpus: &'a Corpus,
+}
+
+#[derive(Deserialize)]
+struct IndexFile {
+    analyzer: String,
+    corpus: Corpus,
+}
+
+/// Why a saved index could not be loaded
+#[derive(Debug)]
+pub enum IndexFileError {
+    Io(io::Error),
+    /// The file is not an index file, or its header or body can't be parsed
+    Malformed(String),
+    UnsupportedVersion { found: u32, supported: u32 },
+    /// The body doesn't match the checksum in the header, the file is truncated or corrupted
+    ChecksumMismatch { expected: u64, found: u64 },
+    /// The index was built with different analyzer settings than the ones it is being loaded with
+    AnalyzerMismatch { saved: String, current: String },
+}
+
+impl fmt::Display for IndexFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexFileError::Io(e) => write!(f, "could not read index file: {}", e),
+            IndexFileError::Malformed(reason) => write!(f, "not a valid index file: {}", reason),
+            IndexFileError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "index format version {} is not supported (this build reads version {}), rebuild the index",
+                found, supported
+            ),
+            IndexFileError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "index file is corrupted: checksum {:016x} does not match header {:016x}",
+                found, expected
+            ),
+            IndexFileError::AnalyzerMismatch { saved, current } => write!(
+                f,
+                "index was built with analyzer [{}] but is being loaded with [{}], rebuild the index or use the same analyzer",
+                saved, current
+            ),
+        }
+    }
+}
+
+impl Error for IndexFileError {}
+
+// From lets the ? operator turn io::Error into IndexFileError automatically
+impl From<io::Error> for IndexFileError {
+    fn from(e: io::Error) -> Self {
+        IndexFileError::Io(e)
+    }
+}
+
+/// 64-bit FNV-1a hash, small and stable, used for checksums and config fingerprints
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Write the corpus to path, prefixed with the versioned header
+pub fn save_corpus(corpus: &Corpus, path: &Path) -> Result<(), IndexFileError> {
+    fs::write(path, write_corpus(corpus)?)?;
+    Ok(())
+}
+
+/// Like save_corpus, storing the index under key in a storage backend
+pub fn save_corpus_to(corpus: &Corpus, backend: &dyn StorageBackend, key: &str) -> Result<(), IndexFileError> {
+    backend.put(key, write_corpus(corpus)?.as_bytes())?;
+    Ok(())
+}
+
+/// The contents of the index file save_corpus writes
+pub fn write_corpus(corpus: &Corpus) -> Result<String, IndexFileError> {
+    let file = IndexFileRef { analyzer: corpus.analyzer().describe(), corpus };
+    let body = serde_json::to_string(&file).map_err(|e| IndexFileError::Malformed(e.to_string()))?;
+    let header = format!(
+        "{} {} {:016x} {:016x}\n",
+        MAGIC,
+        FORMAT_VERSION,
+        corpus.analyzer().fingerprint(),
+        fnv1a(body.as_bytes())
+    );
+    Ok(header + &body)
+}
+
+/// Read a corpus saved with save_corpus, checking version, checksum and analyzer before using it
+pub fn load_corpus(path: &Path, analyzer: Arc<Analyzer>) -> Result<Corpus, IndexFileError> {
+    read_corpus(&fs::read_to_string(path)?, analyzer)
+}
+
+/// Like load_corpus, for an index saved with save_corpus_to
+pub fn load_corpus_from(backend: &dyn StorageBackend, key: &str, analyzer: Arc<Analyzer>) -> Result<Corpus, IndexFileError> {
+    let contents = String::from_utf8(backend.get(key)?).map_err(|_| IndexFileError::Malformed("not UTF-8".to_string()))?;
+    read_corpus(&contents, analyzer)
+}
+
+/// Like load_corpus, for the contents of an index file already in memory
+pub fn read_corpus(contents: &str, analyzer: Arc<Analyzer>) -> Result<Corpus, IndexFileError> {
+    let (header, body) = contents
+        .split_once('\n')
+        .ok_or_else(|| IndexFileError::Malformed("missing header line".to_string()))?;
+
+    let fields: Vec<&str> = header.split(' ').collect();
+    if fields.len() != 4 || fields[0] != MAGIC {
+        return Err(IndexFileError::Malformed("unrecognized header".to_string()));
+    }
+    let version: u32 = fields[1]
+        .parse()
+        .map_err(|_| IndexFileError::Malformed("bad format version".to_string()))?;
+    let analyzer_hash = parse_hex(fields[2])?;
+    let checksum = parse_hex(fields[3])?;
+
+    // Check the cheap things first so the error names the real problem
+    if version != FORMAT_VERSION {
+        return Err(IndexFileError::UnsupportedVersion { found: version, supported: FORMAT_VERSION });
+    }
+    let found = fnv1a(body.as_bytes());
+    if found != checksum {
+        return Err(IndexFileError::ChecksumMismatch { expected: checksum, found });
+    }
+
+    let file: IndexFile = serde_json::from_str(body).map_err(|e| IndexFileError::Malformed(e.to_string()))?;
+    if analyzer_hash != analyzer.fingerprint() {
+        return Err(IndexFileError::AnalyzerMismatch { saved: file.analyzer, current: analyzer.describe() });
+    }
+    let mut corpus = file.corpus;
+    corpus.attach_analyzer(analyzer);
+    corpus.share_text().map_err(IndexFileError::Malformed)?;
+    Ok(corpus)
+}
+
+fn parse_hex(field: &str) -> Result<u64, IndexFileError> {
+    u64::from_str_radix(field, 16).map_err(|_| IndexFileError::Malformed(format!("bad hash '{}'", field)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::WhitespaceTokenizer;
+    use crate::chunker::{ChunkStrategy, ChunkingConfig};
+    use crate::corpus::{DocId, Document};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}_{}.idx", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_save_load_round_trip_and_rejections() {
+        let documents = vec![Document::new("a.txt", "rust borrow checker"), Document::new("b.txt", "python")];
+        let corpus = Corpus::new(documents, ChunkingConfig::default());
+        let path = temp_path("persist_round_trip");
+        save_corpus(&corpus, &path).unwrap();
+
+        let loaded = load_corpus(&path, Arc::new(Analyzer::default())).unwrap();
+        assert_eq!(loaded.search_chunks("borrow").len(), 1);
+        assert_eq!(loaded.score_tfidf("Borrow").len(), 1);
+        assert_eq!(loaded.doc_id("a.txt"), corpus.doc_id("a.txt"));
+
+        // Chunks keep their ranges, also where the same text repeats
+        let repeated = Corpus::new(vec![Document::new("a.txt", "aaaa")], ChunkingConfig::new(ChunkStrategy::Chars, 2, 0).unwrap());
+        save_corpus(&repeated, &path).unwrap();
+        let loaded = load_corpus(&path, Arc::new(Analyzer::default())).unwrap();
+        let ranges: Vec<_> = loaded.chunk_ranges(DocId(0)).unwrap().into_iter().map(|(_, range)| range).collect();
+        assert_eq!(ranges, vec![0..2, 2..4]);
+        save_corpus(&corpus, &path).unwrap();
+
+        // A different analyzer must be refused
+        let other = Arc::new(Analyzer::new(WhitespaceTokenizer));
+        assert!(matches!(load_corpus(&path, other), Err(IndexFileError::AnalyzerMismatch { .. })));
+
+        // Flipping a byte in the body breaks the checksum
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 3;
+        bytes[last] = b'X';
+        fs::write(&path, bytes).unwrap();
+        assert!(matches!(
+            load_corpus(&path, Arc::new(Analyzer::default())),
+            Err(IndexFileError::ChecksumMismatch { .. })
+        ));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use crate::analyzer::Analyzer;
+use crate::inverted_index::InvertedIndex;
+use crate::query::Query;
+
+// Before scoring anything, the document frequencies in the index say roughly how many chunks
+// every part of a query can match. An AND can match at most as many chunks as its rarest operand,
+// so evaluating that one first leaves the fewest candidates, and when it matches nothing the other
+// operands are never scored at all. An OR has to score every operand whatever their order, its
+// scores are added in the order of the query. The estimates are upper bounds: a term matches
+// exactly its document frequency, anything combined can only be counted from above
+
+/// A query with its operands reordered for evaluation, and how it will be evaluated
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlan {
+    /// The same query with the operands of every AND rarest first, negations last
+    pub query: Query,
+    pub root: PlanNode,
+}
+
+/// One step of a plan, with the most chunks it can match
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanNode {
+    pub step: Step,
+    pub estimate: usize,
+    /// In the order they are evaluated
+    pub children: Vec<PlanNode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Score the postings of the analyzed terms and add them up
+    Terms(Vec<String>),
+    /// Intersect the postings of the phrase's terms, then check their positions
+    Phrase { text: String, slop: u32 },
+    /// Keep the chunks every child matches, stopping at the first child that leaves none
+    Intersect,
+    /// Add up the scores of every child
+    Union,
+    /// Drop the chunks the child matches
+    Exclude,
+    /// Matches nothing, nothing is scored
+    Empty,
+}
+
+/// Plan a parsed query against the index it will be evaluated on
+pub fn plan_query(query: &Query, index: &InvertedIndex, analyzer: &Analyzer) -> QueryPlan {
+    let (query, root) = plan(query, index, analyzer);
+    QueryPlan { query, root }
+}
+
+fn plan(query: &Query, index: &InvertedIndex, analyzer: &Analyzer) -> (Query, PlanNode) {
+    let total = index.num_chunks();
+    match query {
+        Query::Term(text) => {
+            let terms = analyzer.analyze(text);
+            let estimate = terms.iter().map(|term| index.doc_freq(term)).sum::<usize>().min(total);
+            (query.clone(), PlanNode { step: Step::Terms(terms), estimate, children: Vec::new() })
+        }
+        Query::Phrase { text, slop } => {
+            let tokens = analyzer.tokens(text);
+            let estimate = tokens.iter().map(|token| index.doc_freq(&token.text)).min().unwrap_or(0);
+            let step = Step::Phrase { text: text.clone(), slop: *slop };
+            (query.clone(), PlanNode { step, estimate, children: Vec::new() })
+        }
+        Query::Not(inner) => {
+            let (inner, node) = plan(inner, index, analyzer);
+            let estimate = node.estimate;
+            (Query::Not(Box::new(inner)), PlanNode { step: Step::Exclude, estimate, children: vec![node] })
+        }
+        Query::And(queries) | Query::Or(queries) => {
+            let mut planned: Vec<(Query, PlanNode)> = queries.iter().map(|q| plan(q, index, analyzer)).collect();
+            let positive: Vec<usize> =
+                planned.iter().filter(|(q, _)| !matches!(q, Query::Not(_))).map(|(_, node)| node.estimate).collect();
+            let (step, estimate) = if matches!(query, Query::And(_)) {
+                // A stable sort keeps operands with the same estimate in query order
+                planned.sort_by_key(|(q, node)| (matches!(q, Query::Not(_)), node.estimate));
+                (Step::Intersect, positive.into_iter().min().unwrap_or(0))
+            } else {
+                (Step::Union, positive.into_iter().sum::<usize>().min(total))
+            };
+            let step = if estimate == 0 { Step::Empty } else { step };
+            let (queries, children): (Vec<Query>, Vec<PlanNode>) = planned.into_iter().unzip();
+            let query = if matches!(query, Query::And(_)) { Query::And(queries) } else { Query::Or(queries) };
+            (query, PlanNode { step, estimate, children })
+        }
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.root.write(f, 0)
+    }
+}
+
+impl PlanNode {
+    // One line per step, children indented below their parent
+    fn write(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let step = match &self.step {
+            Step::Terms(terms) => format!("terms {}", terms.join(" ")),
+            Step::Phrase { text, slop } => format!("phrase \"{}\"~{}", text, slop),
+            Step::Intersect => "intersect, rarest first".to_string(),
+            Step::Union => "union".to_string(),
+            Step::Exclude => "exclude".to_string(),
+            Step::Empty => "empty, not evaluated".to_string(),
+        };
+        writeln!(f, "{}{} (at most {} chunks)", "  ".repeat(depth), step, self.estimate)?;
+        self.children.iter().try_for_each(|child| child.write(f, depth + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::{Corpus, Document};
+    use crate::query::parse_query;
+
+    #[test]
+    fn test_rarest_operand_first() {
+        let corpus = Corpus::new(
+            vec![
+                Document::new("a.txt", "rust borrow checker"),
+                Document::new("b.txt", "rust lifetimes"),
+                Document::new("c.txt", "rust and python"),
+            ],
+            ChunkingConfig::default(),
+        );
+        let plan = corpus.plan("rust AND borrow AND NOT python").unwrap();
+        assert_eq!(plan.query, parse_query("borrow AND rust AND NOT python").unwrap());
+        assert_eq!(plan.root.step, Step::Intersect);
+        assert_eq!(plan.root.children.iter().map(|c| c.estimate).collect::<Vec<_>>(), [1, 3, 1]);
+        assert_eq!(plan.to_string().lines().next(), Some("intersect, rarest first (at most 1 chunks)"));
+
+        let plan = corpus.plan("rust AND java").unwrap();
+        assert_eq!((plan.root.step, plan.root.estimate), (Step::Empty, 0));
+        assert_eq!(corpus.plan("borrow OR python").unwrap().root.estimate, 2);
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use indicatif::{ProgressBar, ProgressStyle};
+
+// Building a corpus has two long stages: loading reads every file, indexing chunks and analyzes
+// every document. CorpusBuilder::on_progress reports both through one callback, a few times a
+// second rather than once per file or chunk, so a callback that writes to a terminal or a pipe
+// never slows the build down. The rates are averages since the stage started, and the ETA assumes
+// the rest goes at the same rate
+
+/// The parts of a corpus build that report progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading files, done and total count sources, every file of a directory is one
+    Loading,
+    /// Chunking and analyzing, done and total count chunks
+    Indexing,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Loading => "loading",
+            Stage::Indexing => "indexing",
+        }
+    }
+}
+
+/// How far a stage has come
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub stage: Stage,
+    pub done: usize,
+    /// Known before the stage starts: directories are listed before the first file is read
+    pub total: usize,
+    /// Bytes of text read or indexed so far
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Files (loading) or chunks (indexing) per second
+    pub fn per_second(&self) -> f64 {
+        self.done as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    pub fn mb_per_second(&self) -> f64 {
+        self.bytes as f64 / 1e6 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    /// Time left at the rate so far, None before anything is done
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.total.saturating_sub(self.done);
+        (self.done > 0).then(|| Duration::from_secs_f64(remaining as f64 / self.per_second()))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.done >= self.total
+    }
+
+    /// One line of JSON, e.g. {"stage":"loading","done":10,"total":20,...}
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "stage": self.stage.name(),
+            "done": self.done,
+            "total": self.total,
+            "bytes": self.bytes,
+            "elapsed_secs": self.elapsed.as_secs_f64(),
+            "per_sec": self.per_second(),
+            "mb_per_sec": self.mb_per_second(),
+            "eta_secs": self.eta().map(|eta| eta.as_secs_f64()),
+        })
+        .to_string()
+    }
+}
+
+/// Called with the progress of a build, see CorpusBuilder::on_progress
+pub type ProgressCallback = Box<dyn Fn(&Progress) + Send + Sync>;
+
+/// How often a stage reports, besides its first and last update
+const INTERVAL: Duration = Duration::from_millis(100);
+
+// Counts the work of one stage and calls the callback when a report is due
+pub(crate) struct Tracker<'a> {
+    callback: Option<&'a (dyn Fn(&Progress) + Send + Sync)>,
+    stage: Stage,
+    total: usize,
+    started: Instant,
+    last: Option<Instant>,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(callback: Option<&'a (dyn Fn(&Progress) + Send + Sync)>, stage: Stage, total: usize) -> Tracker<'a> {
+        Tracker { callback, stage, total, started: Instant::now(), last: None }
+    }
+
+    pub(crate) fn update(&mut self, done: usize, bytes: u64) {
+        let Some(callback) = self.callback else {
+            return;
+        };
+        let now = Instant::now();
+        if done < self.total && self.last.is_some_and(|last| now - last < INTERVAL) {
+            return;
+        }
+        self.last = Some(now);
+        callback(&Progress { stage: self.stage, done, total: self.total, bytes, elapsed: now - self.started });
+    }
+}
+
+/// A progress bar on stderr for every stage, with the rates and the ETA
+pub fn bar() -> impl Fn(&Progress) + Send + Sync {
+    let current: Mutex<Option<(Stage, ProgressBar)>> = Mutex::new(None);
+    move |progress: &Progress| {
+        let mut current = current.lock().unwrap();
+        if current.as_ref().is_none_or(|(stage, _)| *stage != progress.stage) {
+            let bar = ProgressBar::new(progress.total as u64);
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("{prefix:>9} [{bar:40.cyan/blue}] {pos}/{len} {msg}")
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+            bar.set_prefix(progress.stage.name());
+            *current = Some((progress.stage, bar));
+        }
+        let Some((_, bar)) = current.as_ref() else {
+            return;
+        };
+        bar.set_position(progress.done as u64);
+        let eta = progress.eta().map(|eta| format!(", {}s left", eta.as_secs())).unwrap_or_default();
+        bar.set_message(format!("{:.0}/s, {:.1} MB/s{}", progress.per_second(), progress.mb_per_second(), eta));
+        if progress.is_finished() {
+            bar.finish();
+        }
+    }
+}
+
+/// Every update as one line of JSON on stderr, for consumers that aren't a terminal
+pub fn json_lines() -> impl Fn(&Progress) + Send + Sync {
+    |progress: &Progress| {
+        // A closed pipe only means nobody is listening anymore, that doesn't fail the build
+        let _ = writeln!(io::stderr(), "{}", progress.to_json());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::corpus::{Corpus, Document};
+
+    #[test]
+    fn test_builder_reports_both_stages() {
+        let events: Arc<Mutex<Vec<Progress>>> = Arc::default();
+        let recorded = Arc::clone(&events);
+        Corpus::builder()
+            .add_document(Document::new("a.txt", "rust borrow checker"))
+            .add_document(Document::new("b.txt", "python garbage collector"))
+            .on_progress(move |progress| recorded.lock().unwrap().push(*progress))
+            .build()
+            .unwrap();
+        let events = events.lock().unwrap();
+        let last = |stage| events.iter().rfind(|p| p.stage == stage).copied().unwrap();
+        assert_eq!((last(Stage::Loading).done, last(Stage::Loading).total), (2, 2));
+        assert_eq!(last(Stage::Indexing).bytes, 43);
+        assert!(last(Stage::Indexing).is_finished());
+        assert_eq!(last(Stage::Indexing).eta(), Some(Duration::ZERO));
+        assert!(last(Stage::Loading).to_json().starts_with("{\"bytes\":43,"));
+    }
+}
@@ -0,0 +1,837 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+use roaring::RoaringBitmap;
+use crate::analyzer::{Analyzer, Token};
+use crate::bm25::Bm25Params;
+use crate::corpus::ChunkId;
+use crate::inverted_index::InvertedIndex;
+use crate::search::Score;
+
+/// A parsed query
+// Plain words next to each other are combined with Or and their scores summed, which is the
+// bag-of-words behaviour TF-IDF and BM25 are normally used with
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// A single word, run through the analyzer before lookup
+    Term(String),
+    /// "quoted words"~slop: the words in order, or within slop extra positions of each other
+    Phrase { text: String, slop: u32 },
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    /// NOT x or -x, removes the chunks matching x
+    Not(Box<Query>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryErrorKind {
+    UnclosedQuote,
+    UnclosedParen,
+    UnmatchedCloseParen,
+    /// A ~ after a phrase that isn't followed by a number
+    InvalidSlop,
+    /// An operator, group or phrase with nothing in it
+    EmptyClause,
+    EmptyQuery,
+}
+
+/// A query that could not be parsed, with the byte offset of the problem and a suggested fix
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    pub kind: QueryErrorKind,
+    /// Byte offset into the query string
+    pub offset: usize,
+    pub message: String,
+    pub suggestion: String,
+}
+
+impl QueryError {
+    fn new(kind: QueryErrorKind, offset: usize, message: &str, suggestion: &str) -> QueryError {
+        QueryError { kind, offset, message: message.to_string(), suggestion: suggestion.to_string() }
+    }
+
+    /// Multi-line rendering for terminals, with a caret under the offending position
+    pub fn render(&self, query: &str) -> String {
+        // The caret column counts characters, not bytes, so it lines up for non-ASCII queries
+        let column = query[..self.offset.min(query.len())].chars().count();
+        format!(
+            "{}\n  {}\n  {}^\nhelp: {}",
+            self.message,
+            query,
+            " ".repeat(column),
+            self.suggestion
+        )
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {} ({})", self.message, self.offset, self.suggestion)
+    }
+}
+
+impl Error for QueryError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Word(String),
+    Phrase(String, u32),
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+}
+
+fn lex(input: &str) -> Result<Vec<(Lexeme, usize)>, QueryError> {
+    let mut lexemes = Vec::new();
+    // peekable char_indices lets us look at the next character without consuming it
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => lexemes.push((Lexeme::Open, offset)),
+            ')' => lexemes.push((Lexeme::Close, offset)),
+            '"' => {
+                let rest = &input[offset + 1..];
+                let Some(length) = rest.find('"') else {
+                    return Err(QueryError::new(
+                        QueryErrorKind::UnclosedQuote,
+                        offset,
+                        "unclosed quote",
+                        "add a closing \" at the end of the phrase",
+                    ));
+                };
+                // Skip the phrase and its closing quote
+                let closing = offset + 1 + length;
+                while chars.peek().is_some_and(|(o, _)| *o <= closing) {
+                    chars.next();
+                }
+                let mut slop = 0;
+                if chars.peek().is_some_and(|(_, n)| *n == '~') {
+                    chars.next();
+                    let digits: String = input[closing + 2..].chars().take_while(|c| c.is_ascii_digit()).collect();
+                    slop = digits.parse().map_err(|_| {
+                        QueryError::new(
+                            QueryErrorKind::InvalidSlop,
+                            closing + 1,
+                            "~ must be followed by a number",
+                            "write the allowed distance as a number, e.g. \"quick fox\"~3",
+                        )
+                    })?;
+                    for _ in 0..digits.len() {
+                        chars.next();
+                    }
+                }
+                lexemes.push((Lexeme::Phrase(rest[..length].to_string(), slop), offset));
+            }
+            '-' if chars.peek().is_some_and(|(_, n)| !n.is_whitespace()) => lexemes.push((Lexeme::Not, offset)),
+            _ => {
+                let mut end = offset + c.len_utf8();
+                while let Some(&(o, n)) = chars.peek() {
+                    if n.is_whitespace() || n == '"' || n == '(' || n == ')' {
+                        break;
+                    }
+                    end = o + n.len_utf8();
+                    chars.next();
+                }
+                let lexeme = match &input[offset..end] {
+                    "AND" => Lexeme::And,
+                    "OR" => Lexeme::Or,
+                    "NOT" => Lexeme::Not,
+                    word => Lexeme::Word(word.to_string()),
+                };
+                lexemes.push((lexeme, offset));
+            }
+        }
+    }
+    Ok(lexemes)
+}
+
+// Recursive descent parser, one method per precedence level:
+//   query   := clause (OR? clause)*
+//   clause  := unary (AND unary)*
+//   unary   := (NOT | -) unary | primary
+//   primary := WORD | "PHRASE"~N | ( query )
+struct Parser {
+    lexemes: Vec<(Lexeme, usize)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.pos).map(|(l, _)| l)
+    }
+
+    fn offset(&self) -> usize {
+        self.lexemes.get(self.pos).map(|(_, o)| *o).unwrap_or(self.end)
+    }
+
+    // True when nothing that can start a clause comes next
+    fn at_clause_end(&self) -> bool {
+        matches!(self.peek(), None | Some(Lexeme::Close) | Some(Lexeme::And) | Some(Lexeme::Or))
+    }
+
+    fn parse_query(&mut self) -> Result<Vec<Query>, QueryError> {
+        let mut clauses = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Lexeme::Close) => return Ok(clauses),
+                Some(Lexeme::Or) => {
+                    let offset = self.offset();
+                    self.pos += 1;
+                    if clauses.is_empty() || self.at_clause_end() {
+                        return Err(QueryError::new(
+                            QueryErrorKind::EmptyClause,
+                            offset,
+                            "OR needs a term on both sides",
+                            "put a term on each side of OR or remove it",
+                        ));
+                    }
+                }
+                Some(Lexeme::And) => {
+                    return Err(QueryError::new(
+                        QueryErrorKind::EmptyClause,
+                        self.offset(),
+                        "AND needs a term on both sides",
+                        "put a term before AND or remove it",
+                    ));
+                }
+                _ => clauses.push(self.parse_clause()?),
+            }
+        }
+    }
+
+    fn parse_clause(&mut self) -> Result<Query, QueryError> {
+        let mut operands = vec![self.parse_unary()?];
+        while self.peek() == Some(&Lexeme::And) {
+            let offset = self.offset();
+            self.pos += 1;
+            if self.at_clause_end() {
+                return Err(QueryError::new(
+                    QueryErrorKind::EmptyClause,
+                    offset,
+                    "AND needs a term on both sides",
+                    "put a term after AND or remove it",
+                ));
+            }
+            operands.push(self.parse_unary()?);
+        }
+        Ok(collapse(operands, Query::And))
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, QueryError> {
+        if self.peek() == Some(&Lexeme::Not) {
+            let offset = self.offset();
+            self.pos += 1;
+            if self.at_clause_end() {
+                return Err(QueryError::new(
+                    QueryErrorKind::EmptyClause,
+                    offset,
+                    "nothing to exclude",
+                    "put a term right after NOT or -",
+                ));
+            }
+            return Ok(Query::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, QueryError> {
+        let offset = self.offset();
+        let lexeme = self.peek().cloned();
+        self.pos += 1;
+        match lexeme {
+            Some(Lexeme::Word(word)) => Ok(Query::Term(word)),
+            Some(Lexeme::Phrase(phrase, _)) if phrase.trim().is_empty() => Err(QueryError::new(
+                QueryErrorKind::EmptyClause,
+                offset,
+                "empty phrase",
+                "put words between the quotes or remove them",
+            )),
+            Some(Lexeme::Phrase(text, slop)) => Ok(Query::Phrase { text, slop }),
+            Some(Lexeme::Open) => {
+                let clauses = self.parse_query()?;
+                if self.peek() != Some(&Lexeme::Close) {
+                    return Err(QueryError::new(
+                        QueryErrorKind::UnclosedParen,
+                        offset,
+                        "unclosed parenthesis",
+                        "add a closing ) or remove this (",
+                    ));
+                }
+                self.pos += 1;
+                if clauses.is_empty() {
+                    return Err(QueryError::new(
+                        QueryErrorKind::EmptyClause,
+                        offset,
+                        "empty group",
+                        "put terms inside the parentheses or remove them",
+                    ));
+                }
+                Ok(collapse(clauses, Query::Or))
+            }
+            // parse_unary and parse_query never call us on anything else
+            _ => unreachable!("parse_primary called at a clause end"),
+        }
+    }
+}
+
+// A list with one element doesn't need a wrapping And/Or
+fn collapse(mut queries: Vec<Query>, wrap: fn(Vec<Query>) -> Query) -> Query {
+    if queries.len() == 1 { queries.remove(0) } else { wrap(queries) }
+}
+
+/// Parse a query string, reporting unbalanced quotes and parentheses and empty clauses
+pub fn parse_query(input: &str) -> Result<Query, QueryError> {
+    let mut parser = Parser { lexemes: lex(input)?, pos: 0, end: input.len() };
+    let clauses = parser.parse_query()?;
+    if parser.peek() == Some(&Lexeme::Close) {
+        return Err(QueryError::new(
+            QueryErrorKind::UnmatchedCloseParen,
+            parser.offset(),
+            "unmatched closing parenthesis",
+            "remove this ) or add a matching ( before it",
+        ));
+    }
+    if clauses.is_empty() {
+        return Err(QueryError::new(QueryErrorKind::EmptyQuery, 0, "empty query", "type at least one search term"));
+    }
+    Ok(collapse(clauses, Query::Or))
+}
+
+/// How the scores of a chunk's matching query terms are added up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Summation {
+    /// Plain floating point addition, in the order the terms are evaluated
+    #[default]
+    Naive,
+    /// Kahan-Babuska (Neumaier) compensated summation: the rounding error of every addition is
+    /// carried along and added back at the end, so the sum barely depends on the order or the
+    /// number of terms and matches other implementations to a few ulps
+    Compensated,
+}
+
+// A running sum with the low-order bits the additions so far have rounded away
+#[derive(Debug, Clone, Copy, Default)]
+struct ScoreSum {
+    sum: Score,
+    compensation: Score,
+}
+
+impl ScoreSum {
+    fn add(&mut self, value: Score, summation: Summation) {
+        if summation == Summation::Naive {
+            self.sum += value;
+            return;
+        }
+        let sum = self.sum + value;
+        // Whichever of the two is smaller in magnitude lost bits in the addition, recover them
+        self.compensation += if self.sum.abs() >= value.abs() { (self.sum - sum) + value } else { (value - sum) + self.sum };
+        self.sum = sum;
+    }
+
+    fn value(&self) -> Score {
+        self.sum + self.compensation
+    }
+}
+
+/// What evaluating a query needs from an index: the analyzer, per-term scores and positions
+pub trait TermScorer {
+    /// Run query text through the analyzer the index was built with
+    fn tokens(&self, text: &str) -> Vec<Token>;
+
+    /// Every chunk containing the (already analyzed) term with that term's score contribution
+    fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)>;
+
+    /// Token positions of the term within one chunk
+    fn positions(&self, term: &str, chunk: ChunkId) -> Vec<u32>;
+
+    fn analyze(&self, text: &str) -> Vec<String> {
+        self.tokens(text).into_iter().map(|t| t.text).collect()
+    }
+
+    /// Weights of the distinct query terms given how often each occurs, their scores are multiplied by them
+    // The default sums every repetition, which is what adding up the terms one by one would do
+    fn query_weights(&self, counts: &[(String, u32)]) -> Vec<Score> {
+        counts.iter().map(|(_, count)| *count as Score).collect()
+    }
+
+    /// The k1 and b of a BM25 scorer, field scores added to its results saturate the same way
+    fn bm25_params(&self) -> Option<Bm25Params> {
+        None
+    }
+}
+
+/// How a term the query repeats is counted, e.g. rust in "rust rust async"
+// Repeating a word is how many users say it matters most, and scoring it once per repetition is
+// what adding up the terms one by one does. Other implementations often dedupe the query terms
+// first, so the same query ranks differently; a cap keeps some of the emphasis without letting
+// "rust rust rust rust" drown out every other word
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepeatedTerms {
+    /// Every repetition counts, the scorer weighs the full count
+    #[default]
+    Count,
+    /// A repeated term counts once
+    Dedupe,
+    /// A repeated term counts at most this many times
+    Cap(u32),
+}
+
+impl RepeatedTerms {
+    /// The count a term repeated count times in the query is weighed with
+    pub fn count(&self, count: u32) -> u32 {
+        match self {
+            RepeatedTerms::Count => count,
+            RepeatedTerms::Dedupe => count.min(1),
+            RepeatedTerms::Cap(cap) => count.min(*cap),
+        }
+    }
+}
+
+impl FromStr for RepeatedTerms {
+    type Err = String;
+
+    /// count, dedupe or cap:N
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "count" => Ok(RepeatedTerms::Count),
+            "dedupe" => Ok(RepeatedTerms::Dedupe),
+            other => match other.strip_prefix("cap:").map(str::parse::<u32>) {
+                Some(Ok(cap)) if cap > 0 => Ok(RepeatedTerms::Cap(cap)),
+                _ => Err(format!("unknown repeated terms '{}', expected count, dedupe or cap:N with N at least 1", other)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for RepeatedTerms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepeatedTerms::Count => write!(f, "count"),
+            RepeatedTerms::Dedupe => write!(f, "dedupe"),
+            RepeatedTerms::Cap(cap) => write!(f, "cap:{}", cap),
+        }
+    }
+}
+
+/// Wraps a scorer so that repeated query terms are counted the way RepeatedTerms says
+// Only the counts change, the inner scorer still turns them into weights, so TF-IDF's query tf
+// schemes apply to the capped count
+pub struct RepeatedTermsScorer<'a> {
+    inner: &'a dyn TermScorer,
+    repeated: RepeatedTerms,
+}
+
+impl<'a> RepeatedTermsScorer<'a> {
+    pub fn new(inner: &'a dyn TermScorer, repeated: RepeatedTerms) -> RepeatedTermsScorer<'a> {
+        RepeatedTermsScorer { inner, repeated }
+    }
+}
+
+impl TermScorer for RepeatedTermsScorer<'_> {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        self.inner.tokens(text)
+    }
+
+    fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)> {
+        self.inner.score_term(term)
+    }
+
+    fn positions(&self, term: &str, chunk: ChunkId) -> Vec<u32> {
+        self.inner.positions(term, chunk)
+    }
+
+    fn query_weights(&self, counts: &[(String, u32)]) -> Vec<Score> {
+        let counts: Vec<(String, u32)> = counts.iter().map(|(term, count)| (term.clone(), self.repeated.count(*count))).collect();
+        self.inner.query_weights(&counts)
+    }
+
+    fn bm25_params(&self) -> Option<Bm25Params> {
+        self.inner.bm25_params()
+    }
+}
+
+/// Wraps a scorer so that terms looked up after a deadline match nothing
+// Evaluation scores one term at a time, so a query stops costing time at the next term after the
+// deadline and keeps the scores of the terms it already has. One very common term can still overrun
+pub struct DeadlineScorer<'a> {
+    inner: &'a dyn TermScorer,
+    deadline: Instant,
+    // Cell because the trait methods only get &self
+    exceeded: Cell<bool>,
+}
+
+impl<'a> DeadlineScorer<'a> {
+    pub fn new(inner: &'a dyn TermScorer, deadline: Instant) -> DeadlineScorer<'a> {
+        DeadlineScorer { inner, deadline, exceeded: Cell::new(false) }
+    }
+
+    /// Whether any term was skipped because the deadline had passed
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.get()
+    }
+
+    fn expired(&self) -> bool {
+        let expired = Instant::now() >= self.deadline;
+        if expired {
+            self.exceeded.set(true);
+        }
+        expired
+    }
+}
+
+impl TermScorer for DeadlineScorer<'_> {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        self.inner.tokens(text)
+    }
+
+    fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)> {
+        if self.expired() { Vec::new() } else { self.inner.score_term(term) }
+    }
+
+    fn positions(&self, term: &str, chunk: ChunkId) -> Vec<u32> {
+        if self.expired() { Vec::new() } else { self.inner.positions(term, chunk) }
+    }
+
+    fn query_weights(&self, counts: &[(String, u32)]) -> Vec<Score> {
+        self.inner.query_weights(counts)
+    }
+
+    fn bm25_params(&self) -> Option<Bm25Params> {
+        self.inner.bm25_params()
+    }
+}
+
+impl Query {
+    /// Analyzed terms of every non-negated part of the query, used for highlighting
+    pub fn positive_terms(&self, scorer: &dyn TermScorer) -> Vec<String> {
+        match self {
+            Query::Term(text) | Query::Phrase { text, .. } => scorer.analyze(text),
+            Query::And(queries) | Query::Or(queries) => {
+                queries.iter().flat_map(|q| q.positive_terms(scorer)).collect()
+            }
+            Query::Not(_) => Vec::new(),
+        }
+    }
+
+    /// The ids of the chunks the query matches, from the postings alone without scoring anything
+    // Same matching rules as evaluate, but sets instead of score maps, so it only pays for
+    // postings lookups and set operations: what a "N results" count needs. Roaring bitmaps store
+    // sorted ids in compressed blocks and intersect, unite and subtract them block by block
+    pub fn matching_chunks(&self, index: &InvertedIndex, analyzer: &Analyzer) -> RoaringBitmap {
+        match self {
+            Query::Term(text) => analyzer.analyze(text).iter().flat_map(|term| index.postings(term)).map(|p| p.chunk.0).collect(),
+            Query::Phrase { text, slop } => phrase_chunks(text, *slop, index, analyzer),
+            Query::Or(queries) | Query::And(queries) => {
+                let (negated, positive): (Vec<&Query>, Vec<&Query>) = queries.iter().partition(|q| matches!(q, Query::Not(_)));
+                let mut sets: Vec<RoaringBitmap> = positive.iter().map(|q| q.matching_chunks(index, analyzer)).collect();
+                let mut matched = if matches!(self, Query::Or(_)) {
+                    sets.into_iter().fold(RoaringBitmap::new(), |matched, set| matched | set)
+                } else {
+                    // Start from the smallest set, every other one can only make it smaller
+                    sets.sort_by_key(|set| set.len());
+                    let mut sets = sets.into_iter();
+                    let first = sets.next().unwrap_or_default();
+                    sets.fold(first, |matched, set| matched & set)
+                };
+                for query in negated {
+                    if let Query::Not(inner) = query {
+                        matched -= inner.matching_chunks(index, analyzer);
+                    }
+                }
+                matched
+            }
+            Query::Not(_) => RoaringBitmap::new(),
+        }
+    }
+
+    /// Matching chunks and their summed scores
+    pub fn evaluate(&self, scorer: &dyn TermScorer) -> HashMap<ChunkId, Score> {
+        self.evaluate_with(scorer, Summation::Naive)
+    }
+
+    /// Like evaluate, adding up the term scores with the given summation
+    pub fn evaluate_with(&self, scorer: &dyn TermScorer, summation: Summation) -> HashMap<ChunkId, Score> {
+        match self {
+            // Analysis can turn one word into several terms (code identifiers, synonyms), any of them matches
+            Query::Term(text) => weighted_terms(scorer.analyze(text), scorer, summation),
+            Query::Phrase { text, slop } => evaluate_phrase(text, *slop, scorer, summation),
+            Query::Or(queries) => {
+                // Plain terms are pooled so a repeated term is scored once, weighted by how often it occurs
+                let (terms, others): (Vec<&Query>, Vec<&Query>) = queries.iter().partition(|q| matches!(q, Query::Term(_)));
+                let pooled: Vec<String> = terms.iter().flat_map(|q| q.positive_terms(scorer)).collect();
+                let (excluded, mut included) = split_negations(others, scorer, summation);
+                included.push(weighted_terms(pooled, scorer, summation));
+                subtract(union(included, summation), &excluded)
+            }
+            Query::And(queries) => {
+                // The operands are scored one at a time and once no chunk is left the rest are skipped,
+                // which is why the planner puts the rarest one first
+                let (negated, positive): (Vec<&Query>, Vec<&Query>) = queries.iter().partition(|q| matches!(q, Query::Not(_)));
+                let matched = intersect(positive.iter().map(|q| q.evaluate_with(scorer, summation)), summation);
+                if matched.is_empty() {
+                    return matched;
+                }
+                let (excluded, _) = split_negations(negated, scorer, summation);
+                subtract(matched, &excluded)
+            }
+            // A bare NOT with nothing to subtract from matches nothing
+            Query::Not(_) => HashMap::new(),
+        }
+    }
+}
+
+// A phrase matches a chunk when its terms occur within `slop` extra positions of where the
+// phrase puts them, and the summed term scores are divided by (1 + that distance)
+fn evaluate_phrase(text: &str, slop: u32, scorer: &dyn TermScorer, summation: Summation) -> HashMap<ChunkId, Score> {
+    let tokens = scorer.tokens(text);
+    let candidates = intersect(tokens.iter().map(|t| scores(scorer, &t.text)), summation);
+    let Some(first) = tokens.first() else {
+        return candidates;
+    };
+
+    candidates
+        .into_iter()
+        .filter_map(|(chunk, score)| {
+            // Shift every term's positions back by its offset in the phrase, so for an exact
+            // match all terms land on the same number and the spread is how far off we are
+            let shifted: Vec<Vec<i64>> = tokens
+                .iter()
+                .map(|t| {
+                    let offset = t.position as i64 - first.position as i64;
+                    scorer.positions(&t.text, chunk).iter().map(|p| *p as i64 - offset).collect()
+                })
+                .collect();
+            let distance = min_spread(&shifted)?;
+            (distance <= slop as i64).then(|| (chunk, score / (1.0 + distance as Score)))
+        })
+        .collect()
+}
+
+// evaluate_phrase without the scores
+fn phrase_chunks(text: &str, slop: u32, index: &InvertedIndex, analyzer: &Analyzer) -> RoaringBitmap {
+    let tokens = analyzer.tokens(text);
+    let Some(first) = tokens.first() else {
+        return RoaringBitmap::new();
+    };
+    let mut candidates: RoaringBitmap = index.postings(&first.text).iter().map(|p| p.chunk.0).collect();
+    for token in &tokens[1..] {
+        candidates &= index.postings(&token.text).iter().map(|p| p.chunk.0).collect::<RoaringBitmap>();
+    }
+    let within_slop = |chunk: &u32| {
+        let chunk = ChunkId(*chunk);
+        let shifted: Vec<Vec<i64>> = tokens
+            .iter()
+            .map(|t| {
+                let offset = t.position as i64 - first.position as i64;
+                index.positions(&t.text, chunk).iter().map(|p| *p as i64 - offset).collect()
+            })
+            .collect();
+        min_spread(&shifted).is_some_and(|distance| distance <= slop as i64)
+    };
+    candidates.into_iter().filter(within_slop).collect()
+}
+
+/// Smallest max - min over all ways of picking one value from every sorted list
+// Classic k-way sweep: always advance the list that currently holds the minimum
+pub(crate) fn min_spread(lists: &[Vec<i64>]) -> Option<i64> {
+    if lists.iter().any(|l| l.is_empty()) {
+        return None;
+    }
+    let mut cursors = vec![0; lists.len()];
+    let mut best = i64::MAX;
+    loop {
+        let values: Vec<i64> = lists.iter().zip(&cursors).map(|(l, c)| l[*c]).collect();
+        let max = *values.iter().max().unwrap();
+        let (min_list, min) = values.iter().enumerate().min_by_key(|(_, v)| **v).unwrap();
+        best = best.min(max - min);
+        cursors[min_list] += 1;
+        if cursors[min_list] == lists[min_list].len() {
+            return Some(best);
+        }
+    }
+}
+
+// Score every distinct term once and scale it by the scorer's query weight for its count
+fn weighted_terms(terms: Vec<String>, scorer: &dyn TermScorer, summation: Summation) -> HashMap<ChunkId, Score> {
+    let mut counts: Vec<(String, u32)> = Vec::new();
+    for term in terms {
+        match counts.iter_mut().find(|(t, _)| *t == term) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((term, 1)),
+        }
+    }
+    let weights = scorer.query_weights(&counts);
+    union(
+        counts
+            .iter()
+            .zip(weights)
+            .map(|((term, _), weight)| {
+                scorer.score_term(term).into_iter().map(|(chunk, score)| (chunk, score * weight)).collect()
+            })
+            .collect(),
+        summation,
+    )
+}
+
+fn scores(scorer: &dyn TermScorer, term: &str) -> HashMap<ChunkId, Score> {
+    scorer.score_term(term).into_iter().collect()
+}
+
+// Evaluate the positive operands and collect the chunks matched by the negated ones
+fn split_negations<'q>(
+    queries: impl IntoIterator<Item = &'q Query>,
+    scorer: &dyn TermScorer,
+    summation: Summation,
+) -> (HashSet<ChunkId>, Vec<HashMap<ChunkId, Score>>) {
+    let mut excluded = HashSet::new();
+    let mut included = Vec::new();
+    for query in queries {
+        match query {
+            Query::Not(inner) => excluded.extend(inner.evaluate_with(scorer, summation).into_keys()),
+            _ => included.push(query.evaluate_with(scorer, summation)),
+        }
+    }
+    (excluded, included)
+}
+
+fn union(sets: Vec<HashMap<ChunkId, Score>>, summation: Summation) -> HashMap<ChunkId, Score> {
+    let mut result: HashMap<ChunkId, ScoreSum> = HashMap::new();
+    for set in sets {
+        for (chunk, score) in set {
+            result.entry(chunk).or_default().add(score, summation);
+        }
+    }
+    result.into_iter().map(|(chunk, sum)| (chunk, sum.value())).collect()
+}
+
+// Takes the sets lazily and stops taking them once nothing is left to intersect
+fn intersect(sets: impl IntoIterator<Item = HashMap<ChunkId, Score>>, summation: Summation) -> HashMap<ChunkId, Score> {
+    let mut sets = sets.into_iter();
+    let Some(first) = sets.next() else {
+        return HashMap::new();
+    };
+    let mut result: HashMap<ChunkId, ScoreSum> =
+        first.into_iter().map(|(chunk, score)| (chunk, ScoreSum { sum: score, compensation: 0.0 })).collect();
+    while !result.is_empty() {
+        let Some(set) = sets.next() else {
+            break;
+        };
+        result.retain(|chunk, _| set.contains_key(chunk));
+        for (chunk, sum) in result.iter_mut() {
+            sum.add(set[chunk], summation);
+        }
+    }
+    result.into_iter().map(|(chunk, sum)| (chunk, sum.value())).collect()
+}
+
+fn subtract(mut set: HashMap<ChunkId, Score>, excluded: &HashSet<ChunkId>) -> HashMap<ChunkId, Score> {
+    set.retain(|chunk, _| !excluded.contains(chunk));
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(t: &str) -> Query {
+        Query::Term(t.to_string())
+    }
+
+    #[test]
+    fn test_parse_operators_and_groups() {
+        assert_eq!(parse_query("rust").unwrap(), term("rust"));
+        assert_eq!(parse_query("rust borrow").unwrap(), Query::Or(vec![term("rust"), term("borrow")]));
+        assert_eq!(
+            parse_query("(rust OR go) AND \"borrow checker\" -python").unwrap(),
+            Query::Or(vec![
+                Query::And(vec![
+                    Query::Or(vec![term("rust"), term("go")]),
+                    Query::Phrase { text: "borrow checker".to_string(), slop: 0 },
+                ]),
+                Query::Not(Box::new(term("python"))),
+            ])
+        );
+        // A dash inside a word is part of the word
+        assert_eq!(parse_query("utf-8").unwrap(), term("utf-8"));
+        assert_eq!(
+            parse_query("\"quick fox\"~3").unwrap(),
+            Query::Phrase { text: "quick fox".to_string(), slop: 3 }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_have_offsets() {
+        let cases = [
+            ("rust \"borrow checker", QueryErrorKind::UnclosedQuote, 5),
+            ("(rust OR go", QueryErrorKind::UnclosedParen, 0),
+            ("rust)", QueryErrorKind::UnmatchedCloseParen, 4),
+            ("rust AND", QueryErrorKind::EmptyClause, 5),
+            ("rust ()", QueryErrorKind::EmptyClause, 5),
+            ("NOT", QueryErrorKind::EmptyClause, 0),
+            ("  ", QueryErrorKind::EmptyQuery, 0),
+            ("\"quick fox\"~x", QueryErrorKind::InvalidSlop, 11),
+        ];
+        for (input, kind, offset) in cases {
+            let error = parse_query(input).unwrap_err();
+            assert_eq!((error.kind, error.offset), (kind, offset), "query {:?}", input);
+        }
+
+        let rendered = parse_query("rust AND").unwrap_err().render("rust AND");
+        assert!(rendered.contains("\n       ^\n"));
+    }
+
+    #[test]
+    fn test_min_spread() {
+        assert_eq!(min_spread(&[vec![1, 9], vec![4, 10], vec![8]]), Some(2));
+        assert_eq!(min_spread(&[vec![3], vec![3]]), Some(0));
+        assert_eq!(min_spread(&[vec![3], vec![]]), None);
+    }
+
+    // One huge term score followed by many small ones, the small ones vanish in naive f32 addition
+    struct SkewedScorer;
+
+    impl TermScorer for SkewedScorer {
+        fn tokens(&self, text: &str) -> Vec<Token> {
+            let words = text.split_whitespace().enumerate();
+            words.map(|(i, word)| Token { text: word.to_string(), position: i as u32, start: 0, end: 0 }).collect()
+        }
+
+        fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)> {
+            vec![(ChunkId(0), if term == "big" { 1e8 } else { 1.0 })]
+        }
+
+        fn positions(&self, _term: &str, _chunk: ChunkId) -> Vec<u32> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn test_compensated_summation() {
+        let small: Vec<String> = (0..100).map(|i| format!("t{}", i)).collect();
+        let query = parse_query(&format!("big {}", small.join(" "))).unwrap();
+        let naive = query.evaluate(&SkewedScorer)[&ChunkId(0)];
+        let compensated = query.evaluate_with(&SkewedScorer, Summation::Compensated)[&ChunkId(0)];
+        // The exact sum, rounded once to the nearest Score
+        assert_eq!(compensated, 100_000_100.0);
+        assert!(naive <= compensated);
+    }
+
+    #[test]
+    fn test_repeated_terms() {
+        let query = parse_query("small small small other").unwrap();
+        let score = |repeated| query.evaluate(&RepeatedTermsScorer::new(&SkewedScorer, repeated))[&ChunkId(0)];
+        assert_eq!(score(RepeatedTerms::Count), 4.0);
+        assert_eq!(score(RepeatedTerms::Dedupe), 2.0);
+        assert_eq!(score("cap:2".parse().unwrap()), 3.0);
+        assert_eq!(RepeatedTerms::Cap(2).to_string(), "cap:2");
+        assert!("cap:0".parse::<RepeatedTerms>().is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::corpus::Corpus;
+use crate::eval::result_key;
+use crate::search::SearchResult;
+
+// Real queries are the best test set there is. A query log keeps one JSON line per search: the
+// query, the ranker, how long it took and which results came first. Replaying a log against a
+// new index or other settings shows what changed for the queries people actually ask, without
+// anyone judging results first: how much of the old top k is still there, how often the first
+// result changed, and whether it got faster or slower
+
+/// One search as it was logged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggedQuery {
+    pub query: String,
+    /// The ranker and its settings, e.g. "bm25"
+    pub ranker: String,
+    pub latency_ms: f64,
+    /// The top results, best first, as `path#chunk index` or `path:line` for line search
+    pub results: Vec<String>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl LoggedQuery {
+    /// An entry for a search that just ran, keeping the first top results
+    pub fn new(query: &str, ranker: &str, latency: Duration, corpus: &Corpus, results: &[SearchResult], top: usize) -> LoggedQuery {
+        LoggedQuery {
+            query: query.to_string(),
+            ranker: ranker.to_string(),
+            latency_ms: latency.as_secs_f64() * 1000.0,
+            results: results.iter().take(top).filter_map(|result| key(corpus, result)).collect(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+        }
+    }
+}
+
+// Keys that survive a rebuild, so a log can be replayed against a new index
+fn key(corpus: &Corpus, result: &SearchResult) -> Option<String> {
+    match (result.chunk, result.line) {
+        (Some(chunk), _) => result_key(corpus, chunk),
+        (None, Some(line)) => Some(format!("{}:{}", corpus.path(result.doc)?, line)),
+        (None, None) => corpus.path(result.doc).map(str::to_string),
+    }
+}
+
+/// A JSONL file searches are appended to, safe to share between threads
+pub struct QueryLog {
+    file: Mutex<File>,
+}
+
+impl QueryLog {
+    /// Open a log to append to, created if it doesn't exist yet
+    pub fn open(path: &Path) -> io::Result<QueryLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(QueryLog { file: Mutex::new(file) })
+    }
+
+    pub fn record(&self, entry: &LoggedQuery) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        // One write per line: with O_APPEND, lines from several processes never interleave
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+/// Every entry of a log, in the order they were written
+pub fn read_log(path: &Path) -> Result<Vec<LoggedQuery>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(line).map_err(|e| format!("{} line {}: {}", path.display(), number + 1, e))?);
+    }
+    Ok(entries)
+}
+
+/// How a replayed search differs from the logged one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    /// Fraction of the logged results that are among the replayed ones, 1.0 if both are empty
+    pub overlap: f32,
+    /// The first result is the same
+    pub same_first: bool,
+    /// Replayed minus logged latency, negative when it got faster
+    pub latency_change_ms: f64,
+}
+
+pub fn compare(logged: &LoggedQuery, replayed: &LoggedQuery) -> Comparison {
+    let kept: HashSet<&String> = replayed.results.iter().collect();
+    let overlap = match logged.results.len() {
+        0 if replayed.results.is_empty() => 1.0,
+        0 => 0.0,
+        n => logged.results.iter().filter(|key| kept.contains(key)).count() as f32 / n as f32,
+    };
+    Comparison {
+        overlap,
+        same_first: logged.results.first() == replayed.results.first(),
+        latency_change_ms: replayed.latency_ms - logged.latency_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_log_and_compare() {
+        let corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "rust borrow checker"))
+            .add_document(Document::new("b.txt", "rust garbage collector"))
+            .add_document(Document::new("c.txt", "python interpreter"))
+            .build()
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("queries_{}.jsonl", std::process::id()));
+        let log = QueryLog::open(&path).unwrap();
+        let results = corpus.search("borrow OR garbage").unwrap();
+        let logged = LoggedQuery::new("borrow OR garbage", "tfidf", Duration::from_millis(4), &corpus, &results, 10);
+        log.record(&logged).unwrap();
+        log.record(&LoggedQuery::new("python", "tfidf", Duration::ZERO, &corpus, &corpus.search("python").unwrap(), 10)).unwrap();
+
+        let entries = read_log(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], logged);
+        assert_eq!(entries[1].results, ["c.txt#0"]);
+
+        let replayed = LoggedQuery { results: vec![logged.results[1].clone()], latency_ms: 1.0, ..logged.clone() };
+        let comparison = compare(&logged, &replayed);
+        assert_eq!((comparison.overlap, comparison.same_first), (0.5, false));
+        assert_eq!(comparison.latency_change_ms, -3.0);
+        fs::remove_file(path).unwrap();
+    }
+}
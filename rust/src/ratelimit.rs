@@ -0,0 +1,139 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// A token bucket per client: it holds up to burst tokens, refills at per_second tokens a second
+// and every request takes one. A client can send a burst at once and then keeps to the rate, a
+// client that stays under the rate is never refused. Only so many clients are remembered, once
+// there are more the one that sent nothing for longest is forgotten, it has most likely filled
+// up again anyway. The buckets are also kept in order of their last request, so finding it
+// doesn't mean looking at every one
+
+/// Clients remembered at most, see RateLimiter::check
+const MAX_CLIENTS: usize = 10_000;
+
+/// Requests per client and second, see RateLimiter::check
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    max_clients: usize,
+    state: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    /// Client -> its bucket and the tick of its last request
+    buckets: HashMap<String, (Bucket, u64)>,
+    /// Tick of the last request -> client, least recently seen first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64, burst: u32) -> Result<RateLimiter, String> {
+        if !(per_second > 0.0 && per_second.is_finite()) {
+            return Err(format!("the rate must be a positive number of requests per second, not {}", per_second));
+        }
+        if burst == 0 {
+            return Err("the burst must allow at least one request".to_string());
+        }
+        Ok(RateLimiter { per_second, burst: burst as f64, max_clients: MAX_CLIENTS, state: Mutex::new(Buckets::default()) })
+    }
+
+    /// Take a request of client at now, or the time until it may send the next one
+    pub fn check(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let bucket = match state.buckets.remove(client) {
+            Some((bucket, last)) => {
+                state.recency.remove(&last);
+                bucket
+            }
+            None => {
+                while state.buckets.len() >= self.max_clients {
+                    let Some((_, oldest)) = state.recency.pop_first() else {
+                        break;
+                    };
+                    state.buckets.remove(&oldest);
+                }
+                Bucket { tokens: self.burst, updated: now }
+            }
+        };
+        let mut bucket = Bucket { tokens: self.refill(&bucket, now), updated: now };
+        let checked = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second))
+        };
+        state.recency.insert(tick, client.to_string());
+        state.buckets.insert(client.to_string(), (bucket, tick));
+        checked
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+}
+
+/// The client a request from ip counts against. An IPv6 client is its /64: one host usually has a
+/// whole /64 to itself, and could otherwise pick a fresh address, and bucket, for every request
+pub fn client_key(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}:{:x}::/64", segments[0], segments[1], segments[2], segments[3])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_per_client() {
+        let limiter = RateLimiter::new(2.0, 3).unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check("10.0.0.1", start).is_ok());
+        }
+        assert_eq!(limiter.check("10.0.0.1", start), Err(Duration::from_millis(500)));
+        // Other clients have buckets of their own
+        assert!(limiter.check("10.0.0.2", start).is_ok());
+        // Half a second refills one request
+        assert!(limiter.check("10.0.0.1", start + Duration::from_millis(500)).is_ok());
+        assert!(limiter.check("10.0.0.1", start + Duration::from_millis(500)).is_err());
+        assert!(RateLimiter::new(0.0, 1).is_err());
+        assert!(RateLimiter::new(1.0, 0).is_err());
+    }
+
+    #[test]
+    fn test_clients_are_capped_and_keyed_by_prefix() {
+        let limiter = RateLimiter { max_clients: 2, ..RateLimiter::new(1.0, 1).unwrap() };
+        let start = Instant::now();
+        assert!(limiter.check("a", start).is_ok());
+        assert!(limiter.check("b", start).is_ok());
+        assert!(limiter.check("a", start).is_err());
+        // c pushes out b, which was seen longest ago, a keeps its empty bucket
+        assert!(limiter.check("c", start).is_ok());
+        assert_eq!(limiter.state.lock().unwrap().buckets.len(), 2);
+        assert!(limiter.check("a", start).is_err());
+        assert!(limiter.check("b", start).is_ok());
+
+        let first: IpAddr = "2001:db8:1:2::1".parse().unwrap();
+        let second: IpAddr = "2001:db8:1:2:ffff::9".parse().unwrap();
+        assert_eq!(client_key(first), client_key(second));
+        assert_ne!(client_key(first), client_key("2001:db8:1:3::1".parse().unwrap()));
+        assert_eq!(client_key("::ffff:10.0.0.1".parse().unwrap()), "10.0.0.1");
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::time::{Duration, Instant};
+use crate::corpus::{Corpus, DocId, Document};
+use crate::loader::{list_directory_files, read_file};
+
+// Between rebuilding an index from scratch and watching the directory for changes: list the files
+// again, stat every one of them, and only read the files whose modification time or size differs
+// from what the index recorded. Changed files are removed and indexed again, the other documents
+// keep their chunks and postings. Statting is cheap, reading and analyzing is the slow part, so
+// that is what a time budget cuts short. The files it didn't get to keep their old version and
+// are picked up by the next refresh, which skips everything this one already updated
+
+/// What a refresh did to the documents of one directory
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RefreshReport {
+    pub unchanged: usize,
+    /// Files that changed on disk and were indexed again
+    pub updated: usize,
+    /// Files that weren't in the index yet
+    pub added: usize,
+    /// Documents whose file is gone
+    pub removed: usize,
+    /// Changed or new files left for the next refresh because the time budget ran out
+    pub pending: usize,
+}
+
+/// Bring the documents loaded from the directory root up to date with the files in it now
+/// root must be the directory as it was given when the index was built, see Document::root
+pub fn refresh(corpus: &mut Corpus, root: &str, extensions: &[&str], budget: Option<Duration>) -> Result<RefreshReport, Box<dyn Error>> {
+    let deadline = budget.map(|budget| Instant::now() + budget);
+    let mut report = RefreshReport::default();
+    let mut listed = HashSet::new();
+    let mut stale: Vec<DocId> = Vec::new();
+    let mut documents = Vec::new();
+
+    for (path, file) in list_directory_files(root, extensions)? {
+        listed.insert(path.clone());
+        let metadata = fs::metadata(&file)?;
+        let indexed = corpus.doc_id(&path).and_then(|id| corpus.document(id)).filter(|document| document.root == root);
+        if let Some(document) = indexed {
+            // Indexes saved before sizes were recorded only have the time to go by
+            let same_size = document.size.is_none_or(|size| size == metadata.len());
+            if document.modified.is_some() && document.modified == metadata.modified().ok() && same_size {
+                report.unchanged += 1;
+                continue;
+            }
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            report.pending += 1;
+            continue;
+        }
+        match indexed {
+            Some(document) => {
+                stale.push(document.id);
+                report.updated += 1;
+            }
+            None => report.added += 1,
+        }
+        let file = read_file(path, file)?;
+        documents.push(Document {
+            root: root.to_string(),
+            modified: file.modified,
+            size: Some(file.size),
+            file: Some(file.file),
+            ..Document::new(&file.path, &file.text)
+        });
+    }
+
+    let deleted: Vec<DocId> = corpus.documents_from(root).filter(|document| !listed.contains(&document.path)).map(|d| d.id).collect();
+    report.removed = deleted.len();
+    for id in stale.into_iter().chain(deleted) {
+        corpus.remove_document(id);
+    }
+    if !documents.is_empty() {
+        // The new versions get new ids after the existing ones, chunked and analyzed like the rest
+        let added = corpus.with_documents(documents);
+        let merged = Corpus::merge(&[&*corpus, &added])?;
+        *corpus = merged;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+
+    #[test]
+    fn test_refresh_reads_only_changed_files() {
+        let dir = std::env::temp_dir().join(format!("tfidf-refresh-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "rust borrow checker").unwrap();
+        fs::write(dir.join("b.txt"), "python garbage collector").unwrap();
+        fs::write(dir.join("c.txt"), "java virtual machine").unwrap();
+        let root = dir.to_string_lossy().to_string();
+        let mut corpus = Corpus::builder().add_dir(&root).chunking(ChunkingConfig::default()).build().unwrap();
+        let a = corpus.documents()[0].id;
+
+        fs::write(dir.join("b.txt"), "python garbage collector and reference counting").unwrap();
+        fs::remove_file(dir.join("c.txt")).unwrap();
+        fs::write(dir.join("d.txt"), "go goroutines").unwrap();
+        let report = refresh(&mut corpus, &root, &["txt"], None).unwrap();
+        assert_eq!(report, RefreshReport { unchanged: 1, updated: 1, added: 1, removed: 1, pending: 0 });
+        // The unchanged document keeps its id, the changed one is searchable by its new text
+        assert_eq!(corpus.documents()[0].id, a);
+        assert_eq!(corpus.search("counting").unwrap().len(), 1);
+        assert!(corpus.search("java").unwrap().is_empty());
+
+        fs::write(dir.join("a.txt"), "rust borrow checker and lifetimes").unwrap();
+        let report = refresh(&mut corpus, &root, &["txt"], Some(Duration::ZERO)).unwrap();
+        assert_eq!((report.unchanged, report.pending), (2, 1));
+        assert_eq!(refresh(&mut corpus, &root, &["txt"], None).unwrap().updated, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use crate::cancel::CancellationToken;
+use crate::corpus::Corpus;
+use crate::index::Index;
+
+// One process can serve several corpora, e.g. the docs, the code and the wiki, each under a name,
+// without sharing vocabulary or statistics: a term common in code shouldn't lose its idf in the
+// docs. An index opened with a loader remembers how it was built, so it can be reloaded from its
+// sources. A reload swaps the new corpus into the same Index, so every handle to it, a server's or
+// a query thread's, sees the new snapshot, and its generation moves on as with any Index::replace
+
+/// Builds the corpus of a named index, again on every reload, e.g. with CorpusBuilder::cancel_on
+/// so the token can stop it
+pub type Loader = Arc<dyn Fn(&CancellationToken) -> Result<Corpus, Box<dyn Error>> + Send + Sync>;
+
+struct Entry {
+    index: Index,
+    loader: Option<Loader>,
+}
+
+/// Indexes by name, safe to share between threads
+#[derive(Default)]
+pub struct IndexRegistry {
+    entries: RwLock<BTreeMap<String, Entry>>,
+}
+
+impl IndexRegistry {
+    pub fn new() -> IndexRegistry {
+        IndexRegistry::default()
+    }
+
+    /// Load an index under a new name
+    pub fn open(&self, name: &str, loader: Loader) -> Result<Index, Box<dyn Error>> {
+        self.check_free(name)?;
+        // Loading takes long, the registry stays usable meanwhile
+        let index = Index::new(loader(&CancellationToken::new())?);
+        self.add(name, Entry { index: index.clone(), loader: Some(loader) })?;
+        Ok(index)
+    }
+
+    /// Register an index that was built elsewhere, it can't be reloaded
+    pub fn insert(&self, name: &str, index: Index) -> Result<(), Box<dyn Error>> {
+        self.add(name, Entry { index, loader: None })
+    }
+
+    /// The index registered under name
+    pub fn get(&self, name: &str) -> Option<Index> {
+        self.entries.read().unwrap().get(name).map(|entry| entry.index.clone())
+    }
+
+    /// Every registered name, sorted
+    pub fn names(&self) -> Vec<String> {
+        self.entries.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Stop serving an index, handles to it keep working until they are dropped
+    pub fn close(&self, name: &str) -> Option<Index> {
+        self.entries.write().unwrap().remove(name).map(|entry| entry.index)
+    }
+
+    /// Build an index from its sources again and swap it in, returns its new generation
+    /// A reload stopped through the token keeps serving the corpus it had
+    pub fn reload(&self, name: &str, cancel: &CancellationToken) -> Result<u64, Box<dyn Error>> {
+        let (index, loader) = {
+            let entries = self.entries.read().unwrap();
+            let entry = entries.get(name).ok_or_else(|| format!("no index {}", name))?;
+            let loader = entry.loader.clone().ok_or_else(|| format!("index {} wasn't opened from sources and can't be reloaded", name))?;
+            (entry.index.clone(), loader)
+        };
+        Ok(index.replace(loader(cancel)?))
+    }
+
+    fn check_free(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        if self.entries.read().unwrap().contains_key(name) {
+            return Err(format!("an index named {} is already open", name).into());
+        }
+        Ok(())
+    }
+
+    fn add(&self, name: &str, entry: Entry) -> Result<(), Box<dyn Error>> {
+        // Checked again under the write lock, another open of the name may have finished first
+        let mut entries = self.entries.write().unwrap();
+        if entries.contains_key(name) {
+            return Err(format!("an index named {} is already open", name).into());
+        }
+        entries.insert(name.to_string(), entry);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_open_reload_and_close() {
+        let registry = IndexRegistry::new();
+        let loads = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&loads);
+        let loader: Loader = Arc::new(move |cancel| {
+            // Every load sees one more document, like a directory that grows
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            let documents = (0..n).map(|i| Document::new(&format!("{}.txt", i), "rust borrow checker"));
+            documents.fold(Corpus::builder(), |builder, document| builder.add_document(document)).cancel_on(cancel.clone()).build()
+        });
+        let docs = registry.open("docs", loader.clone()).unwrap();
+        registry.insert("wiki", Index::new(Corpus::builder().add_document(Document::new("w.txt", "wiki")).build().unwrap())).unwrap();
+        assert!(registry.open("docs", loader).is_err());
+        assert_eq!(registry.names(), ["docs", "wiki"]);
+
+        let cancel = CancellationToken::new();
+        assert_eq!(registry.reload("docs", &cancel).unwrap(), 1);
+        // The handle from open sees the reloaded corpus
+        assert_eq!(docs.snapshot().documents().len(), 2);
+        assert!(registry.reload("wiki", &cancel).is_err());
+        // A cancelled reload leaves the index as it was
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        assert!(registry.reload("docs", &cancelled).is_err());
+        assert_eq!((docs.snapshot().generation(), docs.snapshot().documents().len()), (1, 2));
+        assert!(registry.close("docs").is_some());
+        assert!(registry.get("docs").is_none() && registry.reload("docs", &cancel).is_err());
+        assert_eq!(docs.snapshot().documents().len(), 2);
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::ops::Range;
+use serde::Serialize;
+use crate::bm25::{Bm25Params, Bm25Scorer};
+use crate::context::{expand_context, Context};
+use crate::corpus::{Corpus, DocId};
+use crate::persist::fnv1a;
+use crate::search::{Score, SearchResult};
+
+// Most chunked lexical retrieval ends up in a prompt: the best passages for a question, as many as
+// fit next to it in the model's context window. A ranked list of 500 character chunks is a poor
+// fit for that. Neighbouring hits repeat each other's context, a chunk on its own starts and ends
+// mid-sentence, and the last result that fits is rarely the next one in the ranking. Passages are
+// taken best first and expanded with their context. A passage that overlaps one already taken is
+// merged into it, and one that doesn't fit the remaining budget is skipped for smaller ones further down
+
+/// Tokens a text takes up in a prompt, estimated at four characters per token
+// About right for English with the BPE tokenizers of current models, callers that need exact
+// counts can leave some budget spare or count the rendered prompt themselves
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// A piece of a document to put in a prompt, with where it came from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Passage {
+    pub doc: DocId,
+    pub path: String,
+    /// 1-based lines of the document the passage spans, for citing it
+    pub first_line: usize,
+    pub last_line: usize,
+    /// Where the passage starts and ends in the document's text
+    pub range: Range<usize>,
+    /// The best score of the results the passage was built from
+    pub score: Score,
+    pub text: String,
+    pub tokens: usize,
+}
+
+/// Passages for the ranked results, best first, at most max_total_tokens estimated tokens in all
+pub fn pack_for_prompt(
+    corpus: &Corpus,
+    results: &[SearchResult],
+    max_total_tokens: usize,
+    context: Context,
+) -> Result<Vec<Passage>, Box<dyn Error>> {
+    let mut passages: Vec<Passage> = Vec::new();
+    // Hashes of the text of every chunk the passages hold
+    let mut held = HashSet::new();
+    let mut used = 0;
+    for result in results {
+        let Some(chunk) = result.chunk else {
+            continue;
+        };
+        let expanded = expand_context(corpus, chunk, context)?;
+        // A chunk another passage already holds adds nothing, be it as the context of a better hit
+        // or as the same text in another file, e.g. a copied README. Within a file that's a chunk
+        // inside a passage's range, across files a whole chunk with the text of one a passage holds,
+        // a chunk that merely appears inside a longer one still says something of its own
+        let own = expanded.range.start + expanded.chunk_range.start..expanded.range.start + expanded.chunk_range.end;
+        if passages.iter().any(|passage| passage.doc == result.doc && passage.range.start <= own.start && own.end <= passage.range.end)
+            || held.contains(&fnv1a(expanded.text[expanded.chunk_range.clone()].as_bytes()))
+        {
+            continue;
+        }
+        let overlapping: Vec<usize> = (0..passages.len())
+            .filter(|&i| passages[i].doc == result.doc && passages[i].range.start <= expanded.range.end && expanded.range.start <= passages[i].range.end)
+            .collect();
+        let start = overlapping.iter().map(|&i| passages[i].range.start).fold(expanded.range.start, usize::min);
+        let end = overlapping.iter().map(|&i| passages[i].range.end).fold(expanded.range.end, usize::max);
+        let passage = passage(corpus, result.doc, start..end, result.score)?;
+        let freed: usize = overlapping.iter().map(|&i| passages[i].tokens).sum();
+        if used - freed + passage.tokens > max_total_tokens {
+            continue;
+        }
+        used = used - freed + passage.tokens;
+        // A merged passage keeps the place of the best passage it absorbed
+        let score = overlapping.iter().map(|&i| passages[i].score).fold(passage.score, Score::max);
+        let place = overlapping.first().copied().unwrap_or(passages.len());
+        for &i in overlapping.iter().rev() {
+            passages.remove(i);
+        }
+        passages.insert(place, Passage { score, ..passage });
+        let text = corpus.document_text(result.doc)?;
+        for (_, range) in corpus.chunk_ranges(result.doc)?.iter().filter(|(_, range)| start <= range.start && range.end <= end) {
+            held.insert(fnv1a(text[range.clone()].as_bytes()));
+        }
+    }
+    Ok(passages)
+}
+
+fn passage(corpus: &Corpus, doc: DocId, range: Range<usize>, score: Score) -> Result<Passage, Box<dyn Error>> {
+    let text = corpus.document_text(doc)?;
+    let first_line = text[..range.start].matches('\n').count() + 1;
+    let passage_text = &text[range.clone()];
+    Ok(Passage {
+        doc,
+        path: corpus.path(doc).unwrap_or("?").to_string(),
+        first_line,
+        last_line: first_line + passage_text.trim_end_matches('\n').matches('\n').count(),
+        range,
+        score,
+        text: passage_text.to_string(),
+        tokens: estimate_tokens(passage_text),
+    })
+}
+
+/// BM25 passages for a query, each with the chunk before and after it, within max_total_tokens
+pub fn retrieve_for_prompt(corpus: &Corpus, query: &str, max_total_tokens: usize) -> Result<Vec<Passage>, Box<dyn Error>> {
+    let results = corpus.search_with(query, &Bm25Scorer { corpus, params: Bm25Params::default() }).map_err(|e| e.render(query))?;
+    pack_for_prompt(corpus, &results, max_total_tokens, Context::Chunks(1))
+}
+
+/// Passages as prompt text, each under a [n] path:lines header the model can cite
+pub fn render_passages(passages: &[Passage]) -> String {
+    let mut rendered = String::new();
+    for (number, passage) in passages.iter().enumerate() {
+        rendered.push_str(&format!("[{}] {}:{}-{}\n{}\n\n", number + 1, passage.path, passage.first_line, passage.last_line, passage.text.trim_end()));
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::{ChunkStrategy, ChunkingConfig};
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_retrieve_for_prompt() {
+        let text = "intro line\nrust borrow checker\nrust lifetimes\nunrelated\nmore unrelated\nstill unrelated\nlast rust line";
+        let corpus = Corpus::builder()
+            .add_document(Document::new("guide.txt", text))
+            .add_document(Document::new("copy.txt", text))
+            .add_document(Document::new("other.txt", "python"))
+            .chunking(ChunkingConfig::new(ChunkStrategy::Lines, 1, 0).unwrap())
+            .build()
+            .unwrap();
+
+        let passages = retrieve_for_prompt(&corpus, "rust", 1000).unwrap();
+        // The shorter line 3 ranks first and brings lines 2 and 4 along, which leaves nothing for
+        // line 2 to add. Line 7 is a passage of its own, and the copy adds nothing
+        assert_eq!(passages.len(), 2);
+        assert_eq!((passages[0].first_line, passages[0].last_line), (2, 4));
+        assert!(passages[0].text.starts_with("rust borrow checker\nrust lifetimes"));
+        assert_eq!((passages[1].first_line, passages[1].last_line), (6, 7));
+        assert!(render_passages(&passages).contains(":6-7\nstill unrelated\nlast rust line\n\n"));
+
+        // A budget too small for the merged passage still takes the passage that fits
+        let small = retrieve_for_prompt(&corpus, "rust", 10).unwrap();
+        assert!(small.iter().map(|passage| passage.tokens).sum::<usize>() <= 10);
+        assert!(!small.is_empty());
+        assert!(retrieve_for_prompt(&corpus, "rust", 0).unwrap().is_empty());
+
+        // A chunk whose text only appears inside a longer one is a passage of its own
+        let corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "rust borrow checker"))
+            .add_document(Document::new("b.txt", "rust"))
+            .add_document(Document::new("c.txt", "python"))
+            .build()
+            .unwrap();
+        let mut results = corpus.search("rust").unwrap();
+        results.sort_by_key(|result| result.doc);
+        assert_eq!(pack_for_prompt(&corpus, &results, 1000, Context::Chunks(0)).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use regex::Regex;
+use crate::analyzer::Token;
+use crate::corpus::ChunkId;
+use crate::query::TermScorer;
+use crate::bm25::Bm25Params;
+use crate::search::Score;
+
+// Every domain has words its users type that its documents don't: k8s for kubernetes, pg for
+// postgres, a product's old name. Rewrite rules fix those queries before they are parsed, from a
+// file that can change without a release. One rule per line, applied in order, each to the output
+// of the ones before it:
+//
+//     k8s => kubernetes                    replace a word
+//     /\bpg(\d+)\b/ => postgres $1         replace what a regex matches, $1 is its first group
+//     expand db => database, datastore     match a word or its alternatives: (db OR database OR datastore)
+//     boost kubernetes => 2                multiply the score of a word's terms
+//
+// Words are matched whole and ignoring case. Expansions leave "phrases" alone, OR has no meaning
+// inside them
+
+/// One line of a rules file
+#[derive(Debug, Clone)]
+pub enum Rule {
+    Replace { word: String, replacement: String },
+    Regex { pattern: Regex, replacement: String },
+    Expand { word: String, alternatives: Vec<String> },
+    Boost { word: String, factor: Score },
+}
+
+/// Rules applied to query text before parsing, in order, see rewrite.rs for the file format
+#[derive(Debug, Clone, Default)]
+pub struct RewriteRules(pub Vec<Rule>);
+
+/// A query after rewriting, with the boosts its words are scored with
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rewritten {
+    pub query: String,
+    /// Words of the rewritten query and their factors, see BoostedScorer
+    pub boosts: Vec<(String, Score)>,
+}
+
+impl RewriteRules {
+    /// Parse a rules file, blank lines and # comments are skipped
+    pub fn parse(text: &str) -> Result<RewriteRules, String> {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let (left, right) = line.split_once("=>").ok_or_else(|| error("expected PATTERN => REPLACEMENT".to_string()))?;
+            let (left, right) = (left.trim(), right.trim());
+            let rule = if let Some(word) = left.strip_prefix("expand ") {
+                let alternatives: Vec<String> = right.split(',').map(str::trim).filter(|alternative| !alternative.is_empty()).map(str::to_string).collect();
+                if alternatives.is_empty() {
+                    return Err(error(format!("nothing to expand {} to", word.trim())));
+                }
+                Rule::Expand { word: word.trim().to_string(), alternatives }
+            } else if let Some(word) = left.strip_prefix("boost ") {
+                let factor: Score = right.parse().map_err(|_| error(format!("the boost {} is not a number", right)))?;
+                if !(factor.is_finite() && factor >= 0.0) {
+                    return Err(error(format!("the boost {} must be 0 or more", factor)));
+                }
+                Rule::Boost { word: word.trim().to_string(), factor }
+            } else if let Some(pattern) = left.strip_prefix('/').and_then(|pattern| pattern.strip_suffix('/')) {
+                let pattern = Regex::new(pattern).map_err(|e| error(e.to_string()))?;
+                Rule::Regex { pattern, replacement: right.to_string() }
+            } else {
+                Rule::Replace { word: left.to_string(), replacement: right.to_string() }
+            };
+            if let Rule::Replace { word, .. } | Rule::Expand { word, .. } | Rule::Boost { word, .. } = &rule
+                && (word.is_empty() || word.contains(|c: char| c.is_whitespace() || is_syntax(c)))
+            {
+                return Err(error(format!("'{}' is not a single word, use a /regex/ for longer patterns", word)));
+            }
+            rules.push(rule);
+        }
+        Ok(RewriteRules(rules))
+    }
+
+    pub fn load(path: &Path) -> Result<RewriteRules, Box<dyn Error>> {
+        Ok(RewriteRules::parse(&fs::read_to_string(path)?).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    pub fn rewrite(&self, query: &str) -> Rewritten {
+        let mut rewritten = Rewritten { query: query.to_string(), boosts: Vec::new() };
+        for rule in &self.0 {
+            match rule {
+                Rule::Replace { word, replacement } => {
+                    rewritten.query = map_words(&rewritten.query, true, |found| found.eq_ignore_ascii_case(word).then(|| replacement.clone()));
+                }
+                Rule::Regex { pattern, replacement } => {
+                    rewritten.query = pattern.replace_all(&rewritten.query, replacement.as_str()).into_owned();
+                }
+                Rule::Expand { word, alternatives } => {
+                    rewritten.query = map_words(&rewritten.query, false, |found| {
+                        found.eq_ignore_ascii_case(word).then(|| format!("({} OR {})", found, alternatives.join(" OR ")))
+                    });
+                }
+                Rule::Boost { word, factor } => {
+                    // Only a word still in the query is boosted, not one an earlier rule replaced
+                    let mut present = false;
+                    map_words(&rewritten.query, true, |found| {
+                        present |= found.eq_ignore_ascii_case(word);
+                        None
+                    });
+                    if present {
+                        rewritten.boosts.push((word.clone(), *factor));
+                    }
+                }
+            }
+        }
+        rewritten
+    }
+}
+
+// Characters of the query syntax, they end a word
+fn is_syntax(c: char) -> bool {
+    matches!(c, '(' | ')' | '"')
+}
+
+// Replace the words f returns something for, inside "phrases" too if in_phrases
+fn map_words(query: &str, in_phrases: bool, mut f: impl FnMut(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(query.len());
+    let mut in_phrase = false;
+    let mut rest = query;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() || is_syntax(c) {
+            in_phrase ^= c == '"';
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let end = rest.find(|c: char| c.is_whitespace() || is_syntax(c)).unwrap_or(rest.len());
+        // A leading - negates, the word after it can still be rewritten
+        let (sign, word) = rest[..end].split_at(usize::from(rest.starts_with('-')));
+        out.push_str(sign);
+        match f(word).filter(|_| in_phrases || !in_phrase) {
+            Some(replacement) => out.push_str(&replacement),
+            None => out.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    out
+}
+
+/// Wraps a scorer so that the terms of boosted words score more, see Rule::Boost
+pub struct BoostedScorer<'a> {
+    inner: &'a dyn TermScorer,
+    factors: HashMap<String, Score>,
+}
+
+impl<'a> BoostedScorer<'a> {
+    /// The words are analyzed like the query, so "Kubernetes" boosts the term the index has for it
+    pub fn new(inner: &'a dyn TermScorer, boosts: &[(String, Score)]) -> BoostedScorer<'a> {
+        let mut factors = HashMap::new();
+        for (word, factor) in boosts {
+            for term in inner.analyze(word) {
+                factors.insert(term, *factor);
+            }
+        }
+        BoostedScorer { inner, factors }
+    }
+}
+
+impl TermScorer for BoostedScorer<'_> {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        self.inner.tokens(text)
+    }
+
+    fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)> {
+        let mut scores = self.inner.score_term(term);
+        if let Some(factor) = self.factors.get(term) {
+            scores.iter_mut().for_each(|(_, score)| *score *= factor);
+        }
+        scores
+    }
+
+    fn positions(&self, term: &str, chunk: ChunkId) -> Vec<u32> {
+        self.inner.positions(term, chunk)
+    }
+
+    fn query_weights(&self, counts: &[(String, u32)]) -> Vec<Score> {
+        self.inner.query_weights(counts)
+    }
+
+    fn bm25_params(&self) -> Option<Bm25Params> {
+        self.inner.bm25_params()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bm25::{Bm25Params, Bm25Scorer};
+    use crate::corpus::{Corpus, Document};
+
+    #[test]
+    fn test_rewrite_rules() {
+        let rules = RewriteRules::parse(
+            "# fixes for ops queries\nk8s => kubernetes\n/\\bpg(\\d+)\\b/ => postgres $1\nexpand db => database, datastore\nboost kubernetes => 3\n",
+        )
+        .unwrap();
+        let rewritten = rules.rewrite("K8S -db \"db k8s\" pg16");
+        assert_eq!(rewritten.query, "kubernetes -(db OR database OR datastore) \"db kubernetes\" postgres 16");
+        assert_eq!(rewritten.boosts, [("kubernetes".to_string(), 3.0)]);
+        assert!(rules.rewrite("docker").boosts.is_empty());
+        assert!(RewriteRules::parse("two words => one").is_err());
+        assert!(RewriteRules::parse("/(/ => x").is_err());
+        assert!(RewriteRules::parse("boost x => lots").is_err());
+
+        let corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "kubernetes cluster"))
+            .add_document(Document::new("b.txt", "docker cluster"))
+            .add_document(Document::new("c.txt", "python"))
+            .build()
+            .unwrap();
+        let scorer = Bm25Scorer { corpus: &corpus, params: Bm25Params::default() };
+        let plain = corpus.rank_with("kubernetes", &scorer).unwrap();
+        let boosted = corpus.rank_with("kubernetes", &BoostedScorer::new(&scorer, &[("Kubernetes".to_string(), 3.0)])).unwrap();
+        assert!((boosted[0].1 - 3.0 * plain[0].1).abs() < 1e-6);
+    }
+}
@@ -1,46 +1,412 @@
-use crate::chunker::{chunk_text, Chunk};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Component, Path};
+use std::str::FromStr;
+use std::time::Duration;
+use serde::Serialize;
+use crate::chunker::Chunk;
+use crate::corpus::{ChunkId, Corpus, DocId, Document};
+use crate::fields::FieldWeights;
+use crate::filter::DocFilter;
+use crate::query::{QueryError, RepeatedTerms, Summation, TermScorer};
 
-/// Search for chunks containing the query string
-// Takes query as &str (borrowed string slice) and files as a slice of tuples
-// &[(String, String)] is a borrowed slice of tuples, where each tuple is (filename, content)
-// The & means we're borrowing the data, not taking ownership
-pub fn search_chunks(query: &str, files: &[(String, String)]) -> Vec<Chunk> {
-    let mut all_chunks = Vec::new();
+/// The float type of every score, f32 unless the f64 feature is enabled
+// f32 is faster and half the memory, but summing many f32 terms drifts from the Python
+// implementation, which scores in f64, enough to swap close results in parity tests
+#[cfg(not(feature = "f64"))]
+pub type Score = f32;
+#[cfg(feature = "f64")]
+pub type Score = f64;
+
+/// A score in single precision, for outputs that are always f32 like the gRPC protocol or Arrow columns
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f32(score: Score) -> f32 {
+    score as f32
+}
+
+/// A single hit, shared by line search, chunk search and the scorers
+// Every search function returns Vec<SearchResult> so callers only need one handling path
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    /// The document the hit came from, resolve it with Corpus::document
+    pub doc: DocId,
+    /// The matching chunk, None for line-based search
+    pub chunk: Option<ChunkId>,
+    /// Relevance score, plain substring matches all score 1.0
+    pub score: Score,
+    /// The score rescaled by normalize_scores, None until it has been called
+    pub normalized: Option<Score>,
+    /// Lines of the matched text that contain the query, cut to MAX_LINE_LENGTH for line search
+    pub highlights: Vec<String>,
+    /// 1-based line number, only set for line-based search
+    pub line: Option<usize>,
+    /// Byte ranges of the matches within the chunk text, or within the line for line-based search
+    // Ranges instead of marked-up strings let GUIs and editors draw their own highlighting
+    pub spans: Vec<Range<usize>>,
+}
+
+impl SearchResult {
+    /// Build a result for a matching chunk, highlighting the lines that contain any of the terms
+    pub fn from_chunk(chunk: &Chunk, score: Score, terms: &[&str]) -> SearchResult {
+        SearchResult {
+            doc: chunk.doc,
+            highlights: highlight_lines(&chunk.text, terms),
+            spans: match_spans(&chunk.text, terms),
+            chunk: Some(chunk.id),
+            score,
+            normalized: None,
+            line: None,
+        }
+    }
+}
+
+/// Ways of putting raw scores on a common scale
+// Raw TF-IDF and BM25 scores depend on the corpus and the query, so "0.8" means nothing by itself;
+// all three methods only look at the scores of the current result list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// (score - min) / (max - min), 0 to 1
+    MinMax,
+    /// Standard deviations above or below the mean score
+    ZScore,
+    /// Fraction of the other results that scored lower, 0 to 1
+    Percentile,
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "minmax" => Ok(Normalization::MinMax),
+            "zscore" => Ok(Normalization::ZScore),
+            "percentile" => Ok(Normalization::Percentile),
+            other => Err(format!("unknown normalization '{}', expected minmax, zscore or percentile", other)),
+        }
+    }
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Normalization::MinMax => "minmax",
+            Normalization::ZScore => "zscore",
+            Normalization::Percentile => "percentile",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Fill in `normalized` for every result, the raw scores are left untouched
+/// Call it on the full result list, before cutting it down to the top k
+pub fn normalize_scores(results: &mut [SearchResult], method: Normalization) {
+    if results.is_empty() {
+        return;
+    }
+    let n = results.len() as Score;
+    let min = results.iter().map(|r| r.score).fold(Score::INFINITY, Score::min);
+    let max = results.iter().map(|r| r.score).fold(Score::NEG_INFINITY, Score::max);
+    let mean = results.iter().map(|r| r.score).sum::<Score>() / n;
+    let std_dev = (results.iter().map(|r| (r.score - mean).powi(2)).sum::<Score>() / n).sqrt();
+    let scores: Vec<Score> = results.iter().map(|r| r.score).collect();
+
+    for result in results.iter_mut() {
+        let score = result.score;
+        // When every score is the same there is no spread to scale by, so all results
+        // get the top value (or the mean, for z-scores)
+        result.normalized = Some(match method {
+            Normalization::MinMax if max > min => (score - min) / (max - min),
+            Normalization::MinMax => 1.0,
+            Normalization::ZScore if std_dev > 0.0 => (score - mean) / std_dev,
+            Normalization::ZScore => 0.0,
+            Normalization::Percentile if scores.len() > 1 => {
+                scores.iter().filter(|s| **s < score).count() as Score / (n - 1.0)
+            }
+            Normalization::Percentile => 1.0,
+        });
+    }
+}
+
+/// Thresholds and filters that drop matches before results are built
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchOptions {
+    /// Drop results scoring below this, after boosts
+    pub min_score: Option<Score>,
+    /// Drop chunks containing fewer than this many distinct query terms, e.g. 2 for "at least 2 of 3"
+    /// More than the query has means all of them
+    pub minimum_should_match: Option<usize>,
+    /// Stop looking up query terms after this long and return what has been scored so far
+    pub time_budget: Option<Duration>,
+    /// How the scores of the matching query terms are added up
+    pub summation: Summation,
+    /// How a term the query repeats is counted
+    pub repeated_terms: RepeatedTerms,
+    /// Only return chunks of the documents the filter allows
+    pub filter: Option<DocFilter>,
+    /// Add the BM25F score of the query over these fields of a document to its matching chunks
+    pub field_weights: Option<FieldWeights>,
+    /// Only return the chunks overlapping this part of a document, with the matches inside it
+    pub selection: Option<Selection>,
+}
+
+/// A byte range of one document's text, e.g. the selection in an editor
+// Searching a selection still scores with the IDF of the whole corpus, only the candidates are
+// restricted: a term that is everywhere in the code base shouldn't count for much inside one
+// function either
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub doc: DocId,
+    pub bytes: Range<usize>,
+}
+
+/// Results of a search with a time budget
+#[derive(Debug, Clone)]
+pub struct TimedResults {
+    pub results: Vec<SearchResult>,
+    /// The budget ran out, some query terms were not scored and the ranking may be incomplete
+    pub truncated: bool,
+    /// Query terms no chunk contains, see Corpus::unknown_terms
+    pub unknown_terms: Vec<String>,
+}
+
+/// Orders a result list can be put in once the results have been chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    /// Highest score first, the order every search returns
+    #[default]
+    Score,
+    /// Document order: by path, then by chunk index or line number
+    Path,
+    /// Most recently modified file first, then by score
+    Modified,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "score" => Ok(SortOrder::Score),
+            "path" => Ok(SortOrder::Path),
+            "modified" => Ok(SortOrder::Modified),
+            other => Err(format!("unknown sort order '{}', expected score, path or modified", other)),
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SortOrder::Score => "score",
+            SortOrder::Path => "path",
+            SortOrder::Modified => "modified",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Reorder results, ties keep their current order
+/// Sorting changes which results come first, so cut the list to the top k by score before calling it
+pub fn sort_results(corpus: &Corpus, results: &mut [SearchResult], order: SortOrder) {
+    // The position within the document: chunk index for ranked search, line number for line search
+    let position = |result: &SearchResult| result.chunk.and_then(|id| corpus.chunk(id)).map(|chunk| chunk.index).or(result.line);
+    match order {
+        SortOrder::Score => results.sort_by(|a, b| b.score.total_cmp(&a.score)),
+        SortOrder::Path => results.sort_by(|a, b| (corpus.path(a.doc), position(a)).cmp(&(corpus.path(b.doc), position(b)))),
+        SortOrder::Modified => {
+            let modified = |result: &SearchResult| corpus.document(result.doc).and_then(|doc| doc.modified);
+            // Reversed so newest comes first, files without a modification time (None) go last
+            results.sort_by(|a, b| modified(b).cmp(&modified(a)).then(b.score.total_cmp(&a.score)));
+        }
+    }
+}
+
+/// A result list together with the corpus it came from, so hits can be grouped by document
+pub struct SearchResults<'a> {
+    pub corpus: &'a Corpus,
+    pub results: &'a [SearchResult],
+}
+
+/// Number of hits per file extension and per top-level directory
+// BTreeMap keeps the keys sorted, so printed and serialized facets come out in a stable order
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Facets {
+    /// Extension without the dot, "" for files without one
+    pub extensions: BTreeMap<String, usize>,
+    /// First directory below the root the document was loaded from, "." for files directly in it
+    pub directories: BTreeMap<String, usize>,
+}
+
+impl<'a> SearchResults<'a> {
+    pub fn new(corpus: &'a Corpus, results: &'a [SearchResult]) -> SearchResults<'a> {
+        SearchResults { corpus, results }
+    }
+
+    /// Search again among the documents of these results only, to narrow a search down step by step
+    // The query is still scored with the statistics of the whole corpus, so the scores of a refined
+    // search mean the same as those of the search it narrows down
+    pub fn refine(&self, query: &str, scorer: &dyn TermScorer) -> Result<Vec<SearchResult>, QueryError> {
+        let documents = self.results.iter().map(|result| result.doc.0).collect();
+        let options = SearchOptions { filter: Some(DocFilter::Documents(documents)), ..SearchOptions::default() };
+        self.corpus.search_with_options(query, scorer, &options)
+    }
+
+    /// Count the hits by extension and directory, every result counts once
+    /// Call it on the full result list to see where all matches are, not only the top k
+    pub fn facets(&self) -> Facets {
+        let mut facets = Facets::default();
+        for result in self.results {
+            let Some(document) = self.corpus.document(result.doc) else {
+                continue;
+            };
+            let path = Path::new(&document.path);
+            let extension = path.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default();
+            *facets.extensions.entry(extension).or_insert(0) += 1;
+            *facets.directories.entry(top_directory(document)).or_insert(0) += 1;
+        }
+        facets
+    }
+}
+
+// Loaded paths start with the name of the root directory ("docs/guide/a.md" for root "docs"),
+// which every document of that root shares, so the directory that says something is the one below it
+fn top_directory(document: &Document) -> String {
+    let path = Path::new(&document.path);
+    let below_root = Path::new(&document.root)
+        .file_name()
+        .and_then(|root| path.strip_prefix(root).ok())
+        .filter(|rest| !rest.as_os_str().is_empty())
+        .unwrap_or(path);
+    match below_root.parent().and_then(|parent| parent.components().find(|c| matches!(c, Component::Normal(_)))) {
+        Some(directory) => directory.as_os_str().to_string_lossy().to_string(),
+        None => ".".to_string(),
+    }
+}
+
+/// Return the trimmed lines of text that contain at least one of the terms (case-insensitive)
+pub fn highlight_lines(text: &str, terms: &[&str]) -> Vec<String> {
+    let lowercase_terms: Vec<String> = terms.iter().map(|t| t.to_lowercase()).collect();
 
-    // First, chunk all files into 500-character segments
-    for (filename, content) in files {
-        // chunk_text returns Vec<Chunk>, we're using 500 chars (roughly 75-100 tokens)
-        let chunks = chunk_text(content, 500, filename);
-        // extend() adds all elements from chunks to all_chunks
-        // Unlike push() which adds one item, extend() adds multiple items
-        all_chunks.extend(chunks);
+    text.lines()
+        .filter(|line| {
+            let lowercase_line = line.to_lowercase();
+            lowercase_terms.iter().any(|t| lowercase_line.contains(t.as_str()))
+        })
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+/// Byte ranges of every case-insensitive occurrence of any of the terms, sorted by start
+pub fn match_spans(text: &str, terms: &[&str]) -> Vec<Range<usize>> {
+    let lowercase_terms: Vec<String> = terms.iter().filter(|t| !t.is_empty()).map(|t| t.to_lowercase()).collect();
+    let mut spans = Vec::new();
+    // Compare from every char boundary, lowercasing as we go, rather than searching text.to_lowercase():
+    // lowercasing can change the byte length of some characters and the offsets would drift
+    for (start, _) in text.char_indices() {
+        for term in &lowercase_terms {
+            if let Some(end) = match_at(text, start, term) {
+                spans.push(start..end);
+            }
+        }
     }
+    spans
+}
+
+// If text starting at `start` equals the lowercase term ignoring case, the byte offset where the match ends
+fn match_at(text: &str, start: usize, term: &str) -> Option<usize> {
+    let mut expected = term.chars();
+    let mut lowered = String::new();
+    for (offset, c) in text[start..].char_indices() {
+        lowered.clear();
+        lowered.extend(c.to_lowercase());
+        for l in lowered.chars() {
+            if expected.next() != Some(l) {
+                return None;
+            }
+        }
+        if expected.as_str().is_empty() {
+            return Some(start + offset + c.len_utf8());
+        }
+    }
+    None
+}
+
+/// Search for chunks containing the query string
+// Takes query as &str (borrowed string slice) and chunks as a slice of already chunked text
+// The & means we're borrowing the data, not taking ownership - chunking happens once in Corpus::new
+pub fn search_chunks(query: &str, chunks: &[Chunk]) -> Vec<SearchResult> {
+    let lowercase: Vec<String> = chunks.iter().map(|chunk| chunk.text.to_lowercase()).collect();
+    search_lowercase_chunks(query, chunks, &lowercase)
+}
+
+// search_chunks with the lowercase text of every chunk already at hand, in the same order.
+// A corpus lowercases its chunks once and keeps the result, so a query only lowercases itself
+pub(crate) fn search_lowercase_chunks(query: &str, chunks: &[Chunk], lowercase: &[impl AsRef<str>]) -> Vec<SearchResult> {
+    let lowercase_query = query.to_lowercase();
 
     // Search within chunks using iterator chains
-    all_chunks
-        .into_iter() // into_iter() consumes the vector, taking ownership (we won't need all_chunks after this)
-        .filter(|chunk| chunk.text.to_lowercase().contains(&query.to_lowercase())) // filter keeps only chunks containing our query
-        .collect() // collect() consumes the iterator and builds a new Vec<Chunk> from filtered results
+    chunks
+        .iter() // iter() borrows every chunk, the corpus keeps ownership so it can answer the next query
+        .zip(lowercase) // zip() pairs every chunk with its lowercase text
+        .filter(|(_, text)| text.as_ref().contains(&lowercase_query)) // filter keeps only chunks containing our query
+        .map(|(chunk, _)| SearchResult::from_chunk(chunk, 1.0, &[query])) // wrap the matches in the shared result type
+        .collect() // collect() consumes the iterator and builds a new Vec<SearchResult> from filtered results
+}
+
+/// Longest line, in bytes, that line search copies into a result whole
+// A minified bundle or a JSON dump can be a single line of 100 MB, and a copy of it per match would
+// blow up memory for what nobody reads anyway. Longer lines are cut to a window around their first
+// match, the spans still locate every match in the whole line
+pub const MAX_LINE_LENGTH: usize = 1000;
+
+// The line, or the MAX_LINE_LENGTH bytes of it around the first match with … where it was cut
+fn excerpt(line: &str, spans: &[Range<usize>]) -> String {
+    if line.len() <= MAX_LINE_LENGTH {
+        return line.to_string();
+    }
+    let first = spans.first().map_or(0, |span| span.start);
+    let start = line.floor_char_boundary(first.saturating_sub(MAX_LINE_LENGTH / 2));
+    let end = line.floor_char_boundary(start + MAX_LINE_LENGTH);
+    let before = if start > 0 { "…" } else { "" };
+    let after = if end < line.len() { "…" } else { "" };
+    format!("{}{}{}", before, &line[start..end], after)
 }
 
 /// Search for lines containing the query string
-/// Returns Vec<(String, Vec<String>)> - a vector of tuples containing (filename, matching_lines)
-pub fn search_files(query: &str, files: &[(String, String)]) -> Vec<(String, Vec<String>)> {
+/// Returns one SearchResult per matching line, with the line number set
+pub fn search_files(query: &str, documents: &[Document]) -> Vec<SearchResult> {
+    let lowercase: Vec<String> = documents.iter().map(|document| document.text.to_lowercase()).collect();
+    search_lowercase_files(query, documents, &lowercase)
+}
+
+// search_files with the lowercase text of every document. Lowercasing never adds or removes a
+// newline, so line n of the lowercase text is line n of the document lowercased
+pub(crate) fn search_lowercase_files(query: &str, documents: &[Document], lowercase: &[impl AsRef<str>]) -> Vec<SearchResult> {
     let mut results = Vec::new();
     // Convert query to lowercase once, outside the loop for efficiency
     let lowercase_query = query.to_lowercase();
 
-    for (filename, content) in files {
-        let matches: Vec<String> = content
+    for (document, lowercase) in documents.iter().zip(lowercase) {
+        let matches = document.text
             .lines() // lines() splits the string by newlines, returns an iterator of &str
-            .filter(|line| line.to_lowercase().contains(&lowercase_query)) // keep only lines containing query
-            .map(|line| line.to_string()) // convert &str to owned String (needed because we're storing them)
-            .collect(); // build Vec<String> from the filtered lines
-
-        if !matches.is_empty() {
-            // clone() creates a copy of filename since we need an owned String
-            // We can't move filename because it's borrowed from files
-            results.push((filename.clone(), matches));
+            .zip(lowercase.as_ref().lines()) // zip() pairs every line with the same line lowercased
+            .enumerate() // enumerate() pairs every line with its 0-based position
+            .filter(|(_, (_, lowercase))| lowercase.contains(&lowercase_query)) // keep only lines containing query
+            .map(|(line_number, (line, _))| (line_number, line));
+
+        for (line_number, line) in matches {
+            let spans = match_spans(line, &[query]);
+            results.push(SearchResult {
+                // DocId is Copy, so unlike the path String it costs nothing to store in every result
+                doc: document.id,
+                chunk: None,
+                score: 1.0,
+                normalized: None,
+                highlights: vec![excerpt(line, &spans)],
+                spans,
+                line: Some(line_number + 1),
+            });
         }
     }
 
@@ -50,29 +416,146 @@ pub fn search_files(query: &str, files: &[(String, String)]) -> Vec<(String, Vec
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chunker::{chunk_files, ChunkingConfig};
+    use crate::corpus::Corpus;
+    use crate::tfidf::{TfIdfParams, TfIdfScorer};
 
     #[test]
     fn test_search_chunks_vs_search_files() {
         // Create test data that demonstrates the difference between line and chunk search
-        let files = vec![(
-            "test.txt".to_string(),
+        let files = vec![Document::new(
+            "test.txt",
             "This is a long paragraph about Rust programming. \
              It contains multiple sentences and spans several lines. \
              The word 'programming' appears here and provides good context \
-             for understanding what this text is about.".to_string()
+             for understanding what this text is about.",
         )];
 
         // Search for 'programming' using both methods
         let line_results = search_files("programming", &files);
-        let chunk_results = search_chunks("programming", &files);
+        let chunks = chunk_files(&files, &ChunkingConfig::default(), ChunkId(0));
+        let chunk_results = search_chunks("programming", &chunks);
 
         // Line search finds the specific line
         assert_eq!(line_results.len(), 1);
+        assert_eq!(line_results[0].line, Some(1));
 
         // Chunk search finds the chunk with more context
         assert_eq!(chunk_results.len(), 1);
-        assert_eq!(chunk_results[0].text.len(), line_results[0].1[0].len());
-        assert!(chunk_results[0].text.contains("Rust programming"));
-        assert!(chunk_results[0].text.contains("context"));
+        let chunk = &chunks[chunk_results[0].chunk.unwrap().0 as usize];
+        assert_eq!(chunk.text.len(), line_results[0].highlights[0].len());
+        assert!(chunk.text.contains("Rust programming"));
+        assert!(chunk.text.contains("context"));
+    }
+
+    #[test]
+    fn test_search_files_reports_each_line() {
+        let corpus = Corpus::new(
+            vec![Document::new("notes.txt", "first line\nno match here\nlast Line")],
+            ChunkingConfig::default(),
+        );
+
+        let results = corpus.search_lines("line");
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].line, Some(1));
+        assert_eq!(results[1].line, Some(3));
+        assert_eq!(results[1].highlights, vec!["last Line".to_string()]);
+        assert_eq!(results[1].spans, vec![5..9]);
+        assert!(results.iter().all(|r| corpus.path(r.doc) == Some("notes.txt") && r.chunk.is_none()));
+    }
+
+    #[test]
+    fn test_search_files_cuts_long_lines() {
+        let line = format!("{}needle{}", "é".repeat(2000), "x".repeat(5000));
+        let results = search_files("NEEDLE", &[Document::new("bundle.min.js", &line)]);
+        let highlight = &results[0].highlights[0];
+        assert!(highlight.len() <= MAX_LINE_LENGTH + 2 * "…".len());
+        assert!(highlight.starts_with('…') && highlight.ends_with('…') && highlight.contains("needle"));
+        assert_eq!(&line[results[0].spans[0].clone()], "needle");
+    }
+
+    #[test]
+    fn test_match_spans() {
+        assert_eq!(match_spans("Rust and rust", &["rust"]), vec![0..4, 9..13]);
+        // The spans index the original text even when lowercasing changes byte lengths
+        let text = "İx RUST";
+        let spans = match_spans(text, &["rust"]);
+        assert_eq!(&text[spans[0].clone()], "RUST");
+    }
+
+    #[test]
+    fn test_normalize_scores() {
+        let chunks = chunk_files(&[Document::new("a.txt", "x")], &ChunkingConfig::default(), ChunkId(0));
+        let mut results: Vec<SearchResult> =
+            [4.0, 2.0, 1.0, 1.0].iter().map(|s| SearchResult::from_chunk(&chunks[0], *s, &[])).collect();
+        let normalized = |results: &[SearchResult]| -> Vec<Score> { results.iter().map(|r| r.normalized.unwrap()).collect() };
+
+        normalize_scores(&mut results, Normalization::MinMax);
+        assert_eq!(normalized(&results), vec![1.0, 1.0 / 3.0, 0.0, 0.0]);
+        normalize_scores(&mut results, Normalization::Percentile);
+        assert_eq!(normalized(&results), vec![1.0, 2.0 / 3.0, 0.0, 0.0]);
+        normalize_scores(&mut results, Normalization::ZScore);
+        assert!(normalized(&results)[0] > 1.0 && normalized(&results)[3] < 0.0);
+        assert_eq!(results[0].score, 4.0);
+        assert_eq!("zscore".parse::<Normalization>(), Ok(Normalization::ZScore));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_facets_by_extension_and_directory() {
+        let document = |path: &str, text: &str| Document { root: "docs".to_string(), ..Document::new(path, text) };
+        let corpus = Corpus::new(
+            vec![
+                document("docs/guide/a.md", "rust guide"),
+                document("docs/guide/b.rs", "fn rust() {}"),
+                document("docs/readme.md", "rust readme"),
+                document("docs/api/c", "python"),
+            ],
+            ChunkingConfig::default(),
+        );
+        let results = corpus.search_chunks("rust");
+        let facets = SearchResults::new(&corpus, &results).facets();
+        assert_eq!(facets.extensions, BTreeMap::from([("md".to_string(), 2), ("rs".to_string(), 1)]));
+        assert_eq!(facets.directories, BTreeMap::from([(".".to_string(), 1), ("guide".to_string(), 2)]));
+    }
+
+    #[test]
+    fn test_refine_searches_previous_results() {
+        let corpus = Corpus::new(
+            vec![
+                Document::new("a.txt", "rust borrow checker"),
+                Document::new("b.txt", "rust garbage collector"),
+                Document::new("c.txt", "python garbage collector"),
+            ],
+            ChunkingConfig::default(),
+        );
+        let scorer = TfIdfScorer::new(&corpus, TfIdfParams::default());
+        let rust = corpus.search("rust").unwrap();
+        let refined = SearchResults::new(&corpus, &rust).refine("garbage", &scorer).unwrap();
+        assert_eq!(refined.iter().map(|r| corpus.path(r.doc).unwrap()).collect::<Vec<_>>(), ["b.txt"]);
+        // Scored against the whole corpus, the same score as in an unrefined search
+        let everywhere = corpus.search("garbage").unwrap();
+        assert_eq!(refined[0].score, everywhere.iter().find(|r| r.doc == refined[0].doc).unwrap().score);
+        assert!(SearchResults::new(&corpus, &refined).refine("python", &scorer).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sort_results() {
+        let now = std::time::SystemTime::now();
+        let dated = |path: &str, text: &str, age: u64| Document {
+            modified: Some(now - std::time::Duration::from_secs(age)),
+            ..Document::new(path, text)
+        };
+        let corpus = Corpus::new(
+            vec![dated("b.txt", "rust rust", 10), dated("a.txt", "rust", 20), dated("c.txt", "rust rust rust", 30)],
+            ChunkingConfig::default(),
+        );
+        let mut results = corpus.search_chunks("rust");
+        let paths = |results: &[SearchResult]| -> Vec<&str> { results.iter().map(|r| corpus.path(r.doc).unwrap()).collect() };
+        sort_results(&corpus, &mut results, SortOrder::Path);
+        assert_eq!(paths(&results), ["a.txt", "b.txt", "c.txt"]);
+        sort_results(&corpus, &mut results, SortOrder::Modified);
+        assert_eq!(paths(&results), ["b.txt", "a.txt", "c.txt"]);
+        assert_eq!("Path".parse::<SortOrder>(), Ok(SortOrder::Path));
+    }
+}
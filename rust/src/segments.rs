@@ -0,0 +1,430 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use crate::analyzer::Token;
+use crate::chunker::Chunk;
+use crate::corpus::{ChunkId, Corpus, DocId, Document};
+use crate::query::{parse_query, QueryError, TermScorer};
+use crate::search::{Score, SearchResult};
+use crate::shard::Scoring;
+
+// The way Lucene keeps an index that changes all the time: instead of one big corpus that is
+// rebuilt on every change, the index is a list of immutable segments. Adding documents indexes
+// only them, as a new segment. Deleting a document only marks it in a set of tombstones, its
+// postings stay where they are. Many small segments make queries slower, so a merge policy
+// merges the smallest ones into one in a background thread, dropping the deleted documents
+
+/// An immutable piece of a SegmentedIndex and the documents deleted from it since it was built
+pub struct Segment {
+    id: u64,
+    // An Arc so marking a document as deleted copies the tombstones, not the postings
+    corpus: Arc<Corpus>,
+    deleted: HashSet<DocId>,
+}
+
+impl Segment {
+    pub fn corpus(&self) -> &Corpus {
+        &self.corpus
+    }
+
+    /// Deleted documents, still in the postings until the segment is merged
+    pub fn deleted(&self) -> &HashSet<DocId> {
+        &self.deleted
+    }
+
+    pub fn live_documents(&self) -> usize {
+        self.corpus.documents().len() - self.deleted.len()
+    }
+
+    fn live_document(&self, id: DocId) -> Option<&Document> {
+        self.corpus.document(id).filter(|_| !self.deleted.contains(&id))
+    }
+
+    // The segment with the live documents whose path is in paths deleted too, itself if there are none
+    fn with_deleted(segment: &Arc<Segment>, paths: &HashSet<&str>) -> Arc<Segment> {
+        let newly_deleted: Vec<DocId> = paths
+            .iter()
+            .filter_map(|path| segment.corpus.doc_id(path))
+            .filter(|id| !segment.deleted.contains(id))
+            .collect();
+        if newly_deleted.is_empty() {
+            return Arc::clone(segment);
+        }
+        let mut deleted = segment.deleted.clone();
+        deleted.extend(newly_deleted);
+        Arc::new(Segment { id: segment.id, corpus: Arc::clone(&segment.corpus), deleted })
+    }
+}
+
+/// When segments are merged: once there are more than max_segments, the merge_factor smallest become one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MergePolicy {
+    pub max_segments: usize,
+    pub merge_factor: usize,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        MergePolicy { max_segments: 8, merge_factor: 4 }
+    }
+}
+
+impl MergePolicy {
+    // The smallest segments are the cheapest to merge, and only ever merging segments of about the
+    // same size means a document is rewritten a logarithmic number of times, not once per add
+    fn select(&self, segments: &[Arc<Segment>]) -> Vec<Arc<Segment>> {
+        if segments.len() <= self.max_segments.max(1) {
+            return Vec::new();
+        }
+        let mut smallest = segments.to_vec();
+        smallest.sort_by_key(|segment| segment.corpus.chunks().len());
+        smallest.truncate(self.merge_factor.max(2));
+        smallest
+    }
+}
+
+/// The segments of a SegmentedIndex at one point in time
+// Like index::Snapshot, a query holds on to one snapshot, and ids in its results are only
+// meaningful within it: a merge gives the documents it rewrites new ids
+pub struct SegmentSnapshot {
+    generation: u64,
+    segments: Vec<Arc<Segment>>,
+}
+
+impl SegmentSnapshot {
+    /// Increases by one with every add, delete and merge
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn segments(&self) -> &[Arc<Segment>] {
+        &self.segments
+    }
+
+    /// The live document with this id
+    pub fn document(&self, id: DocId) -> Option<&Document> {
+        self.segments.iter().find_map(|segment| segment.live_document(id))
+    }
+
+    pub fn document_by_path(&self, path: &str) -> Option<&Document> {
+        self.segments.iter().find_map(|segment| segment.live_document(segment.corpus.doc_id(path)?))
+    }
+
+    pub fn chunk(&self, id: ChunkId) -> Option<&Chunk> {
+        self.segments.iter().find_map(|segment| segment.corpus.chunk(id))
+    }
+
+    /// Top results across all segments, without the deleted documents
+    pub fn search(&self, query: &str, scoring: Scoring, top: usize) -> Result<Vec<SearchResult>, QueryError> {
+        let query = parse_query(query)?;
+        // As with the shards of a ShardedCorpus, every segment scores with the statistics of all of
+        // them. Deleted documents keep counting until a merge drops them, exactly as in Lucene
+        let n_docs: usize = self.segments.iter().map(|segment| segment.corpus.index().num_chunks()).sum();
+        let total_length: Score =
+            self.segments.iter().map(|s| s.corpus.index().avg_len() * s.corpus.index().num_chunks() as Score).sum();
+        let avg_dl = if n_docs == 0 { 0.0 } else { total_length / n_docs as Score };
+
+        let mut results = Vec::new();
+        for segment in &self.segments {
+            let scorer = SegmentScorer { segment: &segment.corpus, all: &self.segments, n_docs, avg_dl, scoring };
+            let terms = query.positive_terms(&scorer);
+            let mut scores = query.evaluate(&scorer);
+            scores.retain(|chunk, _| segment.corpus.chunk(*chunk).is_some_and(|chunk| !segment.deleted.contains(&chunk.doc)));
+            let mut ranked = segment.corpus.rank_boosted(scores);
+            ranked.truncate(top);
+            results.extend(segment.corpus.to_results(ranked, &terms));
+        }
+        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap().then(a.chunk.cmp(&b.chunk)));
+        results.truncate(top);
+        Ok(results)
+    }
+}
+
+// Scores one segment's postings with N, df and the average length of all segments together
+struct SegmentScorer<'a> {
+    segment: &'a Corpus,
+    all: &'a [Arc<Segment>],
+    n_docs: usize,
+    avg_dl: Score,
+    scoring: Scoring,
+}
+
+impl TermScorer for SegmentScorer<'_> {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        self.segment.analyzer().tokens(text)
+    }
+
+    fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)> {
+        let doc_freq: usize = self.all.iter().map(|segment| segment.corpus.index().doc_freq(term)).sum();
+        let index = self.segment.index();
+        index
+            .postings(term)
+            .iter()
+            .map(|posting| {
+                let length = index.chunk_len(posting.chunk) as Score;
+                let score = self.scoring.posting_score(posting.tf as Score, length, self.n_docs, doc_freq, self.avg_dl);
+                (posting.chunk, score)
+            })
+            .collect()
+    }
+
+    fn positions(&self, term: &str, chunk: ChunkId) -> Vec<u32> {
+        self.segment.index().positions(term, chunk).to_vec()
+    }
+}
+
+// The next ids to hand out. Ids are never reused, so every segment has its own range of them
+#[derive(Default)]
+struct Ids {
+    segment: u64,
+    doc: u32,
+    chunk: u32,
+}
+
+// The merge thread and whether it still merges. The thread decides to stop under this lock, after
+// it last found nothing to merge, so an add that finds it running can leave its segment to it
+#[derive(Default)]
+struct Background {
+    handle: Option<JoinHandle<()>>,
+    running: bool,
+}
+
+struct Shared {
+    current: RwLock<Arc<SegmentSnapshot>>,
+    // Serializes changes to the segment list, held only to swap it, never while indexing
+    writer: Mutex<Ids>,
+    // One merge at a time, so two merges can't pick the same segments
+    merging: Mutex<()>,
+    background: Mutex<Background>,
+    template: Corpus,
+    policy: MergePolicy,
+}
+
+/// A corpus kept as immutable segments: cheap adds, tombstoned deletes and background merges
+// Cloning is cheap and every clone refers to the same segments
+#[derive(Clone)]
+pub struct SegmentedIndex {
+    shared: Arc<Shared>,
+}
+
+impl SegmentedIndex {
+    /// An empty index whose segments get template's chunking, analyzer and index options
+    pub fn new(template: &Corpus, policy: MergePolicy) -> SegmentedIndex {
+        let snapshot = SegmentSnapshot { generation: 0, segments: Vec::new() };
+        SegmentedIndex {
+            shared: Arc::new(Shared {
+                current: RwLock::new(Arc::new(snapshot)),
+                writer: Mutex::new(Ids::default()),
+                merging: Mutex::new(()),
+                background: Mutex::new(Background::default()),
+                template: template.with_documents(Vec::new()),
+                policy,
+            }),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<SegmentSnapshot> {
+        Arc::clone(&self.shared.current.read().unwrap())
+    }
+
+    fn publish(&self, generation: u64, segments: Vec<Arc<Segment>>) {
+        *self.shared.current.write().unwrap() = Arc::new(SegmentSnapshot { generation, segments });
+    }
+
+    /// Index the documents as a new segment, replacing live documents with the same paths
+    /// Starts a background merge when there are now more segments than the policy allows
+    pub fn add(&self, documents: Vec<Document>) {
+        if documents.is_empty() {
+            return;
+        }
+        // Chunking and analyzing, the expensive part, happens before any lock is taken
+        let corpus = self.shared.template.with_documents(documents);
+        {
+            let mut ids = self.shared.writer.lock().unwrap();
+            let current = self.snapshot();
+            let paths: HashSet<&str> = corpus.documents().iter().map(|document| document.path.as_str()).collect();
+            let mut segments: Vec<Arc<Segment>> =
+                current.segments.iter().map(|segment| Segment::with_deleted(segment, &paths)).collect();
+            let corpus = corpus.shifted(ids.doc, ids.chunk);
+            ids.doc = corpus.documents().last().map_or(ids.doc, |document| document.id.0 + 1);
+            ids.chunk = corpus.chunks().last().map_or(ids.chunk, |chunk| chunk.id.0 + 1);
+            segments.push(Arc::new(Segment { id: ids.segment, corpus: Arc::new(corpus), deleted: HashSet::new() }));
+            ids.segment += 1;
+            self.publish(current.generation + 1, segments);
+        }
+        self.merge_in_background();
+    }
+
+    /// Mark the document as deleted: it is gone from results at once and from the postings after a merge
+    pub fn delete(&self, path: &str) -> bool {
+        let _ids = self.shared.writer.lock().unwrap();
+        let current = self.snapshot();
+        if current.document_by_path(path).is_none() {
+            return false;
+        }
+        let paths = HashSet::from([path]);
+        let segments = current.segments.iter().map(|segment| Segment::with_deleted(segment, &paths)).collect();
+        self.publish(current.generation + 1, segments);
+        true
+    }
+
+    /// Merge segments on this thread until the policy is satisfied, returns whether anything was merged
+    pub fn merge(&self) -> Result<bool, String> {
+        let mut merged = false;
+        while self.merge_selected(|segments| self.shared.policy.select(segments))? {
+            merged = true;
+        }
+        Ok(merged)
+    }
+
+    /// Merge everything into a single segment without deleted documents, e.g. before saving it
+    pub fn force_merge(&self) -> Result<bool, String> {
+        self.merge_selected(|segments| {
+            if segments.len() > 1 || segments.iter().any(|segment| !segment.deleted.is_empty()) {
+                segments.to_vec()
+            } else {
+                Vec::new()
+            }
+        })
+    }
+
+    /// Block until a running background merge has finished
+    pub fn wait_for_merges(&self) {
+        let handle = self.shared.background.lock().unwrap().handle.take();
+        if let Some(handle) = handle {
+            // A merge that failed leaves the segments as they were, nothing to report
+            let _ = handle.join();
+        }
+    }
+
+    // Start a merge thread if the policy calls for one and none is running yet
+    fn merge_in_background(&self) {
+        if self.shared.policy.select(&self.snapshot().segments).is_empty() {
+            return;
+        }
+        let mut background = self.shared.background.lock().unwrap();
+        if background.running {
+            return;
+        }
+        // A thread that stopped running has nothing left to do but exit
+        if let Some(handle) = background.handle.take() {
+            let _ = handle.join();
+        }
+        background.running = true;
+        let index = self.clone();
+        background.handle = Some(thread::spawn(move || {
+            loop {
+                // A merge that fails leaves the segments as they were, the next add tries again
+                let failed = index.merge().is_err();
+                let mut background = index.shared.background.lock().unwrap();
+                if failed || index.shared.policy.select(&index.snapshot().segments).is_empty() {
+                    background.running = false;
+                    return;
+                }
+            }
+        }));
+    }
+
+    fn merge_selected<F>(&self, select: F) -> Result<bool, String>
+    where
+        F: Fn(&[Arc<Segment>]) -> Vec<Arc<Segment>>,
+    {
+        let _merging = self.shared.merging.lock().unwrap();
+        let chosen = select(&self.snapshot().segments);
+        if chosen.is_empty() {
+            return Ok(false);
+        }
+        // Copy the live documents of every chosen segment into one corpus. No lock is held,
+        // adds, deletes and queries carry on with the old segments in the meantime
+        let live: Vec<Corpus> = chosen
+            .iter()
+            .map(|segment| {
+                let mut corpus = (*segment.corpus).clone();
+                for id in &segment.deleted {
+                    corpus.remove_document(*id);
+                }
+                corpus
+            })
+            .collect();
+        let merged = Corpus::merge(&live.iter().collect::<Vec<&Corpus>>())?;
+
+        let mut ids = self.shared.writer.lock().unwrap();
+        let current = self.snapshot();
+        // Give the merged segment a fresh range of ids, so it can't collide with any other segment
+        let doc_offset = ids.doc - merged.documents().first().map_or(0, |document| document.id.0);
+        let chunk_offset = ids.chunk - merged.chunks().first().map_or(0, |chunk| chunk.id.0);
+        let merged = merged.shifted(doc_offset, chunk_offset);
+        ids.doc = merged.documents().last().map_or(ids.doc, |document| document.id.0 + 1);
+        ids.chunk = merged.chunks().last().map_or(ids.chunk, |chunk| chunk.id.0 + 1);
+
+        // Documents deleted or replaced while the merge ran are deleted in the merged segment too
+        let chosen_ids: HashSet<u64> = chosen.iter().map(|segment| segment.id).collect();
+        let mut deleted = HashSet::new();
+        for (before, now) in chosen.iter().zip(chosen.iter().map(|old| current.segments.iter().find(|s| s.id == old.id))) {
+            let now = now.ok_or("a segment disappeared during the merge")?;
+            for id in now.deleted.difference(&before.deleted) {
+                deleted.extend(now.corpus.path(*id).and_then(|path| merged.doc_id(path)));
+            }
+        }
+
+        let mut segments: Vec<Arc<Segment>> =
+            current.segments.iter().filter(|segment| !chosen_ids.contains(&segment.id)).cloned().collect();
+        if !merged.documents().is_empty() {
+            segments.push(Arc::new(Segment { id: ids.segment, corpus: Arc::new(merged), deleted }));
+            ids.segment += 1;
+        }
+        self.publish(current.generation + 1, segments);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bm25::{Bm25Params, Bm25Scorer};
+    use crate::chunker::ChunkingConfig;
+
+    #[test]
+    fn test_segments_match_a_single_corpus_after_merging() {
+        let template = Corpus::new(Vec::new(), ChunkingConfig::default());
+        let index = SegmentedIndex::new(&template, MergePolicy { max_segments: 2, merge_factor: 2 });
+        index.add(vec![Document::new("a.txt", "rust borrow"), Document::new("b.txt", "python garbage")]);
+        index.add(vec![Document::new("c.txt", "rust rust python")]);
+        index.add(vec![Document::new("b.txt", "rust memory")]);
+        index.add(vec![Document::new("d.txt", "go garbage")]);
+        assert!(index.delete("a.txt"));
+        assert!(!index.delete("a.txt"));
+
+        let paths = |snapshot: &SegmentSnapshot| -> Vec<String> {
+            let results = snapshot.search("rust", Scoring::TfIdf, 10).unwrap();
+            results.iter().map(|r| snapshot.document(r.doc).unwrap().path.clone()).collect()
+        };
+        // The last add may have started a merge, join it so the snapshot is the merged one
+        index.wait_for_merges();
+        assert_eq!(paths(&index.snapshot()).len(), 2);
+        assert!(index.snapshot().segments().len() <= 2);
+
+        index.force_merge().unwrap();
+        let snapshot = index.snapshot();
+        assert_eq!(snapshot.segments().len(), 1);
+        assert!(snapshot.segments()[0].deleted().is_empty());
+        let single = Corpus::new(
+            vec![Document::new("c.txt", "rust rust python"), Document::new("b.txt", "rust memory"), Document::new("d.txt", "go garbage")],
+            ChunkingConfig::default(),
+        );
+        let bm25 = Bm25Scorer { corpus: &single, params: Bm25Params::default() };
+        let expected: Vec<(String, Score)> = single
+            .search_with("rust garbage", &bm25)
+            .unwrap()
+            .iter()
+            .map(|r| (single.path(r.doc).unwrap().to_string(), r.score))
+            .collect();
+        let actual: Vec<(String, Score)> = snapshot
+            .search("rust garbage", Scoring::Bm25(Bm25Params::default()), 10)
+            .unwrap()
+            .iter()
+            .map(|r| (snapshot.document(r.doc).unwrap().path.clone(), r.score))
+            .collect();
+        assert_eq!(actual, expected);
+    }
+}
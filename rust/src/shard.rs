@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+use crate::analyzer::Token;
+use crate::bm25::{self, Bm25Params};
+use crate::corpus::{ChunkId, Corpus, DocId};
+use crate::query::{parse_query, Query, QueryError, TermScorer};
+use crate::search::{Score, SearchResult};
+use crate::stats::CorpusStats;
+
+/// Which formula sharded queries are scored with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scoring {
+    /// The default TF-IDF: tf / chunk length * ln(N / df)
+    TfIdf,
+    Bm25(Bm25Params),
+}
+
+impl Scoring {
+    /// Score of one posting against the statistics of the collection it belongs to:
+    /// n_docs chunks, doc_freq of them containing the term and avg_dl terms per chunk on average
+    pub(crate) fn posting_score(self, tf: Score, length: Score, n_docs: usize, doc_freq: usize, avg_dl: Score) -> Score {
+        match self {
+            Scoring::TfIdf if doc_freq == 0 => 0.0,
+            Scoring::TfIdf => tf / length * (n_docs as Score / doc_freq as Score).ln(),
+            Scoring::Bm25(params) => {
+                let length_norm = 1.0 - params.b + params.b * length / avg_dl;
+                bm25::idf_bm25(n_docs, doc_freq) * tf * (params.k1 + 1.0) / (tf + params.k1 * length_norm)
+            }
+        }
+    }
+}
+
+/// A corpus split into shards that are queried in parallel, one thread per shard
+// Each shard only sees its own documents, so scoring with the shard's own N, df and average
+// length would make scores from different shards incomparable. Every shard therefore scores
+// with the statistics of the whole corpus, and the per-shard top k can be merged by score
+pub struct ShardedCorpus {
+    shards: Vec<Corpus>,
+    stats: Arc<CorpusStats>,
+}
+
+impl ShardedCorpus {
+    /// Split a corpus into at most shard_count shards, whole documents at a time
+    pub fn new(corpus: &Corpus, shard_count: usize) -> ShardedCorpus {
+        let shard_count = shard_count.clamp(1, corpus.documents().len().max(1));
+        let mut assignments = vec![HashSet::new(); shard_count];
+        // Round-robin keeps the shards about the same size without looking at document lengths
+        for (position, document) in corpus.documents().iter().enumerate() {
+            assignments[position % shard_count].insert(document.id);
+        }
+        let shards = assignments.iter().map(|docs: &HashSet<DocId>| corpus.subset(docs)).collect();
+        ShardedCorpus { shards, stats: corpus.stats() }
+    }
+
+    pub fn shards(&self) -> &[Corpus] {
+        &self.shards
+    }
+
+    /// Run the query on every shard in parallel and merge the top results
+    /// Ids in the results are those of the corpus the shards were cut from
+    pub fn search(&self, query: &str, scoring: Scoring, top: usize) -> Result<Vec<SearchResult>, QueryError> {
+        let query = parse_query(query)?;
+        // thread::scope lets the threads borrow the shards and the query, and joins them all
+        // before it returns, so no Arc or 'static lifetime is needed
+        let per_shard: Vec<Vec<SearchResult>> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .shards
+                .iter()
+                .map(|shard| {
+                    let query = &query;
+                    scope.spawn(move || search_with_stats(shard, query, &self.stats, scoring, top))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let mut results: Vec<SearchResult> = per_shard.into_iter().flatten().collect();
+        // The same order rank() uses within a shard: score, then chunk id
+        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap().then(a.chunk.cmp(&b.chunk)));
+        results.truncate(top);
+        Ok(results)
+    }
+}
+
+/// The top results of a query on a corpus that is part of a larger collection, scored with the
+/// statistics of that collection
+pub(crate) fn search_with_stats(corpus: &Corpus, query: &Query, stats: &CorpusStats, scoring: Scoring, top: usize) -> Vec<SearchResult> {
+    let scorer = ShardScorer { shard: corpus, stats, scoring };
+    let terms = query.positive_terms(&scorer);
+    let mut ranked = corpus.rank_boosted(query.evaluate(&scorer));
+    ranked.truncate(top);
+    corpus.to_results(ranked, &terms)
+}
+
+// Scores one shard's postings with the statistics of the whole corpus
+struct ShardScorer<'a> {
+    shard: &'a Corpus,
+    stats: &'a CorpusStats,
+    scoring: Scoring,
+}
+
+impl TermScorer for ShardScorer<'_> {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        self.shard.analyzer().tokens(text)
+    }
+
+    fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)> {
+        let index = self.shard.index();
+        index
+            .postings(term)
+            .iter()
+            .map(|posting| {
+                let tf = posting.tf as Score;
+                let length = index.chunk_len(posting.chunk) as Score;
+                let score = self.scoring.posting_score(tf, length, self.stats.n_docs, self.stats.doc_freq(term), self.stats.avg_dl);
+                (posting.chunk, score)
+            })
+            .collect()
+    }
+
+    fn positions(&self, term: &str, chunk: ChunkId) -> Vec<u32> {
+        self.shard.index().positions(term, chunk).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bm25::Bm25Scorer;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_sharded_search_matches_single_corpus() {
+        let documents: Vec<Document> = (0..20)
+            .map(|i| Document::new(&format!("{}.txt", i), &format!("rust {} garbage {}", "borrow ".repeat(i % 4), i)))
+            .collect();
+        let corpus = Corpus::new(documents, ChunkingConfig::default());
+        let sharded = ShardedCorpus::new(&corpus, 3);
+        assert_eq!(sharded.shards().len(), 3);
+
+        let expected = corpus.search_with("borrow rust", &Bm25Scorer { corpus: &corpus, params: Bm25Params::default() }).unwrap();
+        let results = sharded.search("borrow rust", Scoring::Bm25(Bm25Params::default()), 5).unwrap();
+        let summary = |results: &[SearchResult]| -> Vec<(Option<ChunkId>, i32)> {
+            results.iter().map(|r| (r.chunk, (r.score * 1e4).round() as i32)).collect()
+        };
+        assert_eq!(summary(&results), summary(&expected[..5]));
+
+        // "rust" is in every document, so only the chunks with "borrow" score under TF-IDF
+        let tfidf = sharded.search("borrow rust", Scoring::TfIdf, 3).unwrap();
+        assert_eq!(summary(&tfidf), summary(&corpus.search("borrow rust").unwrap()[..3]));
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use crate::corpus::{ChunkId, Corpus};
+use crate::eval::result_key;
+use crate::tfidf::{unit_vectors, TfIdfParams};
+
+// Cosine similarity between chunks, as an edge list that graph tools (networkx, Gephi) read
+// directly. Comparing every chunk with every other one is quadratic, but two chunks without a
+// shared term have similarity 0, so each chunk is only compared with the chunks that share a term
+// with it, found through term -> chunks lists the same way the inverted index finds documents
+
+/// Two chunks and the cosine similarity of their TF-IDF vectors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub source: ChunkId,
+    pub target: ChunkId,
+    pub similarity: f32,
+}
+
+/// Pairs of chunks with a similarity of at least min_similarity, which should be above 0
+/// With neighbors, the most similar chunks of every chunk, one directed edge each, most similar first.
+/// Without, every pair once with the lower chunk id as source
+pub fn similarity_edges(corpus: &Corpus, neighbors: Option<usize>, min_similarity: f32) -> Vec<Edge> {
+    let vectors = unit_vectors(corpus, TfIdfParams::default()).vectors;
+    let mut chunks_with_term: HashMap<usize, Vec<(usize, f32)>> = HashMap::new();
+    for (position, (_, vector)) in vectors.iter().enumerate() {
+        for (term, weight) in vector {
+            chunks_with_term.entry(*term).or_default().push((position, *weight));
+        }
+    }
+
+    let mut edges = Vec::new();
+    for (position, (source, vector)) in vectors.iter().enumerate() {
+        let mut dots: HashMap<usize, f32> = HashMap::new();
+        for (term, weight) in vector {
+            for (other, other_weight) in &chunks_with_term[term] {
+                *dots.entry(*other).or_insert(0.0) += weight * other_weight;
+            }
+        }
+        let mut similar: Vec<Edge> = dots
+            .into_iter()
+            // Each pair once unless every chunk gets its own neighbor list
+            .filter(|(other, _)| if neighbors.is_some() { *other != position } else { *other > position })
+            .filter(|(_, similarity)| *similarity >= min_similarity)
+            .map(|(other, similarity)| Edge { source: *source, target: vectors[other].0, similarity })
+            .collect();
+        similar.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap().then(a.target.cmp(&b.target)));
+        if let Some(neighbors) = neighbors {
+            similar.truncate(neighbors);
+        }
+        edges.extend(similar);
+    }
+    edges
+}
+
+/// Write edges as CSV with columns source, target, similarity, chunks named path#index
+pub fn write_edges_csv(corpus: &Corpus, edges: &[Edge], mut out: impl Write) -> io::Result<()> {
+    writeln!(out, "source,target,similarity")?;
+    let key = |id: ChunkId| {
+        let key = result_key(corpus, id).unwrap_or_default();
+        // Quote the way CSV readers expect if a path contains a comma or a quote
+        if key.contains([',', '"']) { format!("\"{}\"", key.replace('"', "\"\"")) } else { key }
+    };
+    for edge in edges {
+        writeln!(out, "{},{},{:.6}", key(edge.source), key(edge.target), edge.similarity)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_similarity_edges() {
+        let corpus = Corpus::new(
+            vec![
+                Document::new("a.txt", "rust borrow checker"),
+                Document::new("b.txt", "rust borrow lifetimes"),
+                Document::new("c.txt", "python garbage collector"),
+                Document::new("d.txt", "python garbage pauses"),
+            ],
+            ChunkingConfig::default(),
+        );
+        let all = similarity_edges(&corpus, None, 0.01);
+        let pairs: Vec<(u32, u32)> = all.iter().map(|e| (e.source.0, e.target.0)).collect();
+        assert_eq!(pairs, [(0, 1), (2, 3)]);
+        assert!(all.iter().all(|e| e.similarity > 0.0 && e.similarity < 1.0));
+
+        let nearest = similarity_edges(&corpus, Some(1), 0.01);
+        assert_eq!(nearest.len(), 4);
+        assert_eq!(nearest[1], Edge { source: ChunkId(1), target: ChunkId(0), similarity: all[0].similarity });
+
+        let mut csv = Vec::new();
+        write_edges_csv(&corpus, &all, &mut csv).unwrap();
+        assert!(String::from_utf8(csv).unwrap().starts_with("source,target,similarity\na.txt#0,b.txt#0,0."));
+    }
+}
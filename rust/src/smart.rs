@@ -0,0 +1,137 @@
+use crate::search::Score;
+use crate::tfidf::{IdfScheme, LengthNorm, TfIdfParams, TfScheme};
+
+/// Slope SMART's pivoted unique normalization (u) is defined with
+pub const SMART_PIVOT_SLOPE: Score = 0.2;
+
+// SMART notation writes a weighting as ddd.qqq: three letters for the document side and three
+// for the query side, each naming the tf, idf and normalization component in that order.
+// lnc.ltc for example is log tf, no idf, cosine for documents and log tf, idf, cosine for queries
+//   tf:   n natural, l logarithm, a augmented, b boolean, L log average
+//   idf:  n none, t ln(N/df), p probabilistic
+//   norm: n none, c cosine, u pivoted unique
+
+/// Parse SMART notation such as "lnc.ltc" into TF-IDF params
+pub fn parse_smart(notation: &str) -> Result<TfIdfParams, String> {
+    let (document, query) = notation
+        .split_once('.')
+        .ok_or_else(|| format!("'{}' is not SMART notation, expected ddd.qqq such as lnc.ltc", notation))?;
+    let (tf, idf, norm) = parse_triple(document)?;
+    let (query_tf, query_idf, query_norm) = parse_triple(query)?;
+    if !matches!(query_norm, LengthNorm::None | LengthNorm::Cosine) {
+        return Err(format!("query normalization must be n or c, found '{}'", query));
+    }
+    Ok(TfIdfParams { tf, idf, norm, query_tf, query_idf, query_norm })
+}
+
+/// The SMART notation of params, None when they use a variant SMART has no letter for
+pub fn smart_notation(params: &TfIdfParams) -> Option<String> {
+    Some(format!(
+        "{}{}{}.{}{}{}",
+        tf_letter(params.tf),
+        idf_letter(params.idf)?,
+        norm_letter(params.norm)?,
+        tf_letter(params.query_tf),
+        idf_letter(params.query_idf)?,
+        norm_letter(params.query_norm)?
+    ))
+}
+
+fn parse_triple(triple: &str) -> Result<(TfScheme, IdfScheme, LengthNorm), String> {
+    let letters: Vec<char> = triple.chars().collect();
+    let [tf, idf, norm] = letters.as_slice() else {
+        return Err(format!("'{}' should be three letters: tf, idf and normalization", triple));
+    };
+    let tf = match tf {
+        'n' => TfScheme::Raw,
+        'l' => TfScheme::Log,
+        'a' => TfScheme::Augmented,
+        'b' => TfScheme::Binary,
+        'L' => TfScheme::LogAverage,
+        other => return Err(format!("unknown tf letter '{}', expected n, l, a, b or L", other)),
+    };
+    let idf = match idf {
+        'n' => IdfScheme::None,
+        't' => IdfScheme::Plain,
+        'p' => IdfScheme::Prob,
+        other => return Err(format!("unknown idf letter '{}', expected n, t or p", other)),
+    };
+    let norm = match norm {
+        'n' => LengthNorm::None,
+        'c' => LengthNorm::Cosine,
+        'u' => LengthNorm::PivotedUnique { slope: SMART_PIVOT_SLOPE },
+        'b' => return Err("byte size normalization (b) is not supported".to_string()),
+        other => return Err(format!("unknown normalization letter '{}', expected n, c or u", other)),
+    };
+    Ok((tf, idf, norm))
+}
+
+fn tf_letter(tf: TfScheme) -> char {
+    match tf {
+        TfScheme::Raw => 'n',
+        TfScheme::Log => 'l',
+        TfScheme::Augmented => 'a',
+        TfScheme::Binary => 'b',
+        TfScheme::LogAverage => 'L',
+    }
+}
+
+fn idf_letter(idf: IdfScheme) -> Option<char> {
+    match idf {
+        IdfScheme::None => Some('n'),
+        IdfScheme::Plain => Some('t'),
+        IdfScheme::Prob => Some('p'),
+        IdfScheme::Smooth => None,
+    }
+}
+
+fn norm_letter(norm: LengthNorm) -> Option<char> {
+    match norm {
+        LengthNorm::None => Some('n'),
+        LengthNorm::Cosine => Some('c'),
+        LengthNorm::PivotedUnique { slope } if slope == SMART_PIVOT_SLOPE => Some('u'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::{Corpus, Document};
+    use crate::tfidf::TfIdfScorer;
+
+    #[test]
+    fn test_parse_smart() {
+        let params = parse_smart("lnc.ltc").unwrap();
+        assert_eq!(params.tf, TfScheme::Log);
+        assert_eq!(params.idf, IdfScheme::None);
+        assert_eq!(params.norm, LengthNorm::Cosine);
+        assert_eq!(params.query_idf, IdfScheme::Plain);
+        for notation in ["lnc.ltc", "bnn.ntc", "Lnu.ltc", "apc.atn"] {
+            assert_eq!(smart_notation(&parse_smart(notation).unwrap()).as_deref(), Some(notation));
+        }
+        // The default weighting divides by chunk length, which SMART has no letter for
+        assert_eq!(smart_notation(&TfIdfParams::default()), None);
+        assert!(parse_smart("lnc").is_err());
+        assert!(parse_smart("lxc.ltc").is_err());
+        assert!(parse_smart("lnc.ltu").is_err());
+    }
+
+    #[test]
+    fn test_cosine_scores_are_cosine_similarity() {
+        let files = vec![
+            Document::new("a.txt", "rust rust borrow"),
+            Document::new("b.txt", "python garbage collector"),
+            Document::new("c.txt", "rust garbage"),
+        ];
+        let corpus = Corpus::new(files, ChunkingConfig::default());
+        // nnc.nnc is plain cosine similarity of count vectors
+        let scorer = TfIdfScorer::new(&corpus, parse_smart("nnc.nnc").unwrap());
+        let ranked = corpus.rank_with("rust borrow", &scorer).unwrap();
+        // a = (2, 1) over (rust, borrow), query = (1, 1): (2 + 1) / (sqrt(5) * sqrt(2))
+        assert!((ranked[0].1 - 3.0 / (Score::sqrt(5.0) * Score::sqrt(2.0))).abs() < 1e-6);
+        // A query that matches the chunk exactly scores 1
+        assert!((corpus.rank_with("rust garbage", &scorer).unwrap()[0].1 - 1.0).abs() < 1e-6);
+    }
+}
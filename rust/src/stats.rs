@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::analyzer::Analyzer;
+use crate::bm25;
+use crate::inverted_index::InvertedIndex;
+use crate::persist::IndexFileError;
+use crate::search::Score;
+
+/// The collection-wide numbers TF-IDF and BM25 need, without the postings
+// Scoring a document only needs N, the average length and the document frequency of the
+// query terms, so these few numbers are enough to score text that was never indexed.
+// Nothing in here changes after it is built, so an Arc<CorpusStats> can be shared by any
+// number of threads without a lock
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct CorpusStats {
+    /// Number of indexed chunks, the N in the IDF formulas
+    pub n_docs: usize,
+    /// Average chunk length in terms
+    pub avg_dl: Score,
+    /// Number of chunks every term occurs in
+    pub df: HashMap<String, usize>,
+    analyzer_fingerprint: u64,
+    // Like Corpus, the analyzer is handed back by load() after the fingerprint has been checked
+    #[serde(skip)]
+    analyzer: Arc<Analyzer>,
+}
+
+impl CorpusStats {
+    pub fn from_index(index: &InvertedIndex, analyzer: Arc<Analyzer>) -> CorpusStats {
+        CorpusStats {
+            n_docs: index.num_chunks(),
+            avg_dl: index.avg_len(),
+            df: index.terms().map(|term| (term.to_string(), index.doc_freq(term))).collect(),
+            analyzer_fingerprint: analyzer.fingerprint(),
+            analyzer,
+        }
+    }
+
+    /// The statistics of several indexes as if they were one, for the given terms only
+    // A term's df in the whole collection is its df summed over the indexes, they share no chunks
+    pub fn combined(indexes: &[&InvertedIndex], terms: &[String], analyzer: Arc<Analyzer>) -> CorpusStats {
+        let n_docs: usize = indexes.iter().map(|index| index.num_chunks()).sum();
+        let total_len: Score = indexes.iter().map(|index| index.avg_len() * index.num_chunks() as Score).sum();
+        CorpusStats {
+            n_docs,
+            avg_dl: if n_docs == 0 { 0.0 } else { total_len / n_docs as Score },
+            df: terms.iter().map(|term| (term.clone(), indexes.iter().map(|index| index.doc_freq(term)).sum())).collect(),
+            analyzer_fingerprint: analyzer.fingerprint(),
+            analyzer,
+        }
+    }
+
+    /// The analyzer the statistics were collected with, text to score must go through it too
+    pub fn analyzer(&self) -> &Analyzer {
+        &self.analyzer
+    }
+
+    pub fn doc_freq(&self, term: &str) -> usize {
+        self.df.get(term).copied().unwrap_or(0)
+    }
+
+    /// ln(N / df) as TF-IDF uses it, 0 for unknown terms
+    pub fn idf_tfidf(&self, term: &str) -> Score {
+        match self.doc_freq(term) {
+            0 => 0.0,
+            df => (self.n_docs as Score / df as Score).ln(),
+        }
+    }
+
+    pub fn idf_bm25(&self, term: &str) -> Score {
+        bm25::idf_bm25(self.n_docs, self.doc_freq(term))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), IndexFileError> {
+        let json = serde_json::to_string(self).map_err(|e| IndexFileError::Malformed(e.to_string()))?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Load statistics saved with save, refusing them if they were collected with a different analyzer
+    pub fn load(path: &Path, analyzer: Arc<Analyzer>) -> Result<CorpusStats, IndexFileError> {
+        let json = fs::read_to_string(path)?;
+        let mut stats: CorpusStats = serde_json::from_str(&json).map_err(|e| IndexFileError::Malformed(e.to_string()))?;
+        if stats.analyzer_fingerprint != analyzer.fingerprint() {
+            return Err(IndexFileError::AnalyzerMismatch {
+                saved: format!("fingerprint {:016x}", stats.analyzer_fingerprint),
+                current: analyzer.describe(),
+            });
+        }
+        stats.analyzer = analyzer;
+        Ok(stats)
+    }
+}
+
+/// Both scores of one document, so the two formulas can be compared side by side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DocumentScore {
+    pub tfidf: Score,
+    pub bm25: Score,
+}
+
+/// Score text that isn't in the index against a query, using the IDF statistics of an existing corpus
+// For routing or alerting on documents as they arrive: the document is analyzed and counted
+// on the spot, it is never added to the index and doesn't change the statistics
+pub fn score_document(query: &str, text: &str, stats: &CorpusStats) -> DocumentScore {
+    let mut counts: HashMap<String, u32> = HashMap::new();
+    let tokens = stats.analyzer().analyze(text);
+    for term in &tokens {
+        *counts.entry(term.clone()).or_insert(0) += 1;
+    }
+    let length = tokens.len() as Score;
+    let params = bm25::Bm25Params::default();
+
+    let mut query_terms = stats.analyzer().analyze(query);
+    query_terms.sort();
+    query_terms.dedup();
+    let mut score = DocumentScore { tfidf: 0.0, bm25: 0.0 };
+    for term in &query_terms {
+        let Some(&count) = counts.get(term) else {
+            continue;
+        };
+        let tf = count as Score;
+        score.tfidf += tf / length * stats.idf_tfidf(term);
+        // An empty corpus has no average length, treat the document as average
+        let relative_length = if stats.avg_dl > 0.0 { length / stats.avg_dl } else { 1.0 };
+        let length_norm = 1.0 - params.b + params.b * relative_length;
+        score.bm25 += stats.idf_bm25(term) * tf * (params.k1 + 1.0) / (tf + params.k1 * length_norm);
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::{Corpus, Document};
+
+    #[test]
+    fn test_stats_shared_and_saved() {
+        let files = vec![Document::new("a.txt", "rust borrow checker"), Document::new("b.txt", "rust garbage")];
+        let stats = Corpus::new(files, ChunkingConfig::default()).stats();
+        assert_eq!(stats.n_docs, 2);
+        assert_eq!(stats.avg_dl, 2.5);
+        assert_eq!(stats.idf_tfidf("rust"), 0.0);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let stats = Arc::clone(&stats);
+                thread::spawn(move || stats.doc_freq("borrow"))
+            })
+            .collect();
+        assert!(handles.into_iter().all(|h| h.join().unwrap() == 1));
+
+        let path = std::env::temp_dir().join(format!("stats_{}.json", std::process::id()));
+        stats.save(&path).unwrap();
+        assert_eq!(CorpusStats::load(&path, Arc::new(Analyzer::default())).unwrap().doc_freq("rust"), 2);
+        let other = Arc::new(Analyzer::new(crate::analyzer::WhitespaceTokenizer));
+        assert!(CorpusStats::load(&path, other).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_score_document_matches_index_scores() {
+        let files = vec![
+            Document::new("a.txt", "rust borrow checker"),
+            Document::new("b.txt", "rust garbage"),
+            Document::new("c.txt", "python garbage collector"),
+        ];
+        let corpus = Corpus::new(files, ChunkingConfig::default());
+        let stats = corpus.stats();
+
+        // Scoring the text of an indexed chunk gives the same result as the index
+        let indexed = crate::bm25::term_scores_bm25("borrow", corpus.index(), &crate::bm25::Bm25Params::default());
+        let external = score_document("Borrow", "rust borrow checker", &stats);
+        assert!((external.bm25 - indexed[0].1).abs() < 1e-6);
+        assert!(external.tfidf > 0.0);
+
+        let unrelated = score_document("borrow", "nothing relevant here", &stats);
+        assert_eq!(unrelated, DocumentScore { tfidf: 0.0, bm25: 0.0 });
+    }
+}
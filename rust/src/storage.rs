@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// Saving an index is turning it into bytes (persist.rs) and putting the bytes somewhere. The
+// second half is all a backend does: bytes under string keys, like an object store. Keys use /
+// as separator whatever the backend, "shards/0.idx" is a file in a subdirectory on disk and a
+// plain key everywhere else. Backends take &self, so one can be shared between threads behind
+// an Arc, the ones that need to mutate keep their state behind a Mutex
+
+/// Where saved indexes live, see persist::save_corpus_to and persist::load_corpus_from
+pub trait StorageBackend: Send + Sync {
+    /// The bytes stored under key, an io::ErrorKind::NotFound error if there are none
+    fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+    /// Store bytes under key, replacing what was there
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()>;
+    /// Remove key, removing a key that isn't there is not an error
+    fn delete(&self, key: &str) -> io::Result<()>;
+    /// Every key starting with prefix, sorted
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+}
+
+fn not_found(key: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no object '{}'", key))
+}
+
+/// Keys are files under a directory
+#[derive(Debug, Clone)]
+pub struct FileSystem {
+    root: PathBuf,
+}
+
+impl FileSystem {
+    pub fn new(root: impl Into<PathBuf>) -> FileSystem {
+        FileSystem { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        // A key must not climb out of the root directory
+        if key.is_empty() || key.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid key '{}'", key)));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl StorageBackend for FileSystem {
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(key)?)
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write then rename, so a crash while writing leaves the previous version intact. The
+        // temporary name is unique per put: a.idx and a.json, or two threads putting the same key,
+        // must not write into the same file
+        static PUTS: AtomicU64 = AtomicU64::new(0);
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let put = PUTS.fetch_add(1, Ordering::Relaxed);
+        let temporary = path.with_file_name(format!("{}.{}.{}.tmp", name, std::process::id(), put));
+        fs::write(&temporary, bytes)?;
+        fs::rename(&temporary, &path)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)?) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        if self.root.is_dir() {
+            list_files(&self.root, "", &mut keys)?;
+        }
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+// Every file under dir as a key, with / between directory names
+fn list_files(dir: &Path, prefix: &str, keys: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let key = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), &format!("{}/", key), keys)?;
+        } else {
+            keys.push(key);
+        }
+    }
+    Ok(())
+}
+
+/// Keys in a map, gone when the backend is dropped, for tests and short-lived indexes
+#[derive(Debug, Default)]
+pub struct Memory {
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+}
+
+impl StorageBackend for Memory {
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        self.objects.lock().unwrap().get(key).cloned().ok_or_else(|| not_found(key))
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        self.objects.lock().unwrap().insert(key.to_string(), bytes.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        // The map is sorted, the keys with a prefix are one range of it
+        let objects = self.objects.lock().unwrap();
+        Ok(objects.range(prefix.to_string()..).map(|(key, _)| key).take_while(|key| key.starts_with(prefix)).cloned().collect())
+    }
+}
+
+/// Keys are rows of an objects table in an SQLite database
+#[cfg(feature = "sqlite")]
+pub struct Sqlite {
+    // A connection can't be used from two threads at once
+    connection: Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl Sqlite {
+    /// Open or create the database file, ":memory:" for one in memory
+    pub fn open(path: &str) -> io::Result<Sqlite> {
+        let connection = rusqlite::Connection::open(path).map_err(io::Error::other)?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS objects (key TEXT PRIMARY KEY, bytes BLOB NOT NULL)", [])
+            .map_err(io::Error::other)?;
+        Ok(Sqlite { connection: Mutex::new(connection) })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl StorageBackend for Sqlite {
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let connection = self.connection.lock().unwrap();
+        match connection.query_row("SELECT bytes FROM objects WHERE key = ?1", [key], |row| row.get(0)) {
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(not_found(key)),
+            result => result.map_err(io::Error::other),
+        }
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .execute("INSERT OR REPLACE INTO objects (key, bytes) VALUES (?1, ?2)", rusqlite::params![key, bytes])
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute("DELETE FROM objects WHERE key = ?1", [key]).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let connection = self.connection.lock().unwrap();
+        // substr instead of LIKE, a prefix may contain % or _
+        let mut statement = connection
+            .prepare("SELECT key FROM objects WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key")
+            .map_err(io::Error::other)?;
+        let keys = statement.query_map([prefix], |row| row.get(0)).map_err(io::Error::other)?;
+        keys.collect::<Result<Vec<String>, _>>().map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::analyzer::Analyzer;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::{Corpus, Document};
+    use crate::persist::{load_corpus_from, save_corpus_to};
+
+    // The same checks for every backend
+    fn check_backend(backend: &dyn StorageBackend) {
+        backend.put("shards/1.idx", b"one").unwrap();
+        backend.put("shards/0.idx", b"zero").unwrap();
+        backend.put("main.idx", b"main").unwrap();
+        backend.put("main.idx", b"replaced").unwrap();
+        assert_eq!(backend.get("main.idx").unwrap(), b"replaced");
+        assert_eq!(backend.list("shards/").unwrap(), ["shards/0.idx", "shards/1.idx"]);
+        backend.delete("shards/1.idx").unwrap();
+        backend.delete("shards/1.idx").unwrap();
+        assert_eq!(backend.get("shards/1.idx").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(backend.list("").unwrap(), ["main.idx", "shards/0.idx"]);
+    }
+
+    #[test]
+    fn test_backends_store_by_key() {
+        check_backend(&Memory::new());
+        let root = std::env::temp_dir().join(format!("tfidf-storage-{}", std::process::id()));
+        check_backend(&FileSystem::new(&root));
+        assert!(FileSystem::new(&root).get("../escape").is_err());
+        // Keys differing only in extension, put at the same time, each keep their own bytes
+        let files = FileSystem::new(&root);
+        std::thread::scope(|scope| {
+            for key in ["same.idx", "same.json"] {
+                let files = &files;
+                scope.spawn(move || (0..50).for_each(|_| files.put(key, key.as_bytes()).unwrap()));
+            }
+        });
+        assert_eq!(files.get("same.idx").unwrap(), b"same.idx");
+        assert_eq!(files.get("same.json").unwrap(), b"same.json");
+        fs::remove_dir_all(&root).unwrap();
+        #[cfg(feature = "sqlite")]
+        check_backend(&Sqlite::open(":memory:").unwrap());
+
+        let memory = Memory::new();
+        let corpus = Corpus::new(vec![Document::new("a.txt", "rust borrow checker")], ChunkingConfig::default());
+        save_corpus_to(&corpus, &memory, "indexes/a.idx").unwrap();
+        let loaded = load_corpus_from(&memory, "indexes/a.idx", Arc::new(Analyzer::default())).unwrap();
+        assert_eq!(loaded.search_chunks("borrow").len(), 1);
+    }
+}
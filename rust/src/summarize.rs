@@ -0,0 +1,110 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use crate::corpus::{Corpus, DocId};
+use crate::search::{to_f32, SearchResult};
+use crate::tfidf::IdfScheme;
+
+// Extractive summaries: no text is generated, the summary is the few sentences of the document
+// that carry the most TF-IDF weight. Against a query, a sentence's weight comes from the query
+// terms it contains, so the summary shows why the document matched. Without a query, it comes
+// from the terms that are frequent in this document but rare in the corpus, what it is about
+
+/// Split text into sentences at . ! or ? followed by whitespace, and at blank lines
+pub fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        let end = match (c, next) {
+            ('.' | '!' | '?', Some(next)) if next.is_whitespace() => i + c.len_utf8(),
+            ('\n', Some('\n')) => i,
+            _ => continue,
+        };
+        sentences.push(&text[start..end]);
+        start = end;
+    }
+    sentences.push(&text[start..]);
+    sentences.into_iter().map(str::trim).filter(|sentence| !sentence.is_empty()).collect()
+}
+
+/// The n_sentences sentences of the text with the highest TF-IDF weight, in the order they appear
+/// With a query only its terms count, without one every term of the text weighted by its frequency in it
+pub fn summarize(corpus: &Corpus, text: &str, query: Option<&str>, n_sentences: usize) -> Vec<String> {
+    let index = corpus.index();
+    // Smooth IDF, so a term in every chunk of a small corpus still counts for a little
+    let idf = |term: &str| to_f32(IdfScheme::Smooth.idf(index.num_chunks(), index.doc_freq(term)));
+    let sentences: Vec<(&str, Vec<String>)> =
+        split_sentences(text).into_iter().map(|sentence| (sentence, corpus.analyze_query(sentence))).collect();
+
+    let weights: HashMap<String, f32> = match query {
+        Some(query) => {
+            let terms: HashSet<String> = corpus.analyze_query(query).into_iter().collect();
+            terms.into_iter().map(|term| (term.clone(), idf(&term))).collect()
+        }
+        None => {
+            let mut counts: HashMap<String, f32> = HashMap::new();
+            for term in sentences.iter().flat_map(|(_, terms)| terms) {
+                *counts.entry(term.clone()).or_insert(0.0) += 1.0;
+            }
+            let total: f32 = counts.values().sum();
+            counts.into_iter().map(|(term, count)| (term.clone(), count / total * idf(&term))).collect()
+        }
+    };
+
+    // Dividing by the sentence length keeps long sentences from winning just by having more terms
+    let mut scored: Vec<(usize, f32)> = sentences
+        .iter()
+        .enumerate()
+        .map(|(position, (_, terms))| {
+            let weight: f32 = terms.iter().filter_map(|term| weights.get(term)).sum();
+            (position, weight / terms.len().max(1) as f32)
+        })
+        .filter(|(_, score)| *score > 0.0)
+        .collect();
+    scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+    scored.truncate(n_sentences);
+    scored.sort_by_key(|(position, _)| *position);
+    scored.into_iter().map(|(position, _)| sentences[position].0.to_string()).collect()
+}
+
+/// Summary of a document of the corpus, from its own most characteristic terms
+pub fn summarize_document(corpus: &Corpus, id: DocId, n_sentences: usize) -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(summarize(corpus, &corpus.document_text(id)?, None, n_sentences))
+}
+
+/// A summary of every result's document, against the query the results came from
+/// Documents whose text can't be read get an empty summary
+pub fn summarize_results(corpus: &Corpus, results: &[SearchResult], query: &str, n_sentences: usize) -> Vec<Vec<String>> {
+    results
+        .iter()
+        .map(|result| match corpus.document_text(result.doc) {
+            Ok(text) => summarize(corpus, &text, Some(query), n_sentences),
+            Err(_) => Vec::new(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::ChunkingConfig;
+    use crate::corpus::Document;
+
+    #[test]
+    fn test_summarize_picks_weighted_sentences() {
+        assert_eq!(split_sentences("One. Two!\n\nThree 3.5 four?"), ["One.", "Two!", "Three 3.5 four?"]);
+
+        let text = "Rust has a borrow checker. The weather is nice. Borrow rules keep rust memory safe.";
+        let corpus = Corpus::new(
+            vec![Document::new("a.txt", text), Document::new("b.txt", "the weather is nice and the sky is blue")],
+            ChunkingConfig::default(),
+        );
+        let summary = summarize(&corpus, text, Some("borrow"), 2);
+        assert_eq!(summary, ["Rust has a borrow checker.", "Borrow rules keep rust memory safe."]);
+        // Without a query, terms only in a.txt outweigh the ones it shares with b.txt
+        let summary = summarize_document(&corpus, DocId(0), 1).unwrap();
+        assert_ne!(summary, ["The weather is nice."]);
+        assert_eq!(summary.len(), 1);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use proptest::prelude::*;
+use proptest::sample::select;
+use crate::chunker::{chunk_with_config, ChunkStrategy, ChunkingConfig};
+use crate::corpus::{ChunkId, Corpus, DocId, Document};
+use crate::eval::result_key;
+use crate::query::TermScorer;
+use crate::search::Score;
+
+// proptest strategies for property based tests of code built on this crate, and the invariants
+// every ranker here should satisfy. A strategy describes how to generate random values, proptest
+// runs a test with a few hundred of them and shrinks any failure to a minimal example
+
+// A small vocabulary so generated documents share terms and queries actually match something
+const WORDS: &[&str] = &[
+    "rust", "python", "search", "index", "query", "score", "term", "chunk", "the", "a", "of", "borrow",
+    "garbage", "collector", "memory", "fast", "slow", "BM25", "tf-idf", "naïve",
+];
+
+/// One word from a small vocabulary, sometimes capitalized
+pub fn word() -> impl Strategy<Value = String> {
+    (select(WORDS), any::<bool>()).prop_map(|(word, upper)| if upper { word.to_uppercase() } else { word.to_string() })
+}
+
+/// Up to 60 words separated by spaces, newlines and runs of whitespace
+pub fn text() -> impl Strategy<Value = String> {
+    prop::collection::vec((word(), select(&[" ", " ", " ", "\n", "  ", "\t"][..])), 0..60)
+        .prop_map(|words| words.into_iter().map(|(word, separator)| word + separator).collect())
+}
+
+/// 1 to 8 documents with distinct paths
+pub fn documents() -> impl Strategy<Value = Vec<Document>> {
+    prop::collection::vec(text(), 1..8).prop_map(|texts| {
+        texts.iter().enumerate().map(|(i, text)| Document::new(&format!("doc{}.txt", i), text)).collect()
+    })
+}
+
+/// Any valid chunking: every strategy, sizes from 1 to 50 units and any overlap smaller than the size
+pub fn chunking() -> impl Strategy<Value = ChunkingConfig> {
+    (select(&[ChunkStrategy::Chars, ChunkStrategy::Words, ChunkStrategy::Lines][..]), 1..50usize)
+        .prop_flat_map(|(strategy, size)| (Just(strategy), Just(size), 0..size))
+        .prop_map(|(strategy, size, overlap)| ChunkingConfig::new(strategy, size, overlap).unwrap())
+}
+
+/// A query using the operators the parser supports: plain terms, OR, negation and phrases
+pub fn query() -> impl Strategy<Value = String> {
+    prop_oneof![
+        prop::collection::vec(word(), 1..4).prop_map(|words| words.join(" ")),
+        (word(), word()).prop_map(|(a, b)| format!("{} OR {}", a, b)),
+        (word(), word()).prop_map(|(a, b)| format!("{} -{}", a, b)),
+        (word(), word(), 0..3u32).prop_map(|(a, b, slop)| format!("\"{} {}\"~{}", a, b, slop)),
+    ]
+}
+
+/// Without overlap, the chunks of a text put back together are the text
+/// A text without a single word is the exception, it has no word chunks at all
+pub fn chunks_reassemble(text: &str, chunking: &ChunkingConfig) -> bool {
+    let config = ChunkingConfig { overlap: 0, ..*chunking };
+    let joined: String = chunk_with_config(text, &config, DocId(0)).iter().map(|chunk| chunk.text.as_str()).collect();
+    joined == text || (config.strategy == ChunkStrategy::Words && text.trim().is_empty() && joined.is_empty())
+}
+
+/// Every score the scorer gives for the query is finite and not negative
+pub fn scores_non_negative(corpus: &Corpus, query: &str, scorer: &dyn TermScorer) -> bool {
+    match corpus.rank_with(query, scorer) {
+        Ok(ranked) => ranked.iter().all(|(_, score)| score.is_finite() && *score >= 0.0),
+        // Malformed queries are the parser's business, not a ranking invariant
+        Err(_) => true,
+    }
+}
+
+/// Building the corpus with the documents in reverse order gives every chunk the same score
+// Ids change with the order, so chunks are compared by path#index, and scores within float noise
+pub fn ranking_independent_of_order<F>(documents: &[Document], chunking: ChunkingConfig, query: &str, rank: F) -> bool
+where
+    F: Fn(&Corpus, &str) -> Vec<(ChunkId, Score)>,
+{
+    let keyed = |corpus: &Corpus| -> HashMap<String, Score> {
+        rank(corpus, query).into_iter().filter_map(|(chunk, score)| Some((result_key(corpus, chunk)?, score))).collect()
+    };
+    let forward = keyed(&Corpus::new(documents.to_vec(), chunking));
+    let reversed = keyed(&Corpus::new(documents.iter().rev().cloned().collect(), chunking));
+    forward.len() == reversed.len()
+        && forward.iter().all(|(key, score)| reversed.get(key).is_some_and(|other| (score - other).abs() <= 1e-5 * score.abs().max(1.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bm25::{Bm25Params, Bm25Scorer};
+    use crate::tfidf::{TfIdfParams, TfIdfScorer};
+
+    proptest! {
+        #[test]
+        fn test_chunks_reassemble(text in text(), chunking in chunking()) {
+            prop_assert!(chunks_reassemble(&text, &chunking));
+        }
+
+        #[test]
+        fn test_scores_non_negative_and_order_independent(documents in documents(), chunking in chunking(), query in query()) {
+            let corpus = Corpus::new(documents.clone(), chunking);
+            let tfidf = TfIdfScorer::new(&corpus, TfIdfParams::default());
+            let bm25 = Bm25Scorer { corpus: &corpus, params: Bm25Params::default() };
+            prop_assert!(scores_non_negative(&corpus, &query, &tfidf));
+            prop_assert!(scores_non_negative(&corpus, &query, &bm25));
+
+            let rank_bm25 = |corpus: &Corpus, query: &str| {
+                corpus.rank_with(query, &Bm25Scorer { corpus, params: Bm25Params::default() }).unwrap_or_default()
+            };
+            prop_assert!(ranking_independent_of_order(&documents, chunking, &query, rank_bm25));
+        }
+    }
+}
@@ -1,11 +1,19 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use indicatif::{ProgressBar, ProgressStyle};
+use rustc_hash::FxHashMap;
+use crate::analyzer::Token;
 use crate::chunker::Chunk;
+use crate::corpus::{ChunkId, Corpus};
+use crate::inverted_index::{ChunkTerms, InvertedIndex};
+use crate::kernel;
+use crate::query::TermScorer;
+use crate::search::{to_f32, Score, SearchResult};
 
 
 /// Calculate term frequency: how often does this term appear in this text?
 /// Returns a value between 0.0 and 1.0
-pub fn term_frequency(term: &str, text: &str) -> f32 {
+pub fn term_frequency(term: &str, text: &str) -> Score {
 
     if text.is_empty() || term.is_empty() {
         return 0.0;
@@ -21,35 +29,35 @@ pub fn term_frequency(term: &str, text: &str) -> f32 {
             let cleaned = w.trim_end_matches(|c: char| !c.is_alphanumeric());
             cleaned.to_lowercase().contains(term_lower.as_str())
         })
-        .count() as f32;
-    count / words.len() as f32 // Normalize by document length
+        .count() as Score;
+    count / words.len() as Score // Normalize by document length
 }
 
 /// Calculate inverse document frequency: how rare is this term across all chunks?
 /// Returns higher values for rarer terms
-pub fn inverse_document_frequency(term: &str, chunks: &[Chunk]) -> f32 {
+pub fn inverse_document_frequency(term: &str, chunks: &[Chunk]) -> Score {
     let term_lower = term.to_lowercase();
 
     let chunks_with_term = chunks
         .iter()
         .filter(|chunk| chunk.text.to_lowercase().contains(&term_lower))
-        .count() as f32;
+        .count() as Score;
 
     if chunks_with_term == 0.0 {
         // Term doesn't appear anywhere - return high but finite IDF
-        return ((chunks.len() as f32) / 1.0).ln();
+        return ((chunks.len() as Score) / 1.0).ln();
     }
 
-    ((chunks.len() as f32) / chunks_with_term).ln()
+    ((chunks.len() as Score) / chunks_with_term).ln()
 }
 
 /// Calculate TF-IDF score for a term in a specific chunk
-pub fn tfidf_score(term: &str, chunk: &Chunk, all_chunks: &[Chunk]) -> f32 {
+pub fn tfidf_score(term: &str, chunk: &Chunk, all_chunks: &[Chunk]) -> Score {
     term_frequency(term, &chunk.text) * inverse_document_frequency(term, all_chunks)
 }
 
 /// Score chunks using TF-IDF for a multi-word query
-pub fn score_chunks_tfidf(query: &str, chunks: &[Chunk]) -> Vec<(Chunk, f32)> {
+pub fn score_chunks_tfidf(query: &str, chunks: &[Chunk]) -> Vec<SearchResult> {
     let query_terms: Vec<&str> = query.split_whitespace().collect();
     let pb = ProgressBar::new(chunks.len() as u64);
     pb.set_style(
@@ -59,17 +67,17 @@ pub fn score_chunks_tfidf(query: &str, chunks: &[Chunk]) -> Vec<(Chunk, f32)> {
             .progress_chars("#>-")
     );
     // Pre-calculate IDFs for performance (this is the key improvement)
-    let mut term_idfs: HashMap<&str, f32> = HashMap::new();
+    let mut term_idfs: FxHashMap<&str, Score> = FxHashMap::default();
     for term in &query_terms {
         term_idfs.insert(term, inverse_document_frequency(term, chunks));
     }
 
-    let mut scored_chunks: Vec<(Chunk, f32)> = chunks
+    let mut scored_chunks: Vec<(&Chunk, Score)> = chunks
         .iter()
         .map(|chunk| {
             pb.inc(1);
             // Sum TF-IDF scores for all query terms
-            let score: f32 = query_terms
+            let score: Score = query_terms
                 .iter()
                 .map(|term| {
                     let tf = term_frequency(term, &chunk.text);
@@ -77,7 +85,7 @@ pub fn score_chunks_tfidf(query: &str, chunks: &[Chunk]) -> Vec<(Chunk, f32)> {
                     tf * idf
                 })
                 .sum();
-            (chunk.clone(), score) // Clone instead of borrowing
+            (chunk, score) // Borrow the chunk, results only keep its id
         })
         .filter(|(_, score)| *score > 0.0)  // Only keep chunks with positive scores
         .collect();
@@ -85,19 +93,348 @@ pub fn score_chunks_tfidf(query: &str, chunks: &[Chunk]) -> Vec<(Chunk, f32)> {
     // Sort by score, highest first
     scored_chunks.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
     scored_chunks
+        .into_iter()
+        .map(|(chunk, score)| SearchResult::from_chunk(chunk, score, &query_terms))
+        .collect()
+}
+
+/// How the term frequency part of TF-IDF is computed, before length normalization
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TfScheme {
+    /// Raw count of the term in the chunk
+    #[default]
+    Raw,
+    /// 1 + ln(count), dampens repeated terms
+    Log,
+    /// 1 if the term occurs at all
+    Binary,
+    /// 0.5 + 0.5 * count / count of the chunk's most frequent term
+    Augmented,
+    /// (1 + ln(count)) / (1 + ln(average count of the chunk's terms))
+    LogAverage,
+}
+
+impl TfScheme {
+    /// Weight of a term occurring tf times in a chunk (or query) with the given term counts
+    pub fn weight(&self, tf: u32, terms: &ChunkTerms) -> Score {
+        if tf == 0 {
+            return 0.0;
+        }
+        let count = tf as Score;
+        match self {
+            TfScheme::Raw => count,
+            TfScheme::Log => 1.0 + count.ln(),
+            TfScheme::Binary => 1.0,
+            TfScheme::Augmented => 0.5 + 0.5 * count / terms.max_tf.max(1) as Score,
+            TfScheme::LogAverage => {
+                let avg_tf = terms.len as Score / terms.unique.max(1) as Score;
+                (1.0 + count.ln()) / (1.0 + avg_tf.ln())
+            }
+        }
+    }
+}
+
+/// How the term frequency is adjusted for the length of the chunk
+// Without normalization long chunks win simply by containing more words; dividing by the full
+// length overcorrects and favours very short chunks. Pivoted normalization sits in between,
+// and is the same idea as BM25's b parameter
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LengthNorm {
+    /// Use the term frequency as it is
+    None,
+    /// Divide by the number of terms in the chunk, what score_chunks_tfidf does
+    #[default]
+    Length,
+    /// Divide by (1 - slope) * average length + slope * chunk length
+    /// slope 1 is the same as Length, slope 0 ignores length altogether
+    Pivoted { slope: Score },
+    /// Divide by the Euclidean length of the chunk's whole weight vector, the classic vector space model
+    Cosine,
+    /// Like Pivoted, counting distinct terms instead of all terms (SMART's u)
+    PivotedUnique { slope: Score },
+}
+
+impl LengthNorm {
+    /// What the term weights of a chunk with these term counts are divided by
+    /// Cosine needs every term of the chunk and is computed by cosine_norms instead, here it is 1
+    pub fn divisor(&self, terms: &ChunkTerms, index: &InvertedIndex) -> Score {
+        match self {
+            LengthNorm::None | LengthNorm::Cosine => 1.0,
+            LengthNorm::Length => terms.len as Score,
+            LengthNorm::Pivoted { slope } => (1.0 - slope) * index.avg_len() + slope * terms.len as Score,
+            LengthNorm::PivotedUnique { slope } => (1.0 - slope) * index.avg_unique() + slope * terms.unique as Score,
+        }
+    }
+}
+
+/// How the inverse document frequency part of TF-IDF is computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum IdfScheme {
+    /// No IDF, every term weighs 1
+    None,
+    /// ln(N / df), zero for terms that are in every chunk
+    #[default]
+    Plain,
+    /// ln(1 + N / df), terms in every chunk still count a little
+    Smooth,
+    /// max(0, ln((N - df) / df)), the probabilistic IDF, zero for terms in half the chunks or more
+    Prob,
+}
+
+impl IdfScheme {
+    /// The IDF of a term that occurs in doc_freq of num_chunks chunks, 0 for unknown terms
+    pub fn idf(&self, num_chunks: usize, doc_freq: usize) -> Score {
+        if doc_freq == 0 {
+            return 0.0;
+        }
+        let (n, df) = (num_chunks as Score, doc_freq as Score);
+        match self {
+            IdfScheme::None => 1.0,
+            IdfScheme::Plain => (n / df).ln(),
+            IdfScheme::Smooth => (1.0 + n / df).ln(),
+            IdfScheme::Prob => ((n - df) / df).ln().max(0.0),
+        }
+    }
+}
+
+/// The TF-IDF variant to score with, the default matches the original formula
+// The document side (tf, idf, norm) weighs the chunk, the query side weighs the query's own
+// terms; SMART notation such as lnc.ltc names exactly these six choices, see smart::parse_smart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TfIdfParams {
+    pub tf: TfScheme,
+    pub idf: IdfScheme,
+    pub norm: LengthNorm,
+    /// How a term repeated in the query is weighted, Raw sums every repetition
+    pub query_tf: TfScheme,
+    /// IDF applied a second time on the query side, None by default
+    pub query_idf: IdfScheme,
+    /// Only None and Cosine make sense for a query, other values are treated as None
+    pub query_norm: LengthNorm,
+}
+
+impl Default for TfIdfParams {
+    fn default() -> TfIdfParams {
+        TfIdfParams {
+            tf: TfScheme::Raw,
+            idf: IdfScheme::Plain,
+            norm: LengthNorm::Length,
+            query_tf: TfScheme::Raw,
+            query_idf: IdfScheme::None,
+            query_norm: LengthNorm::None,
+        }
+    }
+}
+
+impl TfIdfParams {
+    /// Weights of the distinct query terms, given how often each occurs in the query
+    pub fn query_weights(&self, counts: &[(String, u32)], index: &InvertedIndex) -> Vec<Score> {
+        // The query is treated like a tiny chunk, so augmented and log-average tf work the same way
+        let terms = ChunkTerms {
+            len: counts.iter().map(|(_, c)| c).sum(),
+            unique: counts.len() as u32,
+            max_tf: counts.iter().map(|(_, c)| *c).max().unwrap_or(0),
+        };
+        let weights: Vec<Score> = counts
+            .iter()
+            .map(|(term, count)| {
+                let idf = match self.query_idf {
+                    // Unknown terms match nothing anyway, weigh them 1 so they don't vanish from the norm
+                    IdfScheme::None => 1.0,
+                    scheme => index.idf(term, scheme),
+                };
+                self.query_tf.weight(*count, &terms) * idf
+            })
+            .collect();
+        if self.query_norm != LengthNorm::Cosine {
+            return weights;
+        }
+        let length = weights.iter().map(|w| w * w).sum::<Score>().sqrt();
+        if length == 0.0 {
+            return weights;
+        }
+        weights.iter().map(|w| w / length).collect()
+    }
+}
+
+/// TF-IDF contribution of one analyzed term to every chunk that contains it
+pub fn term_scores_tfidf(term: &str, index: &InvertedIndex) -> Vec<(ChunkId, Score)> {
+    term_scores_tfidf_with(term, index, &TfIdfParams::default())
+}
+
+/// Like term_scores_tfidf, with a choice of TF, IDF and length normalization
+// Cosine normalization visits the whole index once, then the norms are cached on the index
+pub fn term_scores_tfidf_with(term: &str, index: &InvertedIndex, params: &TfIdfParams) -> Vec<(ChunkId, Score)> {
+    let norms = (params.norm == LengthNorm::Cosine).then(|| cached_cosine_norms(index, params));
+    weighted_postings(term, index, params, norms.as_deref())
+}
+
+fn weighted_postings(
+    term: &str,
+    index: &InvertedIndex,
+    params: &TfIdfParams,
+    cosine: Option<&HashMap<ChunkId, Score>>,
+) -> Vec<(ChunkId, Score)> {
+    let postings = index.postings(term);
+    if postings.is_empty() {
+        return Vec::new();
+    }
+    let idf = index.idf(term, params.idf);
+    let (weights, divisors): (Vec<Score>, Vec<Score>) = postings
+        .iter()
+        .map(|posting| {
+            let terms = index.chunk_terms(posting.chunk);
+            let divisor = match cosine {
+                Some(norms) => norms.get(&posting.chunk).copied().filter(|n| *n > 0.0).unwrap_or(1.0),
+                None => params.norm.divisor(&terms, index),
+            };
+            (params.tf.weight(posting.tf, &terms), divisor)
+        })
+        .unzip();
+    let scores = kernel::tfidf_scores(&weights, &divisors, idf);
+    postings.iter().map(|posting| posting.chunk).zip(scores).collect()
+}
+
+/// Euclidean length of every chunk's vector of tf * idf weights
+pub fn cosine_norms(index: &InvertedIndex, params: &TfIdfParams) -> HashMap<ChunkId, Score> {
+    let mut squares: HashMap<ChunkId, Score> = HashMap::new();
+    for term in index.terms() {
+        let postings = index.postings(term);
+        let idf = index.idf(term, params.idf);
+        for posting in postings {
+            let weight = params.tf.weight(posting.tf, &index.chunk_terms(posting.chunk)) * idf;
+            *squares.entry(posting.chunk).or_insert(0.0) += weight * weight;
+        }
+    }
+    squares.into_iter().map(|(chunk, square)| (chunk, square.sqrt())).collect()
+}
+
+// cosine_norms through the index's cache, they only depend on the tf and idf schemes
+fn cached_cosine_norms(index: &InvertedIndex, params: &TfIdfParams) -> Arc<HashMap<ChunkId, Score>> {
+    let key = (params.tf, params.idf);
+    if let Some(norms) = index.cache().cosine.read().unwrap().get(&key) {
+        return Arc::clone(norms);
+    }
+    let norms = Arc::new(cosine_norms(index, params));
+    index.cache().cosine.write().unwrap().insert(key, Arc::clone(&norms));
+    norms
+}
+
+/// The chunk-term matrix of TF-IDF weights in sparse form, one (chunk, term, weight) per posting
+// Sorted by chunk and then term, so exports are reproducible and rows of one chunk are adjacent
+pub fn document_term_matrix(corpus: &Corpus, params: TfIdfParams) -> Vec<(ChunkId, String, Score)> {
+    let scorer = TfIdfScorer::new(corpus, params);
+    let mut entries: Vec<(ChunkId, String, Score)> = corpus
+        .index()
+        .terms()
+        .flat_map(|term| scorer.score_term(term).into_iter().map(move |(chunk, weight)| (chunk, term.to_string(), weight)))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+    entries
+}
+
+/// The TF-IDF vector of every chunk scaled to length 1, see unit_vectors
+pub struct ChunkVectors {
+    /// Every term of the vocabulary, sorted, vectors refer to terms by position in this list
+    pub terms: Vec<String>,
+    /// (chunk, [(term position, weight)]) in chunk id order, term positions increasing
+    pub vectors: Vec<(ChunkId, Vec<(usize, f32)>)>,
+}
+
+/// Chunk vectors of unit length, so the cosine similarity of two chunks is their dot product
+// Chunks whose every term is in every chunk have no weight at all and get an empty vector
+pub fn unit_vectors(corpus: &Corpus, params: TfIdfParams) -> ChunkVectors {
+    let mut terms: Vec<String> = corpus.index().terms().map(str::to_string).collect();
+    terms.sort();
+    let mut vectors: Vec<(ChunkId, Vec<(usize, f32)>)> = corpus.chunks().iter().map(|chunk| (chunk.id, Vec::new())).collect();
+    // Both are sorted by chunk, so one pass over the matrix fills the vectors in order
+    let mut position = 0;
+    for (chunk, term, weight) in document_term_matrix(corpus, params) {
+        while vectors[position].0 != chunk {
+            position += 1;
+        }
+        if weight > 0.0 {
+            vectors[position].1.push((terms.binary_search(&term).unwrap(), to_f32(weight)));
+        }
+    }
+    for (_, vector) in &mut vectors {
+        let norm = vector.iter().map(|(_, w)| w * w).sum::<f32>().sqrt();
+        vector.iter_mut().for_each(|(_, w)| *w /= norm);
+    }
+    ChunkVectors { terms, vectors }
+}
+
+/// TF-IDF over an inverted index for already analyzed query terms, highest score first
+// Same formula as score_chunks_tfidf (tf normalized by chunk length, idf = ln(N / df)),
+// but terms match exactly and only chunks that contain a query term are visited
+pub fn score_terms_tfidf(terms: &[String], index: &InvertedIndex) -> Vec<(ChunkId, Score)> {
+    let mut scores: HashMap<ChunkId, Score> = HashMap::new();
+    for term in terms {
+        for (chunk, score) in term_scores_tfidf(term, index) {
+            *scores.entry(chunk).or_insert(0.0) += score;
+        }
+    }
+    rank(scores)
+}
+
+/// Sort chunk scores highest first, dropping chunks that scored zero
+// Score ties are broken by chunk id so the order doesn't depend on HashMap iteration
+pub fn rank(scores: HashMap<ChunkId, Score>) -> Vec<(ChunkId, Score)> {
+    let mut ranked: Vec<(ChunkId, Score)> = scores.into_iter().filter(|(_, score)| *score > 0.0).collect();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+    ranked
+}
+
+/// Query evaluation with TF-IDF term scores
+pub struct TfIdfScorer<'a> {
+    corpus: &'a Corpus,
+    params: TfIdfParams,
+}
+
+impl<'a> TfIdfScorer<'a> {
+    pub fn new(corpus: &'a Corpus, params: TfIdfParams) -> TfIdfScorer<'a> {
+        TfIdfScorer { corpus, params }
+    }
+
+    pub fn params(&self) -> &TfIdfParams {
+        &self.params
+    }
+}
+
+impl TermScorer for TfIdfScorer<'_> {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        self.corpus.analyzer().tokens(text)
+    }
+
+    fn score_term(&self, term: &str) -> Vec<(ChunkId, Score)> {
+        let index = self.corpus.index();
+        // Computed by the first query that asks for cosine normalization, then shared by all of them
+        let cosine = (self.params.norm == LengthNorm::Cosine).then(|| cached_cosine_norms(index, &self.params));
+        weighted_postings(term, index, &self.params, cosine.as_deref())
+    }
+
+    fn positions(&self, term: &str, chunk: ChunkId) -> Vec<u32> {
+        self.corpus.index().positions(term, chunk).to_vec()
+    }
+
+    fn query_weights(&self, counts: &[(String, u32)]) -> Vec<Score> {
+        self.params.query_weights(counts, self.corpus.index())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::corpus::{ChunkId, DocId};
     use std::time::Instant;
 
     // Helper function to create test chunks
     fn create_chunk(text: &str) -> Chunk {
         Chunk {
-            text: text.to_string(),
-            file: "".to_string(),
-            index: 1
+            id: ChunkId(1),
+            doc: DocId(0),
+            index: 1,
+            text: text.into(),
         }
     }
 
@@ -107,9 +444,10 @@ mod tests {
         // Create 1000 test chunks
         let chunks: Vec<Chunk> = (0..10000)
             .map(|i| Chunk {
-                text: format!("test document {}", i),
-                file: "test.txt".to_string(),
-                index: i,
+                id: ChunkId(i as u32),
+                doc: DocId(i as u32),
+                index: 0,
+                text: format!("test document {}", i).into(),
             })
             .collect();
 
@@ -145,4 +483,43 @@ mod tests {
         assert_eq!(score_missing, 0.0);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_length_normalization() {
+        let short = Chunk { id: ChunkId(0), doc: DocId(0), index: 0, text: "rust".into() };
+        let long = Chunk { id: ChunkId(1), doc: DocId(1), index: 0, text: "rust with many more words".into() };
+        let other = Chunk { id: ChunkId(2), doc: DocId(2), index: 0, text: "python".into() };
+        let index = InvertedIndex::build(&[short, long, other], &crate::analyzer::Analyzer::default());
+        let scores = |norm| {
+            let params = TfIdfParams { norm, ..TfIdfParams::default() };
+            let scores = term_scores_tfidf_with("rust", &index, &params);
+            (scores[0].1, scores[1].1)
+        };
+
+        let (short, long) = scores(LengthNorm::None);
+        assert_eq!(short, long);
+        let (short, long) = scores(LengthNorm::Length);
+        assert_eq!(short, 5.0 * long);
+        // A pivoted slope between 0 and 1 still prefers the short chunk, but by less
+        let (short, long) = scores(LengthNorm::Pivoted { slope: 0.5 });
+        assert!(short > long && short < 5.0 * long);
+        assert_eq!(scores(LengthNorm::Pivoted { slope: 1.0 }), scores(LengthNorm::Length));
+    }
+
+    #[test]
+    fn test_query_tf_weighting() {
+        let files = vec![
+            crate::corpus::Document::new("a.txt", "rust borrow"),
+            crate::corpus::Document::new("b.txt", "python garbage"),
+        ];
+        let corpus = Corpus::new(files, crate::chunker::ChunkingConfig::default());
+        let score = |query_tf, query: &str| {
+            let scorer = TfIdfScorer::new(&corpus, TfIdfParams { query_tf, ..TfIdfParams::default() });
+            corpus.rank_with(query, &scorer).unwrap()[0].1
+        };
+
+        let once = score(TfScheme::Raw, "rust");
+        assert!((score(TfScheme::Raw, "rust rust rust") - 3.0 * once).abs() < 1e-6);
+        assert_eq!(score(TfScheme::Binary, "rust rust rust"), once);
+        assert!((score(TfScheme::Log, "rust rust") - (1.0 + Score::ln(2.0)) * once).abs() < 1e-6);
+    }
+}
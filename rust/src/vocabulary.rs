@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use crate::corpus::Corpus;
+use crate::search::Score;
+use crate::tfidf::IdfScheme;
+
+// Two indexes only score alike when they have the same terms: the same text analyzed by this
+// crate and by the Python implementation, or an index and its rebuild, end up with vocabularies
+// that differ in a few tokenizer edge cases, and every one of them moves an idf and a length. The
+// vocabulary of one index, written as TSV, can be read back as the fixed vocabulary of another:
+// terms outside it are dropped, so both score in the same term space and any difference left is
+// a difference in scoring
+
+/// A term of the index with its statistics, a line of the vocabulary TSV
+#[derive(Debug, Clone, PartialEq)]
+pub struct VocabularyEntry {
+    pub term: String,
+    /// Chunks containing the term
+    pub df: usize,
+    /// Occurrences of the term in all chunks
+    pub cf: u64,
+    /// ln(N / df), the idf `search --mode tfidf` uses by default
+    pub idf: Score,
+}
+
+/// Every term of the corpus with its statistics, sorted by term
+pub fn vocabulary(corpus: &Corpus) -> Vec<VocabularyEntry> {
+    let index = corpus.index();
+    let mut entries: Vec<VocabularyEntry> = index
+        .terms()
+        .map(|term| VocabularyEntry {
+            term: term.to_string(),
+            df: index.doc_freq(term),
+            cf: index.collection_freq(term),
+            idf: IdfScheme::Plain.idf(index.num_chunks(), index.doc_freq(term)),
+        })
+        .collect();
+    entries.sort_unstable_by(|a, b| a.term.cmp(&b.term));
+    entries
+}
+
+/// Write entries as TSV with a term, df, cf, idf header
+pub fn write_vocabulary<W: Write>(entries: &[VocabularyEntry], mut out: W) -> io::Result<()> {
+    writeln!(out, "term\tdf\tcf\tidf")?;
+    for entry in entries {
+        writeln!(out, "{}\t{}\t{}\t{}", entry.term, entry.df, entry.cf, entry.idf)?;
+    }
+    out.flush()
+}
+
+/// A fixed set of terms an index is restricted to, see Corpus::restrict_vocabulary
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Vocabulary(pub HashSet<String>);
+
+impl Vocabulary {
+    /// The first column of a vocabulary TSV, the header is skipped. A plain list of terms, one
+    /// per line, reads the same
+    pub fn parse(text: &str) -> Vocabulary {
+        let terms = text
+            .lines()
+            .enumerate()
+            .filter(|(number, line)| !(*number == 0 && line.starts_with("term\t")))
+            .filter_map(|(_, line)| line.split('\t').next())
+            .filter(|term| !term.is_empty())
+            .map(str::to_string)
+            .collect();
+        Vocabulary(terms)
+    }
+
+    pub fn load(path: &Path) -> Result<Vocabulary, Box<dyn Error>> {
+        Ok(Vocabulary::parse(&fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::Document;
+    use crate::index::Index;
+
+    #[test]
+    fn test_export_and_import_vocabulary() {
+        let corpus = Corpus::builder()
+            .add_document(Document::new("a.txt", "rust rust borrow"))
+            .add_document(Document::new("b.txt", "rust python"))
+            .build()
+            .unwrap();
+        let entries = vocabulary(&corpus);
+        assert_eq!(entries.iter().map(|entry| entry.term.as_str()).collect::<Vec<_>>(), ["borrow", "python", "rust"]);
+        assert_eq!((entries[2].df, entries[2].cf, entries[2].idf), (2, 3, 0.0));
+        let path = std::env::temp_dir().join(format!("vocabulary_{}.tsv", std::process::id()));
+        Index::new(corpus).export_vocabulary(&path).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().starts_with("term\tdf\tcf\tidf\nborrow\t1\t1\t0.6931"));
+
+        // Another corpus forced onto the first one's terms
+        let mut other = Corpus::builder()
+            .add_document(Document::new("c.txt", "rust go"))
+            .add_document(Document::new("d.txt", "python pip"))
+            .vocabulary(Vocabulary::load(&path).unwrap())
+            .build()
+            .unwrap();
+        let mut terms: Vec<String> = vocabulary(&other).into_iter().map(|entry| entry.term).collect();
+        terms.sort();
+        assert_eq!(terms, ["python", "rust"]);
+        assert_eq!(other.restrict_vocabulary(&Vocabulary::parse("rust\n")), 1);
+        fs::remove_file(path).unwrap();
+    }
+}
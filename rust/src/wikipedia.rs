@@ -0,0 +1,281 @@
+use std::error::Error;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Deserialize;
+use crate::corpus::Document;
+use crate::fields::TITLE;
+use crate::loader::open_text;
+
+// English Wikipedia is six million articles and about 20 GB of text, big enough that the rankers
+// start to differ and open enough that anyone can rerun a comparison. It comes in two dumps:
+// pages-articles.xml.bz2 from dumps.wikimedia.org, the wikitext of every page, and the
+// CirrusSearch content dump, JSON lines with the rendered plain text. Both are read as a stream,
+// one page at a time, so only the articles are ever in memory and not the dump. Talk, user and
+// other non-article pages and redirects are skipped. Wikitext markup is stripped roughly, well
+// enough that templates and link targets don't end up in the vocabulary
+
+/// An article from a dump
+#[derive(Debug, Clone, PartialEq)]
+pub struct Article {
+    pub title: String,
+    /// Plain text, wikitext markup removed
+    pub text: String,
+}
+
+/// Read every article of an XML or CirrusSearch JSON dump, calling each with one after the other
+/// until it returns false. The format is told by the first character, < or {
+pub fn read_articles<R: BufRead>(mut reader: R, mut each: impl FnMut(Article) -> bool) -> Result<(), Box<dyn Error>> {
+    // fill_buf looks at the start of the stream without consuming it
+    let xml = loop {
+        let buffer = reader.fill_buf()?;
+        match buffer.iter().position(|byte| !byte.is_ascii_whitespace()) {
+            Some(start) => break buffer[start] == b'<',
+            None if buffer.is_empty() => return Ok(()),
+            None => {
+                let length = buffer.len();
+                reader.consume(length);
+            }
+        }
+    };
+    if xml { read_xml(reader, &mut each) } else { read_cirrus(reader, &mut each) }
+}
+
+/// The articles of a dump file as documents, named by their title, with the title in the title
+/// field, at most max_articles of them. The file may be compressed, see loader::open_text
+pub fn load_wikipedia(path: &Path, max_articles: Option<usize>) -> Result<Vec<Document>, Box<dyn Error>> {
+    let root = path.to_string_lossy().to_string();
+    let mut documents = Vec::new();
+    // Dumps are read in large pieces, decompressing is the slow part
+    let reader = BufReader::with_capacity(1 << 20, open_text(path)?);
+    read_articles(reader, |article| {
+        if max_articles.is_some_and(|max| documents.len() >= max) {
+            return false;
+        }
+        let mut document = Document { root: root.clone(), ..Document::new(&article.title, &article.text) };
+        document.fields.insert(TITLE.to_string(), article.title);
+        documents.push(document);
+        true
+    })?;
+    Ok(documents)
+}
+
+// The elements of a <page> that are kept
+#[derive(Clone, Copy)]
+enum Field {
+    Title,
+    Namespace,
+    Text,
+}
+
+#[derive(Default)]
+struct Page {
+    title: String,
+    namespace: String,
+    text: String,
+    redirect: bool,
+}
+
+impl Page {
+    fn field(&mut self, field: Field) -> &mut String {
+        match field {
+            Field::Title => &mut self.title,
+            Field::Namespace => &mut self.namespace,
+            Field::Text => &mut self.text,
+        }
+    }
+}
+
+// <mediawiki><siteinfo>...</siteinfo><page><title>..</title><ns>0</ns><redirect title=".." />
+// <revision>...<text>wikitext</text></revision></page>...</mediawiki>
+fn read_xml<R: BufRead>(reader: R, each: &mut dyn FnMut(Article) -> bool) -> Result<(), Box<dyn Error>> {
+    let mut xml = Reader::from_reader(reader);
+    let mut buffer = Vec::new();
+    let mut page = Page::default();
+    // The element whose text is being read, if it's one that is kept
+    let mut field = None;
+    loop {
+        buffer.clear();
+        match xml.read_event_into(&mut buffer)? {
+            Event::Start(element) => match element.name().as_ref() {
+                b"page" => page = Page::default(),
+                b"title" => field = Some(Field::Title),
+                b"ns" => field = Some(Field::Namespace),
+                b"text" => field = Some(Field::Text),
+                _ => {}
+            },
+            Event::Empty(element) if element.name().as_ref() == b"redirect" => page.redirect = true,
+            Event::Text(text) => {
+                if let Some(field) = field {
+                    page.field(field).push_str(&text.decode()?);
+                }
+            }
+            // Entities come apart from the text around them, &amp; in a title is its own event
+            Event::GeneralRef(entity) => {
+                if let Some(field) = field {
+                    let name = entity.decode()?;
+                    match entity.resolve_char_ref()? {
+                        Some(c) => page.field(field).push(c),
+                        None => page.field(field).push_str(resolve_predefined_entity(&name).unwrap_or_default()),
+                    }
+                }
+            }
+            Event::End(element) => match element.name().as_ref() {
+                b"title" | b"ns" | b"text" => field = None,
+                // Namespace 0 holds the articles, the rest are talk pages, user pages, templates...
+                b"page" if page.namespace.trim() == "0" && !page.redirect => {
+                    let page = std::mem::take(&mut page);
+                    if !each(Article { text: strip_wikitext(&page.text), title: page.title }) {
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+// A line of the CirrusSearch dump, the lines in between only say which page comes next
+#[derive(Deserialize)]
+struct CirrusPage {
+    title: Option<String>,
+    text: Option<String>,
+    namespace: Option<i64>,
+}
+
+fn read_cirrus<R: BufRead>(reader: R, each: &mut dyn FnMut(Article) -> bool) -> Result<(), Box<dyn Error>> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let page: CirrusPage = serde_json::from_str(&line)?;
+        if let (Some(title), Some(text), 0) = (page.title, page.text, page.namespace.unwrap_or(0))
+            && !each(Article { title, text })
+        {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+// Links to these namespaces are images and categories, not words of the article
+const HIDDEN_LINKS: [&str; 4] = ["File:", "Image:", "Category:", "Media:"];
+
+/// Roughly plain text from wikitext: templates, tables, references, comments, images and
+/// categories are dropped, links become their label and bold, italics and headings lose their markup
+pub fn strip_wikitext(wikitext: &str) -> String {
+    let mut text = String::with_capacity(wikitext.len());
+    let mut rest = wikitext;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("{{") {
+            // Templates (infoboxes, citations, navboxes) nest, and can't be expanded without MediaWiki
+            rest = skip_nested(rest, "{{", "}}").unwrap_or("");
+        } else if rest.starts_with("{|") {
+            rest = skip_nested(rest, "{|", "|}").unwrap_or("");
+        } else if rest.starts_with("[[")
+            && let Some(after) = skip_nested(rest, "[[", "]]")
+            && let Some(inner) = rest[2..rest.len() - after.len()].strip_suffix("]]")
+        {
+            // A link that never closes, e.g. at the end of a truncated dump, is left as plain text
+            if !HIDDEN_LINKS.iter().any(|prefix| inner.starts_with(prefix)) {
+                // [[Rust (programming language)|Rust]] reads as Rust
+                text.push_str(&strip_wikitext(inner.rsplit('|').next().unwrap_or(inner)));
+            }
+            rest = after;
+        } else if rest.starts_with("[http") || rest.starts_with("[//") {
+            // [https://example.org the label] reads as the label, a bare URL as nothing
+            let end = rest.find(']').unwrap_or(rest.len());
+            if let Some((_, label)) = rest[..end].split_once(' ') {
+                text.push_str(label);
+            }
+            rest = &rest[(end + 1).min(rest.len())..];
+        } else if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.split_once("-->").map_or("", |(_, after)| after);
+        } else if rest.starts_with("<ref") {
+            let tag_end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            rest = if rest[..tag_end].ends_with("/>") {
+                &rest[tag_end..]
+            } else {
+                rest.split_once("</ref>").map_or("", |(_, after)| after)
+            };
+        } else if c == '<' && rest[1..].starts_with(|next: char| next.is_ascii_alphabetic() || next == '/') {
+            // Other tags (<small>, <sup>, <br />) go, what they enclose stays
+            rest = rest.split_once('>').map_or("", |(_, after)| after);
+        } else if rest.starts_with("''") || rest.starts_with("==") {
+            // '' italic, ''' bold, == Heading ==
+            rest = rest.trim_start_matches(c);
+        } else if let Some(after) = rest.strip_prefix("&nbsp;") {
+            text.push(' ');
+            rest = after;
+        } else {
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    text
+}
+
+// The text after the close that matches the open text starts with, None if it never closes
+fn skip_nested<'a>(text: &'a str, open: &str, close: &str) -> Option<&'a str> {
+    let mut depth = 0;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with(open) {
+            depth += 1;
+            rest = &rest[open.len()..];
+        } else if rest.starts_with(close) {
+            depth -= 1;
+            rest = &rest[close.len()..];
+            if depth == 0 {
+                return Some(rest);
+            }
+        } else {
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn articles(dump: &str) -> Vec<Article> {
+        let mut articles = Vec::new();
+        read_articles(dump.as_bytes(), |article| {
+            articles.push(article);
+            true
+        })
+        .unwrap();
+        articles
+    }
+
+    #[test]
+    fn test_read_xml_and_cirrus_dumps() {
+        // Markup in the wikitext is escaped in the XML, &lt;ref&gt; is a <ref> tag of the wikitext
+        let xml = r#"<mediawiki><siteinfo><sitename>Wikipedia</sitename></siteinfo>
+<page><title>Rust &amp; safety</title><ns>0</ns><revision><text xml:space="preserve">{{Infobox|name={{lang|x}}}}'''Rust''' is a [[programming language|language]]&lt;ref name="a"&gt;cite&lt;/ref&gt;.
+== History ==
+See [[Graydon Hoare]] and [https://rust-lang.org the site].[[Category:Languages]]</text></revision></page>
+<page><title>Rust lang</title><ns>0</ns><redirect title="Rust" /><revision><text>#REDIRECT [[Rust]]</text></revision></page>
+<page><title>Talk:Rust</title><ns>1</ns><revision><text>talk</text></revision></page>
+</mediawiki>"#;
+        let parsed = articles(xml);
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].title, "Rust & safety");
+        assert_eq!(parsed[0].text, "Rust is a language.\n History \nSee Graydon Hoare and the site.");
+
+        let cirrus = "{\"index\":{\"_id\":\"1\"}}\n{\"namespace\":0,\"title\":\"Python\",\"text\":\"Python is a language\"}\n\
+                      {\"index\":{\"_id\":\"2\"}}\n{\"namespace\":4,\"title\":\"Wikipedia:About\",\"text\":\"about\"}\n";
+        assert_eq!(articles(cirrus), [Article { title: "Python".to_string(), text: "Python is a language".to_string() }]);
+
+        // A dump cut off inside a link keeps the text instead of slicing into a character
+        assert_eq!(strip_wikitext("[[日本"), "[[日本");
+        assert_eq!(strip_wikitext("a [[b [[c]] d"), "a [[b c d");
+    }
+}
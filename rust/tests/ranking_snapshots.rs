@@ -0,0 +1,61 @@
+// Snapshot tests of the full ranking for a fixture corpus and query set, one snapshot per ranker
+// configuration. A change to tokenizing, chunking or scoring that reorders results or moves
+// scores shows up as a snapshot diff. Review it with `cargo insta review`, or rerun with
+// INSTA_UPDATE=always when the change is intended
+
+use std::path::Path;
+use rust::analyzer::{Analyzer, LightStemmer, Lowercase, Stopwords, WhitespaceTokenizer};
+use rust::bm25::{Bm25Params, Bm25Scorer};
+use rust::chunker::{ChunkStrategy, ChunkingConfig};
+use rust::corpus::Corpus;
+use rust::eval::{load_queries, result_key};
+use rust::query::TermScorer;
+use rust::smart::parse_smart;
+use rust::tfidf::{LengthNorm, TfIdfParams, TfIdfScorer};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+fn fixture_corpus(analyzer: Analyzer) -> Corpus {
+    Corpus::builder()
+        .add_dir(format!("{}/corpus", FIXTURES))
+        .chunking(ChunkingConfig::new(ChunkStrategy::Words, 24, 4).unwrap())
+        .analyzer(analyzer)
+        .build()
+        .unwrap()
+}
+
+// Every query with its top 5 results, scores rounded so the snapshots survive float noise
+fn rankings(corpus: &Corpus, scorer: &dyn TermScorer) -> String {
+    let mut out = String::new();
+    for (id, query) in load_queries(&Path::new(FIXTURES).join("queries.tsv")).unwrap() {
+        out.push_str(&format!("{} {}\n", id, query));
+        for (rank, (chunk, score)) in corpus.rank_with(&query, scorer).unwrap().into_iter().take(5).enumerate() {
+            out.push_str(&format!("  {} {:.4} {}\n", rank + 1, score, result_key(corpus, chunk).unwrap()));
+        }
+    }
+    out
+}
+
+#[test]
+fn test_tfidf_rankings() {
+    let corpus = fixture_corpus(Analyzer::default());
+    let pivoted = TfIdfParams { norm: LengthNorm::Pivoted { slope: 0.25 }, ..TfIdfParams::default() };
+    insta::assert_snapshot!("tfidf_default", rankings(&corpus, &TfIdfScorer::new(&corpus, TfIdfParams::default())));
+    insta::assert_snapshot!("tfidf_pivoted", rankings(&corpus, &TfIdfScorer::new(&corpus, pivoted)));
+    insta::assert_snapshot!("tfidf_lnc_ltc", rankings(&corpus, &TfIdfScorer::new(&corpus, parse_smart("lnc.ltc").unwrap())));
+}
+
+#[test]
+fn test_bm25_rankings() {
+    let corpus = fixture_corpus(Analyzer::default());
+    let no_length_norm = Bm25Params { k1: 2.0, b: 0.0 };
+    insta::assert_snapshot!("bm25_default", rankings(&corpus, &Bm25Scorer { corpus: &corpus, params: Bm25Params::default() }));
+    insta::assert_snapshot!("bm25_k1_2_b_0", rankings(&corpus, &Bm25Scorer { corpus: &corpus, params: no_length_norm }));
+
+    let analyzer = Analyzer::new(WhitespaceTokenizer)
+        .with_filter(Lowercase)
+        .with_filter(Stopwords::english())
+        .with_filter(LightStemmer);
+    let stemmed = fixture_corpus(analyzer);
+    insta::assert_snapshot!("bm25_stemmed", rankings(&stemmed, &Bm25Scorer { corpus: &stemmed, params: Bm25Params::default() }));
+}